@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec;
+use lib::*;
+
+extern crate lib;
+
+/// Level names by the number in a record, tagged like the kernel console
+const LEVELS: [(&str, &str); 5] = [
+    ("error", "[E]"),
+    ("warn", "[!]"),
+    ("info", "[+]"),
+    ("debug", "[D]"),
+    ("trace", "[T]"),
+];
+
+/// Parse `error,warn` into the shown levels, all of them if empty
+fn parse_levels(input: &str) -> Option<[bool; 5]> {
+    if input.is_empty() {
+        return Some([true; 5]);
+    }
+
+    let mut shown = [false; 5];
+    for name in input.split(',') {
+        let level = LEVELS.iter().position(|(level, _)| *level == name.trim())?;
+        shown[level] = true;
+    }
+
+    Some(shown)
+}
+
+fn main() -> usize {
+    print!("Levels (e.g. `error,warn`, empty for all): ");
+    let input = lib::stdin().read_line();
+
+    let Some(shown) = parse_levels(input.trim()) else {
+        errln!("Unknown level in: {}", input.trim());
+        return 1;
+    };
+
+    print!("Clear the log after reading? [y/N] ");
+    let clear = lib::stdin().read_line().trim() == "y";
+
+    let size = sys_syslog(SyslogAction::SizeBuffer, &mut []).unwrap_or(0);
+    let mut buf = vec![0u8; size];
+
+    let action = if clear {
+        SyslogAction::ReadClear
+    } else {
+        SyslogAction::ReadAll
+    };
+
+    let Some(len) = sys_syslog(action, &mut buf) else {
+        errln!("Failed to read the kernel log");
+        return 1;
+    };
+
+    let text = String::from_utf8_lossy(&buf[..len]);
+
+    // lines without a level continue the record before them
+    let mut show = false;
+    for line in text.lines() {
+        if let Some((level, rest)) = line.strip_prefix('<').and_then(|line| line.split_once('>')) {
+            let level = level.parse::<usize>().unwrap_or(0);
+            show = (1..=5).contains(&level) && shown[level - 1];
+
+            if show {
+                // keep the timestamp first, then the level tag
+                let (time, message) = rest.split_once("] ").unwrap_or((rest, ""));
+                println!("{}] {} {}", time, LEVELS[level - 1].1, message);
+            }
+        } else if show {
+            println!("{}", line);
+        }
+    }
+
+    0
+}
+
+entry!(main);
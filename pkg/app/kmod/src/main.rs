@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+use lib::*;
+
+extern crate lib;
+
+fn check(name: &str, passed: bool) -> bool {
+    println!("{}: {}", name, if passed { "passed" } else { "FAILED" });
+    passed
+}
+
+fn main() -> isize {
+    println!("hello, this is a kernel module test!");
+
+    let mut passed = check("no device", sys_open("/dev/zero").is_none());
+    passed &= check("insmod", sys_init_module("zero"));
+    passed &= check("insmod twice", !sys_init_module("zero"));
+
+    sys_list_module();
+
+    let fd = match sys_open("/dev/zero") {
+        Some(fd) => fd,
+        None => {
+            check("open", false);
+            sys_delete_module("zero");
+            return 1;
+        }
+    };
+
+    let mut buf = [0xffu8; 64];
+    passed &= check("read", sys_read(fd, &mut buf) == Some(buf.len()));
+    passed &= check("zeroed", buf.iter().all(|&b| b == 0));
+    passed &= check("write", sys_write(fd, b"discarded") == Some(9));
+
+    passed &= check("rmmod busy", !sys_delete_module("zero"));
+    passed &= check("close", sys_close(fd));
+    passed &= check("rmmod", sys_delete_module("zero"));
+    passed &= check("device removed", sys_open("/dev/zero").is_none());
+    passed &= check("rmmod twice", !sys_delete_module("zero"));
+
+    if passed {
+        println!("All tests passed.");
+        0
+    } else {
+        1
+    }
+}
+
+entry!(main);
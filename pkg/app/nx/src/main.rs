@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+
+use lib::*;
+
+extern crate lib;
+
+/// exit code of processes killed on page fault
+const KILLED: isize = 0xdead;
+
+/// jump into a `ret` instruction on the stack
+fn exec_stack() -> isize {
+    let code = core::hint::black_box([0xc3u8; 16]);
+    println!("Executing stack at {:#x}...", code.as_ptr() as usize);
+
+    let f: extern "C" fn() = unsafe { core::mem::transmute(code.as_ptr()) };
+    f();
+
+    println!("Stack is executable!");
+    0
+}
+
+/// overwrite the first byte of `main`
+fn write_text() -> isize {
+    let text = main as fn() -> isize as usize as *mut u8;
+    println!("Writing text at {:#x}...", text as usize);
+
+    unsafe {
+        let byte = text.read_volatile();
+        text.write_volatile(byte);
+    }
+
+    println!("Text is writable!");
+    0
+}
+
+fn run(name: &str, test: fn() -> isize) -> bool {
+    let pid = sys_fork();
+
+    if pid == 0 {
+        sys_exit(test() as usize);
+    }
+
+    let ret = sys_wait_pid(pid);
+    let passed = ret == KILLED;
+
+    println!(
+        "{}: {} (exit code {:#x})",
+        name,
+        if passed { "killed" } else { "FAILED" },
+        ret
+    );
+
+    passed
+}
+
+fn main() -> isize {
+    println!("hello, this is a W^X test!");
+
+    let passed = run("exec stack", exec_stack) & run("write text", write_text);
+
+    if passed {
+        println!("All tests passed.");
+        0
+    } else {
+        1
+    }
+}
+
+entry!(main);
@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+use lib::*;
+
+extern crate lib;
+
+const STACK_LIMIT: usize = 0x10000;
+
+#[allow(unconditional_recursion)]
+fn recurse(depth: usize) -> usize {
+    let buf = core::hint::black_box([depth as u8; 0x100]);
+
+    if depth % 0x20 == 0 {
+        println!("Depth: {}", depth);
+    }
+
+    recurse(depth + 1) + buf[0] as usize
+}
+
+fn main() -> isize {
+    println!("hello, this is a stack overflow test!");
+
+    println!(
+        "Stack limit: {:#x}",
+        sys_get_rlimit(Rlimit::Stack).expect("Failed to get stack limit")
+    );
+
+    assert!(
+        sys_set_rlimit(Rlimit::Stack, STACK_LIMIT),
+        "Failed to set stack limit"
+    );
+
+    println!(
+        "Stack limit: {:#x}",
+        sys_get_rlimit(Rlimit::Stack).expect("Failed to get stack limit")
+    );
+
+    // should be killed by the kernel before returning
+    recurse(0) as isize
+}
+
+entry!(main);
@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+
+use lib::*;
+
+extern crate lib;
+
+const SEM_KEY: u32 = 0x7ace;
+const CHILD_EXIT_CODE: isize = 7;
+
+static mut MAGIC: u64 = 0x1234_5678;
+
+fn check(name: &str, passed: bool) -> bool {
+    println!("{}: {}", name, if passed { "passed" } else { "FAILED" });
+    passed
+}
+
+fn child() -> isize {
+    // wait until the parent is tracing
+    sys_wait_sem(SEM_KEY);
+
+    println!("child: pid {}", sys_get_pid());
+
+    unsafe {
+        core::arch::asm!("int3");
+    }
+
+    CHILD_EXIT_CODE
+}
+
+fn stop_of(status: isize) -> Option<PtraceStop> {
+    PtraceStop::from_status(status)
+}
+
+fn main() -> isize {
+    println!("hello, this is a ptrace test!");
+
+    if !sys_new_sem(SEM_KEY, 0) {
+        println!("failed to create semaphore");
+        return 1;
+    }
+
+    let pid = sys_fork();
+    if pid == 0 {
+        return child();
+    }
+
+    let mut passed = check("attach", sys_ptrace_attach(pid));
+    passed &= check("attach twice", !sys_ptrace_attach(pid));
+    passed &= check("not stopped", sys_ptrace_get_regs(pid).is_none());
+
+    sys_signal_sem(SEM_KEY);
+    passed &= check(
+        "attach stop",
+        stop_of(sys_wait_pid(pid)) == Some(PtraceStop::Attach),
+    );
+
+    let addr = core::ptr::addr_of!(MAGIC) as usize;
+    passed &= check("peek", sys_ptrace_peek(pid, addr) == Some(0x1234_5678));
+    passed &= check("poke", sys_ptrace_poke(pid, addr, 0xdead_beef));
+    passed &= check(
+        "peek poked",
+        sys_ptrace_peek(pid, addr) == Some(0xdead_beef),
+    );
+    passed &= check("peek bad address", sys_ptrace_peek(pid, 0).is_none());
+
+    let regs = sys_ptrace_get_regs(pid);
+    passed &= check("get regs", regs.is_some());
+
+    if let Some(regs) = regs {
+        passed &= check("set regs", sys_ptrace_set_regs(pid, &regs));
+        passed &= check("step", sys_ptrace_step(pid));
+        passed &= check(
+            "step stop",
+            stop_of(sys_wait_pid(pid)) == Some(PtraceStop::Step),
+        );
+        passed &= check(
+            "step moved",
+            sys_ptrace_get_regs(pid).is_some_and(|new| new.rip != regs.rip),
+        );
+    }
+
+    // trace the syscalls until the child exits
+    let mut entries = 0;
+    let mut exits = 0;
+    let mut breakpoints = 0;
+
+    let status = loop {
+        if !sys_ptrace_syscall(pid) {
+            break -1;
+        }
+
+        let status = sys_wait_pid(pid);
+
+        match stop_of(status) {
+            Some(PtraceStop::SyscallEntry) => {
+                entries += 1;
+                if let Some(regs) = sys_ptrace_get_regs(pid) {
+                    println!(
+                        "syscall {} ({:#x}, {:#x}, {:#x})",
+                        regs.rax, regs.rdi, regs.rsi, regs.rdx
+                    );
+                }
+            }
+            Some(PtraceStop::SyscallExit) => {
+                exits += 1;
+                if let Some(regs) = sys_ptrace_get_regs(pid) {
+                    println!("  = {:#x}", regs.rax);
+                }
+            }
+            Some(PtraceStop::Breakpoint) => breakpoints += 1,
+            Some(stop) => println!("unexpected stop: {:?}", stop),
+            None => break status,
+        }
+    };
+
+    passed &= check("syscall entries", entries > 0);
+    passed &= check("syscall exits", exits > 0);
+    passed &= check("breakpoint", breakpoints == 1);
+    passed &= check("exit code", status == CHILD_EXIT_CODE);
+    passed &= check("magic poked", unsafe { MAGIC } == 0xdead_beef);
+
+    sys_del_sem(SEM_KEY);
+
+    if passed {
+        println!("All tests passed.");
+        0
+    } else {
+        1
+    }
+}
+
+entry!(main);
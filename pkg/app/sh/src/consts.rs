@@ -8,9 +8,16 @@ pub const fn help_text() -> &'static str {
 Usage:
     help        | show this help
     ps          | show process list
-    ls          | show app list
+    ls [path]   | show app list, or list directory
+    cmdline     | show kernel command line
     exec <name> | execute program
     kill <pid>  | kill process
+    strace <p>  | log syscalls of process, `off` to stop
+    sysstat <p> | show syscall counters of process
+    log [spec]  | show or set log levels, e.g. `info,proc=trace`
+    lsmod       | show loaded kernel modules
+    insmod <m>  | load kernel module
+    rmmod <m>   | unload kernel module
     clear       | clear screen
     exit        | exit shell
 
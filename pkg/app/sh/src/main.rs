@@ -25,7 +25,11 @@ fn main() -> usize {
                 break;
             }
             "ps" => sys_stat(),
-            "ls" => sys_list_app(),
+            "ls" => match line.get(1) {
+                Some(path) => services::ls(path),
+                None => sys_list_app(),
+            },
+            "cmdline" => services::cmdline(),
             "exec" => {
                 if line.len() < 2 {
                     println!("Usage: exec <file>");
@@ -48,6 +52,52 @@ fn main() -> usize {
 
                 services::kill(pid.unwrap());
             }
+            "strace" => {
+                if line.len() < 2 {
+                    println!("Usage: strace <pid> [off]");
+                    continue;
+                }
+                let pid = line[1].to_string().parse::<u16>();
+
+                if pid.is_err() {
+                    errln!("Cannot parse pid");
+                    continue;
+                }
+
+                services::strace(pid.unwrap(), line.get(2) != Some(&"off"));
+            }
+            "sysstat" => {
+                if line.len() < 2 {
+                    println!("Usage: sysstat <pid>");
+                    continue;
+                }
+                let pid = line[1].to_string().parse::<u16>();
+
+                if pid.is_err() {
+                    errln!("Cannot parse pid");
+                    continue;
+                }
+
+                services::sysstat(pid.unwrap());
+            }
+            "log" => services::log_level(line.get(1).copied().unwrap_or("")),
+            "lsmod" => sys_list_module(),
+            "insmod" => {
+                if line.len() < 2 {
+                    println!("Usage: insmod <module>");
+                    continue;
+                }
+
+                services::insmod(line[1]);
+            }
+            "rmmod" => {
+                if line.len() < 2 {
+                    println!("Usage: rmmod <module>");
+                    continue;
+                }
+
+                services::rmmod(line[1]);
+            }
             "help" => print!("{}", consts::help_text()),
             "clear" => print!("\x1b[1;1H\x1b[2J"),
             _ => {
@@ -1,3 +1,4 @@
+use alloc::string::String;
 use lib::*;
 
 pub fn exec(name: &str) {
@@ -23,3 +24,70 @@ pub fn exec(name: &str) {
 pub fn kill(pid: u16) {
     sys_kill(pid);
 }
+
+pub fn strace(pid: u16, enable: bool) {
+    if !sys_strace(pid, enable) {
+        errln!("no such process: {}", pid);
+    }
+}
+
+pub fn sysstat(pid: u16) {
+    if !sys_syscall_stat(pid) {
+        errln!("no such process: {}", pid);
+    }
+}
+
+pub fn log_level(filters: &str) {
+    if !sys_log_level(filters) {
+        errln!("invalid log filters: {}", filters);
+    }
+}
+
+pub fn cmdline() {
+    let Some(fd) = sys_open("/proc/cmdline") else {
+        errln!("failed to open /proc/cmdline");
+        return;
+    };
+
+    let mut buf = [0u8; 256];
+    while let Some(count @ 1..) = sys_read(fd, &mut buf) {
+        print!("{}", String::from_utf8_lossy(&buf[..count]));
+    }
+    println!();
+
+    sys_close(fd);
+}
+
+pub fn ls(path: &str) {
+    let Some(fd) = sys_open(path) else {
+        errln!("no such file or directory: {}", path);
+        return;
+    };
+
+    match sys_fstat(fd) {
+        Some(stat) if stat.file_type() == FileType::Directory => {
+            while let Some(entry) = sys_read_dir(fd) {
+                match entry.file_type() {
+                    FileType::Directory => println!("{}/", entry.name()),
+                    _ => println!("{}", entry.name()),
+                }
+            }
+        }
+        Some(stat) => println!("{} {}", path, stat.size),
+        None => errln!("not a file: {}", path),
+    }
+
+    sys_close(fd);
+}
+
+pub fn insmod(name: &str) {
+    if !sys_init_module(name) {
+        errln!("failed to load module: {}", name);
+    }
+}
+
+pub fn rmmod(name: &str) {
+    if !sys_delete_module(name) {
+        errln!("failed to unload module: {}", name);
+    }
+}
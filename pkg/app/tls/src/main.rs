@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+#![feature(thread_local)]
+
+use lib::*;
+
+extern crate lib;
+
+/// initialized from .tdata
+#[thread_local]
+static mut COUNTER: usize = 0x1234;
+
+/// zeroed from .tbss
+#[thread_local]
+static mut ZEROED: [u64; 4] = [0; 4];
+
+/// read the thread pointer through its self pointer at `fs:0`
+fn thread_pointer() -> usize {
+    let tp: usize;
+    unsafe { core::arch::asm!("mov {}, fs:0", out(reg) tp) };
+    tp
+}
+
+fn check(name: &str, passed: bool) -> bool {
+    println!("{}: {}", name, if passed { "passed" } else { "FAILED" });
+    passed
+}
+
+fn main() -> isize {
+    println!("hello, this is a TLS test!");
+
+    let fs_base = sys_get_fs_base().unwrap_or_default();
+    println!("FS base: {:#x}", fs_base);
+
+    let mut passed = check("fs base", fs_base != 0 && thread_pointer() == fs_base);
+    passed &= check("tdata", unsafe { COUNTER } == 0x1234);
+    let zeroed = unsafe { ZEROED };
+    passed &= check("tbss", zeroed == [0; 4]);
+
+    unsafe { COUNTER = 1 };
+
+    let pid = sys_fork();
+
+    if pid == 0 {
+        // the child has its own copy of the parent's block
+        let mut passed = check("child copy", unsafe { COUNTER } == 1);
+        passed &= check("child fs base", sys_get_fs_base() == Some(thread_pointer()));
+        passed &= check("child fs base moved", thread_pointer() != fs_base);
+
+        unsafe { COUNTER = 2 };
+        sys_exit(if passed { 0 } else { 1 });
+    }
+
+    passed &= check("child", sys_wait_pid(pid) == 0);
+    passed &= check("parent untouched", unsafe { COUNTER } == 1);
+
+    passed &= check("invalid fs base", !sys_set_fs_base(0xffff_8000_0000_0000));
+    passed &= check("fs base kept", sys_get_fs_base() == Some(fs_base));
+
+    if passed {
+        println!("All tests passed.");
+        0
+    } else {
+        1
+    }
+}
+
+entry!(main);
@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+use lib::*;
+
+extern crate lib;
+
+fn check(name: &str, passed: bool) -> bool {
+    println!("{}: {}", name, if passed { "passed" } else { "FAILED" });
+    passed
+}
+
+fn main() -> isize {
+    println!("hello, this is a vfs test!");
+
+    let create = OpenFlags::CREATE;
+    let mkdir = OpenFlags::CREATE | OpenFlags::DIRECTORY;
+
+    let mut passed = check("missing", sys_open("/tmp/vfs/missing").is_none());
+
+    let Some(dir) = sys_open_with("/tmp/vfs", mkdir) else {
+        check("mkdir", false);
+        return 1;
+    };
+    passed &= check("close dir", sys_close(dir));
+
+    let Some(fd) = sys_open_with("/tmp/vfs/./file", create | OpenFlags::TRUNCATE) else {
+        check("create", false);
+        return 1;
+    };
+
+    passed &= check("write", sys_write(fd, b"hello, vfs!") == Some(11));
+    passed &= check("seek", sys_lseek(fd, 7, SeekWhence::Set) == Some(7));
+
+    let mut buf = [0u8; 16];
+    passed &= check("read", sys_read(fd, &mut buf) == Some(4));
+    passed &= check("content", &buf[..4] == b"vfs!");
+    passed &= check("eof", sys_read(fd, &mut buf) == Some(0));
+    passed &= check("seek back", sys_lseek(fd, -4, SeekWhence::End) == Some(7));
+    passed &= check("bad seek", sys_lseek(fd, -1, SeekWhence::Set).is_none());
+
+    let stat = sys_fstat(fd);
+    passed &= check(
+        "fstat",
+        stat.is_some_and(|stat| stat.size == 11 && stat.file_type() == FileType::File),
+    );
+    passed &= check("close", sys_close(fd));
+
+    match sys_open_with("/tmp/vfs/../vfs/file", OpenFlags::APPEND) {
+        Some(fd) => {
+            passed &= check("append", sys_write(fd, b"!!") == Some(2));
+            passed &= check(
+                "appended",
+                sys_fstat(fd).is_some_and(|stat| stat.size == 13),
+            );
+            sys_close(fd);
+        }
+        None => passed &= check("dotdot", false),
+    }
+
+    passed &= check("not a dir", sys_open_with("/tmp/vfs/file", mkdir).is_none());
+    passed &= check("mkdir sub", sys_open_with("/tmp/vfs/sub", mkdir).is_some());
+
+    match sys_open("/tmp/vfs") {
+        Some(dir) => {
+            let mut names = [false; 2];
+            while let Some(entry) = sys_read_dir(dir) {
+                println!("  {} {:?}", entry.name(), entry.file_type());
+                match (entry.name(), entry.file_type()) {
+                    ("file", FileType::File) => names[0] = true,
+                    ("sub", FileType::Directory) => names[1] = true,
+                    _ => passed = false,
+                }
+            }
+            passed &= check("readdir", names == [true, true]);
+            passed &= check("read dir", sys_read(dir, &mut buf).is_none());
+            sys_close(dir);
+        }
+        None => passed &= check("open dir", false),
+    }
+
+    match sys_open("/") {
+        Some(root) => {
+            let mut tmp = false;
+            while let Some(entry) = sys_read_dir(root) {
+                tmp |= entry.name() == "tmp";
+            }
+            passed &= check("mount point", tmp);
+            sys_close(root);
+        }
+        None => passed &= check("open root", false),
+    }
+
+    passed &= check("read-only", sys_open_with("/bin/new", create).is_none());
+
+    if passed {
+        println!("All tests passed.");
+        0
+    } else {
+        1
+    }
+}
+
+entry!(main);
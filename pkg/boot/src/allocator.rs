@@ -14,3 +14,30 @@ unsafe impl FrameAllocator<Size4KiB> for UEFIFrameAllocator<'_> {
         Some(frame)
     }
 }
+
+unsafe impl FrameAllocator<Size2MiB> for UEFIFrameAllocator<'_> {
+    /// UEFI does not align allocations, so twice the size is allocated
+    /// and the pages around the aligned frame are given back
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        const PAGES: usize = (Size2MiB::SIZE / Size4KiB::SIZE) as usize;
+
+        let addr = self
+            .0
+            .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, PAGES * 2)
+            .ok()?;
+
+        let start = x86_64::align_up(addr, Size2MiB::SIZE);
+        let head = ((start - addr) / Size4KiB::SIZE) as usize;
+
+        unsafe {
+            if head > 0 {
+                self.0.free_pages(addr, head).ok()?;
+            }
+            self.0
+                .free_pages(start + Size2MiB::SIZE, PAGES - head)
+                .ok()?;
+        }
+
+        Some(PhysFrame::containing_address(PhysAddr::new(start)))
+    }
+}
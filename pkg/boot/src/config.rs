@@ -21,6 +21,8 @@ pub struct Config<'a> {
     pub load_apps: bool,
     /// Log level
     pub log_level: &'a str,
+    /// Randomize the kernel base and the physical memory offset
+    pub kaslr: bool,
 }
 
 const DEFAULT_CONFIG: Config = Config {
@@ -33,6 +35,7 @@ const DEFAULT_CONFIG: Config = Config {
     cmdline: "",
     load_apps: false,
     log_level: "info",
+    kaslr: true,
 };
 
 impl<'a> Config<'a> {
@@ -73,6 +76,7 @@ impl<'a> Config<'a> {
             "cmdline" => self.cmdline = value,
             "load_apps" => self.load_apps = r10 != 0,
             "log_level" => self.log_level = value,
+            "kaslr" => self.kaslr = r10 != 0,
             _ => warn!("undefined config key: {}", key),
         }
     }
@@ -1,4 +1,4 @@
-use crate::App;
+use crate::{App, AppList};
 use arrayvec::{ArrayString, ArrayVec};
 use uefi::proto::media::file::*;
 use uefi::proto::media::fs::SimpleFileSystem;
@@ -62,7 +62,7 @@ pub fn load_file(bs: &BootServices, file: &mut RegularFile) -> &'static mut [u8]
 /// Load apps into memory, when no fs implemented in kernel
 ///
 /// List all file under "APP" and load them.
-pub fn load_apps(bs: &BootServices) -> ArrayVec<App<'static>, 16> {
+pub fn load_apps(bs: &BootServices) -> AppList {
     let mut root = open_root(bs);
 
     let mut buf = [0; 8];
@@ -98,6 +98,11 @@ pub fn load_apps(bs: &BootServices) -> ArrayVec<App<'static>, 16> {
                     continue;
                 }
 
+                if apps.is_full() {
+                    warn!("Too many apps, skip \"{}\"", info.file_name());
+                    continue;
+                }
+
                 let mut file = file.into_regular_file().unwrap();
                 let buf = load_file(bs, &mut file);
 
@@ -15,13 +15,18 @@ use xmas_elf::ElfFile;
 pub mod allocator;
 pub mod config;
 pub mod fs;
+pub mod random;
 
 #[macro_use]
 extern crate log;
 
 pub type MemoryMap = ArrayVec<MemoryDescriptor, 256>;
 pub type KernelPages = ArrayVec<PageRangeInclusive, 8>;
-pub type AppListRef = Option<&'static ArrayVec<App<'static>, 16>>;
+pub type AppList = ArrayVec<App<'static>, MAX_APPS>;
+pub type AppListRef = Option<&'static AppList>;
+
+/// The max count of apps (and shared libraries) loaded by the bootloader
+pub const MAX_APPS: usize = 64;
 
 /// This structure represents the information that the bootloader passes to the kernel.
 pub struct BootInfo {
@@ -31,15 +36,30 @@ pub struct BootInfo {
     /// The offset into the virtual address space where the physical memory is mapped.
     pub physical_memory_offset: u64,
 
+    /// The lowest virtual address of the loaded kernel
+    pub kernel_base: u64,
+
+    /// The offset of the kernel from its linked address, 0 without KASLR
+    pub kernel_slide: u64,
+
+    /// The function symbols of the kernel, packed by `elf::compress_symbols`
+    pub kernel_symbols: &'static [u8],
+
     /// UEFI SystemTable
     pub system_table: SystemTable<Runtime>,
 
     // Loaded apps
-    pub loaded_apps: Option<ArrayVec<App<'static>, 16>>,
+    pub loaded_apps: Option<AppList>,
 
     // Log Level
     pub log_level: &'static str,
 
+    /// The kernel command line
+    pub cmdline: &'static str,
+
+    /// The initramfs image, a ustar archive
+    pub initramfs: Option<&'static [u8]>,
+
     // Kernel pages
     pub kernel_pages: KernelPages,    
 }
@@ -24,6 +24,14 @@ mod config;
 
 const CONFIG_PATH: &str = "\\EFI\\BOOT\\boot.conf";
 
+/// The physical memory window is placed at a random 1 GiB slot
+/// in this range above the configured offset
+const PHYSICAL_OFFSET_RANGE: u64 = 0x4000_0000_0000; // 64 TiB
+
+/// The kernel is slid by a random multiple of 2 MiB below this,
+/// which keeps it away from the kernel stack
+const KERNEL_SLIDE_RANGE: u64 = 0x4000_0000; // 1 GiB
+
 #[entry]
 fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status {
     uefi::helpers::init(&mut system_table).expect("Failed to initialize utilities");
@@ -47,8 +55,18 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
         let buf = load_file(bs, &mut file);
         ElfFile::new(buf).expect("failed to parse ELF")
     };
+
+    let kernel_slide = if !config.kaslr {
+        0
+    } else if elf::is_pie(&elf) {
+        ysos_boot::random::random_below(KERNEL_SLIDE_RANGE / Size2MiB::SIZE) * Size2MiB::SIZE
+    } else {
+        warn!("Kernel is not position independent, KASLR is disabled for it.");
+        0
+    };
+
     unsafe {
-        ENTRY = elf.header.pt2.entry_point() as usize;
+        ENTRY = (elf.header.pt2.entry_point() + kernel_slide) as usize;
     }
 
     let apps = if config.load_apps {
@@ -59,6 +77,12 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
         None
     };
 
+    let initramfs = config.initramfs.map(|path| {
+        info!("Loading initramfs...");
+        let mut file = open_file(bs, path);
+        &*load_file(bs, &mut file)
+    });
+
     // 3. Load MemoryMap
     let max_mmap_size = system_table.boot_services().memory_map_size();
     let mmap_storage = Box::leak(
@@ -76,6 +100,19 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
         .unwrap()
         .max(0x1_0000_0000); // include IOAPIC MMIO area
 
+    let physical_memory_offset = if config.kaslr {
+        let window = max_phys_addr.next_multiple_of(Size1GiB::SIZE) + Size1GiB::SIZE;
+        let slots = PHYSICAL_OFFSET_RANGE.saturating_sub(window) / Size1GiB::SIZE;
+        config.physical_memory_offset + ysos_boot::random::random_below(slots) * Size1GiB::SIZE
+    } else {
+        config.physical_memory_offset
+    };
+
+    info!(
+        "Physical memory offset: {:#x}, kernel slide: {:#x}",
+        physical_memory_offset, kernel_slide
+    );
+
     // 4. Map ELF segments, kernel stack and physical memory to virtual memory
     let mut page_table = current_page_table();
 
@@ -86,7 +123,7 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
     }
 
     elf::map_physical_memory(
-        config.physical_memory_offset,
+        physical_memory_offset,
         max_phys_addr,
         &mut page_table,
         &mut UEFIFrameAllocator(bs),
@@ -94,13 +131,31 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
 
     let kernel_pages = elf::load_elf(
         &elf,
-        config.physical_memory_offset,
+        kernel_slide,
+        physical_memory_offset,
         &mut page_table,
         &mut UEFIFrameAllocator(bs),
         false,
     )
     .expect("Failed to load ELF");
 
+    if elf::is_pie(&elf) {
+        // write protect is disabled, read-only pages can be patched
+        let count =
+            unsafe { elf::relocate(&elf, kernel_slide) }.expect("Failed to relocate kernel");
+        info!("Applied {} relocations to the kernel", count);
+    }
+
+    // the symbol table is not loaded with the kernel, keep it for backtraces
+    let kernel_symbols: &'static [u8] = Box::leak(elf::compress_symbols(&elf).into_boxed_slice());
+    info!("Kernel symbol table: {} bytes", kernel_symbols.len());
+
+    let kernel_base = kernel_pages
+        .iter()
+        .map(|range| range.start.start_address().as_u64())
+        .min()
+        .unwrap_or_default();
+
     let (stack_start, stack_size) = if config.kernel_stack_auto_grow > 0 {
         let stack_start = config.kernel_stack_address
             + (config.kernel_stack_size - config.kernel_stack_auto_grow) * 0x1000;
@@ -143,10 +198,15 @@ fn efi_main(image: uefi::Handle, mut system_table: SystemTable<Boot>) -> Status
     // construct BootInfo
     let bootinfo = BootInfo {
         memory_map: mmap.entries().copied().collect(),
-        physical_memory_offset: config.physical_memory_offset,
+        physical_memory_offset,
+        kernel_base,
+        kernel_slide,
+        kernel_symbols,
         system_table: runtime,
         loaded_apps: apps,
         log_level: config.log_level,
+        cmdline: config.cmdline,
+        initramfs,
         kernel_pages: kernel_pages,
     };
 
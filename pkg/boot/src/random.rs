@@ -0,0 +1,42 @@
+//! Random numbers for address space layout randomization
+//!
+//! RDRAND is used when the CPU supports it, otherwise the time stamp
+//! counter is mixed into a splitmix64 state.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::instructions::random::RdRand;
+
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+fn rdtsc() -> u64 {
+    let (lo, hi): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") lo, out("edx") hi, options(nomem, nostack));
+    }
+    ((hi as u64) << 32) | lo as u64
+}
+
+/// Get a random u64, not suitable for cryptography
+pub fn random_u64() -> u64 {
+    if let Some(value) = RdRand::new().and_then(|rdrand| rdrand.get_u64()) {
+        return value;
+    }
+
+    // splitmix64, reseeded by the time stamp counter on every call
+    let mut z = STATE
+        .fetch_add(rdtsc() | 1, Ordering::Relaxed)
+        .wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Get a random number in `[0, bound)`
+pub fn random_below(bound: u64) -> u64 {
+    if bound == 0 {
+        return 0;
+    }
+    random_u64() % bound
+}
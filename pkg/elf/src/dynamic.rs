@@ -0,0 +1,282 @@
+use alloc::vec::Vec;
+use xmas_elf::{program, ElfFile};
+
+use crate::ElfError;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_STRSZ: u64 = 10;
+const DT_SONAME: u64 = 14;
+const DT_GNU_HASH: u64 = 0x6fff_fef5;
+
+/// the size of an `Elf64_Dyn` entry
+const DYN_SIZE: usize = 16;
+/// the size of an `Elf64_Sym` entry
+const SYM_SIZE: usize = 24;
+
+const STB_GLOBAL: u8 = 1;
+const STB_WEAK: u8 = 2;
+
+/// A symbol exported by a shared object, `value` is the linked address
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub value: u64,
+    pub size: u64,
+}
+
+/// The dynamic section (PT_DYNAMIC) of an ELF file
+///
+/// the tables it refers to are read from the file, through the loadable
+/// segments which contain them.
+pub struct Dynamic<'a> {
+    data: &'a [u8],
+    /// (virtual address, file offset, file size) of loadable segments
+    loads: Vec<(u64, u64, u64)>,
+    /// offsets of the names of needed libraries in the string table
+    needed: Vec<u64>,
+    soname: Option<u64>,
+    strtab: usize,
+    strsz: usize,
+    symtab: usize,
+    hash: Option<usize>,
+    gnu_hash: Option<usize>,
+}
+
+impl<'a> Dynamic<'a> {
+    /// Parse the dynamic section, `None` if the file is statically linked
+    pub fn new(elf: &ElfFile<'a>) -> Result<Option<Self>, ElfError> {
+        let segment = match elf
+            .program_iter()
+            .find(|segment| segment.get_type() == Ok(program::Type::Dynamic))
+        {
+            Some(segment) => segment,
+            None => return Ok(None),
+        };
+
+        let loads = elf
+            .program_iter()
+            .filter(|segment| segment.get_type() == Ok(program::Type::Load))
+            .map(|segment| {
+                (
+                    segment.virtual_addr(),
+                    segment.offset(),
+                    segment.file_size(),
+                )
+            })
+            .collect();
+
+        let mut dynamic = Self {
+            data: elf.input,
+            loads,
+            needed: Vec::new(),
+            soname: None,
+            strtab: 0,
+            strsz: 0,
+            symtab: 0,
+            hash: None,
+            gnu_hash: None,
+        };
+
+        let start = segment.offset() as usize;
+        let count = segment.file_size() as usize / DYN_SIZE;
+
+        let (mut strtab, mut symtab) = (None, None);
+
+        for idx in 0..count {
+            let entry = start + idx * DYN_SIZE;
+            let tag = dynamic
+                .read_u64(entry)
+                .ok_or(ElfError::Malformed("dynamic"))?;
+            let val = dynamic
+                .read_u64(entry + 8)
+                .ok_or(ElfError::Malformed("dynamic"))?;
+
+            match tag {
+                DT_NULL => break,
+                DT_NEEDED => dynamic.needed.push(val),
+                DT_SONAME => dynamic.soname = Some(val),
+                DT_STRTAB => strtab = Some(val),
+                DT_STRSZ => dynamic.strsz = val as usize,
+                DT_SYMTAB => symtab = Some(val),
+                DT_HASH => dynamic.hash = dynamic.offset_of(val),
+                DT_GNU_HASH => dynamic.gnu_hash = dynamic.offset_of(val),
+                _ => {}
+            }
+        }
+
+        dynamic.strtab = strtab
+            .and_then(|addr| dynamic.offset_of(addr))
+            .ok_or(ElfError::Malformed("dynamic string table"))?;
+        dynamic.symtab = symtab
+            .and_then(|addr| dynamic.offset_of(addr))
+            .ok_or(ElfError::Malformed("dynamic symbol table"))?;
+
+        Ok(Some(dynamic))
+    }
+
+    /// Names of the libraries needed (DT_NEEDED)
+    pub fn needed(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.needed.iter().filter_map(|&offset| self.string(offset))
+    }
+
+    /// The name of the shared object (DT_SONAME)
+    pub fn soname(&self) -> Option<&'a str> {
+        self.soname.and_then(|offset| self.string(offset))
+    }
+
+    /// Look up a symbol defined by the file, through DT_GNU_HASH or DT_HASH
+    pub fn lookup(&self, name: &str) -> Option<Symbol> {
+        if let Some(table) = self.gnu_hash {
+            return self.lookup_gnu(table, name);
+        }
+
+        self.lookup_sysv(self.hash?, name)
+    }
+
+    fn lookup_gnu(&self, table: usize, name: &str) -> Option<Symbol> {
+        let nbuckets = self.read_u32(table)? as usize;
+        let symoffset = self.read_u32(table + 4)? as usize;
+        let bloom_size = self.read_u32(table + 8)? as usize;
+        let bloom_shift = self.read_u32(table + 12)?;
+
+        if nbuckets == 0 || bloom_size == 0 {
+            return None;
+        }
+
+        let hash = gnu_hash(name);
+
+        // the bloom filter rejects most of the missing names
+        let bloom = table + 16;
+        let word = self.read_u64(bloom + (hash as usize / 64 % bloom_size) * 8)?;
+        let mask = (1u64 << (hash % 64)) | (1u64 << ((hash >> bloom_shift) % 64));
+        if word & mask != mask {
+            return None;
+        }
+
+        let buckets = bloom + bloom_size * 8;
+        let chains = buckets + nbuckets * 4;
+
+        let mut idx = self.read_u32(buckets + (hash as usize % nbuckets) * 4)? as usize;
+        if idx < symoffset {
+            return None;
+        }
+
+        loop {
+            let chain = self.read_u32(chains + (idx - symoffset) * 4)?;
+
+            if chain | 1 == hash | 1 {
+                if let Some(symbol) = self.symbol(idx, name) {
+                    return Some(symbol);
+                }
+            }
+
+            // the lowest bit marks the end of a chain
+            if chain & 1 != 0 {
+                return None;
+            }
+
+            idx += 1;
+        }
+    }
+
+    fn lookup_sysv(&self, table: usize, name: &str) -> Option<Symbol> {
+        let nbucket = self.read_u32(table)? as usize;
+        let nchain = self.read_u32(table + 4)? as usize;
+
+        if nbucket == 0 {
+            return None;
+        }
+
+        let buckets = table + 8;
+        let chains = buckets + nbucket * 4;
+
+        let mut idx = self.read_u32(buckets + (sysv_hash(name) as usize % nbucket) * 4)? as usize;
+
+        // a malformed chain may loop, it has at most `nchain` entries
+        for _ in 0..nchain {
+            if idx == 0 {
+                break;
+            }
+
+            if let Some(symbol) = self.symbol(idx, name) {
+                return Some(symbol);
+            }
+
+            idx = self.read_u32(chains + idx * 4)? as usize;
+        }
+
+        None
+    }
+
+    /// The symbol at `idx` if it is named `name` and defined
+    fn symbol(&self, idx: usize, name: &str) -> Option<Symbol> {
+        let entry = self.symtab + idx * SYM_SIZE;
+
+        let name_offset = self.read_u32(entry)?;
+        let info = *self.data.get(entry + 4)?;
+        let shndx = u16::from_le_bytes(self.bytes(entry + 6)?);
+
+        if shndx == 0 || !matches!(info >> 4, STB_GLOBAL | STB_WEAK) {
+            return None;
+        }
+
+        if self.string(name_offset as u64)? != name {
+            return None;
+        }
+
+        Some(Symbol {
+            value: self.read_u64(entry + 8)?,
+            size: self.read_u64(entry + 16)?,
+        })
+    }
+
+    fn string(&self, offset: u64) -> Option<&'a str> {
+        let start = self.strtab.checked_add(offset as usize)?;
+        let end = (self.strtab + self.strsz).min(self.data.len());
+        let bytes = self.data.get(start..end)?;
+        let len = bytes.iter().position(|&b| b == 0)?;
+        core::str::from_utf8(&bytes[..len]).ok()
+    }
+
+    /// The file offset of a linked address
+    fn offset_of(&self, addr: u64) -> Option<usize> {
+        self.loads
+            .iter()
+            .find(|(vaddr, _, size)| *vaddr <= addr && addr < vaddr + size)
+            .map(|(vaddr, offset, _)| (offset + addr - vaddr) as usize)
+    }
+
+    fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.data
+            .get(offset..offset.checked_add(N)?)?
+            .try_into()
+            .ok()
+    }
+
+    fn read_u32(&self, offset: usize) -> Option<u32> {
+        self.bytes(offset).map(u32::from_le_bytes)
+    }
+
+    fn read_u64(&self, offset: usize) -> Option<u64> {
+        self.bytes(offset).map(u64::from_le_bytes)
+    }
+}
+
+/// The hash function of DT_HASH
+fn sysv_hash(name: &str) -> u32 {
+    name.bytes().fold(0u32, |hash, byte| {
+        let hash = (hash << 4).wrapping_add(byte as u32);
+        let high = hash & 0xf000_0000;
+        (hash ^ (high >> 24)) & !high
+    })
+}
+
+/// The hash function of DT_GNU_HASH
+fn gnu_hash(name: &str) -> u32 {
+    name.bytes().fold(5381u32, |hash, byte| {
+        hash.wrapping_mul(33).wrapping_add(byte as u32)
+    })
+}
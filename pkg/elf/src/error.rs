@@ -0,0 +1,33 @@
+use x86_64::structures::paging::{mapper::MapToError, Size4KiB};
+
+/// Errors of validating, relocating and loading an ELF file
+#[derive(Debug)]
+pub enum ElfError {
+    /// not a 64-bit ELF file
+    InvalidClass,
+    /// not built for x86_64
+    InvalidMachine,
+    /// neither an executable nor a position independent executable
+    InvalidType,
+    /// a segment at the address is not in the file or overflows
+    InvalidSegment(u64),
+    /// two loadable segments overlap at the address
+    SegmentOverlap(u64),
+    /// a section or a table of the file cannot be parsed
+    Malformed(&'static str),
+    /// a relocation of the type at the address is not supported
+    UnsupportedRelocation { ty: u32, offset: u64 },
+    /// a relocation refers to a symbol which is not defined by the file
+    /// or any library it needs
+    UndefinedSymbol { offset: u64 },
+    /// a library needed by the file is not found
+    MissingLibrary,
+    /// failed to map a page
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for ElfError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        Self::Map(err)
+    }
+}
@@ -4,6 +4,16 @@
 extern crate log;
 extern crate alloc;
 
+mod dynamic;
+mod error;
+mod reloc;
+mod symbols;
+
+pub use dynamic::*;
+pub use error::*;
+pub use reloc::*;
+pub use symbols::*;
+
 use core::intrinsics::{copy_nonoverlapping, write_bytes};
 
 use alloc::vec::Vec;
@@ -12,31 +22,61 @@ use x86_64::structures::paging::{mapper::*, *};
 use x86_64::{align_up, PhysAddr, VirtAddr};
 use xmas_elf::{program, ElfFile};
 
+/// The count of 4 KiB pages in a 2 MiB page
+const HUGE_PAGES: u64 = Size2MiB::SIZE / Size4KiB::SIZE;
+
 /// Map physical memory [0, max_addr)
 ///
 /// to virtual space [offset, offset + max_addr)
+///
+/// 1 GiB pages are used where the CPU supports them, 2 MiB pages otherwise.
 pub fn map_physical_memory(
     offset: u64,
     max_addr: u64,
-    page_table: &mut impl Mapper<Size2MiB>,
+    page_table: &mut (impl Mapper<Size2MiB> + Mapper<Size1GiB>),
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) {
     trace!("Mapping physical memory...");
-    let start_frame = PhysFrame::containing_address(PhysAddr::new(0));
-    let end_frame = PhysFrame::containing_address(PhysAddr::new(max_addr));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let use_1gib = offset % Size1GiB::SIZE == 0 && has_1gib_pages();
 
-    for frame in PhysFrame::range_inclusive(start_frame, end_frame) {
-        let page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64() + offset));
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe {
-            page_table
-                .map_to(page, frame, flags, frame_allocator)
-                .expect("Failed to map physical memory")
-                .flush();
+    // the frame containing max_addr is included
+    let end = max_addr.next_multiple_of(Size2MiB::SIZE) + Size2MiB::SIZE;
+    let mut addr = 0;
+
+    while addr < end {
+        if use_1gib && addr % Size1GiB::SIZE == 0 && addr + Size1GiB::SIZE <= end {
+            let frame = PhysFrame::<Size1GiB>::containing_address(PhysAddr::new(addr));
+            let page = Page::<Size1GiB>::containing_address(VirtAddr::new(addr + offset));
+            unsafe {
+                page_table
+                    .map_to(page, frame, flags, frame_allocator)
+                    .expect("Failed to map physical memory")
+                    .flush();
+            }
+            addr += Size1GiB::SIZE;
+        } else {
+            let frame = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(addr));
+            let page = Page::<Size2MiB>::containing_address(VirtAddr::new(addr + offset));
+            unsafe {
+                page_table
+                    .map_to(page, frame, flags, frame_allocator)
+                    .expect("Failed to map physical memory")
+                    .flush();
+            }
+            addr += Size2MiB::SIZE;
         }
     }
 }
 
+/// Check if the CPU supports 1 GiB pages (CPUID.80000001H:EDX.Page1GB)
+fn has_1gib_pages() -> bool {
+    use core::arch::x86_64::{__cpuid, __get_cpuid_max};
+
+    let (max_leaf, _) = __get_cpuid_max(0x8000_0000);
+    max_leaf >= 0x8000_0001 && __cpuid(0x8000_0001).edx & (1 << 26) != 0
+}
+
 /// Map ELF file
 ///
 /// for each segment, map current frame and set page table
@@ -67,8 +107,8 @@ pub fn unmap_elf(elf: &ElfFile, page_table: &mut impl Mapper<Size4KiB>) -> Resul
 pub fn map_pages(
     addr: u64,
     pages: u64,
-    page_table: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    page_table: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>),
     user_access: bool,
 ) -> Result<PageRange, MapToError<Size4KiB>> {
     debug_assert!(pages > 0, "pages must be greater than 0");
@@ -83,21 +123,22 @@ pub fn map_pages(
     )?;
 
     trace!(
-        "Map hint: {:#x} -> {:#x}",
+        "Map hint: {:#x} -> {:#x?}",
         addr,
-        page_table
-            .translate_page(range_start)
-            .unwrap()
-            .start_address()
+        Mapper::<Size4KiB>::translate_page(page_table, range_start).map(|f| f.start_address())
     );
 
     Ok(Page::range(range_start, range_end))
 }
 
+/// map a range of memory to new frames
+///
+/// the aligned 2 MiB pages in the range are mapped as a whole where a
+/// 2 MiB frame can be allocated, the rest with 4 KiB pages.
 pub fn map_range(
     page_range: PageRangeInclusive,
-    page_table: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    page_table: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>),
     user_access: bool,
 ) -> Result<(), MapToError<Size4KiB>> {
     trace!(
@@ -107,7 +148,8 @@ pub fn map_range(
         page_range.count()
     );
 
-    let mut flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    // stacks and data, never executable
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
 
     if user_access {
         flags |= PageTableFlags::USER_ACCESSIBLE;
@@ -115,26 +157,70 @@ pub fn map_range(
 
     trace!("Flags: {:?}", flags);
 
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
+    let start = page_range.start;
+    let total = page_range.count() as u64;
+    let mut idx = 0;
+
+    while idx < total {
+        let page = start + idx;
+
+        if total - idx >= HUGE_PAGES
+            && map_huge_page(page, flags, page_table, frame_allocator)?.is_some()
+        {
+            idx += HUGE_PAGES;
+            continue;
+        }
+
+        let frame = FrameAllocator::<Size4KiB>::allocate_frame(frame_allocator)
             .ok_or(MapToError::FrameAllocationFailed)?;
         unsafe {
-            page_table
-                .map_to(page, frame, flags, frame_allocator)?
-                .flush();
+            Mapper::<Size4KiB>::map_to(page_table, page, frame, flags, frame_allocator)?.flush();
         }
+        idx += 1;
     }
 
     Ok(())
 }
 
-/// map a range of memory
+/// Map the 2 MiB page starting at `page` to a new 2 MiB frame
+///
+/// returns `None` if `page` is not 2 MiB aligned or no 2 MiB frame is
+/// free, so the caller falls back to 4 KiB pages.
+fn map_huge_page(
+    page: Page,
+    flags: PageTableFlags,
+    page_table: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>),
+) -> Result<Option<PhysFrame<Size2MiB>>, MapToError<Size4KiB>> {
+    let huge = match Page::<Size2MiB>::from_start_address(page.start_address()) {
+        Ok(huge) => huge,
+        Err(_) => return Ok(None),
+    };
+
+    let frame = match FrameAllocator::<Size2MiB>::allocate_frame(frame_allocator) {
+        Some(frame) => frame,
+        None => return Ok(None),
+    };
+
+    unsafe { Mapper::<Size2MiB>::map_to(page_table, huge, frame, flags, frame_allocator) }
+        .map_err(|err| match err {
+            MapToError::FrameAllocationFailed => MapToError::FrameAllocationFailed,
+            MapToError::ParentEntryHugePage => MapToError::ParentEntryHugePage,
+            MapToError::PageAlreadyMapped(frame) => {
+                MapToError::PageAlreadyMapped(PhysFrame::containing_address(frame.start_address()))
+            }
+        })?
+        .flush();
+
+    Ok(Some(frame))
+}
+
+/// unmap a range of memory
 pub fn unmap_pages(
     addr: u64,
     pages: u64,
-    page_table: &mut impl Mapper<Size4KiB>,
-    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+    page_table: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_deallocator: &mut (impl FrameDeallocator<Size4KiB> + FrameDeallocator<Size2MiB>),
     do_dealloc: bool,
 ) -> Result<(), UnmapError> {
     debug_assert!(pages > 0, "pages must be greater than 0");
@@ -149,10 +235,14 @@ pub fn unmap_pages(
     )
 }
 
+/// unmap a range of memory, all pages must be mapped
+///
+/// 2 MiB pages which start in the range are unmapped as a whole,
+/// see `unmap_range_sparse`.
 pub fn unmap_range(
     page_range: PageRangeInclusive,
-    page_table: &mut impl Mapper<Size4KiB>,
-    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+    page_table: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_deallocator: &mut (impl FrameDeallocator<Size4KiB> + FrameDeallocator<Size2MiB>),
     do_dealloc: bool,
 ) -> Result<(), UnmapError> {
     trace!(
@@ -162,17 +252,242 @@ pub fn unmap_range(
         page_range.count()
     );
 
-    for page in page_range {
-        let (frame, flush) = page_table.unmap(page)?;
-        if do_dealloc {
-            unsafe {
-                frame_deallocator.deallocate_frame(frame);
+    unmap_range_inner(page_range, page_table, frame_deallocator, do_dealloc, false)?;
+
+    Ok(())
+}
+
+/// unmap a range of memory which may be partially mapped
+///
+/// pages that are not mapped are skipped, return the count of unmapped pages
+///
+/// a 2 MiB page is unmapped as a whole if it starts in the range, and
+/// counts as 512 pages. one that starts before the range is kept, since
+/// it cannot be split.
+pub fn unmap_range_sparse(
+    page_range: PageRangeInclusive,
+    page_table: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_deallocator: &mut (impl FrameDeallocator<Size4KiB> + FrameDeallocator<Size2MiB>),
+    do_dealloc: bool,
+) -> Result<u64, UnmapError> {
+    trace!(
+        "Unmap Sparse Range: {:#x} - {:#x} ({})",
+        page_range.start.start_address().as_u64(),
+        page_range.end.start_address().as_u64(),
+        page_range.count()
+    );
+
+    unmap_range_inner(page_range, page_table, frame_deallocator, do_dealloc, true)
+}
+
+fn unmap_range_inner(
+    page_range: PageRangeInclusive,
+    page_table: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_deallocator: &mut (impl FrameDeallocator<Size4KiB> + FrameDeallocator<Size2MiB>),
+    do_dealloc: bool,
+    sparse: bool,
+) -> Result<u64, UnmapError> {
+    let start = page_range.start;
+    let total = page_range.count() as u64;
+
+    let mut count = 0;
+    let mut idx = 0;
+
+    // iterate by index, `Page + n` may overflow at the end of the address space
+    while idx < total {
+        let page = start + idx;
+
+        match Mapper::<Size4KiB>::unmap(page_table, page) {
+            Ok((frame, flush)) => {
+                if do_dealloc {
+                    unsafe {
+                        FrameDeallocator::<Size4KiB>::deallocate_frame(frame_deallocator, frame);
+                    }
+                }
+                flush.flush();
+                count += 1;
+                idx += 1;
             }
+            Err(UnmapError::PageNotMapped) if sparse => idx += 1,
+            Err(UnmapError::ParentEntryHugePage) => {
+                let huge = Page::<Size2MiB>::containing_address(page.start_address());
+
+                if huge.start_address() >= start.start_address() {
+                    let (frame, flush) = Mapper::<Size2MiB>::unmap(page_table, huge)?;
+                    if do_dealloc {
+                        unsafe {
+                            FrameDeallocator::<Size2MiB>::deallocate_frame(
+                                frame_deallocator,
+                                frame,
+                            );
+                        }
+                    }
+                    flush.flush();
+                    count += HUGE_PAGES;
+                }
+
+                // skip the rest of the huge page
+                idx += HUGE_PAGES - u64::from(page.p1_index());
+            }
+            Err(e) => return Err(e),
         }
-        flush.flush();
     }
 
-    Ok(())
+    Ok(count)
+}
+
+/// Get the page table flags of a loadable segment
+///
+/// code is R+X, rodata is R and data is RW+NX. a segment asking for
+/// both W and X is mapped as RW+NX to keep W^X.
+pub fn segment_flags(segment: &program::ProgramHeader, user_access: bool) -> PageTableFlags {
+    let flags = segment.flags();
+    let mut page_table_flags = PageTableFlags::PRESENT;
+
+    if flags.is_write() {
+        page_table_flags |= PageTableFlags::WRITABLE;
+    }
+
+    if flags.is_write() && flags.is_execute() {
+        warn!(
+            "Segment at {:#x} is writable and executable, mapped as non-executable",
+            segment.virtual_addr()
+        );
+    }
+
+    if !flags.is_execute() || flags.is_write() {
+        page_table_flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    if user_access {
+        page_table_flags |= PageTableFlags::USER_ACCESSIBLE;
+    }
+
+    page_table_flags
+}
+
+/// Load a single page of loadable segments, for demand paging
+///
+/// the page is filled by [`fill_segment_page`] in a new frame, and mapped.
+#[allow(clippy::too_many_arguments)]
+pub fn load_segment_page(
+    data: &[u8],
+    physical_offset: u64,
+    segments: &[program::ProgramHeader],
+    bias: u64,
+    relocations: &[Relocation],
+    page: Page,
+    page_table: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    user_access: bool,
+) -> Result<PhysFrame, MapToError<Size4KiB>> {
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+
+    let frame_ptr = (frame.start_address().as_u64() + physical_offset) as *mut u8;
+
+    // the frame is not mapped yet, read-only pages can be patched
+    let page_table_flags = fill_segment_page(
+        data,
+        unsafe { core::slice::from_raw_parts_mut(frame_ptr, page.size() as usize) },
+        segments,
+        bias,
+        relocations,
+        page,
+        user_access,
+    );
+
+    trace!(
+        "Load page: {:#x} -> {:#x} ({:?})",
+        page.start_address().as_u64(),
+        frame.start_address().as_u64(),
+        page_table_flags
+    );
+
+    unsafe {
+        page_table
+            .map_to(page, frame, page_table_flags, frame_allocator)?
+            .flush();
+    }
+
+    Ok(frame)
+}
+
+/// Fill the content of a page of loadable segments, returns its flags
+///
+/// the page is filled with the file content of every segment that covers it,
+/// and the rest of the page (.bss or padding) is zeroed. segments are loaded
+/// at their virtual address plus `bias`, which is 0 for ET_EXEC files, and
+/// the `relocations` (see [`relocations`]) inside the page are applied.
+pub fn fill_segment_page(
+    data: &[u8],
+    content: &mut [u8],
+    segments: &[program::ProgramHeader],
+    bias: u64,
+    relocations: &[Relocation],
+    page: Page,
+    user_access: bool,
+) -> PageTableFlags {
+    let page_start = page.start_address().as_u64();
+    let page_end = page_start + page.size();
+
+    content.fill(0);
+
+    for segment in segments {
+        let virt_start = segment.virtual_addr() + bias;
+        if virt_start >= page_end || virt_start + segment.mem_size() <= page_start {
+            continue;
+        }
+
+        // the file backed part of the segment, the rest is .bss
+        let copy_start = virt_start.max(page_start);
+        let copy_end = (virt_start + segment.file_size()).min(page_end);
+
+        if copy_start < copy_end {
+            let offset = (segment.offset() + copy_start - virt_start) as usize;
+            let count = (copy_end - copy_start) as usize;
+            let dest = (copy_start - page_start) as usize;
+            content[dest..dest + count].copy_from_slice(&data[offset..offset + count]);
+        }
+    }
+
+    relocate_page(content, page_start, bias, relocations);
+
+    segment_page_flags(segments, bias, page, user_access)
+}
+
+/// The flags of a page of loadable segments loaded at `bias`
+pub fn segment_page_flags(
+    segments: &[program::ProgramHeader],
+    bias: u64,
+    page: Page,
+    user_access: bool,
+) -> PageTableFlags {
+    let page_start = page.start_address().as_u64();
+    let page_end = page_start + page.size();
+
+    let covers = |s: &program::ProgramHeader| {
+        s.virtual_addr() + bias < page_end && s.virtual_addr() + bias + s.mem_size() > page_start
+    };
+
+    let mut page_table_flags = segments
+        .iter()
+        .filter(|s| covers(s))
+        .fold(PageTableFlags::empty(), |flags, s| {
+            flags | segment_flags(s, user_access)
+        });
+
+    // a page is executable if any of its segments is,
+    // unless it is also writable (W^X)
+    if page_table_flags.contains(PageTableFlags::NO_EXECUTE)
+        && !page_table_flags.contains(PageTableFlags::WRITABLE)
+        && segments.iter().any(|s| s.flags().is_execute() && covers(s))
+    {
+        page_table_flags.remove(PageTableFlags::NO_EXECUTE);
+    }
+
+    page_table_flags
 }
 
 fn map_segment(
@@ -181,7 +496,7 @@ fn map_segment(
     page_table: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
-    if segment.get_type().unwrap() != program::Type::Load {
+    if segment.get_type() != Ok(program::Type::Load) {
         return Ok(());
     }
 
@@ -196,16 +511,7 @@ fn map_segment(
     let start_frame = PhysFrame::containing_address(phys_start_addr);
     let end_frame = PhysFrame::containing_address(phys_start_addr + file_size - 1u64);
 
-    let flags = segment.flags();
-    let mut page_table_flags = PageTableFlags::PRESENT;
-
-    if !flags.is_execute() {
-        page_table_flags |= PageTableFlags::NO_EXECUTE;
-    }
-
-    if flags.is_write() {
-        page_table_flags |= PageTableFlags::WRITABLE;
-    }
+    let page_table_flags = segment_flags(segment, false);
 
     trace!("Segment page table flag: {:?}", page_table_flags);
     // DONT MAP ADDR DIRECTLY, ALLOCATE THEN COPY DATA
@@ -288,29 +594,36 @@ fn map_segment(
 
 /// Load & Map ELF file
 ///
-/// for each segment, load code to new frame and set page table
+/// for each segment, load code to new frame and set page table,
+/// segments are loaded at their virtual address plus `bias`.
+/// the file is validated first, relocations are not applied.
 pub fn load_elf(
     elf: &ElfFile,
+    bias: u64,
     physical_offset: u64,
-    page_table: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    page_table: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>),
     user_access: bool,
-) -> Result<Vec<PageRangeInclusive>, MapToError<Size4KiB>> {
+) -> Result<Vec<PageRangeInclusive>, ElfError> {
     trace!("Loading ELF file...{:?}", elf.input.as_ptr());
 
+    validate(elf)?;
+
     // use iterator and functional programming to load segments
     // and collect the loaded pages into a vector
     elf.program_iter()
-        .filter(|segment| segment.get_type().unwrap() == program::Type::Load)
+        .filter(|segment| segment.get_type() == Ok(program::Type::Load))
         .map(|segment| {
             load_segment(
                 elf,
+                bias,
                 physical_offset,
                 &segment,
                 page_table,
                 frame_allocator,
                 user_access,
             )
+            .map_err(ElfError::from)
         })
         .collect()
 }
@@ -318,32 +631,20 @@ pub fn load_elf(
 // load segments to new allocated frames
 fn load_segment(
     elf: &ElfFile,
+    bias: u64,
     physical_offset: u64,
     segment: &program::ProgramHeader,
-    page_table: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    page_table: &mut (impl Mapper<Size4KiB> + Mapper<Size2MiB>),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>),
     user_access: bool,
 ) -> Result<PageRangeInclusive, MapToError<Size4KiB>> {
     trace!("Loading & mapping segment: {:#x?}", segment);
     let mem_size = segment.mem_size();
     let file_size = segment.file_size();
     let file_offset = segment.offset() & !0xfff;
-    let virt_start_addr = VirtAddr::new(segment.virtual_addr());
-
-    let flags = segment.flags();
-    let mut page_table_flags = PageTableFlags::PRESENT;
-
-    if !flags.is_execute() {
-        page_table_flags |= PageTableFlags::NO_EXECUTE;
-    }
-
-    if flags.is_write() {
-        page_table_flags |= PageTableFlags::WRITABLE;
-    }
+    let virt_start_addr = VirtAddr::new(segment.virtual_addr() + bias);
 
-    if user_access {
-        page_table_flags |= PageTableFlags::USER_ACCESSIBLE;
-    }
+    let page_table_flags = segment_flags(segment, user_access);
 
     trace!("Segment page table flag: {:?}", page_table_flags);
 
@@ -354,8 +655,7 @@ fn load_segment(
     let data = unsafe { elf.input.as_ptr().add(file_offset as usize) };
 
     for (idx, page) in pages.enumerate() {
-        let frame = frame_allocator
-            .allocate_frame()
+        let frame = FrameAllocator::<Size4KiB>::allocate_frame(frame_allocator)
             .ok_or(MapToError::FrameAllocationFailed)?;
 
         let offset = idx as u64 * page.size();
@@ -386,8 +686,7 @@ fn load_segment(
                 count as usize,
             );
 
-            page_table
-                .map_to(page, frame, page_table_flags, frame_allocator)?
+            Mapper::<Size4KiB>::map_to(page_table, page, frame, page_table_flags, frame_allocator)?
                 .flush();
 
             if count < page.size() {
@@ -414,15 +713,40 @@ fn load_segment(
         let start_page: Page =
             Page::containing_address(VirtAddr::new(align_up(zero_start.as_u64(), Size4KiB::SIZE)));
         let end_page = Page::containing_address(zero_end);
+        let total = Page::range_inclusive(start_page, end_page).count() as u64;
+        let mut idx = 0;
+
+        // a large .bss (as the static kernel heap) is mapped with 2 MiB pages
+        while idx < total {
+            let page = start_page + idx;
+
+            if total - idx >= HUGE_PAGES {
+                if let Some(frame) =
+                    map_huge_page(page, page_table_flags, page_table, frame_allocator)?
+                {
+                    unsafe {
+                        write_bytes(
+                            (frame.start_address().as_u64() + physical_offset) as *mut u8,
+                            0,
+                            Size2MiB::SIZE as usize,
+                        );
+                    }
+                    idx += HUGE_PAGES;
+                    continue;
+                }
+            }
 
-        for page in Page::range_inclusive(start_page, end_page) {
-            let frame = frame_allocator
-                .allocate_frame()
+            let frame = FrameAllocator::<Size4KiB>::allocate_frame(frame_allocator)
                 .ok_or(MapToError::FrameAllocationFailed)?;
             unsafe {
-                page_table
-                    .map_to(page, frame, page_table_flags, frame_allocator)?
-                    .flush();
+                Mapper::<Size4KiB>::map_to(
+                    page_table,
+                    page,
+                    frame,
+                    page_table_flags,
+                    frame_allocator,
+                )?
+                .flush();
                 // zero bss
 
                 write_bytes(
@@ -431,6 +755,7 @@ fn load_segment(
                     page.size() as usize,
                 );
             }
+            idx += 1;
         }
     }
 
@@ -443,7 +768,7 @@ fn unmap_segment(
     kernel_start: PhysAddr,
     page_table: &mut impl Mapper<Size4KiB>,
 ) -> Result<(), UnmapError> {
-    if segment.get_type().unwrap() != program::Type::Load {
+    if segment.get_type() != Ok(program::Type::Load) {
         return Ok(());
     }
     trace!("Unmapping segment: {:#x?}", segment);
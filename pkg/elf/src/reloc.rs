@@ -0,0 +1,215 @@
+use alloc::vec::Vec;
+use xmas_elf::sections::{SectionData, ShType};
+use xmas_elf::symbol_table::{Binding, Entry};
+use xmas_elf::{header, program, ElfFile};
+
+use crate::ElfError;
+
+const R_X86_64_64: u32 = 1;
+const R_X86_64_GLOB_DAT: u32 = 6;
+const R_X86_64_JUMP_SLOT: u32 = 7;
+const R_X86_64_RELATIVE: u32 = 8;
+
+/// A resolved relocation, `value` is written at `offset + bias`
+#[derive(Debug, Clone, Copy)]
+pub struct Relocation {
+    /// the linked address to patch
+    pub offset: u64,
+    /// the value to write, the bias is already applied
+    pub value: u64,
+}
+
+/// Check if the ELF file is position independent (ET_DYN)
+pub fn is_pie(elf: &ElfFile) -> bool {
+    elf.header.pt2.type_().as_type() == header::Type::SharedObject
+}
+
+/// Check the class, machine and type of an ELF file, and that
+/// the loadable segments (and the TLS template) are inside the file
+/// and do not overlap
+pub fn validate(elf: &ElfFile) -> Result<(), ElfError> {
+    if elf.header.pt1.class() != header::Class::SixtyFour {
+        return Err(ElfError::InvalidClass);
+    }
+
+    if elf.header.pt2.machine().as_machine() != header::Machine::X86_64 {
+        return Err(ElfError::InvalidMachine);
+    }
+
+    match elf.header.pt2.type_().as_type() {
+        header::Type::Executable | header::Type::SharedObject => {}
+        _ => return Err(ElfError::InvalidType),
+    }
+
+    let mut ranges = Vec::new();
+
+    for segment in elf.program_iter() {
+        let ty = segment.get_type();
+        if !matches!(ty, Ok(program::Type::Load | program::Type::Tls)) || segment.mem_size() == 0 {
+            continue;
+        }
+
+        let start = segment.virtual_addr();
+        let in_file = segment
+            .offset()
+            .checked_add(segment.file_size())
+            .is_some_and(|end| end <= elf.input.len() as u64);
+
+        if !in_file || segment.file_size() > segment.mem_size() {
+            return Err(ElfError::InvalidSegment(start));
+        }
+
+        // the TLS template lies in a loadable segment
+        if ty == Ok(program::Type::Tls) {
+            if segment.align() > 1 && !segment.align().is_power_of_two() {
+                return Err(ElfError::InvalidSegment(start));
+            }
+            continue;
+        }
+
+        let end = start
+            .checked_add(segment.mem_size())
+            .ok_or(ElfError::InvalidSegment(start))?;
+
+        ranges.push((start, end));
+    }
+
+    ranges.sort_unstable();
+
+    for pair in ranges.windows(2) {
+        if pair[0].1 > pair[1].0 {
+            return Err(ElfError::SegmentOverlap(pair[1].0));
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolve the relocations of an ELF file loaded at `bias`
+///
+/// `R_X86_64_RELATIVE` and `R_X86_64_64`, `R_X86_64_GLOB_DAT`,
+/// `R_X86_64_JUMP_SLOT` against symbols defined by the file itself are
+/// supported. the result is sorted by offset.
+pub fn relocations(elf: &ElfFile, bias: u64) -> Result<Vec<Relocation>, ElfError> {
+    relocations_with(elf, bias, |_| None)
+}
+
+/// Resolve the relocations of an ELF file loaded at `bias`, symbols not
+/// defined by the file are resolved to their address by `resolve`
+///
+/// undefined weak symbols which are not resolved are 0.
+pub fn relocations_with(
+    elf: &ElfFile,
+    bias: u64,
+    resolve: impl Fn(&str) -> Option<u64>,
+) -> Result<Vec<Relocation>, ElfError> {
+    let mut relocations = Vec::new();
+
+    for section in elf.section_iter() {
+        if section.get_type() != Ok(ShType::Rela) {
+            continue;
+        }
+
+        let entries = match section.get_data(elf) {
+            Ok(SectionData::Rela64(entries)) => entries,
+            _ => return Err(ElfError::Malformed("relocation section")),
+        };
+
+        // the symbol table which the relocations refer to, none for
+        // sections with only relative relocations
+        let symbols = match section.link() {
+            0 => None,
+            link => match elf
+                .section_header(link as u16)
+                .and_then(|sh| sh.get_data(elf))
+            {
+                Ok(SectionData::DynSymbolTable64(symbols)) => Some(symbols),
+                Ok(SectionData::SymbolTable64(_)) => None,
+                _ => return Err(ElfError::Malformed("symbol table")),
+            },
+        };
+
+        for rela in entries {
+            let offset = rela.get_offset();
+            let addend = rela.get_addend();
+
+            let symbol = || {
+                let index = rela.get_symbol_table_index() as usize;
+                let symbol = symbols
+                    .and_then(|symbols| symbols.get(index))
+                    .ok_or(ElfError::Malformed("symbol index"))?;
+
+                if symbol.shndx() != 0 {
+                    return Ok(symbol.value().wrapping_add(bias));
+                }
+
+                let name = symbol
+                    .get_name(elf)
+                    .map_err(|_| ElfError::Malformed("symbol name"))?;
+
+                match resolve(name) {
+                    Some(addr) => Ok(addr),
+                    None if symbol.get_binding() == Ok(Binding::Weak) => Ok(0),
+                    None => Err(ElfError::UndefinedSymbol { offset }),
+                }
+            };
+
+            let value = match rela.get_type() {
+                R_X86_64_RELATIVE => bias.wrapping_add(addend),
+                R_X86_64_64 => symbol()?.wrapping_add(addend),
+                R_X86_64_GLOB_DAT | R_X86_64_JUMP_SLOT => symbol()?,
+                ty => return Err(ElfError::UnsupportedRelocation { ty, offset }),
+            };
+
+            relocations.push(Relocation { offset, value });
+        }
+    }
+
+    relocations.sort_unstable_by_key(|rela| rela.offset);
+
+    Ok(relocations)
+}
+
+/// Apply the relocations to a page loaded at `page_start`
+///
+/// `page` is the content of the page, relocations crossing the page
+/// boundary are written partially.
+pub fn relocate_page(page: &mut [u8], page_start: u64, bias: u64, relocations: &[Relocation]) {
+    let page_end = page_start + page.len() as u64;
+
+    // the first relocation which may overlap the page
+    let first = relocations.partition_point(|rela| rela.offset + bias + 8 <= page_start);
+
+    for rela in relocations[first..]
+        .iter()
+        .take_while(|rela| rela.offset + bias < page_end)
+    {
+        let target = rela.offset + bias;
+        let bytes = rela.value.to_le_bytes();
+
+        for (i, byte) in bytes.iter().enumerate() {
+            let addr = target + i as u64;
+            if (page_start..page_end).contains(&addr) {
+                page[(addr - page_start) as usize] = *byte;
+            }
+        }
+    }
+}
+
+/// Apply the relocations of an ELF file loaded at `bias`
+///
+/// returns the count of relocations applied.
+///
+/// # Safety
+///
+/// the ELF file must be loaded at `bias` in the current address space,
+/// and its pages must be writable (or CR0.WP is cleared).
+pub unsafe fn relocate(elf: &ElfFile, bias: u64) -> Result<usize, ElfError> {
+    let relocations = relocations(elf, bias)?;
+
+    for rela in relocations.iter() {
+        ((rela.offset + bias) as *mut u64).write_unaligned(rela.value);
+    }
+
+    Ok(relocations.len())
+}
@@ -0,0 +1,184 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use xmas_elf::sections::{SectionData, ShType};
+use xmas_elf::symbol_table::{Entry, Type};
+use xmas_elf::ElfFile;
+
+/// Function symbols of an ELF file, as (address, size, name),
+/// from .symtab or .dynsym if the file is stripped
+fn functions<'a>(elf: &ElfFile<'a>) -> Vec<(u64, u64, &'a str)> {
+    let mut symtab = Vec::new();
+    let mut dynsym = Vec::new();
+
+    for section in elf.section_iter() {
+        match (section.get_type(), section.get_data(elf)) {
+            (Ok(ShType::SymTab), Ok(SectionData::SymbolTable64(entries))) => {
+                collect_functions(elf, entries, &mut symtab)
+            }
+            (Ok(ShType::DynSym), Ok(SectionData::DynSymbolTable64(entries))) => {
+                collect_functions(elf, entries, &mut dynsym)
+            }
+            _ => {}
+        }
+    }
+
+    if symtab.is_empty() {
+        dynsym
+    } else {
+        symtab
+    }
+}
+
+fn collect_functions<'a, E: Entry>(
+    elf: &ElfFile<'a>,
+    entries: &'a [E],
+    symbols: &mut Vec<(u64, u64, &'a str)>,
+) {
+    symbols.extend(
+        entries
+            .iter()
+            .filter(|entry| entry.get_type() == Ok(Type::Func))
+            .filter(|entry| entry.shndx() != 0 && entry.size() > 0)
+            .filter_map(|entry| Some((entry.value(), entry.size(), entry.get_name(elf).ok()?))),
+    );
+}
+
+/// Find the function containing the linked address `addr`,
+/// returns its (mangled) name and the offset of `addr` in it
+pub fn find_symbol<'a>(elf: &ElfFile<'a>, addr: u64) -> Option<(&'a str, u64)> {
+    functions(elf)
+        .into_iter()
+        .find(|(start, size, _)| *start <= addr && addr - start < *size)
+        .map(|(start, _, name)| (name, addr - start))
+}
+
+/// Pack the function symbols of an ELF file into a [`SymbolTable`]
+///
+/// symbols are sorted by address, and each one is stored as varints of
+/// the address delta from the previous one, its size, the length of the
+/// prefix shared with the previous name and the length of the rest of the
+/// name, followed by the rest of the name. mangled names share long prefixes.
+pub fn compress_symbols(elf: &ElfFile) -> Vec<u8> {
+    let mut symbols = functions(elf);
+    symbols.sort_unstable_by_key(|(addr, _, _)| *addr);
+    symbols.dedup_by_key(|(addr, _, _)| *addr);
+
+    let mut data = Vec::new();
+    write_varint(&mut data, symbols.len() as u64);
+
+    let mut last_addr = 0;
+    let mut last_name: &[u8] = &[];
+
+    for (addr, size, name) in symbols {
+        let name = name.as_bytes();
+        let shared = name
+            .iter()
+            .zip(last_name)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        write_varint(&mut data, addr - last_addr);
+        write_varint(&mut data, size);
+        write_varint(&mut data, shared as u64);
+        write_varint(&mut data, (name.len() - shared) as u64);
+        data.extend_from_slice(&name[shared..]);
+
+        last_addr = addr;
+        last_name = name;
+    }
+
+    data
+}
+
+/// A symbol table packed by [`compress_symbols`]
+#[derive(Clone, Copy)]
+pub struct SymbolTable<'a> {
+    data: &'a [u8],
+    count: u64,
+    /// the offset of the first symbol
+    start: usize,
+}
+
+impl<'a> SymbolTable<'a> {
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let mut pos = 0;
+        let count = read_varint(data, &mut pos)?;
+
+        Some(Self {
+            data,
+            count,
+            start: pos,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Find the function containing the linked address `addr`,
+    /// returns its (mangled) name and the offset of `addr` in it
+    pub fn lookup(&self, addr: u64) -> Option<(String, u64)> {
+        let mut pos = self.start;
+        let mut start = 0u64;
+        let mut name = Vec::new();
+
+        for _ in 0..self.count {
+            start = start.checked_add(read_varint(self.data, &mut pos)?)?;
+            let size = read_varint(self.data, &mut pos)?;
+            let shared = read_varint(self.data, &mut pos)? as usize;
+            let len = read_varint(self.data, &mut pos)? as usize;
+
+            // symbols are sorted, the rest are above `addr`
+            if start > addr {
+                return None;
+            }
+
+            let rest = self.data.get(pos..pos.checked_add(len)?)?;
+            pos += len;
+
+            name.truncate(shared);
+            name.extend_from_slice(rest);
+
+            if addr - start < size {
+                return Some((String::from_utf8_lossy(&name).into_owned(), addr - start));
+            }
+        }
+
+        None
+    }
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            data.push(byte);
+            return;
+        }
+
+        data.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+
+        value |= ((byte & 0x7f) as u64) << shift;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
@@ -0,0 +1,270 @@
+//! ATA disks by PIO
+//!
+//! drives on the two legacy IDE buses, addressed by LBA28, a sector at a
+//! time. disks are only accessed with interrupts disabled, in syscalls,
+//! page faults and init, so the status of the drive is polled for a
+//! bounded time and the IRQs of the buses (14 and 15) are masked by nIEN.
+
+use alloc::{string::String, sync::Arc};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use super::block::*;
+
+/// The IO ports of the buses, with their device control ports
+const BUS_PORTS: [(u16, u16); 2] = [(0x1f0, 0x3f6), (0x170, 0x376)];
+
+static BUSES: [Mutex<AtaBus>; 2] = [Mutex::new(AtaBus::new(0)), Mutex::new(AtaBus::new(1))];
+
+/// Polls of the status before a wait is given up
+const POLL_LIMIT: usize = 0x100000;
+
+/// The last sector addressable by LBA28
+const LBA28_MAX: usize = 0x0fff_ffff;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_CACHE_FLUSH: u8 = 0xe7;
+const CMD_IDENTIFY: u8 = 0xec;
+
+bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    struct Status: u8 {
+        const ERR = 1 << 0;
+        const DRQ = 1 << 3;
+        const DF = 1 << 5;
+        const DRDY = 1 << 6;
+        const BSY = 1 << 7;
+    }
+}
+
+/// The registers of an IDE bus
+struct AtaBus {
+    data: Port<u16>,
+    sector_count: Port<u8>,
+    lba_low: Port<u8>,
+    lba_mid: Port<u8>,
+    lba_high: Port<u8>,
+    drive_head: Port<u8>,
+    /// status on read, command on write
+    command: Port<u8>,
+    /// alternate status on read, device control on write
+    control: Port<u8>,
+}
+
+impl AtaBus {
+    const fn new(index: usize) -> Self {
+        let (io, control) = BUS_PORTS[index];
+
+        Self {
+            data: Port::new(io),
+            sector_count: Port::new(io + 2),
+            lba_low: Port::new(io + 3),
+            lba_mid: Port::new(io + 4),
+            lba_high: Port::new(io + 5),
+            drive_head: Port::new(io + 6),
+            command: Port::new(io + 7),
+            control: Port::new(control),
+        }
+    }
+
+    fn status(&mut self) -> Status {
+        Status::from_bits_retain(unsafe { self.command.read() })
+    }
+
+    /// Select a drive and wait 400ns for it, by reading the alternate status
+    fn select(&mut self, slave: bool, lba: usize) {
+        unsafe {
+            self.drive_head
+                .write(0xe0 | (slave as u8) << 4 | (lba >> 24) as u8 & 0x0f);
+            for _ in 0..4 {
+                self.control.read();
+            }
+        }
+    }
+
+    fn wait_idle(&mut self) -> Result<Status, BlockError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.status();
+            if !status.contains(Status::BSY) {
+                return Ok(status);
+            }
+            core::hint::spin_loop();
+        }
+
+        Err(BlockError::DeviceError)
+    }
+
+    /// Wait until the drive is ready to transfer data
+    fn wait_data(&mut self) -> Result<(), BlockError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.status();
+            if !status.contains(Status::BSY) {
+                if status.intersects(Status::ERR | Status::DF) {
+                    return Err(BlockError::DeviceError);
+                }
+                if status.contains(Status::DRQ) {
+                    return Ok(());
+                }
+            }
+            core::hint::spin_loop();
+        }
+
+        Err(BlockError::DeviceError)
+    }
+
+    fn command(&mut self, slave: bool, lba: usize, command: u8) {
+        self.select(slave, lba);
+
+        unsafe {
+            self.sector_count.write(1);
+            self.lba_low.write(lba as u8);
+            self.lba_mid.write((lba >> 8) as u8);
+            self.lba_high.write((lba >> 16) as u8);
+            self.command.write(command);
+        }
+    }
+
+    /// Mask the IRQ of the bus, as the drives are polled
+    fn mask_irq(&mut self) {
+        // nIEN, the drives do not raise the IRQ if it is set
+        unsafe { self.control.write(0x02) };
+    }
+
+    /// Identify a drive, `None` if there is no ATA drive
+    fn identify(&mut self, slave: bool) -> Option<[u16; 256]> {
+        self.command(slave, 0, CMD_IDENTIFY);
+
+        // a floating bus reads as all ones, a missing drive as zero
+        let status = unsafe { self.command.read() };
+        if status == 0 || status == 0xff {
+            return None;
+        }
+
+        self.wait_idle().ok()?;
+
+        // ATAPI and SATA drives set the signature
+        if unsafe { self.lba_mid.read() != 0 || self.lba_high.read() != 0 } {
+            return None;
+        }
+
+        self.wait_data().ok()?;
+
+        let mut words = [0u16; 256];
+        for word in words.iter_mut() {
+            *word = unsafe { self.data.read() };
+        }
+
+        Some(words)
+    }
+}
+
+pub struct AtaDrive {
+    bus: usize,
+    slave: bool,
+    sectors: usize,
+    model: String,
+}
+
+impl AtaDrive {
+    /// The name of the drive, as `hd<bus><drive>`
+    pub fn name(&self) -> String {
+        let drive = if self.slave { 'b' } else { 'a' };
+        format!("hd{}{}", self.bus, drive)
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn block_count(&self) -> usize {
+        self.sectors
+    }
+
+    fn read_block(&self, offset: usize, block: &mut Block) -> Result<(), BlockError> {
+        if offset >= self.sectors {
+            return Err(BlockError::OutOfRange(offset));
+        }
+
+        let mut bus = BUSES[self.bus].lock();
+        bus.command(self.slave, offset, CMD_READ_SECTORS);
+        bus.wait_data()?;
+
+        for pair in block.chunks_exact_mut(2) {
+            pair.copy_from_slice(&unsafe { bus.data.read() }.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    fn write_block(&self, offset: usize, block: &Block) -> Result<(), BlockError> {
+        if offset >= self.sectors {
+            return Err(BlockError::OutOfRange(offset));
+        }
+
+        let mut bus = BUSES[self.bus].lock();
+        bus.command(self.slave, offset, CMD_WRITE_SECTORS);
+        bus.wait_data()?;
+
+        for pair in block.chunks_exact(2) {
+            unsafe { bus.data.write(u16::from_le_bytes([pair[0], pair[1]])) };
+        }
+
+        bus.wait_idle()?;
+
+        unsafe { bus.command.write(CMD_CACHE_FLUSH) };
+        bus.wait_idle()?;
+
+        Ok(())
+    }
+}
+
+/// Find the ATA drives on the IDE buses and register them,
+/// as `hd<bus><drive>` with `a` for the master and `b` for the slave
+pub fn init() {
+    for bus in 0..BUSES.len() {
+        BUSES[bus].lock().mask_irq();
+
+        for slave in [false, true] {
+            let Some(words) = BUSES[bus].lock().identify(slave) else {
+                continue;
+            };
+
+            // the count of LBA28 sectors is in words 60 and 61
+            let sectors = (words[60] as usize | (words[61] as usize) << 16).min(LBA28_MAX + 1);
+
+            // the model is in words 27 to 46, with the bytes of words swapped
+            let model: String = words[27..47]
+                .iter()
+                .flat_map(|word| word.to_be_bytes())
+                .map(|byte| byte as char)
+                .collect();
+
+            let drive = AtaDrive {
+                bus,
+                slave,
+                sectors,
+                model: String::from(model.trim()),
+            };
+
+            info!(
+                "ATA drive {}: {}, {} MiB",
+                drive.name(),
+                drive.model(),
+                sectors * BLOCK_SIZE / 1024 / 1024
+            );
+
+            register(drive.name(), Arc::new(drive));
+        }
+    }
+}
+
+/// Acknowledge a stray IRQ of a bus, from a drive ignoring nIEN
+pub fn receive_irq(bus: usize) {
+    // reading the status clears the IRQ of the drive, the bus may be
+    // locked by the polling cpu, but the port is not changed by reads
+    let mut status: Port<u8> = Port::new(BUS_PORTS[bus].0 + 7);
+    unsafe { status.read() };
+}
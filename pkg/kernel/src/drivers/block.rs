@@ -0,0 +1,106 @@
+//! Block devices
+//!
+//! devices found by drivers are registered by name, with their partitions
+//! named after them, to be mounted by filesystems.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use spin::Mutex;
+
+static DEVICES: Mutex<BTreeMap<String, Arc<dyn BlockDevice>>> = Mutex::new(BTreeMap::new());
+
+/// The size of a block in bytes
+pub const BLOCK_SIZE: usize = 512;
+
+pub type Block = [u8; BLOCK_SIZE];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// the block offset is beyond the device
+    OutOfRange(usize),
+    /// the device failed to transfer the block
+    DeviceError,
+}
+
+/// A device that is accessed by blocks of `BLOCK_SIZE` bytes
+pub trait BlockDevice: Send + Sync {
+    /// The count of blocks in the device
+    fn block_count(&self) -> usize;
+
+    /// Read the block at `offset` into `block`
+    fn read_block(&self, offset: usize, block: &mut Block) -> Result<(), BlockError>;
+
+    /// Write `block` to the block at `offset`
+    fn write_block(&self, offset: usize, block: &Block) -> Result<(), BlockError>;
+}
+
+/// Register a device and the partitions on it, as `<name><n>` from 1
+pub fn register(name: String, device: Arc<dyn BlockDevice>) {
+    let partitions = match super::partition::read_partitions(&device) {
+        Ok(partitions) => partitions,
+        Err(err) => {
+            warn!("Failed to read the partitions of {}: {:?}", name, err);
+            Vec::new()
+        }
+    };
+
+    let mut devices = DEVICES.lock();
+
+    for (i, partition) in partitions.into_iter().enumerate() {
+        let part_name = format!("{}{}", name, i + 1);
+        info!(
+            "Partition {}: {}, {} MiB at block {}",
+            part_name,
+            partition.kind(),
+            partition.block_count() * BLOCK_SIZE / 1024 / 1024,
+            partition.start()
+        );
+        devices.insert(part_name, Arc::new(partition));
+    }
+
+    devices.insert(name, device);
+}
+
+/// The registered devices, by name
+pub fn devices() -> Vec<(String, Arc<dyn BlockDevice>)> {
+    DEVICES
+        .lock()
+        .iter()
+        .map(|(name, device)| (name.clone(), device.clone()))
+        .collect()
+}
+
+/// A device in memory, for the tests of partitions and filesystems
+#[cfg(test)]
+pub struct MemoryDevice(pub Mutex<Vec<Block>>);
+
+#[cfg(test)]
+impl MemoryDevice {
+    pub fn new(blocks: Vec<Block>) -> Arc<dyn BlockDevice> {
+        Arc::new(Self(Mutex::new(blocks)))
+    }
+}
+
+#[cfg(test)]
+impl BlockDevice for MemoryDevice {
+    fn block_count(&self) -> usize {
+        self.0.lock().len()
+    }
+
+    fn read_block(&self, offset: usize, block: &mut Block) -> Result<(), BlockError> {
+        *block = *self
+            .0
+            .lock()
+            .get(offset)
+            .ok_or(BlockError::OutOfRange(offset))?;
+        Ok(())
+    }
+
+    fn write_block(&self, offset: usize, block: &Block) -> Result<(), BlockError> {
+        *self
+            .0
+            .lock()
+            .get_mut(offset)
+            .ok_or(BlockError::OutOfRange(offset))? = *block;
+        Ok(())
+    }
+}
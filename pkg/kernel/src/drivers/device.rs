@@ -0,0 +1,130 @@
+//! Character devices, opened by user processes as `/dev/<name>`
+//!
+//! devices are registered by kernel modules through C functions,
+//! so a device holds the entry points of its module.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::ops::Range;
+use spin::Mutex;
+
+/// Read at most `len` bytes into `buf`, returns the count or a negative error
+pub type ReadFn = extern "C" fn(buf: *mut u8, len: usize) -> isize;
+
+/// Write `len` bytes from `buf`, returns the count or a negative error
+pub type WriteFn = extern "C" fn(buf: *const u8, len: usize) -> isize;
+
+static DEVICES: Mutex<BTreeMap<String, Arc<CharDevice>>> = Mutex::new(BTreeMap::new());
+
+pub struct CharDevice {
+    name: String,
+    read: Option<ReadFn>,
+    write: Option<WriteFn>,
+}
+
+impl CharDevice {
+    pub fn new(name: String, read: Option<ReadFn>, write: Option<WriteFn>) -> Self {
+        Self { name, read, write }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let count = (self.read?)(buf.as_mut_ptr(), buf.len());
+        usize::try_from(count)
+            .ok()
+            .map(|count| count.min(buf.len()))
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Option<usize> {
+        let count = (self.write?)(buf.as_ptr(), buf.len());
+        usize::try_from(count)
+            .ok()
+            .map(|count| count.min(buf.len()))
+    }
+
+    /// Check if an entry point of the device is in the address range
+    fn is_in(&self, range: &Range<u64>) -> bool {
+        let read = self.read.map(|f| f as usize as u64);
+        let write = self.write.map(|f| f as usize as u64);
+
+        [read, write]
+            .into_iter()
+            .flatten()
+            .any(|addr| range.contains(&addr))
+    }
+
+    /// Check if the device is opened by a process
+    fn is_open(self: &Arc<Self>) -> bool {
+        // one reference is held by the registry
+        Arc::strong_count(self) > 1
+    }
+}
+
+impl core::fmt::Debug for CharDevice {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CharDevice")
+            .field("name", &self.name)
+            .field("read", &self.read.is_some())
+            .field("write", &self.write.is_some())
+            .finish()
+    }
+}
+
+/// Register a device, fails if the name is taken
+pub fn register(device: CharDevice) -> bool {
+    let mut devices = DEVICES.lock();
+
+    if device.name.is_empty() || devices.contains_key(&device.name) {
+        return false;
+    }
+
+    info!("Device registered: /dev/{}", device.name);
+    devices.insert(device.name.clone(), Arc::new(device));
+    true
+}
+
+/// Unregister a device, fails if it is not found or still opened
+pub fn unregister(name: &str) -> bool {
+    let mut devices = DEVICES.lock();
+
+    match devices.get(name) {
+        Some(device) if !device.is_open() => {
+            devices.remove(name);
+            info!("Device unregistered: /dev/{}", name);
+            true
+        }
+        _ => false,
+    }
+}
+
+pub fn find(name: &str) -> Option<Arc<CharDevice>> {
+    DEVICES.lock().get(name).cloned()
+}
+
+/// Check if any device provided by the code in `range` is opened
+pub fn is_range_busy(range: &Range<u64>) -> bool {
+    DEVICES
+        .lock()
+        .values()
+        .any(|device| device.is_in(range) && device.is_open())
+}
+
+/// Remove the devices provided by the code in `range`,
+/// returns the names of the devices removed
+pub fn unregister_range(range: &Range<u64>) -> Vec<String> {
+    let mut devices = DEVICES.lock();
+
+    let names: Vec<String> = devices
+        .values()
+        .filter(|device| device.is_in(range))
+        .map(|device| device.name.clone())
+        .collect();
+
+    for name in names.iter() {
+        devices.remove(name);
+    }
+
+    names
+}
@@ -1,6 +1,12 @@
 mod uart16550;
 
+pub mod ata;
+pub mod block;
+pub mod device;
 pub mod input;
+pub mod partition;
+pub mod ramdisk;
 pub mod serial;
 
 pub use input::{get_line, push_key};
+pub use uart16550::SerialPort;
@@ -0,0 +1,433 @@
+//! Partitions of block devices
+//!
+//! the partition table is the MBR in the first block, with four primary
+//! partitions, or the GPT it protects. extended partitions are not
+//! followed. a device with no table has no partitions, as a volume on a
+//! whole device has a boot sector with the signature of the MBR, so
+//! tables with invalid boot flags are ignored.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use super::block::*;
+
+/// Offset of the partition entries in the MBR
+const MBR_ENTRIES: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+/// The type of the MBR entry protecting a GPT
+const MBR_TYPE_GPT: u8 = 0xee;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// The min size of the GPT header, the size covered by its checksum
+const GPT_HEADER_SIZE: usize = 92;
+const GPT_ENTRY_MIN_SIZE: usize = 128;
+/// The max count of GPT entries read, as in most tables
+const GPT_ENTRIES_MAX: usize = 128;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// the type byte of an MBR entry
+    Mbr(u8),
+    /// the type GUID and the name of a GPT entry
+    Gpt([u8; 16], String),
+}
+
+impl core::fmt::Display for PartitionKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PartitionKind::Mbr(kind) => write!(f, "MBR type {:#04x}", kind),
+            PartitionKind::Gpt(guid, name) => {
+                // the first three fields of a GUID are little endian
+                write!(
+                    f,
+                    "GPT type {:08x}-{:04x}-{:04x}-",
+                    u32_at(guid, 0),
+                    u16::from_le_bytes([guid[4], guid[5]]),
+                    u16::from_le_bytes([guid[6], guid[7]])
+                )?;
+                for (i, byte) in guid[8..].iter().enumerate() {
+                    if i == 2 {
+                        write!(f, "-")?;
+                    }
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, " \"{}\"", name)
+            }
+        }
+    }
+}
+
+/// A range of blocks of a device
+pub struct Partition {
+    device: Arc<dyn BlockDevice>,
+    start: usize,
+    count: usize,
+    kind: PartitionKind,
+}
+
+impl Partition {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn kind(&self) -> &PartitionKind {
+        &self.kind
+    }
+}
+
+impl BlockDevice for Partition {
+    fn block_count(&self) -> usize {
+        self.count
+    }
+
+    fn read_block(&self, offset: usize, block: &mut Block) -> Result<(), BlockError> {
+        if offset >= self.count {
+            return Err(BlockError::OutOfRange(offset));
+        }
+
+        self.device.read_block(self.start + offset, block)
+    }
+
+    fn write_block(&self, offset: usize, block: &Block) -> Result<(), BlockError> {
+        if offset >= self.count {
+            return Err(BlockError::OutOfRange(offset));
+        }
+
+        self.device.write_block(self.start + offset, block)
+    }
+}
+
+/// The partitions of a device, from its GPT or its MBR
+///
+/// entries beyond the end of the device are skipped.
+pub fn read_partitions(device: &Arc<dyn BlockDevice>) -> Result<Vec<Partition>, BlockError> {
+    let mut mbr = [0; BLOCK_SIZE];
+    device.read_block(0, &mut mbr)?;
+
+    if mbr[BLOCK_SIZE - 2..] != MBR_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let entries: Vec<&[u8]> = mbr[MBR_ENTRIES..BLOCK_SIZE - 2]
+        .chunks_exact(MBR_ENTRY_SIZE)
+        .collect();
+
+    if entries.iter().any(|entry| !matches!(entry[0], 0x00 | 0x80)) {
+        return Ok(Vec::new());
+    }
+
+    if entries.iter().any(|entry| entry[4] == MBR_TYPE_GPT) {
+        return read_gpt(device);
+    }
+
+    let partitions = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let kind = entry[4];
+            let start = u32_at(entry, 8) as usize;
+            let count = u32_at(entry, 12) as usize;
+
+            if kind == 0 {
+                return None;
+            }
+
+            new_partition(device, start, count, PartitionKind::Mbr(kind))
+        })
+        .collect();
+
+    Ok(partitions)
+}
+
+/// The partitions of a GPT, whose header is in the second block
+fn read_gpt(device: &Arc<dyn BlockDevice>) -> Result<Vec<Partition>, BlockError> {
+    let mut header = [0; BLOCK_SIZE];
+    device.read_block(1, &mut header)?;
+
+    let header_size = u32_at(&header, 12) as usize;
+    let entries_start = u64_at(&header, 72) as usize;
+    let entry_count = (u32_at(&header, 80) as usize).min(GPT_ENTRIES_MAX);
+    let entry_size = u32_at(&header, 84) as usize;
+
+    let valid = &header[..8] == GPT_SIGNATURE
+        && (GPT_HEADER_SIZE..=BLOCK_SIZE).contains(&header_size)
+        && entry_size >= GPT_ENTRY_MIN_SIZE
+        && entry_size.is_power_of_two()
+        && entry_size <= BLOCK_SIZE;
+
+    if !valid {
+        warn!("Invalid GPT header");
+        return Ok(Vec::new());
+    }
+
+    // the checksum is computed with its own field as zero
+    let mut checked = header;
+    checked[16..20].fill(0);
+    if crc32(&checked[..header_size]) != u32_at(&header, 16) {
+        warn!("Invalid GPT header checksum");
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::with_capacity(entry_count * entry_size);
+    let mut block = [0; BLOCK_SIZE];
+    for i in 0..(entry_count * entry_size).div_ceil(BLOCK_SIZE) {
+        let offset = entries_start
+            .checked_add(i)
+            .ok_or(BlockError::OutOfRange(entries_start))?;
+        device.read_block(offset, &mut block)?;
+        entries.extend_from_slice(&block);
+    }
+    entries.truncate(entry_count * entry_size);
+
+    // the checksum covers all the entries, only checked if all are read
+    if entry_count == u32_at(&header, 80) as usize && crc32(&entries) != u32_at(&header, 88) {
+        warn!("Invalid GPT entries checksum");
+        return Ok(Vec::new());
+    }
+
+    let partitions = entries
+        .chunks_exact(entry_size)
+        .filter_map(|entry| {
+            let ty: [u8; 16] = entry[..16].try_into().unwrap();
+            if ty == [0; 16] {
+                return None;
+            }
+
+            let first = u64_at(entry, 32) as usize;
+            let last = u64_at(entry, 40) as usize;
+
+            // the name is UTF-16, ended by a zero unit
+            let name = char::decode_utf16(
+                entry[56..128]
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                    .take_while(|&unit| unit != 0),
+            )
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+            let count = last.checked_sub(first)?.checked_add(1)?;
+            new_partition(device, first, count, PartitionKind::Gpt(ty, name))
+        })
+        .collect();
+
+    Ok(partitions)
+}
+
+fn new_partition(
+    device: &Arc<dyn BlockDevice>,
+    start: usize,
+    count: usize,
+    kind: PartitionKind,
+) -> Option<Partition> {
+    // the table itself is not in a partition
+    if start == 0
+        || count == 0
+        || start
+            .checked_add(count)
+            .is_none_or(|end| end > device.block_count())
+    {
+        return None;
+    }
+
+    Some(Partition {
+        device: device.clone(),
+        start,
+        count,
+        kind,
+    })
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// CRC-32 of IEEE 802.3, as used by the GPT
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPE_FAT32: u8 = 0x0c;
+    const GUID_ESP: [u8; 16] = [
+        0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9,
+        0x3b,
+    ];
+
+    fn mbr(entries: &[(u8, u8, u32, u32)]) -> Block {
+        let mut mbr = [0; BLOCK_SIZE];
+        for (i, &(boot, kind, start, count)) in entries.iter().enumerate() {
+            let entry = &mut mbr[MBR_ENTRIES + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+            entry[0] = boot;
+            entry[4] = kind;
+            entry[8..12].copy_from_slice(&start.to_le_bytes());
+            entry[12..16].copy_from_slice(&count.to_le_bytes());
+        }
+        mbr[BLOCK_SIZE - 2..].copy_from_slice(&MBR_SIGNATURE);
+        mbr
+    }
+
+    /// A device of 64 blocks with a GPT of 4 entries in block 2
+    fn gpt(entries: &[([u8; 16], u64, u64, &str)]) -> Vec<Block> {
+        let mut blocks = vec![[0; BLOCK_SIZE]; 64];
+        blocks[0] = mbr(&[(0, MBR_TYPE_GPT, 1, 63)]);
+
+        for (i, &(ty, first, last, name)) in entries.iter().enumerate() {
+            let entry = &mut blocks[2][i * GPT_ENTRY_MIN_SIZE..][..GPT_ENTRY_MIN_SIZE];
+            entry[..16].copy_from_slice(&ty);
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+            for (j, unit) in name.encode_utf16().enumerate() {
+                entry[56 + j * 2..58 + j * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+
+        let header = &mut blocks[1];
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[12..16].copy_from_slice(&(GPT_HEADER_SIZE as u32).to_le_bytes());
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&(GPT_ENTRY_MIN_SIZE as u32).to_le_bytes());
+        let entries_crc = crc32(&blocks[2]);
+        blocks[1][88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let header_crc = crc32(&blocks[1][..GPT_HEADER_SIZE]);
+        blocks[1][16..20].copy_from_slice(&header_crc.to_le_bytes());
+
+        blocks
+    }
+
+    fn starts(partitions: &[Partition]) -> Vec<(usize, usize)> {
+        partitions
+            .iter()
+            .map(|partition| (partition.start(), partition.block_count()))
+            .collect()
+    }
+
+    #[test]
+    fn crc32_of_check_string() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn mbr_partitions_within_the_device() {
+        let mut blocks = vec![[0; BLOCK_SIZE]; 64];
+        blocks[0] = mbr(&[
+            (0x80, TYPE_FAT32, 8, 16),
+            (0, 0, 24, 8),
+            (0, TYPE_FAT32, 32, 33),
+            (0, 0x83, 40, 24),
+        ]);
+
+        let partitions = read_partitions(&MemoryDevice::new(blocks)).unwrap();
+
+        assert_eq!(starts(&partitions), [(8, 16), (40, 24)]);
+        assert_eq!(partitions[0].kind(), &PartitionKind::Mbr(TYPE_FAT32));
+        assert_eq!(partitions[1].kind(), &PartitionKind::Mbr(0x83));
+    }
+
+    #[test]
+    fn boot_sector_is_not_a_partition_table() {
+        let mut blocks = vec![[0; BLOCK_SIZE]; 64];
+        assert!(read_partitions(&MemoryDevice::new(blocks.clone()))
+            .unwrap()
+            .is_empty());
+
+        // the code of a boot sector is read as invalid boot flags
+        blocks[0] = mbr(&[(0x80, TYPE_FAT32, 8, 16), (0x31, 0xc0, 0, 0)]);
+        assert!(read_partitions(&MemoryDevice::new(blocks))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn partition_reads_are_bounded() {
+        let mut blocks = vec![[0; BLOCK_SIZE]; 64];
+        blocks[0] = mbr(&[(0, TYPE_FAT32, 8, 16)]);
+        blocks[8][0] = 1;
+        blocks[24][0] = 2;
+
+        let partitions = read_partitions(&MemoryDevice::new(blocks)).unwrap();
+        let mut block = [0; BLOCK_SIZE];
+
+        partitions[0].read_block(0, &mut block).unwrap();
+        assert_eq!(block[0], 1);
+        assert_eq!(
+            partitions[0].read_block(16, &mut block),
+            Err(BlockError::OutOfRange(16))
+        );
+    }
+
+    #[test]
+    fn gpt_partitions() {
+        let blocks = gpt(&[(GUID_ESP, 34, 63, "EFI system"), ([0; 16], 8, 9, "empty")]);
+
+        let partitions = read_partitions(&MemoryDevice::new(blocks)).unwrap();
+
+        assert_eq!(starts(&partitions), [(34, 30)]);
+        assert_eq!(
+            partitions[0].kind(),
+            &PartitionKind::Gpt(GUID_ESP, String::from("EFI system"))
+        );
+        assert_eq!(
+            format!("{}", partitions[0].kind()),
+            "GPT type c12a7328-f81f-11d2-ba4b-00a0c93ec93b \"EFI system\""
+        );
+    }
+
+    #[test]
+    fn gpt_with_invalid_checksums_is_ignored() {
+        let mut blocks = gpt(&[(GUID_ESP, 34, 63, "EFI system")]);
+        blocks[2][32] = 35;
+        assert!(read_partitions(&MemoryDevice::new(blocks.clone()))
+            .unwrap()
+            .is_empty());
+
+        blocks[1][72] = 3;
+        assert!(read_partitions(&MemoryDevice::new(blocks))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn gpt_entries_with_invalid_bounds_are_skipped() {
+        let blocks = gpt(&[
+            (GUID_ESP, 40, 39, "reversed"),
+            (GUID_ESP, 1 << 63, u64::MAX, "overflow"),
+            (GUID_ESP, 34, 64, "too large"),
+            (GUID_ESP, 34, 34, "one block"),
+        ]);
+
+        let partitions = read_partitions(&MemoryDevice::new(blocks)).unwrap();
+
+        assert_eq!(starts(&partitions), [(34, 1)]);
+    }
+
+    #[test]
+    fn gpt_entries_beyond_the_device_are_an_error() {
+        let mut blocks = gpt(&[]);
+        blocks[1][72..80].copy_from_slice(&u64::MAX.to_le_bytes());
+        blocks[1][16..20].fill(0);
+        let header_crc = crc32(&blocks[1][..GPT_HEADER_SIZE]);
+        blocks[1][16..20].copy_from_slice(&header_crc.to_le_bytes());
+
+        assert_eq!(
+            read_partitions(&MemoryDevice::new(blocks)).err(),
+            Some(BlockError::OutOfRange(usize::MAX))
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::block::*;
+
+/// A block device backed by the kernel heap
+pub struct Ramdisk {
+    blocks: Mutex<Vec<Block>>,
+}
+
+impl Ramdisk {
+    /// Create a ramdisk of `size` bytes, rounded up to blocks
+    pub fn new(size: usize) -> Self {
+        Self {
+            blocks: Mutex::new(vec![[0; BLOCK_SIZE]; size.div_ceil(BLOCK_SIZE)]),
+        }
+    }
+}
+
+impl BlockDevice for Ramdisk {
+    fn block_count(&self) -> usize {
+        self.blocks.lock().len()
+    }
+
+    fn read_block(&self, offset: usize, block: &mut Block) -> Result<(), BlockError> {
+        let blocks = self.blocks.lock();
+        let src = blocks.get(offset).ok_or(BlockError::OutOfRange(offset))?;
+        block.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write_block(&self, offset: usize, block: &Block) -> Result<(), BlockError> {
+        let mut blocks = self.blocks.lock();
+        let dst = blocks
+            .get_mut(offset)
+            .ok_or(BlockError::OutOfRange(offset))?;
+        dst.copy_from_slice(block);
+        Ok(())
+    }
+}
@@ -49,6 +49,15 @@ impl SerialPort {
         }
     }
 
+    /// Checks if the serial port exists, by writing the scratch register
+    /// and reading it back. absent ports read as 0xFF.
+    pub fn probe(&mut self) -> bool {
+        unsafe {
+            self.scratch.write(0x5A);
+            self.scratch.read() == 0x5A
+        }
+    }
+
     /// Sends a byte on the serial port.
     pub fn send(&mut self, data: u8) {
         unsafe {
@@ -0,0 +1,604 @@
+//! Directory entries
+//!
+//! an entry is 32 bytes with an 8.3 name. a long name is kept in the
+//! entries before it, 13 UTF-16 units each, in reverse order, with the
+//! checksum of the short name.
+
+use alloc::{string::String, vec::Vec};
+use chrono::{Datelike, Timelike};
+
+use super::*;
+
+pub const ENTRY_SIZE: usize = 32;
+const ENTRIES_PER_SECTOR: usize = BLOCK_SIZE / ENTRY_SIZE;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+/// The first name byte of a free entry, and of the free entries after the last
+const FREE: u8 = 0xe5;
+const END: u8 = 0x00;
+
+/// The sequence number bit of the last entry of a long name
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// Offsets of the UTF-16 units in a long name entry
+const LONG_NAME_UNITS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// The max length of a long name in UTF-16 units
+const LONG_NAME_MAX: usize = 255;
+
+/// Flags of the reserved byte, for short names in lower case
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+
+/// The position of an entry on the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntryPos {
+    pub sector: usize,
+    pub index: usize,
+}
+
+impl EntryPos {
+    /// The index of the entry on the device, as an inode number
+    pub fn ino(self) -> u64 {
+        (self.sector * ENTRIES_PER_SECTOR + self.index) as u64
+    }
+}
+
+/// Where the entries of a directory are
+#[derive(Clone, Copy, Debug)]
+pub enum DirLoc {
+    /// the fixed root directory of FAT12/16
+    Root,
+    Chain(u32),
+}
+
+#[derive(Clone, Copy)]
+pub struct FatEntry([u8; ENTRY_SIZE]);
+
+impl FatEntry {
+    fn new(name: [u8; 11], attr: u8, cluster: u32) -> Self {
+        let mut entry = Self([0; ENTRY_SIZE]);
+        entry.0[..11].copy_from_slice(&name);
+        entry.0[11] = attr;
+        entry.set_cluster(cluster);
+        entry.touch();
+
+        // created and accessed when it is modified
+        entry.0.copy_within(22..26, 14);
+        entry.0.copy_within(24..26, 18);
+        entry
+    }
+
+    fn short_name(&self) -> [u8; 11] {
+        self.0[..11].try_into().unwrap()
+    }
+
+    fn attr(&self) -> u8 {
+        self.0[11]
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attr() & ATTR_DIRECTORY != 0
+    }
+
+    pub fn cluster(&self) -> u32 {
+        let high = u16::from_le_bytes([self.0[20], self.0[21]]) as u32;
+        let low = u16::from_le_bytes([self.0[26], self.0[27]]) as u32;
+        high << 16 | low
+    }
+
+    pub fn set_cluster(&mut self, cluster: u32) {
+        self.0[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        self.0[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    }
+
+    pub fn size(&self) -> usize {
+        u32::from_le_bytes(self.0[28..32].try_into().unwrap()) as usize
+    }
+
+    pub fn set_size(&mut self, size: usize) {
+        self.0[28..32].copy_from_slice(&(size as u32).to_le_bytes());
+    }
+
+    /// Set the modification time to now
+    pub fn touch(&mut self) {
+        let now = crate::clock::now();
+
+        let date = ((now.year() - 1980).clamp(0, 127) as u16) << 9
+            | (now.month() as u16) << 5
+            | now.day() as u16;
+        let time = (now.hour() as u16) << 11 | (now.minute() as u16) << 5 | now.second() as u16 / 2;
+
+        self.0[22..24].copy_from_slice(&time.to_le_bytes());
+        self.0[24..26].copy_from_slice(&date.to_le_bytes());
+    }
+
+    /// The short name as `NAME.EXT`, in lower case if the flags say so
+    fn display_name(&self) -> String {
+        let mut name = self.short_name();
+
+        // 0x05 stands for 0xe5 as the first byte
+        if name[0] == 0x05 {
+            name[0] = FREE;
+        }
+
+        let case = self.0[12];
+        if case & CASE_LOWER_BASE != 0 {
+            name[..8].make_ascii_lowercase();
+        }
+        if case & CASE_LOWER_EXT != 0 {
+            name[8..].make_ascii_lowercase();
+        }
+
+        let base: String = name[..8].iter().map(|&byte| byte as char).collect();
+        let ext: String = name[8..].iter().map(|&byte| byte as char).collect();
+
+        match ext.trim_end() {
+            "" => String::from(base.trim_end()),
+            ext => format!("{}.{}", base.trim_end(), ext),
+        }
+    }
+}
+
+/// An entry found in a directory
+pub struct Found {
+    pub name: String,
+    pub entry: FatEntry,
+    pub pos: EntryPos,
+}
+
+/// The parts of a long name, collected before its short entry
+struct LongName {
+    units: Vec<u16>,
+    checksum: u8,
+    /// the sequence number of the next entry, 0 when the name is complete
+    next: u8,
+}
+
+impl LongName {
+    fn start(entry: &[u8]) -> Option<Self> {
+        let count = (entry[0] & !LAST_LONG_ENTRY) as usize;
+        if entry[0] & LAST_LONG_ENTRY == 0 || count == 0 || count * 13 > LONG_NAME_MAX + 13 {
+            return None;
+        }
+
+        Some(Self {
+            units: vec![0xffff; count * 13],
+            checksum: entry[13],
+            next: count as u8,
+        })
+    }
+
+    /// Add the next entry, `None` if it does not follow
+    fn push(mut self, entry: &[u8]) -> Option<Self> {
+        let seq = entry[0] & !LAST_LONG_ENTRY;
+        if seq != self.next || entry[13] != self.checksum {
+            return None;
+        }
+
+        let start = (seq as usize - 1) * 13;
+        for (i, &offset) in LONG_NAME_UNITS.iter().enumerate() {
+            self.units[start + i] = u16::from_le_bytes([entry[offset], entry[offset + 1]]);
+        }
+
+        self.next -= 1;
+        Some(self)
+    }
+
+    /// The name, if it is complete and belongs to `short_name`
+    fn finish(self, short_name: &[u8; 11]) -> Option<String> {
+        if self.next != 0 || self.checksum != checksum(short_name) {
+            return None;
+        }
+
+        let len = self
+            .units
+            .iter()
+            .position(|&unit| unit == 0)
+            .unwrap_or(self.units.len());
+
+        char::decode_utf16(self.units[..len].iter().copied())
+            .collect::<Result<String, _>>()
+            .ok()
+    }
+}
+
+/// The checksum of a short name, kept in its long name entries
+fn checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Characters allowed in a short name other than letters and digits
+const SHORT_NAME_SPECIAL: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// Characters not allowed in a long name other than control characters
+const LONG_NAME_INVALID: &str = "\"*/:<>?\\|";
+
+/// The short entry name of `name`, if it is an upper case 8.3 name
+fn short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+
+    let valid = |part: &str, max: usize| {
+        part.len() <= max
+            && part.bytes().all(|byte| {
+                byte.is_ascii_uppercase()
+                    || byte.is_ascii_digit()
+                    || SHORT_NAME_SPECIAL.contains(&byte)
+            })
+    };
+
+    if base.is_empty() || !valid(base, 8) || !valid(ext, 3) {
+        return None;
+    }
+
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short)
+}
+
+/// The short name of a long name, `BASE~N.EXT`, unique among `taken`
+fn numbered_short_name(name: &str, taken: &[[u8; 11]]) -> FsResult<[u8; 11]> {
+    let to_short = |part: &str, max: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                if c.is_ascii_alphanumeric()
+                    || c.is_ascii() && SHORT_NAME_SPECIAL.contains(&(c as u8))
+                {
+                    c as u8
+                } else {
+                    b'_'
+                }
+            })
+            .take(max)
+            .collect()
+    };
+
+    let name = name.trim_start_matches('.');
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    let mut base = to_short(base, 6);
+    let ext = to_short(ext, 3);
+
+    if base.is_empty() {
+        base.push(b'_');
+    }
+
+    for n in 1..1000000 {
+        let tail = format!("~{}", n);
+        let len = base.len().min(8 - tail.len());
+
+        let mut short = [b' '; 11];
+        short[..len].copy_from_slice(&base[..len]);
+        short[len..len + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+
+        if !taken.contains(&short) {
+            return Ok(short);
+        }
+    }
+
+    Err(FsError::AlreadyExists)
+}
+
+/// The entries of a long name, in the order they are written
+fn long_name_entries(name: &str, short_name: &[u8; 11]) -> Vec<[u8; ENTRY_SIZE]> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+
+    // the name is ended by a zero unit and padded with 0xffff
+    if units.len() % 13 != 0 {
+        units.push(0);
+    }
+    units.resize(units.len().next_multiple_of(13), 0xffff);
+
+    let count = units.len() / 13;
+    let checksum = checksum(short_name);
+
+    (1..=count)
+        .rev()
+        .map(|seq| {
+            let mut entry = [0; ENTRY_SIZE];
+            entry[0] = seq as u8 | if seq == count { LAST_LONG_ENTRY } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+
+            for (i, &offset) in LONG_NAME_UNITS.iter().enumerate() {
+                let unit = units[(seq - 1) * 13 + i];
+                entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+
+            entry
+        })
+        .collect()
+}
+
+impl FatFs {
+    /// The sectors of a directory
+    fn dir_sectors(&self, dir: DirLoc) -> FsResult<Vec<usize>> {
+        match dir {
+            DirLoc::Root => Ok((self.root_start..self.root_start + self.root_sectors).collect()),
+            DirLoc::Chain(first) => Ok(self
+                .chain(first)?
+                .into_iter()
+                .flat_map(|cluster| {
+                    let sector = self.cluster_sector(cluster);
+                    sector..sector + self.sectors_per_cluster
+                })
+                .collect()),
+        }
+    }
+
+    pub fn read_entry(&self, pos: EntryPos) -> FsResult<FatEntry> {
+        let sector = self.read_sector(pos.sector)?;
+        let offset = pos.index * ENTRY_SIZE;
+        Ok(FatEntry(
+            sector[offset..offset + ENTRY_SIZE].try_into().unwrap(),
+        ))
+    }
+
+    pub fn write_entry(&self, pos: EntryPos, entry: &FatEntry) -> FsResult {
+        self.write_raw_entry(pos, &entry.0)
+    }
+
+    fn write_raw_entry(&self, pos: EntryPos, entry: &[u8; ENTRY_SIZE]) -> FsResult {
+        let mut sector = self.read_sector(pos.sector)?;
+        let offset = pos.index * ENTRY_SIZE;
+        sector[offset..offset + ENTRY_SIZE].copy_from_slice(entry);
+        self.write_sector(pos.sector, &sector)
+    }
+
+    /// Call `f` on each slot of a directory until it returns false,
+    /// or until the end of the directory
+    fn for_each_slot(&self, dir: DirLoc, mut f: impl FnMut(EntryPos, &[u8]) -> bool) -> FsResult {
+        for sector in self.dir_sectors(dir)? {
+            let block = self.read_sector(sector)?;
+
+            for (index, slot) in block.chunks_exact(ENTRY_SIZE).enumerate() {
+                if !f(EntryPos { sector, index }, slot) {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The files and directories in a directory, without `.` and `..`
+    pub fn read_dir(&self, dir: DirLoc) -> FsResult<Vec<Found>> {
+        let mut found = Vec::new();
+        let mut long_name: Option<LongName> = None;
+
+        self.for_each_slot(dir, |pos, slot| {
+            match slot[0] {
+                END => return false,
+                FREE => long_name = None,
+                _ if slot[11] == ATTR_LONG_NAME => {
+                    let name = if slot[0] & LAST_LONG_ENTRY != 0 {
+                        LongName::start(slot)
+                    } else {
+                        long_name.take()
+                    };
+                    long_name = name.and_then(|name| name.push(slot));
+                }
+                _ if slot[11] & ATTR_VOLUME_ID != 0 => long_name = None,
+                _ => {
+                    let entry = FatEntry(slot.try_into().unwrap());
+                    let name = long_name
+                        .take()
+                        .and_then(|name| name.finish(&entry.short_name()))
+                        .unwrap_or_else(|| entry.display_name());
+
+                    if name != "." && name != ".." {
+                        found.push(Found { name, entry, pos });
+                    }
+                }
+            }
+
+            true
+        })?;
+
+        Ok(found)
+    }
+
+    /// Find `count` free slots in a row, extending the directory if needed
+    fn free_slots(
+        &self,
+        next_free: &mut u32,
+        dir: DirLoc,
+        count: usize,
+    ) -> FsResult<Vec<EntryPos>> {
+        loop {
+            let mut slots = Vec::new();
+
+            self.for_each_slot(dir, |pos, slot| {
+                if matches!(slot[0], END | FREE) {
+                    slots.push(pos);
+                } else {
+                    slots.clear();
+                }
+
+                slots.len() < count
+            })?;
+
+            if slots.len() == count {
+                return Ok(slots);
+            }
+
+            // the fixed root directory cannot grow
+            let DirLoc::Chain(first) = dir else {
+                return Err(FsError::NoSpace);
+            };
+
+            let last = self.chain(first)?.last().copied();
+            self.alloc_cluster(next_free, last)?;
+        }
+    }
+
+    /// Create an entry in a directory, with its long name if needed
+    ///
+    /// a directory gets a cluster with `.` and `..`.
+    pub fn create_entry(
+        &self,
+        next_free: &mut u32,
+        dir: DirLoc,
+        name: &str,
+        is_dir: bool,
+    ) -> FsResult<EntryPos> {
+        let invalid = name.is_empty()
+            || name == "."
+            || name == ".."
+            || name.ends_with(['.', ' '])
+            || name
+                .chars()
+                .any(|c| c.is_control() || LONG_NAME_INVALID.contains(c));
+
+        if invalid {
+            return Err(FsError::InvalidPath);
+        }
+
+        if name.encode_utf16().count() > LONG_NAME_MAX {
+            return Err(FsError::NameTooLong);
+        }
+
+        let existing = self.read_dir(dir)?;
+        if existing
+            .iter()
+            .any(|found| found.name.eq_ignore_ascii_case(name))
+        {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let taken: Vec<_> = existing
+            .iter()
+            .map(|found| found.entry.short_name())
+            .collect();
+        let (short, long_entries) = match short_name(name) {
+            Some(short) if taken.contains(&short) => return Err(FsError::AlreadyExists),
+            Some(short) => (short, Vec::new()),
+            None => {
+                let short = numbered_short_name(name, &taken)?;
+                (short, long_name_entries(name, &short))
+            }
+        };
+
+        let slots = self.free_slots(next_free, dir, long_entries.len() + 1)?;
+
+        let (attr, cluster) = if is_dir {
+            let cluster = self.alloc_cluster(next_free, None)?;
+
+            let parent = match dir {
+                DirLoc::Chain(parent) if parent != self.root_cluster => parent,
+                // `..` of a directory in the root is cluster 0
+                _ => 0,
+            };
+
+            let pos = |index| EntryPos {
+                sector: self.cluster_sector(cluster),
+                index,
+            };
+            self.write_entry(
+                pos(0),
+                &FatEntry::new(*b".          ", ATTR_DIRECTORY, cluster),
+            )?;
+            self.write_entry(
+                pos(1),
+                &FatEntry::new(*b"..         ", ATTR_DIRECTORY, parent),
+            )?;
+
+            (ATTR_DIRECTORY, cluster)
+        } else {
+            (ATTR_ARCHIVE, 0)
+        };
+
+        for (pos, entry) in slots.iter().zip(long_entries.iter()) {
+            self.write_raw_entry(*pos, entry)?;
+        }
+
+        let pos = *slots.last().unwrap();
+        self.write_entry(pos, &FatEntry::new(short, attr, cluster))?;
+
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_names_are_upper_case_8_3() {
+        assert_eq!(short_name("README.TXT"), Some(*b"README  TXT"));
+        assert_eq!(short_name("SHELL"), Some(*b"SHELL      "));
+        assert_eq!(short_name("readme.txt"), None);
+        assert_eq!(short_name("TOOLONGNAME"), None);
+        assert_eq!(short_name("A.TEXT"), None);
+        assert_eq!(short_name(".TXT"), None);
+        assert_eq!(short_name("A B"), None);
+    }
+
+    #[test]
+    fn numbered_short_names_are_unique() {
+        let first = numbered_short_name("hello world.txt", &[]).unwrap();
+        assert_eq!(&first, b"HELLOW~1TXT");
+
+        let second = numbered_short_name("hello world.txt", &[first]).unwrap();
+        assert_eq!(&second, b"HELLOW~2TXT");
+
+        assert_eq!(
+            &numbered_short_name(".profile", &[]).unwrap(),
+            b"PROFIL~1   "
+        );
+        assert_eq!(&numbered_short_name("été.c", &[]).unwrap(), b"_T_~1   C  ");
+    }
+
+    #[test]
+    fn long_names_are_read_back() {
+        let short = *b"HELLOW~1TXT";
+        let entries = long_name_entries("hello world.txt", &short);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0][0], 2 | LAST_LONG_ENTRY);
+        assert_eq!(entries[1][0], 1);
+
+        let mut name = LongName::start(&entries[0]).unwrap();
+        for entry in &entries {
+            name = name.push(entry).unwrap();
+        }
+        assert_eq!(name.finish(&short).as_deref(), Some("hello world.txt"));
+
+        // a long name belongs to the short name it was made for
+        let mut name = LongName::start(&entries[0]).unwrap();
+        for entry in &entries {
+            name = name.push(entry).unwrap();
+        }
+        assert_eq!(name.finish(b"HELLOW~2TXT"), None);
+    }
+
+    #[test]
+    fn long_names_out_of_order_are_dropped() {
+        let entries = long_name_entries("hello world.txt", b"HELLOW~1TXT");
+
+        assert!(LongName::start(&entries[1]).is_none());
+
+        let name = LongName::start(&entries[0]).unwrap();
+        assert!(name.push(&entries[1]).is_none());
+    }
+
+    #[test]
+    fn short_name_display() {
+        let mut entry = FatEntry([0; ENTRY_SIZE]);
+        entry.0[..11].copy_from_slice(b"README  TXT");
+        assert_eq!(entry.display_name(), "README.TXT");
+
+        entry.0[12] = CASE_LOWER_BASE;
+        assert_eq!(entry.display_name(), "readme.TXT");
+
+        entry.0[12] = 0;
+        entry.0[..11].copy_from_slice(b"\x05BC        ");
+        assert_eq!(entry.display_name(), "\u{e5}BC");
+    }
+}
@@ -0,0 +1,770 @@
+//! FAT12/16/32 filesystems
+//!
+//! a volume on a block device, with long file names. the FAT type is given
+//! by the count of clusters, as the specification says. an inode is the
+//! position of a directory entry, which is read from the device at each
+//! access, so the inodes of a file opened twice agree.
+
+mod dir;
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use spin::Mutex;
+use syscall_def::FileType;
+
+use super::*;
+use crate::drivers::block::*;
+use dir::*;
+
+/// The inode number of the root directory, no entry is at this position
+const ROOT_INO: u64 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+pub struct FatFs {
+    device: Arc<dyn BlockDevice>,
+    ty: FatType,
+    sectors_per_cluster: usize,
+    fat_start: usize,
+    fat_sectors: usize,
+    fat_count: usize,
+    /// the fixed root directory of FAT12/16, in sectors
+    root_start: usize,
+    root_sectors: usize,
+    /// the first cluster of the root directory of FAT32
+    root_cluster: u32,
+    data_start: usize,
+    cluster_count: u32,
+    /// held while the volume is changed, with the cluster to search for a
+    /// free one from
+    next_free: Mutex<u32>,
+    /// the count of writes to each file since the volume is mounted
+    versions: Mutex<BTreeMap<u64, u64>>,
+}
+
+impl FatFs {
+    /// Read the boot sector of a volume, `None` if it is not a FAT volume
+    pub fn new(device: Arc<dyn BlockDevice>) -> FsResult<Option<Arc<Self>>> {
+        let mut boot = [0; BLOCK_SIZE];
+        device.read_block(0, &mut boot)?;
+
+        let u16_at = |offset: usize| u16::from_le_bytes([boot[offset], boot[offset + 1]]) as usize;
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(boot[offset..offset + 4].try_into().unwrap()) as usize
+        };
+
+        let bytes_per_sector = u16_at(11);
+        let sectors_per_cluster = boot[13] as usize;
+        let reserved_sectors = u16_at(14);
+        let fat_count = boot[16] as usize;
+        let root_entries = u16_at(17);
+        let fat_sectors = match u16_at(22) {
+            0 => u32_at(36),
+            sectors => sectors,
+        };
+        let total_sectors = match u16_at(19) {
+            0 => u32_at(32),
+            sectors => sectors,
+        };
+
+        let valid = boot[BLOCK_SIZE - 2..] == [0x55, 0xaa]
+            && matches!(boot[0], 0xeb | 0xe9)
+            && bytes_per_sector == BLOCK_SIZE
+            && sectors_per_cluster.is_power_of_two()
+            && reserved_sectors > 0
+            && fat_count > 0
+            && fat_sectors > 0
+            && total_sectors <= device.block_count();
+
+        if !valid {
+            return Ok(None);
+        }
+
+        let root_sectors = (root_entries * ENTRY_SIZE).div_ceil(BLOCK_SIZE);
+        let fat_start = reserved_sectors;
+        let root_start = fat_start + fat_count * fat_sectors;
+        let data_start = root_start + root_sectors;
+
+        let Some(data_sectors) = total_sectors.checked_sub(data_start) else {
+            return Ok(None);
+        };
+
+        let clusters = data_sectors / sectors_per_cluster;
+        let ty = match clusters {
+            0..4085 => FatType::Fat12,
+            4085..65525 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+
+        // the clusters are also limited by the entries the FAT holds
+        let bits = match ty {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        };
+        let cluster_count = clusters.min(fat_sectors * BLOCK_SIZE * 8 / bits - 2) as u32;
+
+        let root_cluster = match ty {
+            FatType::Fat32 => u32_at(44) as u32,
+            _ => 0,
+        };
+
+        let fs = Self {
+            device,
+            ty,
+            sectors_per_cluster,
+            fat_start,
+            fat_sectors,
+            fat_count,
+            root_start,
+            root_sectors,
+            root_cluster,
+            data_start,
+            cluster_count,
+            next_free: Mutex::new(2),
+            versions: Mutex::new(BTreeMap::new()),
+        };
+
+        if ty == FatType::Fat32 && !fs.is_cluster(root_cluster) {
+            return Ok(None);
+        }
+
+        Ok(Some(Arc::new(fs)))
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.ty
+    }
+
+    /// The size of the volume in bytes
+    pub fn size(&self) -> usize {
+        self.cluster_count as usize * self.cluster_size()
+    }
+
+    pub fn root(self: &Arc<Self>) -> Arc<dyn Inode> {
+        Arc::new(FatInode {
+            fs: self.clone(),
+            pos: None,
+        })
+    }
+
+    fn read_sector(&self, sector: usize) -> FsResult<Block> {
+        let mut block = [0; BLOCK_SIZE];
+        self.device.read_block(sector, &mut block)?;
+        Ok(block)
+    }
+
+    fn write_sector(&self, sector: usize, block: &Block) -> FsResult {
+        Ok(self.device.write_block(sector, block)?)
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster * BLOCK_SIZE
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> usize {
+        self.data_start + (cluster as usize - 2) * self.sectors_per_cluster
+    }
+
+    fn is_cluster(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+
+    /// The FAT entry marking the end of a chain
+    fn end_of_chain(&self) -> u32 {
+        match self.ty {
+            FatType::Fat12 => 0xfff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+
+    fn is_end_of_chain(&self, entry: u32) -> bool {
+        entry >= self.end_of_chain() & !7
+    }
+
+    /// Read bytes of the first FAT, from a byte offset
+    fn read_fat(&self, offset: usize, buf: &mut [u8]) -> FsResult {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let offset = offset + i;
+            let sector = self.read_sector(self.fat_start + offset / BLOCK_SIZE)?;
+            *byte = sector[offset % BLOCK_SIZE];
+        }
+
+        Ok(())
+    }
+
+    /// Write bytes to all the FATs, from a byte offset
+    fn write_fat(&self, offset: usize, buf: &[u8]) -> FsResult {
+        for fat in 0..self.fat_count {
+            let start = self.fat_start + fat * self.fat_sectors;
+
+            for (i, &byte) in buf.iter().enumerate() {
+                let offset = offset + i;
+                let mut sector = self.read_sector(start + offset / BLOCK_SIZE)?;
+                sector[offset % BLOCK_SIZE] = byte;
+                self.write_sector(start + offset / BLOCK_SIZE, &sector)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fat_entry(&self, cluster: u32) -> FsResult<u32> {
+        let cluster = cluster as usize;
+
+        Ok(match self.ty {
+            FatType::Fat12 => {
+                let mut pair = [0; 2];
+                self.read_fat(cluster + cluster / 2, &mut pair)?;
+                let pair = u16::from_le_bytes(pair) as u32;
+                if cluster % 2 == 0 {
+                    pair & 0xfff
+                } else {
+                    pair >> 4
+                }
+            }
+            FatType::Fat16 => {
+                let mut entry = [0; 2];
+                self.read_fat(cluster * 2, &mut entry)?;
+                u16::from_le_bytes(entry) as u32
+            }
+            FatType::Fat32 => {
+                let mut entry = [0; 4];
+                self.read_fat(cluster * 4, &mut entry)?;
+                u32::from_le_bytes(entry) & 0x0fff_ffff
+            }
+        })
+    }
+
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> FsResult {
+        let cluster = cluster as usize;
+
+        match self.ty {
+            FatType::Fat12 => {
+                let offset = cluster + cluster / 2;
+                let mut pair = [0; 2];
+                self.read_fat(offset, &mut pair)?;
+                let old = u16::from_le_bytes(pair);
+                let new = if cluster % 2 == 0 {
+                    old & 0xf000 | value as u16 & 0xfff
+                } else {
+                    old & 0x000f | (value as u16) << 4
+                };
+                self.write_fat(offset, &new.to_le_bytes())
+            }
+            FatType::Fat16 => self.write_fat(cluster * 2, &(value as u16).to_le_bytes()),
+            FatType::Fat32 => {
+                // the high 4 bits are reserved
+                let mut old = [0; 4];
+                self.read_fat(cluster * 4, &mut old)?;
+                let new = u32::from_le_bytes(old) & 0xf000_0000 | value & 0x0fff_ffff;
+                self.write_fat(cluster * 4, &new.to_le_bytes())
+            }
+        }
+    }
+
+    /// The clusters of a chain, empty if it starts at cluster 0
+    fn chain(&self, first: u32) -> FsResult<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+
+        while cluster != 0 {
+            // a chain longer than the volume has a loop
+            if !self.is_cluster(cluster) || chain.len() >= self.cluster_count as usize {
+                return Err(FsError::Io);
+            }
+
+            chain.push(cluster);
+
+            let next = self.fat_entry(cluster)?;
+            cluster = if self.is_end_of_chain(next) { 0 } else { next };
+        }
+
+        Ok(chain)
+    }
+
+    /// Allocate a zeroed cluster, appended to the chain ending at `last`
+    ///
+    /// the caller holds `next_free`.
+    fn alloc_cluster(&self, next_free: &mut u32, last: Option<u32>) -> FsResult<u32> {
+        let start = *next_free;
+        let mut cluster = start;
+
+        loop {
+            if !self.is_cluster(cluster) {
+                cluster = 2;
+            }
+
+            if self.fat_entry(cluster)? == 0 {
+                break;
+            }
+
+            cluster += 1;
+            if cluster == start {
+                return Err(FsError::NoSpace);
+            }
+        }
+
+        let zero = [0; BLOCK_SIZE];
+        let sector = self.cluster_sector(cluster);
+        for i in 0..self.sectors_per_cluster {
+            self.write_sector(sector + i, &zero)?;
+        }
+
+        self.set_fat_entry(cluster, self.end_of_chain())?;
+        if let Some(last) = last {
+            self.set_fat_entry(last, cluster)?;
+        }
+
+        *next_free = cluster + 1;
+        Ok(cluster)
+    }
+
+    fn free_clusters(&self, clusters: &[u32]) -> FsResult {
+        for &cluster in clusters {
+            self.set_fat_entry(cluster, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the data of a chain from a byte offset
+    fn read_chain(&self, chain: &[u32], offset: usize, buf: &mut [u8]) -> FsResult {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = offset + done;
+            // a corrupt entry may be larger than its chain
+            let cluster = *chain.get(pos / self.cluster_size()).ok_or(FsError::Io)?;
+            let sector = self.cluster_sector(cluster) + pos % self.cluster_size() / BLOCK_SIZE;
+            let start = pos % BLOCK_SIZE;
+            let count = (BLOCK_SIZE - start).min(buf.len() - done);
+
+            let block = self.read_sector(sector)?;
+            buf[done..done + count].copy_from_slice(&block[start..start + count]);
+            done += count;
+        }
+
+        Ok(())
+    }
+
+    /// Write data to a chain from a byte offset, within its clusters
+    fn write_chain(&self, chain: &[u32], offset: usize, buf: &[u8]) -> FsResult {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = offset + done;
+            // a corrupt entry may be larger than its chain
+            let cluster = *chain.get(pos / self.cluster_size()).ok_or(FsError::Io)?;
+            let sector = self.cluster_sector(cluster) + pos % self.cluster_size() / BLOCK_SIZE;
+            let start = pos % BLOCK_SIZE;
+            let count = (BLOCK_SIZE - start).min(buf.len() - done);
+
+            let mut block = if count == BLOCK_SIZE {
+                [0; BLOCK_SIZE]
+            } else {
+                self.read_sector(sector)?
+            };
+            block[start..start + count].copy_from_slice(&buf[done..done + count]);
+            self.write_sector(sector, &block)?;
+            done += count;
+        }
+
+        Ok(())
+    }
+}
+
+/// A file or a directory, by the position of its entry
+struct FatInode {
+    fs: Arc<FatFs>,
+    /// `None` for the root directory
+    pos: Option<EntryPos>,
+}
+
+impl FatInode {
+    fn entry(&self) -> FsResult<Option<FatEntry>> {
+        match self.pos {
+            Some(pos) => self.fs.read_entry(pos).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Where the entries are, if this is a directory
+    fn dir(&self) -> FsResult<DirLoc> {
+        match self.entry()? {
+            None if self.fs.ty == FatType::Fat32 => Ok(DirLoc::Chain(self.fs.root_cluster)),
+            None => Ok(DirLoc::Root),
+            Some(entry) if entry.is_dir() => Ok(DirLoc::Chain(entry.cluster())),
+            Some(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    /// The entry of a file, which is not a directory
+    fn file(&self) -> FsResult<FatEntry> {
+        match self.entry()? {
+            Some(entry) if !entry.is_dir() => Ok(entry),
+            _ => Err(FsError::IsADirectory),
+        }
+    }
+
+    /// Bump the version of a file before it is written,
+    /// as a failed write may have changed part of it
+    fn changed(&self) {
+        let ino = self.pos.map_or(ROOT_INO, EntryPos::ino);
+        *self.fs.versions.lock().entry(ino).or_default() += 1;
+    }
+
+    /// Resize a file, its entry is written back by the caller
+    fn resize(&self, entry: &mut FatEntry, next_free: &mut u32, size: usize) -> FsResult {
+        let fs = &self.fs;
+        let mut chain = fs.chain(entry.cluster())?;
+        let clusters = size.div_ceil(fs.cluster_size());
+
+        while chain.len() < clusters {
+            let cluster = fs.alloc_cluster(next_free, chain.last().copied())?;
+            chain.push(cluster);
+        }
+
+        if chain.len() > clusters {
+            match clusters.checked_sub(1) {
+                Some(last) => fs.set_fat_entry(chain[last], fs.end_of_chain())?,
+                None => entry.set_cluster(0),
+            }
+            fs.free_clusters(&chain[clusters..])?;
+        }
+
+        if let Some(&first) = chain.first().filter(|_| clusters > 0) {
+            entry.set_cluster(first);
+        }
+
+        // the data after the old end is zeroed
+        let old_size = entry.size();
+        if size > old_size {
+            let end = size.min(old_size.next_multiple_of(fs.cluster_size()));
+            fs.write_chain(&chain, old_size, &vec![0; end - old_size])?;
+        }
+
+        entry.set_size(size);
+        Ok(())
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Metadata {
+        let ino = self.pos.map_or(ROOT_INO, EntryPos::ino);
+
+        match self.entry() {
+            Ok(Some(entry)) if !entry.is_dir() => Metadata {
+                ino,
+                ty: FileType::File,
+                size: entry.size() as u64,
+                version: self.fs.versions.lock().get(&ino).copied().unwrap_or(0),
+            },
+            _ => Metadata {
+                ino,
+                ty: FileType::Directory,
+                size: self.read_dir().map_or(0, |entries| entries.len()) as u64,
+                version: 0,
+            },
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let entry = self.file()?;
+        let count = entry.size().saturating_sub(offset).min(buf.len());
+
+        if count > 0 {
+            let chain = self.fs.chain(entry.cluster())?;
+            self.fs.read_chain(&chain, offset, &mut buf[..count])?;
+        }
+
+        Ok(count)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> FsResult<usize> {
+        let mut next_free = self.fs.next_free.lock();
+        let mut entry = self.file()?;
+        self.changed();
+
+        let end = offset + buf.len();
+        if end > u32::MAX as usize {
+            return Err(FsError::NoSpace);
+        }
+
+        if end > entry.size() {
+            self.resize(&mut entry, &mut next_free, end)?;
+        }
+
+        let chain = self.fs.chain(entry.cluster())?;
+        self.fs.write_chain(&chain, offset, buf)?;
+
+        entry.touch();
+        self.fs
+            .write_entry(self.pos.ok_or(FsError::IsADirectory)?, &entry)?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: usize) -> FsResult {
+        let mut next_free = self.fs.next_free.lock();
+        let mut entry = self.file()?;
+        self.changed();
+
+        if size > u32::MAX as usize {
+            return Err(FsError::NoSpace);
+        }
+
+        self.resize(&mut entry, &mut next_free, size)?;
+
+        entry.touch();
+        self.fs
+            .write_entry(self.pos.ok_or(FsError::IsADirectory)?, &entry)
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let found = self
+            .fs
+            .read_dir(self.dir()?)?
+            .into_iter()
+            .find(|found| found.name.eq_ignore_ascii_case(name))
+            .ok_or(FsError::NotFound)?;
+
+        Ok(Arc::new(FatInode {
+            fs: self.fs.clone(),
+            pos: Some(found.pos),
+        }))
+    }
+
+    fn create(&self, name: &str, ty: FileType) -> FsResult<Arc<dyn Inode>> {
+        let mut next_free = self.fs.next_free.lock();
+        let dir = self.dir()?;
+
+        let pos = self
+            .fs
+            .create_entry(&mut next_free, dir, name, ty == FileType::Directory)?;
+
+        Ok(Arc::new(FatInode {
+            fs: self.fs.clone(),
+            pos: Some(pos),
+        }))
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<(String, FileType)>> {
+        Ok(self
+            .fs
+            .read_dir(self.dir()?)?
+            .into_iter()
+            .map(|found| {
+                let ty = if found.entry.is_dir() {
+                    FileType::Directory
+                } else {
+                    FileType::File
+                };
+                (found.name, ty)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A FAT12 volume of 128 sectors, with 2 FATs of 1 sector, a root
+    /// directory of 1 sector and the data from sector 4
+    fn volume() -> Vec<Block> {
+        let mut blocks = vec![[0; BLOCK_SIZE]; 128];
+
+        let boot = &mut blocks[0];
+        boot[0] = 0xeb;
+        boot[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+        boot[13] = 1;
+        boot[14..16].copy_from_slice(&1u16.to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&16u16.to_le_bytes());
+        boot[19..21].copy_from_slice(&128u16.to_le_bytes());
+        boot[22..24].copy_from_slice(&1u16.to_le_bytes());
+        boot[BLOCK_SIZE - 2..].copy_from_slice(&[0x55, 0xaa]);
+
+        let fat = [(2, 3), (3, 0xfff), (4, 4), (5, 0xfff), (6, 0xfff)];
+        for (cluster, value) in fat {
+            set_fat12(&mut blocks[1], cluster, value);
+            set_fat12(&mut blocks[2], cluster, value);
+        }
+
+        let root = [
+            (b"YSOS       ", 0x08, 0, 0, 0),
+            (b"README  TXT", 0x20, 0x18, 2, 700),
+            (b"LOOP    BIN", 0x20, 0, 4, 10),
+            (b"BIG     BIN", 0x20, 0, 5, 1000),
+            (b"APPS       ", 0x10, 0, 6, 0),
+        ];
+        for (i, &(name, attr, case, cluster, size)) in root.iter().enumerate() {
+            put_entry(&mut blocks[3], i, name, attr, case, cluster, size);
+        }
+        blocks[3][5 * ENTRY_SIZE] = 0xe5;
+
+        // cluster 6 is the directory `APPS`
+        put_entry(&mut blocks[8], 0, b".          ", 0x10, 0, 6, 0);
+        put_entry(&mut blocks[8], 1, b"..         ", 0x10, 0, 0, 0);
+        put_entry(&mut blocks[8], 2, b"SHELL      ", 0x20, 0, 0, 0);
+
+        // README.TXT is in clusters 2 and 3, at sectors 4 and 5
+        for (i, byte) in blocks[4..6].iter_mut().flatten().enumerate() {
+            *byte = i as u8;
+        }
+
+        blocks
+    }
+
+    fn set_fat12(fat: &mut Block, cluster: usize, value: u16) {
+        let offset = cluster + cluster / 2;
+        let old = u16::from_le_bytes([fat[offset], fat[offset + 1]]);
+        let new = if cluster % 2 == 0 {
+            old & 0xf000 | value
+        } else {
+            old & 0x000f | value << 4
+        };
+        fat[offset..offset + 2].copy_from_slice(&new.to_le_bytes());
+    }
+
+    fn put_entry(
+        sector: &mut Block,
+        index: usize,
+        name: &[u8; 11],
+        attr: u8,
+        case: u8,
+        cluster: u16,
+        size: u32,
+    ) {
+        let entry = &mut sector[index * ENTRY_SIZE..][..ENTRY_SIZE];
+        entry[..11].copy_from_slice(name);
+        entry[11] = attr;
+        entry[12] = case;
+        entry[26..28].copy_from_slice(&cluster.to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+    }
+
+    fn mount(blocks: Vec<Block>) -> Arc<FatFs> {
+        FatFs::new(MemoryDevice::new(blocks)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn fat_type_is_given_by_the_cluster_count() {
+        let fs = mount(volume());
+        assert_eq!(fs.fat_type(), FatType::Fat12);
+        assert_eq!(fs.cluster_count, 124);
+        assert_eq!(fs.size(), 124 * BLOCK_SIZE);
+
+        // 4085 clusters after 1 reserved sector and 2 FATs of 16 sectors
+        let mut blocks = volume();
+        blocks[0][17..19].copy_from_slice(&0u16.to_le_bytes());
+        blocks[0][19..21].copy_from_slice(&(33 + 4085u16).to_le_bytes());
+        blocks[0][22..24].copy_from_slice(&16u16.to_le_bytes());
+        blocks.resize(33 + 4085, [0; BLOCK_SIZE]);
+        assert_eq!(mount(blocks).fat_type(), FatType::Fat16);
+    }
+
+    #[test]
+    fn invalid_boot_sectors_are_not_volumes() {
+        let mut blocks = volume();
+        blocks[0][0] = 0;
+        assert!(FatFs::new(MemoryDevice::new(blocks)).unwrap().is_none());
+
+        // more sectors than the device
+        let mut blocks = volume();
+        blocks.truncate(64);
+        assert!(FatFs::new(MemoryDevice::new(blocks)).unwrap().is_none());
+
+        let mut blocks = volume();
+        blocks[0][13] = 3;
+        assert!(FatFs::new(MemoryDevice::new(blocks)).unwrap().is_none());
+    }
+
+    #[test]
+    fn fat12_entries_share_bytes() {
+        let fs = mount(volume());
+
+        assert_eq!(fs.fat_entry(2).unwrap(), 3);
+        assert_eq!(fs.fat_entry(3).unwrap(), 0xfff);
+        assert_eq!(fs.fat_entry(7).unwrap(), 0);
+
+        fs.set_fat_entry(7, 0xabc).unwrap();
+        assert_eq!(fs.fat_entry(6).unwrap(), 0xfff);
+        assert_eq!(fs.fat_entry(7).unwrap(), 0xabc);
+        assert_eq!(fs.fat_entry(8).unwrap(), 0);
+
+        // both FATs are written
+        let mut fats = [[0; BLOCK_SIZE]; 2];
+        fs.device.read_block(1, &mut fats[0]).unwrap();
+        fs.device.read_block(2, &mut fats[1]).unwrap();
+        assert_eq!(fats[0], fats[1]);
+        assert_eq!(u16::from_le_bytes([fats[1][10], fats[1][11]]) >> 4, 0xabc);
+    }
+
+    #[test]
+    fn chains_end_and_loops_are_detected() {
+        let fs = mount(volume());
+
+        assert_eq!(fs.chain(2).unwrap(), [2, 3]);
+        assert!(fs.chain(0).unwrap().is_empty());
+        assert_eq!(fs.chain(4).err(), Some(FsError::Io));
+        assert_eq!(fs.chain(200).err(), Some(FsError::Io));
+    }
+
+    #[test]
+    fn root_directory_entries() {
+        let root = mount(volume()).root();
+
+        let names: Vec<_> = root.read_dir().unwrap();
+        assert_eq!(
+            names,
+            [
+                (String::from("readme.txt"), FileType::File),
+                (String::from("LOOP.BIN"), FileType::File),
+                (String::from("BIG.BIN"), FileType::File),
+                (String::from("APPS"), FileType::Directory),
+            ]
+        );
+
+        let apps = root.lookup("apps").unwrap();
+        assert_eq!(
+            apps.read_dir().unwrap(),
+            [(String::from("SHELL"), FileType::File)]
+        );
+        assert_eq!(root.lookup("YSOS").err(), Some(FsError::NotFound));
+    }
+
+    #[test]
+    fn file_data_spans_clusters() {
+        let root = mount(volume()).root();
+        let readme = root.lookup("README.TXT").unwrap();
+        assert_eq!(readme.metadata().size, 700);
+
+        let mut buf = [0; 8];
+        assert_eq!(readme.read_at(508, &mut buf).unwrap(), 8);
+        assert_eq!(buf, [252, 253, 254, 255, 0, 1, 2, 3]);
+
+        assert_eq!(readme.read_at(696, &mut buf).unwrap(), 4);
+        assert_eq!(readme.read_at(700, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn corrupt_files_are_io_errors() {
+        let root = mount(volume()).root();
+        let mut buf = [0; 8];
+
+        // the chain of LOOP.BIN points to itself
+        let looped = root.lookup("LOOP.BIN").unwrap();
+        assert_eq!(looped.read_at(0, &mut buf).err(), Some(FsError::Io));
+
+        // BIG.BIN is larger than its only cluster
+        let big = root.lookup("BIG.BIN").unwrap();
+        assert_eq!(big.read_at(0, &mut buf).unwrap(), 8);
+        assert_eq!(big.read_at(600, &mut buf).err(), Some(FsError::Io));
+    }
+}
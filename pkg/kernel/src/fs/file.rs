@@ -0,0 +1,94 @@
+use alloc::string::String;
+use syscall_def::{FileStat, FileType, OpenFlags, SeekWhence};
+
+use super::*;
+
+/// An opened file, with its offset
+///
+/// the offset of a directory is the index of the next entry to read.
+pub struct File {
+    dentry: Dentry,
+    offset: usize,
+    flags: OpenFlags,
+}
+
+impl File {
+    pub fn new(dentry: Dentry, flags: OpenFlags) -> Self {
+        Self {
+            dentry,
+            offset: 0,
+            flags,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        self.dentry.path()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.dentry.inode().metadata().ty == FileType::Directory
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> FsResult<usize> {
+        if self.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+
+        let count = self.dentry.inode().read_at(self.offset, buf)?;
+        self.offset += count;
+        Ok(count)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> FsResult<usize> {
+        if self.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+
+        if self.flags.contains(OpenFlags::APPEND) {
+            self.offset = self.dentry.inode().metadata().size as usize;
+        }
+
+        let count = self.dentry.inode().write_at(self.offset, buf)?;
+        self.offset += count;
+        Ok(count)
+    }
+
+    /// Move the offset, which may be past the end of the file
+    pub fn seek(&mut self, offset: isize, whence: SeekWhence) -> FsResult<usize> {
+        let base = match whence {
+            SeekWhence::Set => 0,
+            SeekWhence::Current => self.offset,
+            SeekWhence::End => self.dentry.inode().metadata().size as usize,
+            SeekWhence::Unknown => return Err(FsError::InvalidSeek),
+        };
+
+        self.offset = base
+            .checked_add_signed(offset)
+            .ok_or(FsError::InvalidSeek)?;
+        Ok(self.offset)
+    }
+
+    pub fn stat(&self) -> FileStat {
+        let metadata = self.dentry.inode().metadata();
+
+        FileStat {
+            ino: metadata.ino,
+            ty: metadata.ty as usize,
+            size: metadata.size,
+        }
+    }
+
+    /// The next entry of a directory, `None` at the end
+    pub fn read_dir(&mut self) -> FsResult<Option<(String, FileType)>> {
+        if !self.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+
+        let entry = self.dentry.read_dir()?.into_iter().nth(self.offset);
+        if entry.is_some() {
+            self.offset += 1;
+        }
+
+        Ok(entry)
+    }
+}
@@ -0,0 +1,300 @@
+//! Initial ramdisk
+//!
+//! a ustar archive loaded by the bootloader, given by `initramfs` of
+//! `boot.conf`. files are indexed by path and read in place, the image is
+//! never freed. directories are implied by the paths of files.
+//!
+//! programs are in `/bin`, shared libraries in `/lib` and kernel modules
+//! in `/lib/modules`. it is mounted at the root as a read-only filesystem.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use spin::Once;
+use syscall_def::FileType;
+
+use super::*;
+
+static INITRAMFS: Once<InitRamFs> = Once::new();
+
+const BLOCK_SIZE: usize = 512;
+
+/// Offsets of the ustar header fields
+const NAME: core::ops::Range<usize> = 0..100;
+const SIZE: core::ops::Range<usize> = 124..136;
+const CHECKSUM: core::ops::Range<usize> = 148..156;
+const TYPE_FLAG: usize = 156;
+const MAGIC: core::ops::Range<usize> = 257..262;
+const PREFIX: core::ops::Range<usize> = 345..500;
+
+pub struct InitRamFs {
+    /// file contents by absolute path
+    files: BTreeMap<String, &'static [u8]>,
+}
+
+impl InitRamFs {
+    /// Index the regular files of a ustar archive,
+    /// stops at the end of the archive or at the first invalid header
+    pub fn unpack(image: &'static [u8]) -> Self {
+        let mut files = BTreeMap::new();
+        let mut offset = 0;
+
+        while let Some(header) = image.get(offset..offset + BLOCK_SIZE) {
+            // the archive ends with zero blocks
+            if header.iter().all(|&byte| byte == 0) {
+                break;
+            }
+
+            if !is_valid(header) {
+                warn!("Initramfs: invalid header at {:#x}", offset);
+                break;
+            }
+
+            let Some(size) = parse_octal(&header[SIZE]) else {
+                warn!("Initramfs: invalid size at {:#x}", offset);
+                break;
+            };
+
+            let start = offset + BLOCK_SIZE;
+            let Some(data) = image.get(start..start + size) else {
+                warn!("Initramfs: truncated file at {:#x}", offset);
+                break;
+            };
+
+            // regular files only, directories are implied
+            if matches!(header[TYPE_FLAG], b'0' | 0) {
+                let mut path = String::from("/");
+                let prefix = field_str(&header[PREFIX]);
+                if !prefix.is_empty() {
+                    path.push_str(prefix.trim_matches('/'));
+                    path.push('/');
+                }
+                path.push_str(field_str(&header[NAME]).trim_start_matches("./"));
+
+                files.insert(normalize(&path), data);
+            }
+
+            offset = start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        }
+
+        Self { files }
+    }
+
+    /// The content of a file by its absolute path
+    pub fn get(&self, path: &str) -> Option<&'static [u8]> {
+        self.files.get(normalize(path).as_str()).copied()
+    }
+
+    /// All files with their absolute paths
+    pub fn files(&self) -> impl Iterator<Item = (&str, &'static [u8])> {
+        self.files.iter().map(|(path, data)| (path.as_str(), *data))
+    }
+
+    /// The names of the files and directories in a directory
+    pub fn read_dir(&self, dir: &str) -> Vec<&str> {
+        let dir = normalize(dir);
+        let prefix = if dir == "/" { dir } else { dir + "/" };
+
+        let mut names: Vec<&str> = self
+            .files
+            .keys()
+            .filter_map(|path| path.strip_prefix(prefix.as_str()))
+            .map(|rest| rest.split('/').next().unwrap_or(rest))
+            .collect();
+
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Check if a directory is implied by the paths of files
+    pub fn is_dir(&self, dir: &str) -> bool {
+        let dir = normalize(dir);
+        if dir == "/" {
+            return true;
+        }
+
+        let prefix = dir + "/";
+        self.files
+            .range(prefix.clone()..)
+            .next()
+            .is_some_and(|(path, _)| path.starts_with(&prefix))
+    }
+
+    /// The root directory, as an inode of the VFS
+    pub fn root(&'static self) -> Arc<dyn Inode> {
+        Arc::new(InitRamFsInode {
+            fs: self,
+            path: String::from("/"),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// A file or a directory of the initramfs, by its path
+struct InitRamFsInode {
+    fs: &'static InitRamFs,
+    path: String,
+}
+
+impl InitRamFsInode {
+    fn child(&self, name: &str) -> String {
+        let mut path = self.path.clone();
+        if path != "/" {
+            path.push('/');
+        }
+        path.push_str(name);
+        path
+    }
+}
+
+impl Inode for InitRamFsInode {
+    fn metadata(&self) -> Metadata {
+        // paths are unique, so their hashes are inode numbers
+        let ino = fnv1a(self.path.as_bytes());
+
+        match self.fs.get(&self.path) {
+            Some(data) => Metadata {
+                ino,
+                ty: FileType::File,
+                size: data.len() as u64,
+                version: 0,
+            },
+            None => Metadata {
+                ino,
+                ty: FileType::Directory,
+                size: self.fs.read_dir(&self.path).len() as u64,
+                version: 0,
+            },
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let data = self.fs.get(&self.path).ok_or(FsError::IsADirectory)?;
+        let rest = data.get(offset..).unwrap_or_default();
+        let count = rest.len().min(buf.len());
+        buf[..count].copy_from_slice(&rest[..count]);
+        Ok(count)
+    }
+
+    fn static_data(&self) -> Option<&'static [u8]> {
+        self.fs.get(&self.path)
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        if self.fs.get(&self.path).is_some() {
+            return Err(FsError::NotADirectory);
+        }
+
+        let path = self.child(name);
+        if self.fs.get(&path).is_none() && !self.fs.is_dir(&path) {
+            return Err(FsError::NotFound);
+        }
+
+        Ok(Arc::new(Self { fs: self.fs, path }))
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<(String, FileType)>> {
+        if self.fs.get(&self.path).is_some() {
+            return Err(FsError::NotADirectory);
+        }
+
+        Ok(self
+            .fs
+            .read_dir(&self.path)
+            .into_iter()
+            .map(|name| {
+                let ty = match self.fs.get(&self.child(name)) {
+                    Some(_) => FileType::File,
+                    None => FileType::Directory,
+                };
+                (String::from(name), ty)
+            })
+            .collect())
+    }
+}
+
+/// FNV-1a hash of 64 bits
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Check the magic and the checksum of a header,
+/// which is the sum of its bytes with the checksum field as spaces
+fn is_valid(header: &[u8]) -> bool {
+    if &header[MAGIC] != b"ustar" {
+        return false;
+    }
+
+    let sum: usize = header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| {
+            if CHECKSUM.contains(&i) {
+                b' ' as usize
+            } else {
+                byte as usize
+            }
+        })
+        .sum();
+
+    parse_octal(&header[CHECKSUM]) == Some(sum)
+}
+
+/// A NUL terminated field
+fn field_str(field: &[u8]) -> &str {
+    let len = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).unwrap_or("")
+}
+
+/// An octal number padded by spaces or NULs
+fn parse_octal(field: &[u8]) -> Option<usize> {
+    let digits = field_str(field).trim_matches(' ');
+    usize::from_str_radix(digits, 8).ok()
+}
+
+/// Make a path absolute without empty, `.` and trailing components
+fn normalize(path: &str) -> String {
+    let mut normalized = String::new();
+
+    for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+
+    normalized
+}
+
+pub fn init(boot_info: &'static boot::BootInfo) {
+    let Some(image) = boot_info.initramfs else {
+        info!("No initramfs loaded.");
+        return;
+    };
+
+    let initramfs = INITRAMFS.call_once(|| InitRamFs::unpack(image));
+
+    info!(
+        "Initramfs: {} files in {} KiB.",
+        initramfs.len(),
+        image.len() / 1024
+    );
+}
+
+/// The initramfs, if the bootloader loaded one
+pub fn get() -> Option<&'static InitRamFs> {
+    INITRAMFS.get()
+}
@@ -0,0 +1,79 @@
+use alloc::{string::String, sync::Arc, vec::Vec};
+use syscall_def::FileType;
+
+use crate::drivers::block::BlockError;
+
+pub type FsResult<T = ()> = Result<T, FsError>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    /// the filesystem cannot be written
+    ReadOnly,
+    /// the path is empty or has no name to create
+    InvalidPath,
+    /// a name is longer than `NAME_MAX`
+    NameTooLong,
+    /// the offset would be negative
+    InvalidSeek,
+    /// the device failed, or the filesystem on it is corrupted
+    Io,
+    /// no space is left on the device
+    NoSpace,
+}
+
+impl From<BlockError> for FsError {
+    fn from(_: BlockError) -> Self {
+        FsError::Io
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+    /// unique in the filesystem
+    pub ino: u64,
+    pub ty: FileType,
+    /// the size in bytes, the count of entries for a directory
+    pub size: u64,
+    /// changed by every write to the file
+    pub version: u64,
+}
+
+/// A file or a directory in a filesystem
+///
+/// writes fail with `ReadOnly` unless the filesystem implements them.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    /// Read from `offset`, returns the count of bytes read, 0 at the end
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize>;
+
+    /// Write at `offset`, filling a gap after the end with zeros
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&self, _size: usize) -> FsResult {
+        Err(FsError::ReadOnly)
+    }
+
+    /// Find an entry of a directory
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>>;
+
+    /// Create an entry in a directory
+    fn create(&self, _name: &str, _ty: FileType) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::ReadOnly)
+    }
+
+    /// The entries of a directory, without `.` and `..`
+    fn read_dir(&self) -> FsResult<Vec<(String, FileType)>>;
+
+    /// The content of a file kept in memory for the kernel lifetime,
+    /// which is mapped without a copy
+    fn static_data(&self) -> Option<&'static [u8]> {
+        None
+    }
+}
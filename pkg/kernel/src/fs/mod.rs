@@ -0,0 +1,184 @@
+//! Filesystems of the kernel
+//!
+//! the VFS resolves absolute paths through the mount table to the inodes
+//! of the mounted filesystems, and opened files are resources of processes.
+
+mod file;
+mod inode;
+mod mount;
+
+pub mod fat;
+pub mod initramfs;
+pub mod tmpfs;
+
+pub use file::*;
+pub use inode::*;
+pub use mount::*;
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::Mutex;
+use syscall_def::{FileType, OpenFlags};
+
+use crate::drivers::block;
+
+/// Where the boot ESP is mounted
+pub const ESP_PATH: &str = "/esp";
+
+/// Files loaded to be mapped by processes, by path
+static LOADED: Mutex<BTreeMap<String, LoadedFile>> = Mutex::new(BTreeMap::new());
+
+/// A file read into memory, shared by the processes that map it
+struct LoadedFile {
+    ino: u64,
+    version: u64,
+    data: Weak<[u8]>,
+}
+
+/// The content of a file loaded to be mapped by processes
+pub enum FileData {
+    /// kept in memory for the kernel lifetime
+    Static(&'static [u8]),
+    /// read from a filesystem, freed with its last owner
+    Shared(Arc<[u8]>),
+}
+
+pub fn init(boot_info: &'static boot::BootInfo) {
+    initramfs::init(boot_info);
+
+    match initramfs::get() {
+        Some(initramfs) => {
+            mount("/", "initramfs", initramfs.root()).unwrap();
+            mount("/tmp", "tmpfs", tmpfs::TmpFs::new().root()).unwrap();
+        }
+        None => mount("/", "tmpfs", tmpfs::TmpFs::new().root()).unwrap(),
+    }
+
+    match find_fat() {
+        Some(fs) => mount(ESP_PATH, "fat", fs.root()).unwrap(),
+        None => info!("No FAT volume found."),
+    }
+}
+
+/// The first FAT volume of the block devices,
+/// on a whole device or in a partition
+fn find_fat() -> Option<Arc<fat::FatFs>> {
+    for (name, device) in block::devices() {
+        match fat::FatFs::new(device) {
+            Ok(Some(fs)) => {
+                info!(
+                    "{:?} volume of {} MiB on {}.",
+                    fs.fat_type(),
+                    fs.size() / 1024 / 1024,
+                    name
+                );
+                return Some(fs);
+            }
+            Ok(None) => {}
+            Err(err) => warn!("Failed to read {}: {:?}", name, err),
+        }
+    }
+
+    None
+}
+
+/// Open a file or a directory by its absolute path
+///
+/// `CREATE` creates a missing file, or a directory with `DIRECTORY`,
+/// `TRUNCATE` empties an existing file.
+pub fn open(path: &str, flags: OpenFlags) -> FsResult<File> {
+    let dentry = match lookup(path) {
+        Ok(dentry) => dentry,
+        Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => {
+            let ty = if flags.contains(OpenFlags::DIRECTORY) {
+                FileType::Directory
+            } else {
+                FileType::File
+            };
+            create(path, ty)?
+        }
+        Err(err) => return Err(err),
+    };
+
+    let ty = dentry.inode().metadata().ty;
+
+    if flags.contains(OpenFlags::DIRECTORY) && ty != FileType::Directory {
+        return Err(FsError::NotADirectory);
+    }
+
+    if flags.contains(OpenFlags::TRUNCATE) {
+        if ty == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        dentry.inode().truncate(0)?;
+    }
+
+    Ok(File::new(dentry, flags))
+}
+
+/// Read a whole file
+pub fn read(path: &str) -> FsResult<Vec<u8>> {
+    read_inode(lookup(path)?.inode())
+}
+
+fn read_inode(inode: &Arc<dyn Inode>) -> FsResult<Vec<u8>> {
+    let metadata = inode.metadata();
+    if metadata.ty != FileType::File {
+        return Err(FsError::IsADirectory);
+    }
+
+    let mut data = vec![0; metadata.size as usize];
+    let mut offset = 0;
+    while offset < data.len() {
+        match inode.read_at(offset, &mut data[offset..])? {
+            0 => break,
+            count => offset += count,
+        }
+    }
+
+    data.truncate(offset);
+    Ok(data)
+}
+
+/// The content of a file to be mapped by processes
+///
+/// files not in memory are read at once, and the copy is shared by the
+/// processes that map it until the file changes.
+pub fn load(path: &str) -> FsResult<FileData> {
+    let dentry = lookup(path)?;
+    let inode = dentry.inode();
+
+    if let Some(data) = inode.static_data() {
+        return Ok(FileData::Static(data));
+    }
+
+    let metadata = inode.metadata();
+
+    let mut loaded = LOADED.lock();
+    if let Some(file) = loaded.get(dentry.path()) {
+        if file.ino == metadata.ino && file.version == metadata.version {
+            if let Some(data) = file.data.upgrade() {
+                return Ok(FileData::Shared(data));
+            }
+        }
+    }
+
+    let data: Arc<[u8]> = read_inode(inode)?.into();
+
+    // forget the files no process maps anymore
+    loaded.retain(|_, file| file.data.strong_count() > 0);
+    loaded.insert(
+        String::from(dentry.path()),
+        LoadedFile {
+            ino: metadata.ino,
+            version: metadata.version,
+            data: Arc::downgrade(&data),
+        },
+    );
+
+    Ok(FileData::Shared(data))
+}
@@ -0,0 +1,159 @@
+//! Mount table and path resolution
+//!
+//! paths are resolved from the root, `.` and `..` are removed from them
+//! before any lookup, as there are no links. the mount with the longest
+//! path containing a file is the filesystem it is looked up in.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use spin::RwLock;
+use syscall_def::FileType;
+
+use super::*;
+
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+
+struct Mount {
+    path: String,
+    root: Arc<dyn Inode>,
+}
+
+/// A resolved path and its inode
+#[derive(Clone)]
+pub struct Dentry {
+    path: String,
+    inode: Arc<dyn Inode>,
+}
+
+impl Dentry {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    /// The entries of a directory, with the mount points in it
+    pub fn read_dir(&self) -> FsResult<Vec<(String, FileType)>> {
+        let mut entries = self.inode.read_dir()?;
+
+        for mount in MOUNTS.read().iter() {
+            let Some((parent, name)) = split_parent(&mount.path) else {
+                continue;
+            };
+
+            if parent == self.path && entries.iter().all(|(entry, _)| entry != name) {
+                entries.push((String::from(name), FileType::Directory));
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Mount a filesystem of type `fs` by its root at an absolute path,
+/// replacing the one mounted there
+pub fn mount(path: &str, fs: &'static str, root: Arc<dyn Inode>) -> FsResult {
+    let path = normalize(path).ok_or(FsError::InvalidPath)?;
+
+    info!("Mounted {} at {}", fs, path);
+
+    let mut mounts = MOUNTS.write();
+    mounts.retain(|mount| mount.path != path);
+    mounts.push(Mount { path, root });
+    Ok(())
+}
+
+/// Resolve a path to its inode
+pub fn lookup(path: &str) -> FsResult<Dentry> {
+    let path = normalize(path).ok_or(FsError::InvalidPath)?;
+
+    let inode = {
+        let mounts = MOUNTS.read();
+        let mount = mounts
+            .iter()
+            .filter(|mount| is_within(&path, &mount.path))
+            .max_by_key(|mount| mount.path.len())
+            .ok_or(FsError::NotFound)?;
+
+        // the components below the mount point
+        let rest = &path[mount.path.len()..];
+        let mut inode = mount.root.clone();
+        for name in rest.split('/').filter(|name| !name.is_empty()) {
+            inode = inode.lookup(name)?;
+        }
+        inode
+    };
+
+    Ok(Dentry { path, inode })
+}
+
+/// Create a file or a directory, which must not exist
+pub fn create(path: &str, ty: FileType) -> FsResult<Dentry> {
+    let path = normalize(path).ok_or(FsError::InvalidPath)?;
+    let (parent, name) = split_parent(&path).ok_or(FsError::InvalidPath)?;
+
+    if name.len() > syscall_def::NAME_MAX {
+        return Err(FsError::NameTooLong);
+    }
+
+    let parent = lookup(parent)?;
+    if parent.inode.metadata().ty != FileType::Directory {
+        return Err(FsError::NotADirectory);
+    }
+
+    let inode = parent.inode.create(name, ty)?;
+    Ok(Dentry { path, inode })
+}
+
+/// Make a path absolute, without empty, `.` and `..` components
+///
+/// `..` of the root is the root. fails if the path is empty.
+pub fn normalize(path: &str) -> Option<String> {
+    if path.is_empty() {
+        return None;
+    }
+
+    let mut components: Vec<&str> = Vec::new();
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
+        }
+    }
+
+    let mut normalized = String::new();
+    for component in components {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+
+    Some(normalized)
+}
+
+/// Split a normalized path into its parent and its name, `None` for the root
+fn split_parent(path: &str) -> Option<(&str, &str)> {
+    let (parent, name) = path.rsplit_once('/')?;
+    if name.is_empty() {
+        return None;
+    }
+
+    Some((if parent.is_empty() { "/" } else { parent }, name))
+}
+
+/// Check if a normalized path is `dir` or in it
+fn is_within(path: &str, dir: &str) -> bool {
+    dir == "/"
+        || path == dir
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
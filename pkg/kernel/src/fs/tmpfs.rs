@@ -0,0 +1,151 @@
+//! A filesystem in memory
+//!
+//! files are kept in kernel heap, and are lost when nothing refers to them.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::RwLock;
+use syscall_def::FileType;
+
+use super::*;
+
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    pub fn new() -> Self {
+        let next_ino = Arc::new(AtomicU64::new(1));
+        let root = TmpInode::new(FileType::Directory, next_ino.clone());
+
+        Self { root }
+    }
+
+    pub fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+impl Default for TmpFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum TmpData {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, Arc<TmpInode>>),
+}
+
+struct TmpInode {
+    ino: u64,
+    data: RwLock<TmpData>,
+    version: AtomicU64,
+    /// shared by the inodes of the filesystem
+    next_ino: Arc<AtomicU64>,
+}
+
+impl TmpInode {
+    fn new(ty: FileType, next_ino: Arc<AtomicU64>) -> Arc<Self> {
+        let data = match ty {
+            FileType::Directory => TmpData::Dir(BTreeMap::new()),
+            _ => TmpData::File(Vec::new()),
+        };
+
+        Arc::new(Self {
+            ino: next_ino.fetch_add(1, Ordering::Relaxed),
+            data: RwLock::new(data),
+            version: AtomicU64::new(0),
+            next_ino,
+        })
+    }
+}
+
+impl Inode for TmpInode {
+    fn metadata(&self) -> Metadata {
+        let (ty, size) = match &*self.data.read() {
+            TmpData::File(data) => (FileType::File, data.len()),
+            TmpData::Dir(entries) => (FileType::Directory, entries.len()),
+        };
+
+        Metadata {
+            ino: self.ino,
+            ty,
+            size: size as u64,
+            version: self.version.load(Ordering::Relaxed),
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        match &*self.data.read() {
+            TmpData::File(data) => {
+                let rest = data.get(offset..).unwrap_or_default();
+                let count = rest.len().min(buf.len());
+                buf[..count].copy_from_slice(&rest[..count]);
+                Ok(count)
+            }
+            TmpData::Dir(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> FsResult<usize> {
+        match &mut *self.data.write() {
+            TmpData::File(data) => {
+                let end = offset + buf.len();
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[offset..end].copy_from_slice(buf);
+                self.version.fetch_add(1, Ordering::Relaxed);
+                Ok(buf.len())
+            }
+            TmpData::Dir(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn truncate(&self, size: usize) -> FsResult {
+        match &mut *self.data.write() {
+            TmpData::File(data) => {
+                data.resize(size, 0);
+                self.version.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            TmpData::Dir(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        match &*self.data.read() {
+            TmpData::Dir(entries) => match entries.get(name) {
+                Some(inode) => Ok(inode.clone()),
+                None => Err(FsError::NotFound),
+            },
+            TmpData::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn create(&self, name: &str, ty: FileType) -> FsResult<Arc<dyn Inode>> {
+        match &mut *self.data.write() {
+            TmpData::Dir(entries) => {
+                if entries.contains_key(name) {
+                    return Err(FsError::AlreadyExists);
+                }
+
+                let inode = TmpInode::new(ty, self.next_ino.clone());
+                entries.insert(String::from(name), inode.clone());
+                Ok(inode)
+            }
+            TmpData::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn read_dir(&self) -> FsResult<Vec<(String, FileType)>> {
+        match &*self.data.read() {
+            TmpData::Dir(entries) => Ok(entries
+                .iter()
+                .map(|(name, inode)| (name.clone(), inode.metadata().ty))
+                .collect()),
+            TmpData::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+}
@@ -0,0 +1,527 @@
+//! GDB remote stub on COM2
+//!
+//! The stub debugs one user process at a time. A process stops when it
+//! hits a software breakpoint or finishes a single step, or when gdb sends
+//! an interrupt (Ctrl-C) while it is running. The first process to stop
+//! becomes the target until gdb detaches, kills it or it exits.
+//!
+//! While a process is stopped, the stub serves gdb with interrupts disabled:
+//! registers are read and written in the saved [`ProcessContext`], and memory
+//! through the page table of the process. breakpoints patch the code with
+//! `int3`, and are seen by all processes sharing the page table.
+//!
+//! Run with `ysos.py --gdb-stub <addr:port>` to connect COM2 to a TCP port,
+//! then `target remote <addr:port>` in gdb with the app loaded. the load
+//! bias of position independent apps is reported through `qOffsets`.
+
+mod packet;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use spin::{Mutex, Once};
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+
+use self::packet::*;
+use crate::drivers::SerialPort;
+use crate::proc::{self, ProcessContext, ProcessContextValue, ProcessId};
+use crate::RegistersValue;
+
+const GDB_IO_PORT: u16 = 0x2F8; // COM2
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const INT3: u8 = 0xCC;
+
+/// Registers of the `g` packet, in the order of x86-64 gdb up to `gs`.
+/// the floating point registers after them are reported as unavailable.
+const REGISTER_COUNT: usize = 24;
+
+/// The max bytes of a memory read, the reply is in hex
+const MAX_MEMORY_READ: usize = 0x7f0;
+
+static STUB: Once<Mutex<GdbStub>> = Once::new();
+
+/// The cause of a trap in a user process
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trap {
+    Breakpoint,
+    Step,
+}
+
+enum Resume {
+    Run,
+    Kill,
+}
+
+struct GdbStub {
+    conn: Connection,
+    /// the process being debugged
+    target: Option<ProcessId>,
+    /// inserted breakpoints of the target and the bytes they replaced
+    breakpoints: BTreeMap<u64, u8>,
+}
+
+pub fn init() {
+    let mut port = SerialPort::new(GDB_IO_PORT);
+
+    if !port.probe() {
+        debug!("COM2 not found, GDB stub disabled.");
+        return;
+    }
+
+    port.init();
+
+    STUB.call_once(|| {
+        Mutex::new(GdbStub {
+            conn: Connection::new(port),
+            target: None,
+            breakpoints: BTreeMap::new(),
+        })
+    });
+
+    info!("GDB stub listening on COM2.");
+}
+
+/// Stop the current user process on a trap
+///
+/// returns false if the stub is disabled or debugging another process.
+pub fn handle_trap(context: &mut ProcessContext, trap: Trap) -> bool {
+    let stub = match STUB.get() {
+        Some(stub) if context.is_user_mode() => stub,
+        _ => return false,
+    };
+
+    let pid = proc::current_pid();
+
+    let resume = {
+        let mut stub = stub.lock();
+
+        if stub.target.is_some_and(|target| target != pid) {
+            return false;
+        }
+
+        let mut value = read_context(context);
+        let rip = value.stack_frame.instruction_pointer.as_u64();
+
+        match trap {
+            // report the address of an inserted `int3`, as gdb expects
+            Trap::Breakpoint if stub.breakpoints.contains_key(&rip.wrapping_sub(1)) => {
+                value.stack_frame.instruction_pointer = VirtAddr::new(rip - 1);
+            }
+            Trap::Breakpoint => {}
+            Trap::Step => value.stack_frame.cpu_flags.remove(RFlags::TRAP_FLAG),
+        }
+
+        write_context(context, value);
+        stub.stop(pid, context, SIGTRAP)
+    };
+
+    if let Resume::Kill = resume {
+        proc::kill(pid, context);
+    }
+
+    true
+}
+
+/// Stop the current user process if gdb sent an interrupt,
+/// called on clock interrupts
+pub fn poll(context: &mut ProcessContext) {
+    let stub = match STUB.get() {
+        Some(stub) if context.is_user_mode() => stub,
+        _ => return,
+    };
+
+    let pid = proc::current_pid();
+
+    let resume = {
+        let mut stub = stub.lock();
+
+        // the byte is kept until the target runs
+        if stub.target.is_some_and(|target| target != pid) || !stub.conn.poll() {
+            return;
+        }
+
+        stub.stop(pid, context, SIGINT)
+    };
+
+    if let Resume::Kill = resume {
+        proc::kill(pid, context);
+    }
+}
+
+/// Tell gdb the target exited, called when a process is killed
+pub fn process_exited(pid: ProcessId, ret: isize) {
+    let Some(stub) = STUB.get() else {
+        return;
+    };
+
+    let mut stub = stub.lock();
+
+    if stub.target != Some(pid) {
+        return;
+    }
+
+    stub.target = None;
+    stub.breakpoints.clear();
+    stub.conn
+        .write_packet(format!("W{:02x}", ret as u8).as_bytes());
+
+    info!("GDB: process #{} exited", pid);
+}
+
+impl GdbStub {
+    /// Serve gdb until it resumes the stopped process
+    fn stop(&mut self, pid: ProcessId, context: &mut ProcessContext, signal: u8) -> Resume {
+        if self.target == Some(pid) {
+            // gdb is waiting for the process to stop
+            self.conn.write_packet(&stop_reply(pid, signal));
+        } else {
+            info!("GDB: process #{} stopped, waiting for gdb", pid);
+            self.target = Some(pid);
+        }
+
+        loop {
+            let packet = self.conn.read_packet();
+            let (&cmd, args) = match packet.split_first() {
+                Some(split) => split,
+                None => continue,
+            };
+
+            let reply = match cmd {
+                b'?' => stop_reply(pid, signal),
+                b'g' => read_registers(context),
+                b'G' => ok_or(write_registers(context, args), b"E01"),
+                b'p' => read_register_packet(context, args).unwrap_or_else(|| b"E01".to_vec()),
+                b'P' => ok_or(write_register_packet(context, args), b"E01"),
+                b'm' => self.read_memory(args).unwrap_or_else(|| b"E14".to_vec()),
+                b'M' => ok_or(self.write_memory(args).is_some(), b"E14"),
+                b'Z' | b'z' => self.breakpoint(cmd == b'Z', args),
+                b'c' | b's' => {
+                    if self.resume(context, args, cmd == b's') {
+                        return Resume::Run;
+                    }
+                    b"E01".to_vec()
+                }
+                b'D' => {
+                    self.conn.write_packet(b"OK");
+                    self.detach(context);
+                    return Resume::Run;
+                }
+                b'k' => {
+                    self.detach(context);
+                    return Resume::Kill;
+                }
+                b'v' if args.starts_with(b"Kill") => {
+                    self.conn.write_packet(b"OK");
+                    self.detach(context);
+                    return Resume::Kill;
+                }
+                b'q' => query(pid, args),
+                b'H' | b'T' => b"OK".to_vec(),
+                _ => Vec::new(),
+            };
+
+            self.conn.write_packet(&reply);
+        }
+    }
+
+    /// `c [addr]` or `s [addr]`, resume at `addr` if given
+    fn resume(&mut self, context: &mut ProcessContext, args: &[u8], step: bool) -> bool {
+        let mut value = read_context(context);
+
+        if !args.is_empty() {
+            match parse_hex(args).and_then(|addr| VirtAddr::try_new(addr).ok()) {
+                Some(addr) => value.stack_frame.instruction_pointer = addr,
+                None => return false,
+            }
+        }
+
+        value.stack_frame.cpu_flags.set(RFlags::TRAP_FLAG, step);
+        write_context(context, value);
+        true
+    }
+
+    /// Remove the breakpoints and let the target run freely
+    fn detach(&mut self, context: &mut ProcessContext) {
+        for (addr, byte) in core::mem::take(&mut self.breakpoints) {
+            proc::write_memory(addr, &[byte]);
+        }
+
+        let mut value = read_context(context);
+        value.stack_frame.cpu_flags.remove(RFlags::TRAP_FLAG);
+        write_context(context, value);
+
+        if let Some(pid) = self.target.take() {
+            info!("GDB: detached from process #{}", pid);
+        }
+    }
+
+    /// `m addr,len`
+    fn read_memory(&self, args: &[u8]) -> Option<Vec<u8>> {
+        let (addr, len) = parse_pair(args, b',')?;
+
+        let mut buf = vec![0u8; (len as usize).min(MAX_MEMORY_READ)];
+        if !proc::read_memory(addr, &mut buf) {
+            return None;
+        }
+
+        let mut reply = Vec::with_capacity(buf.len() * 2);
+        encode_hex(&mut reply, &buf);
+        Some(reply)
+    }
+
+    /// `M addr,len:data`
+    fn write_memory(&self, args: &[u8]) -> Option<()> {
+        let (range, data) = split_at_byte(args, b':')?;
+        let (addr, len) = parse_pair(range, b',')?;
+        let data = decode_hex(data)?;
+
+        if data.len() as u64 != len {
+            return None;
+        }
+
+        proc::write_memory(addr, &data).then_some(())
+    }
+
+    /// `Z0,addr,kind` or `z0,addr,kind`, only software breakpoints
+    fn breakpoint(&mut self, insert: bool, args: &[u8]) -> Vec<u8> {
+        let addr = match args
+            .strip_prefix(b"0,")
+            .and_then(|args| split_at_byte(args, b','))
+            .and_then(|(addr, _)| parse_hex(addr))
+        {
+            Some(addr) => addr,
+            // other kinds are not supported
+            None => return Vec::new(),
+        };
+
+        let done = if insert {
+            self.breakpoints.contains_key(&addr) || {
+                let mut byte = [0u8];
+                let done = proc::read_memory(addr, &mut byte) && proc::write_memory(addr, &[INT3]);
+                if done {
+                    self.breakpoints.insert(addr, byte[0]);
+                }
+                done
+            }
+        } else {
+            match self.breakpoints.remove(&addr) {
+                Some(byte) => proc::write_memory(addr, &[byte]),
+                None => true,
+            }
+        };
+
+        ok_or(done, b"E14")
+    }
+}
+
+/// Replies to `q` packets
+fn query(pid: ProcessId, args: &[u8]) -> Vec<u8> {
+    if args.starts_with(b"Supported") {
+        b"PacketSize=1000".to_vec()
+    } else if args == b"Attached" {
+        b"1".to_vec()
+    } else if args == b"C" {
+        format!("QC{:x}", pid.0).into_bytes()
+    } else if args == b"fThreadInfo" {
+        format!("m{:x}", pid.0).into_bytes()
+    } else if args == b"sThreadInfo" {
+        b"l".to_vec()
+    } else if args == b"Offsets" {
+        let bias = proc::load_bias();
+        format!("Text={:x};Data={:x};Bss={:x}", bias, bias, bias).into_bytes()
+    } else {
+        Vec::new()
+    }
+}
+
+fn stop_reply(pid: ProcessId, signal: u8) -> Vec<u8> {
+    format!("T{:02x}thread:{:x};", signal, pid.0).into_bytes()
+}
+
+fn ok_or(ok: bool, err: &[u8]) -> Vec<u8> {
+    if ok {
+        b"OK".to_vec()
+    } else {
+        err.to_vec()
+    }
+}
+
+fn split_at_byte(data: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let idx = data.iter().position(|&byte| byte == sep)?;
+    Some((&data[..idx], &data[idx + 1..]))
+}
+
+fn parse_pair(data: &[u8], sep: u8) -> Option<(u64, u64)> {
+    let (a, b) = split_at_byte(data, sep)?;
+    Some((parse_hex(a)?, parse_hex(b)?))
+}
+
+fn read_context(context: &ProcessContext) -> ProcessContextValue {
+    context.as_ref().as_ptr().read()
+}
+
+fn write_context(context: &mut ProcessContext, value: ProcessContextValue) {
+    context.as_mut().as_mut_ptr().write(value);
+}
+
+fn register_size(n: usize) -> usize {
+    if n <= 16 {
+        8
+    } else {
+        4
+    }
+}
+
+/// The general purpose registers in the order of gdb, except `rsp` (7)
+fn general_register(regs: &mut RegistersValue, n: usize) -> Option<&mut usize> {
+    Some(match n {
+        0 => &mut regs.rax,
+        1 => &mut regs.rbx,
+        2 => &mut regs.rcx,
+        3 => &mut regs.rdx,
+        4 => &mut regs.rsi,
+        5 => &mut regs.rdi,
+        6 => &mut regs.rbp,
+        8 => &mut regs.r8,
+        9 => &mut regs.r9,
+        10 => &mut regs.r10,
+        11 => &mut regs.r11,
+        12 => &mut regs.r12,
+        13 => &mut regs.r13,
+        14 => &mut regs.r14,
+        15 => &mut regs.r15,
+        _ => return None,
+    })
+}
+
+fn get_register(value: &mut ProcessContextValue, n: usize) -> u64 {
+    if let Some(reg) = general_register(&mut value.regs, n) {
+        return *reg as u64;
+    }
+
+    let frame = &value.stack_frame;
+
+    match n {
+        7 => frame.stack_pointer.as_u64(),
+        16 => frame.instruction_pointer.as_u64(),
+        17 => frame.cpu_flags.bits(),
+        18 => frame.code_segment.0 as u64,
+        19 => frame.stack_segment.0 as u64,
+        // ds, es, fs and gs are not saved
+        _ => 0,
+    }
+}
+
+/// Set a register, segment registers and kernel flags are not changed
+fn set_register(value: &mut ProcessContextValue, n: usize, reg: u64) -> bool {
+    if let Some(general) = general_register(&mut value.regs, n) {
+        *general = reg as usize;
+        return true;
+    }
+
+    match n {
+        7 | 16 => {
+            let Ok(addr) = VirtAddr::try_new(reg) else {
+                return false;
+            };
+
+            if n == 7 {
+                value.stack_frame.stack_pointer = addr;
+            } else {
+                value.stack_frame.instruction_pointer = addr;
+            }
+        }
+        17 => value.set_user_flags(reg),
+        _ => {}
+    }
+
+    true
+}
+
+/// `g`, registers as little endian hex
+fn read_registers(context: &ProcessContext) -> Vec<u8> {
+    let mut value = read_context(context);
+    let mut reply = Vec::new();
+
+    for n in 0..REGISTER_COUNT {
+        let reg = get_register(&mut value, n).to_le_bytes();
+        encode_hex(&mut reply, &reg[..register_size(n)]);
+    }
+
+    reply
+}
+
+/// `G data`, gdb may send more registers than the stub reports
+fn write_registers(context: &mut ProcessContext, args: &[u8]) -> bool {
+    let Some(data) = decode_hex(args) else {
+        return false;
+    };
+
+    let mut value = read_context(context);
+    let mut offset = 0;
+
+    for n in 0..REGISTER_COUNT {
+        let size = register_size(n);
+        let Some(bytes) = data.get(offset..offset + size) else {
+            break;
+        };
+
+        if !set_register(&mut value, n, le_bytes(bytes)) {
+            return false;
+        }
+
+        offset += size;
+    }
+
+    write_context(context, value);
+    true
+}
+
+/// `p n`
+fn read_register_packet(context: &ProcessContext, args: &[u8]) -> Option<Vec<u8>> {
+    let n = parse_hex(args)? as usize;
+
+    if n >= REGISTER_COUNT {
+        return None;
+    }
+
+    let reg = get_register(&mut read_context(context), n).to_le_bytes();
+
+    let mut reply = Vec::new();
+    encode_hex(&mut reply, &reg[..register_size(n)]);
+    Some(reply)
+}
+
+/// `P n=value`
+fn write_register_packet(context: &mut ProcessContext, args: &[u8]) -> bool {
+    let Some((n, data)) = split_at_byte(args, b'=') else {
+        return false;
+    };
+
+    let (Some(n), Some(data)) = (parse_hex(n), decode_hex(data)) else {
+        return false;
+    };
+
+    let n = n as usize;
+    if n >= REGISTER_COUNT || data.len() != register_size(n) {
+        return false;
+    }
+
+    let mut value = read_context(context);
+
+    if !set_register(&mut value, n, le_bytes(&data)) {
+        return false;
+    }
+
+    write_context(context, value);
+    true
+}
+
+fn le_bytes(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0u64, |value, &byte| value << 8 | byte as u64)
+}
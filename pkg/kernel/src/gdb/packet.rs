@@ -0,0 +1,143 @@
+//! Packets of the GDB remote serial protocol
+//!
+//! a packet is `$<data>#<checksum>`, where the checksum is the sum of the
+//! data bytes modulo 256 as two hex digits. the receiver acknowledges a
+//! packet with `+`, or asks to resend it with `-`.
+
+use alloc::vec::Vec;
+
+use crate::drivers::SerialPort;
+
+pub struct Connection {
+    port: SerialPort,
+    /// a byte taken by `poll`, read again by the next `read_byte`
+    pending: Option<u8>,
+}
+
+impl Connection {
+    pub fn new(port: SerialPort) -> Self {
+        Self {
+            port,
+            pending: None,
+        }
+    }
+
+    /// Check if gdb sent anything other than acknowledgements,
+    /// an interrupt (0x03) or the first packet of a connection
+    ///
+    /// the byte is kept for the next packet read.
+    pub fn poll(&mut self) -> bool {
+        if self.pending.is_none() {
+            self.pending = self.port.receive();
+        }
+
+        match self.pending {
+            Some(b'+' | b'-') => {
+                self.pending = None;
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        if let Some(byte) = self.pending.take() {
+            return byte;
+        }
+
+        loop {
+            if let Some(byte) = self.port.receive() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Wait for a packet with a valid checksum and acknowledge it
+    pub fn read_packet(&mut self) -> Vec<u8> {
+        loop {
+            // acknowledgements and interrupts between packets are ignored
+            while self.read_byte() != b'$' {}
+
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+
+            loop {
+                match self.read_byte() {
+                    b'#' => break,
+                    byte => {
+                        sum = sum.wrapping_add(byte);
+                        data.push(byte);
+                    }
+                }
+            }
+
+            let checksum = [self.read_byte(), self.read_byte()];
+
+            if parse_hex(&checksum) == Some(sum as u64) {
+                self.port.send(b'+');
+                return data;
+            }
+
+            self.port.send(b'-');
+        }
+    }
+
+    /// Send a packet until gdb acknowledges it
+    pub fn write_packet(&mut self, data: &[u8]) {
+        let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+        loop {
+            self.port.send(b'$');
+            for &byte in data {
+                self.port.send(byte);
+            }
+            self.port.send(b'#');
+            self.port.send(HEX_DIGITS[(sum >> 4) as usize]);
+            self.port.send(HEX_DIGITS[(sum & 0xf) as usize]);
+
+            match self.read_byte() {
+                b'+' => return,
+                b'-' => continue,
+                // gdb sent the next packet without acknowledging
+                byte => {
+                    self.pending = Some(byte);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Parse a big endian hex number
+pub fn parse_hex(data: &[u8]) -> Option<u64> {
+    if data.is_empty() || data.len() > 16 {
+        return None;
+    }
+
+    data.iter().try_fold(0u64, |value, &byte| {
+        Some(value << 4 | (byte as char).to_digit(16)? as u64)
+    })
+}
+
+/// Decode hex pairs into bytes
+pub fn decode_hex(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() % 2 != 0 {
+        return None;
+    }
+
+    data.chunks(2)
+        .map(|pair| parse_hex(pair).map(|byte| byte as u8))
+        .collect()
+}
+
+/// Encode bytes as hex pairs
+pub fn encode_hex(out: &mut Vec<u8>, data: &[u8]) {
+    for byte in data {
+        out.push(HEX_DIGITS[(byte >> 4) as usize]);
+        out.push(HEX_DIGITS[(byte & 0xf) as usize]);
+    }
+}
@@ -25,6 +25,27 @@ impl XApic {
     pub unsafe fn new(addr: u64) -> Self {
         XApic { addr }
     }
+
+    /// Make the timer tick `hz` times a second,
+    /// with the bus frequency calibrated against the TSC
+    pub fn set_timer_frequency(&mut self, hz: u32) {
+        const CALIBRATION_MS: u64 = 10;
+
+        unsafe {
+            // count down once, without interrupts
+            self.write(TIMER, MASKED);
+            self.write(TICR, u32::MAX);
+
+            let start = crate::clock::uptime();
+            while (crate::clock::uptime() - start).as_millis() < CALIBRATION_MS as u128 {}
+
+            let ticks = (u32::MAX - self.read(TCCR)) as u64 * (1000 / CALIBRATION_MS);
+            let count = (ticks / hz as u64).clamp(1, u32::MAX as u64) as u32;
+
+            self.write(TIMER, PERIODIC | (T_IRQ0 + IRQ_TIMER));
+            self.write(TICR, count);
+        }
+    }
 }
 
 impl LocalApic for XApic {
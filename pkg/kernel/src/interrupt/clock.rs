@@ -9,6 +9,7 @@ pub unsafe fn reg_idt(idt: &mut InterruptDescriptorTable) {
 }
 
 pub extern "C" fn clock(mut context: ProcessContext) {
+    crate::gdb::poll(&mut context);
     crate::proc::switch(&mut context);
     super::ack(consts::Interrupts::IrqBase as u8);
 }
@@ -1,4 +1,8 @@
+use crate::backtrace;
+use crate::gdb::Trap;
 use crate::memory::*;
+use crate::proc::ProcessContext;
+use syscall_def::PtraceStop;
 use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
@@ -35,36 +39,116 @@ pub unsafe fn reg_idt(idt: &mut InterruptDescriptorTable) {
         .set_handler_fn(simd_floating_point_handler);
 }
 
+/// The faulting instruction, symbolised
+fn describe(stack_frame: &InterruptStackFrame) -> alloc::string::String {
+    backtrace::describe(stack_frame.instruction_pointer.as_u64())
+}
+
 pub extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: DIVIDE ERROR\n\n{:#?}", stack_frame);
+    panic!(
+        "EXCEPTION: DIVIDE ERROR at {}\n\n{:#?}",
+        describe(&stack_frame),
+        stack_frame
+    );
 }
 
-pub extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: DEBUG\n\n{:#?}", stack_frame);
+pub extern "C" fn debug(mut context: ProcessContext) {
+    // a tracee is resumed by its tracer with the trap flag set as asked
+    if crate::proc::trace_trap(PtraceStop::Step, &mut context) {
+        return;
+    }
+
+    if crate::gdb::handle_trap(&mut context, Trap::Step) {
+        return;
+    }
+
+    if context.is_user_mode() {
+        kill_on_trap("DEBUG", &mut context);
+        return;
+    }
+
+    panic!(
+        "EXCEPTION: DEBUG at {}\n\n{:#?}",
+        backtrace::describe(context.stack_frame.instruction_pointer.as_u64()),
+        context
+    );
 }
 
+as_handler!(debug);
+
 pub extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: NMI\n\n{:#?}", stack_frame);
+    panic!(
+        "EXCEPTION: NMI at {}\n\n{:#?}",
+        describe(&stack_frame),
+        stack_frame
+    );
 }
 
-pub extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: BREAKPOINT\n\n{:#?}", stack_frame);
+pub extern "C" fn breakpoint(mut context: ProcessContext) {
+    if crate::proc::trace_trap(PtraceStop::Breakpoint, &mut context) {
+        return;
+    }
+
+    if crate::gdb::handle_trap(&mut context, Trap::Breakpoint) {
+        return;
+    }
+
+    if context.is_user_mode() {
+        kill_on_trap("BREAKPOINT", &mut context);
+        return;
+    }
+
+    panic!(
+        "EXCEPTION: BREAKPOINT at {}\n\n{:#?}",
+        backtrace::describe(context.stack_frame.instruction_pointer.as_u64()),
+        context
+    );
+}
+
+as_handler!(breakpoint);
+
+/// A trap in a user process not handled by the gdb stub, kill the process
+fn kill_on_trap(name: &str, context: &mut ProcessContext) {
+    let pid = crate::proc::current_pid();
+    warn!(
+        "EXCEPTION: {} in pid {} at {:#x}, no debugger attached",
+        name,
+        pid,
+        context.stack_frame.instruction_pointer
+    );
+    crate::proc::kill(pid, context);
 }
 
 pub extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: OVERFLOW\n\n{:#?}", stack_frame);
+    panic!(
+        "EXCEPTION: OVERFLOW at {}\n\n{:#?}",
+        describe(&stack_frame),
+        stack_frame
+    );
 }
 
 pub extern "x86-interrupt" fn bound_range_exceeded_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: BOUND RANGE EXCEEDED\n\n{:#?}", stack_frame);
+    panic!(
+        "EXCEPTION: BOUND RANGE EXCEEDED at {}\n\n{:#?}",
+        describe(&stack_frame),
+        stack_frame
+    );
 }
 
 pub extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: INVALID OPCODE\n\n{:#?}", stack_frame);
+    panic!(
+        "EXCEPTION: INVALID OPCODE at {}\n\n{:#?}",
+        describe(&stack_frame),
+        stack_frame
+    );
 }
 
 pub extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: DEVICE NOT AVAILABLE\n\n{:#?}", stack_frame);
+    panic!(
+        "EXCEPTION: DEVICE NOT AVAILABLE at {}\n\n{:#?}",
+        describe(&stack_frame),
+        stack_frame
+    );
 }
 
 pub extern "x86-interrupt" fn double_fault_handler(
@@ -72,8 +156,10 @@ pub extern "x86-interrupt" fn double_fault_handler(
     error_code: u64,
 ) -> ! {
     panic!(
-        "EXCEPTION: DOUBLE FAULT, ERROR_CODE: 0x{:016x}\n\n{:#?}",
-        error_code, stack_frame
+        "EXCEPTION: DOUBLE FAULT at {}, ERROR_CODE: 0x{:016x}\n\n{:#?}",
+        describe(&stack_frame),
+        error_code,
+        stack_frame
     );
 }
 
@@ -82,8 +168,10 @@ pub extern "x86-interrupt" fn invalid_tss_handler(
     error_code: u64,
 ) {
     panic!(
-        "EXCEPTION: INVALID TSS, ERROR_CODE: 0x{:016x}\n\n{:#?}",
-        error_code, stack_frame
+        "EXCEPTION: INVALID TSS at {}, ERROR_CODE: 0x{:016x}\n\n{:#?}",
+        describe(&stack_frame),
+        error_code,
+        stack_frame
     );
 }
 
@@ -92,8 +180,10 @@ pub extern "x86-interrupt" fn segment_not_present_handler(
     error_code: u64,
 ) {
     panic!(
-        "EXCEPTION: SEGMENT NOT PRESENT, ERROR_CODE: 0x{:016x}\n\n{:#?}",
-        error_code, stack_frame
+        "EXCEPTION: SEGMENT NOT PRESENT at {}, ERROR_CODE: 0x{:016x}\n\n{:#?}",
+        describe(&stack_frame),
+        error_code,
+        stack_frame
     );
 }
 
@@ -102,8 +192,10 @@ pub extern "x86-interrupt" fn stack_segment_fault_handler(
     error_code: u64,
 ) {
     panic!(
-        "EXCEPTION: STACK SEGMENT FAULT, ERROR_CODE: 0x{:016x}\n\n{:#?}",
-        error_code, stack_frame
+        "EXCEPTION: STACK SEGMENT FAULT at {}, ERROR_CODE: 0x{:016x}\n\n{:#?}",
+        describe(&stack_frame),
+        error_code,
+        stack_frame
     );
 }
 
@@ -112,8 +204,10 @@ pub extern "x86-interrupt" fn general_protection_fault_handler(
     error_code: u64,
 ) {
     panic!(
-        "EXCEPTION: GENERAL PROTECTION FAULT, ERROR_CODE: 0x{:016x}\n\n{:#?}",
-        error_code, stack_frame
+        "EXCEPTION: GENERAL PROTECTION FAULT at {}, ERROR_CODE: 0x{:016x}\n\n{:#?}",
+        describe(&stack_frame),
+        error_code,
+        stack_frame
     );
 }
 
@@ -122,31 +216,89 @@ pub extern "x86-interrupt" fn alignment_check_handler(
     error_code: u64,
 ) {
     panic!(
-        "EXCEPTION: ALIGNMENT CHECK, ERROR_CODE: 0x{:016x}\n\n{:#?}",
-        error_code, stack_frame
+        "EXCEPTION: ALIGNMENT CHECK at {}, ERROR_CODE: 0x{:016x}\n\n{:#?}",
+        describe(&stack_frame),
+        error_code,
+        stack_frame
     );
 }
 
 pub extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
-    panic!("EXCEPTION: MACHINE CHECK\n\n{:#?}", stack_frame);
+    panic!(
+        "EXCEPTION: MACHINE CHECK at {}\n\n{:#?}",
+        describe(&stack_frame),
+        stack_frame
+    );
 }
 
 pub extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: SIMD FLOATING POINT\n\n{:#?}", stack_frame);
+    panic!(
+        "EXCEPTION: SIMD FLOATING POINT at {}\n\n{:#?}",
+        describe(&stack_frame),
+        stack_frame
+    );
 }
 
-pub extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    err_code: PageFaultErrorCode,
-) {
+pub extern "C" fn page_fault(err_code: u64, mut context: ProcessContext) {
     let addr = Cr2::read().unwrap();
+    let err_code = PageFaultErrorCode::from_bits_truncate(err_code);
+
+    if crate::proc::handle_page_fault(addr, err_code) {
+        return;
+    }
+
+    // a user process cannot take down the kernel, just kill it
+    if err_code.contains(PageFaultErrorCode::USER_MODE) {
+        crate::proc::kill_on_page_fault(addr, err_code, &mut context);
+        return;
+    }
 
-    if !crate::proc::handle_page_fault(addr, err_code) {
-        warn!(
-            "EXCEPTION: PAGE FAULT, ERROR_CODE: {:?}\n\nTrying to access: {:#x}\n{:#?}",
-            err_code, addr, stack_frame
+    // a bad pointer passed to copy_from_user/copy_to_user
+    if uaccess::is_user_access(addr) {
+        uaccess::deny_user_access();
+        crate::proc::kill_on_page_fault(addr, err_code, &mut context);
+        return;
+    }
+
+    let rip = backtrace::describe(context.stack_frame.instruction_pointer.as_u64());
+
+    if uaccess::is_enabled()
+        && addr.as_u64() < uaccess::USER_SPACE_END
+        && err_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+    {
+        let (name, action) = if err_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            ("SMEP", "execute")
+        } else {
+            ("SMAP", "access")
+        };
+
+        panic!(
+            "EXCEPTION: {} VIOLATION\n\nKernel at {} trying to {} user memory: {:#x}\n{:#?}",
+            name, rip, action, addr, context
+        );
+    }
+
+    if let Some(name) = gdt::guard_page_of(addr) {
+        panic!(
+            "EXCEPTION: {} OVERFLOW at {}\n\nTrying to access guard page: {:#x}\n{:#?}",
+            name, rip, addr, context
         );
-        crate::proc::current_proc_info();
-        panic!("Failed to handle page fault.");
     }
+
+    warn!(
+        "EXCEPTION: PAGE FAULT at {}, ERROR_CODE: {:?}\n\nTrying to access: {:#x}\n{:#?}",
+        rip, err_code, addr, context
+    );
+    crate::proc::current_proc_info();
+
+    if crate::proc::is_stack_overflow(addr) {
+        panic!(
+            "Kernel stack overflow in pid {}.",
+            crate::proc::current_pid()
+        );
+    }
+
+    panic!("Failed to handle page fault.");
 }
+
+as_handler!(page_fault, PageFaultErrorCode);
@@ -0,0 +1,24 @@
+use super::consts;
+use crate::drivers::ata;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+pub unsafe fn reg_idt(idt: &mut InterruptDescriptorTable) {
+    idt[consts::Interrupts::IrqBase as u8 + consts::Irq::Ide0 as u8].set_handler_fn(ide0_handler);
+    idt[consts::Interrupts::IrqBase as u8 + consts::Irq::Ide1 as u8].set_handler_fn(ide1_handler);
+}
+
+pub fn init() {
+    super::enable_irq(consts::Irq::Ide0 as u8, 0);
+    super::enable_irq(consts::Irq::Ide1 as u8, 0);
+    debug!("Ide0 and Ide1 IRQs enabled.");
+}
+
+pub extern "x86-interrupt" fn ide0_handler(_st: InterruptStackFrame) {
+    super::ack(consts::Irq::Ide0 as u8);
+    ata::receive_irq(0);
+}
+
+pub extern "x86-interrupt" fn ide1_handler(_st: InterruptStackFrame) {
+    super::ack(consts::Irq::Ide1 as u8);
+    ata::receive_irq(1);
+}
@@ -2,6 +2,7 @@ mod apic;
 mod clock;
 mod consts;
 mod exception;
+mod ide;
 mod serial;
 mod syscall;
 
@@ -17,6 +18,7 @@ lazy_static! {
         unsafe {
             exception::reg_idt(&mut idt);
             serial::reg_idt(&mut idt);
+            ide::reg_idt(&mut idt);
             clock::reg_idt(&mut idt);
             syscall::reg_idt(&mut idt);
         }
@@ -30,7 +32,12 @@ pub fn init() {
     debug!("XApic support = {}.", apic::XApic::support());
     let mut lapic = unsafe { XApic::new(physical_to_virtual(LAPIC_ADDR)) };
     lapic.cpu_init();
+    if let Some(hz) = crate::cmdline::get().timer_hz {
+        lapic.set_timer_frequency(hz);
+        info!("Timer Frequency: {} Hz", hz);
+    }
     serial::init();
+    ide::init();
 
     info!("Interrupts Initialized.");
 }
@@ -1,6 +1,7 @@
 use crate::{memory::gdt, proc::*};
 use alloc::format;
 use syscall_def::Syscall;
+use x86::time::rdtsc;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 
 mod service;
@@ -15,8 +16,16 @@ pub unsafe fn reg_idt(idt: &mut InterruptDescriptorTable) {
 }
 
 pub extern "C" fn syscall(mut context: ProcessContext) {
+    crate::memory::uaccess::deny_user_access();
+
     x86_64::instructions::interrupts::without_interrupts(|| {
+        if trace_syscall_entry(&mut context) {
+            return;
+        }
+
+        let pid = current_pid();
         super::syscall::dispatcher(&mut context);
+        trace_syscall_exit(pid, &mut context);
     });
 }
 
@@ -28,6 +37,7 @@ pub struct SyscallArgs {
     pub arg0: usize,
     pub arg1: usize,
     pub arg2: usize,
+    pub arg3: usize,
 }
 
 pub fn dispatcher(context: &mut ProcessContext) {
@@ -36,8 +46,18 @@ pub fn dispatcher(context: &mut ProcessContext) {
         context.regs.rdi,
         context.regs.rsi,
         context.regs.rdx,
+        context.regs.r10,
     );
 
+    let pid = current_pid();
+    let traced = strace_enabled();
+
+    if traced {
+        debug!("#{} {}", pid, args);
+    }
+
+    let start = unsafe { rdtsc() };
+
     match args.syscall {
         Syscall::Brk => context.set_rax(sys_brk(&args)),
         // op: u8, key: u32, val: usize -> ret: any
@@ -48,14 +68,38 @@ pub fn dispatcher(context: &mut ProcessContext) {
         Syscall::Read => context.set_rax(sys_read(&args)),
         // fd: arg0 as u8, buf: &[u8] (arg1 as *const u8, arg2 as len)
         Syscall::Write => context.set_rax(sys_write(&args)),
+        // path: &str (arg0 as *const u8, arg1 as len), flags: arg2 as OpenFlags -> fd: u8 or -1
+        Syscall::Open => context.set_rax(sys_open(&args)),
+        // fd: arg0 as u8 -> 0 or 1
+        Syscall::Close => context.set_rax(sys_close(&args)),
+        // fd: arg0 as u8, offset: arg1 as isize, whence: arg2 as SeekWhence -> offset or -1
+        Syscall::Lseek => context.set_rax(sys_lseek(&args)),
+        // fd: arg0 as u8, stat: arg1 as *mut FileStat -> 0 or 1
+        Syscall::Fstat => context.set_rax(sys_fstat(&args)),
+        // fd: arg0 as u8, entry: arg1 as *mut DirEntry -> 1, 0 at the end or -1
+        Syscall::ReadDir => context.set_rax(sys_read_dir(&args)),
         // None -> pid: u16
         Syscall::GetPid => context.set_rax(sys_get_pid() as usize),
+        // resource: arg0 as Rlimit -> limit: usize or !0
+        Syscall::GetRlimit => context.set_rax(sys_get_rlimit(&args)),
+        // resource: arg0 as Rlimit, limit: arg1 as usize -> 0 or 1
+        Syscall::SetRlimit => context.set_rax(sys_set_rlimit(&args)),
+        // code: arg0 as ArchPrctlCode, addr: arg1 as usize -> 0 or 1
+        Syscall::ArchPrctl => context.set_rax(sys_arch_prctl(&args)),
+        // name: &str (arg0 as *const u8, arg1 as len) -> 0 or 1
+        Syscall::InitModule => context.set_rax(sys_init_module(&args)),
+        // name: &str (arg0 as *const u8, arg1 as len) -> 0 or 1
+        Syscall::DeleteModule => context.set_rax(sys_delete_module(&args)),
         // path: &str (arg0 as *const u8, arg1 as len) -> pid: u16
         Syscall::Spawn => context.set_rax(spawn_process(&args)),
         // pid: arg0 as u16
         Syscall::Exit => exit_process(&args, context),
         // pid: arg0 as u16 -> status: isize
         Syscall::WaitPid => sys_wait_pid(&args, context),
+        // op: arg0 as PtraceOp, pid: arg1 as u16, addr: arg2, data: arg3 -> 0 or 1
+        Syscall::Ptrace => context.set_rax(sys_ptrace(&args)),
+        // action: arg0 as SyslogAction, buf: &mut [u8] (arg1 as *mut u8, arg2 as len) -> size: usize or !0
+        Syscall::Syslog => context.set_rax(sys_syslog(&args)),
         // pid: arg0 as u16
         Syscall::Kill => sys_kill(&args, context),
         // None -> time: usize
@@ -64,23 +108,47 @@ pub fn dispatcher(context: &mut ProcessContext) {
         Syscall::Stat => list_process(),
         // None
         Syscall::ListApp => list_app(),
+        // None
+        Syscall::ListModule => crate::module::print_module_list(),
+        // pid: arg0 as u16, enable: arg1 as bool -> 0 or 1
+        Syscall::Strace => context.set_rax(sys_strace(&args)),
+        // filters: &str (arg0 as *const u8, arg1 as len), prints them if empty -> 0 or 1
+        Syscall::LogLevel => context.set_rax(sys_log_level(&args)),
+        // pid: arg0 as u16 -> 0 or 1
+        Syscall::SyscallStat => context.set_rax(sys_syscall_stat(&args)),
 
-        // layout: arg0 as *const Layout -> ptr: *mut u8
+        // size: arg0 as usize, align: arg1 as usize -> ptr: *mut u8
         Syscall::Allocate => context.set_rax(sys_allocate(&args)),
-        // ptr: arg0 as *mut u8
+        // ptr: arg0 as *mut u8, size: arg1 as usize, align: arg2 as usize
         Syscall::Deallocate => sys_deallocate(&args),
         // None
         Syscall::None => {}
     }
+
+    let cycles = unsafe { rdtsc() } - start;
+    record_syscall(pid, args.syscall.clone() as usize, cycles);
+
+    if traced {
+        // the context belongs to another process if the syscall switched
+        if current_pid() == pid {
+            debug!(
+                "#{} {:?} = {:#x} ({} cycles)",
+                pid, args.syscall, context.regs.rax, cycles
+            );
+        } else {
+            debug!("#{} {:?} switched ({} cycles)", pid, args.syscall, cycles);
+        }
+    }
 }
 
 impl SyscallArgs {
-    pub fn new(syscall: Syscall, arg0: usize, arg1: usize, arg2: usize) -> Self {
+    pub fn new(syscall: Syscall, arg0: usize, arg1: usize, arg2: usize, arg3: usize) -> Self {
         Self {
             syscall,
             arg0,
             arg1,
             arg2,
+            arg3,
         }
     }
 }
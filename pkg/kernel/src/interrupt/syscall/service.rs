@@ -1,12 +1,21 @@
 use core::alloc::Layout;
 
+use syscall_def::{
+    ArchPrctlCode, DirEntry, OpenFlags, PtraceOp, PtraceRegs, Rlimit, SeekWhence, SyslogAction,
+};
 use x86_64::VirtAddr;
 
+use crate::drivers::device;
+use crate::memory::uaccess::*;
 use crate::proc::*;
+use crate::resource::Resource;
 use crate::utils::*;
 
 use super::SyscallArgs;
 
+/// Size of the kernel buffer for `read` and `write`
+const IO_BUF_SIZE: usize = 0x1000;
+
 pub fn sys_clock() -> i64 {
     clock::now()
         .and_utc()
@@ -15,47 +24,53 @@ pub fn sys_clock() -> i64 {
 }
 
 pub fn sys_allocate(args: &SyscallArgs) -> usize {
-    let layout = unsafe { (args.arg0 as *const Layout).as_ref().unwrap() };
+    let layout = match Layout::from_size_align(args.arg0, args.arg1) {
+        Ok(layout) => layout,
+        Err(_) => return 0,
+    };
 
-    if layout.size() == 0 {
+    // a layout larger than the heap never fits
+    if layout.size() == 0 || layout.size() > crate::memory::user::USER_HEAP_SIZE {
         return 0;
     }
 
-    let ret = crate::memory::user::USER_ALLOCATOR
+    crate::memory::user::USER_ALLOCATOR
         .lock()
-        .allocate_first_fit(*layout);
-
-    match ret {
-        Ok(ptr) => ptr.as_ptr() as usize,
-        Err(_) => 0,
-    }
+        .allocate(layout)
+        .unwrap_or(0)
 }
 
 pub fn sys_deallocate(args: &SyscallArgs) {
-    let layout = unsafe { (args.arg1 as *const Layout).as_ref().unwrap() };
+    let layout = match Layout::from_size_align(args.arg1, args.arg2) {
+        Ok(layout) => layout,
+        Err(_) => return,
+    };
 
     if args.arg0 == 0 || layout.size() == 0 {
         return;
     }
 
-    let ptr = args.arg0 as *mut u8;
-
-    unsafe {
-        crate::memory::user::USER_ALLOCATOR
+    let addr = args.arg0 as u64;
+    if !crate::memory::user::is_heap_range(addr, layout.size())
+        || addr % layout.align() as u64 != 0
+        || !crate::memory::user::USER_ALLOCATOR
             .lock()
-            .deallocate(core::ptr::NonNull::new_unchecked(ptr), *layout);
+            .deallocate(args.arg0, layout)
+    {
+        warn!("sys_deallocate: invalid pointer {:#x}", addr);
     }
 }
 
 pub fn spawn_process(args: &SyscallArgs) -> usize {
-    let name = unsafe {
-        core::str::from_utf8_unchecked(core::slice::from_raw_parts(
-            args.arg0 as *const u8,
-            args.arg1,
-        ))
+    let name = match string_from_user(args.arg0 as u64, args.arg1) {
+        Some(name) => name,
+        None => {
+            warn!("spawn_process: invalid name at {:#x}", args.arg0);
+            return 0;
+        }
     };
 
-    let pid = crate::proc::spawn(name);
+    let pid = crate::proc::spawn(&name);
 
     if pid.is_err() {
         warn!("spawn_process: failed to spawn process: {}", name);
@@ -66,15 +81,50 @@ pub fn spawn_process(args: &SyscallArgs) -> usize {
 }
 
 pub fn sys_read(args: &SyscallArgs) -> usize {
-    let buf = unsafe { core::slice::from_raw_parts_mut(args.arg1 as *mut u8, args.arg2) };
+    if !is_user_range(args.arg1 as u64, args.arg2) {
+        return -1isize as usize;
+    }
+
+    // read at most one buffer at a time, the caller gets the count
+    let mut buf = vec![0; args.arg2.min(IO_BUF_SIZE)];
     let fd = args.arg0 as u8;
-    read(fd, buf) as usize
+    let ret = read(fd, &mut buf);
+
+    // the data read is lost if it cannot be copied
+    if ret > 0 && !copy_to_user(args.arg1 as u64, &buf[..ret as usize]) {
+        return -1isize as usize;
+    }
+
+    ret as usize
 }
 
 pub fn sys_write(args: &SyscallArgs) -> usize {
-    let buf = unsafe { core::slice::from_raw_parts(args.arg1 as *const u8, args.arg2) };
+    if !is_user_range(args.arg1 as u64, args.arg2) {
+        return -1isize as usize;
+    }
+
     let fd = args.arg0 as u8;
-    write(fd, buf) as usize
+    let mut buf = vec![0; args.arg2.min(IO_BUF_SIZE)];
+    let mut written = 0;
+
+    while written < args.arg2 {
+        let len = (args.arg2 - written).min(IO_BUF_SIZE);
+        if !copy_from_user(&mut buf[..len], (args.arg1 + written) as u64) {
+            return if written == 0 { -1isize as usize } else { written };
+        }
+
+        let ret = write(fd, &buf[..len]);
+        if ret < 0 {
+            return if written == 0 { ret as usize } else { written };
+        }
+
+        written += ret as usize;
+        if (ret as usize) < len {
+            break;
+        }
+    }
+
+    written
 }
 
 pub fn sys_get_pid() -> u16 {
@@ -103,6 +153,100 @@ pub fn sys_kill(args: &SyscallArgs, context: &mut ProcessContext) {
     kill(pid, context);
 }
 
+pub fn sys_ptrace(args: &SyscallArgs) -> usize {
+    let pid = ProcessId(args.arg1 as u16);
+    let (addr, data) = (args.arg2 as u64, args.arg3 as u64);
+
+    let ok = match PtraceOp::from(args.arg0) {
+        // data: *mut u64
+        PtraceOp::PeekData => match ptrace_peek(pid, addr) {
+            Some(word) => copy_to_user(data, &word.to_ne_bytes()),
+            None => false,
+        },
+        // data: the word
+        PtraceOp::PokeData => ptrace_poke(pid, addr, data),
+        // data: *mut PtraceRegs
+        PtraceOp::GetRegs => match ptrace_get_regs(pid) {
+            Some(regs) => unsafe { write_to_user(data, &regs) },
+            None => false,
+        },
+        // data: *const PtraceRegs
+        PtraceOp::SetRegs => match unsafe { read_from_user::<PtraceRegs>(data) } {
+            Some(regs) => ptrace_set_regs(pid, &regs),
+            None => false,
+        },
+        op => ptrace_control(op, pid),
+    };
+
+    if ok {
+        0
+    } else {
+        1
+    }
+}
+
+pub fn sys_strace(args: &SyscallArgs) -> usize {
+    if strace(ProcessId(args.arg0 as u16), args.arg1 != 0) {
+        0
+    } else {
+        1
+    }
+}
+
+pub fn sys_syscall_stat(args: &SyscallArgs) -> usize {
+    if print_syscall_stats(ProcessId(args.arg0 as u16)) {
+        0
+    } else {
+        1
+    }
+}
+
+pub fn sys_syslog(args: &SyscallArgs) -> usize {
+    let (buf, len) = (args.arg1 as u64, args.arg2);
+
+    let action = SyslogAction::from(args.arg0);
+
+    match action {
+        SyslogAction::ReadAll | SyslogAction::ReadClear => {
+            let mut kbuf = vec![0u8; len.min(logbuf::LOG_BUFFER_SIZE)];
+            let count = logbuf::read(&mut kbuf);
+
+            if !copy_to_user(buf, &kbuf[..count]) {
+                return !0;
+            }
+
+            if let SyslogAction::ReadClear = action {
+                logbuf::clear();
+            }
+
+            count
+        }
+        SyslogAction::Clear => {
+            logbuf::clear();
+            0
+        }
+        SyslogAction::SizeBuffer => logbuf::LOG_BUFFER_SIZE,
+        SyslogAction::Unknown => !0,
+    }
+}
+
+pub fn sys_log_level(args: &SyscallArgs) -> usize {
+    let Some(spec) = string_from_user(args.arg0 as u64, args.arg1) else {
+        return 1;
+    };
+
+    if spec.trim().is_empty() {
+        logger::print_filters();
+        return 0;
+    }
+
+    if logger::set_filters(&spec) {
+        0
+    } else {
+        1
+    }
+}
+
 pub fn sys_fork(context: &mut ProcessContext) {
     let status = fork(context);
     status
@@ -119,11 +263,150 @@ pub fn sys_sem(args: &SyscallArgs, context: &mut ProcessContext) {
 }
 
 pub fn sys_brk(args: &SyscallArgs) -> usize {
-    info!("sys_brk: {:?}", args);
+    trace!("sys_brk: {:?}", args);
     let new_heap_end = if args.arg0 == 0 {
         None
     } else {
         Some(args.arg0)
     };
     brk(new_heap_end)
-}
\ No newline at end of file
+}
+pub fn sys_get_rlimit(args: &SyscallArgs) -> usize {
+    match get_rlimit(Rlimit::from(args.arg0)) {
+        Some(limit) => limit as usize,
+        None => !0,
+    }
+}
+
+pub fn sys_set_rlimit(args: &SyscallArgs) -> usize {
+    if set_rlimit(Rlimit::from(args.arg0), args.arg1 as u64) {
+        0
+    } else {
+        1
+    }
+}
+
+pub fn sys_arch_prctl(args: &SyscallArgs) -> usize {
+    let ok = match ArchPrctlCode::from(args.arg0) {
+        ArchPrctlCode::SetFs => set_fs_base(args.arg1 as u64),
+        // addr: arg1 as *mut usize
+        ArchPrctlCode::GetFs => copy_to_user(args.arg1 as u64, &get_fs_base().to_ne_bytes()),
+        ArchPrctlCode::Unknown => false,
+    };
+
+    if ok {
+        0
+    } else {
+        1
+    }
+}
+
+pub fn sys_open(args: &SyscallArgs) -> usize {
+    let path = match string_from_user(args.arg0 as u64, args.arg1) {
+        Some(path) => path,
+        None => return -1isize as usize,
+    };
+
+    // devices and the kernel command line are not in the VFS
+    let res = match path.strip_prefix("/dev/") {
+        Some("null") => Resource::Null,
+        Some(name) => match device::find(name) {
+            Some(device) => Resource::Device(device),
+            None => return -1isize as usize,
+        },
+        None if path == "/proc/cmdline" => Resource::Text(cmdline::get().raw(), 0),
+        None => match crate::fs::open(&path, OpenFlags::from_bits_truncate(args.arg2)) {
+            Ok(file) => Resource::File(file),
+            Err(err) => {
+                debug!("sys_open: failed to open {}: {:?}", path, err);
+                return -1isize as usize;
+            }
+        },
+    };
+
+    match open(res) {
+        Some(fd) => fd as usize,
+        None => -1isize as usize,
+    }
+}
+
+pub fn sys_close(args: &SyscallArgs) -> usize {
+    if close(args.arg0 as u8) {
+        0
+    } else {
+        1
+    }
+}
+
+pub fn sys_lseek(args: &SyscallArgs) -> usize {
+    let whence = SeekWhence::from(args.arg2);
+
+    let ret = with_resource(args.arg0 as u8, |res| match res {
+        Resource::File(file) => file.seek(args.arg1 as isize, whence).ok(),
+        _ => None,
+    });
+
+    ret.flatten().unwrap_or(-1isize as usize)
+}
+
+pub fn sys_fstat(args: &SyscallArgs) -> usize {
+    let stat = with_resource(args.arg0 as u8, |res| match res {
+        Resource::File(file) => Some(file.stat()),
+        _ => None,
+    });
+
+    match stat.flatten() {
+        Some(stat) if unsafe { write_to_user(args.arg1 as u64, &stat) } => 0,
+        _ => 1,
+    }
+}
+
+pub fn sys_read_dir(args: &SyscallArgs) -> usize {
+    let entry = with_resource(args.arg0 as u8, |res| match res {
+        Resource::File(file) => file.read_dir().ok(),
+        _ => None,
+    });
+
+    match entry.flatten() {
+        Some(Some((name, ty))) => {
+            let entry = DirEntry::new(&name, ty);
+            if unsafe { write_to_user(args.arg1 as u64, &entry) } {
+                1
+            } else {
+                -1isize as usize
+            }
+        }
+        Some(None) => 0,
+        None => -1isize as usize,
+    }
+}
+
+pub fn sys_init_module(args: &SyscallArgs) -> usize {
+    let name = match string_from_user(args.arg0 as u64, args.arg1) {
+        Some(name) => name,
+        None => return 1,
+    };
+
+    match crate::module::insert(&name) {
+        Ok(()) => 0,
+        Err(err) => {
+            warn!("sys_init_module: failed to load {}: {:?}", name, err);
+            1
+        }
+    }
+}
+
+pub fn sys_delete_module(args: &SyscallArgs) -> usize {
+    let name = match string_from_user(args.arg0 as u64, args.arg1) {
+        Some(name) => name,
+        None => return 1,
+    };
+
+    match crate::module::remove(&name) {
+        Ok(()) => 0,
+        Err(err) => {
+            warn!("sys_delete_module: failed to unload {}: {:?}", name, err);
+            1
+        }
+    }
+}
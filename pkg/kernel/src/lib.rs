@@ -1,8 +1,7 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(naked_functions)]
 #![feature(abi_x86_interrupt)]
 #![feature(alloc_error_handler)]
-#![feature(type_alias_impl_trait)]
 #![feature(panic_info_message)]
 
 #[macro_use]
@@ -23,8 +22,11 @@ pub use utils::*;
 pub mod drivers;
 pub use drivers::*;
 
+pub mod fs;
+pub mod gdb;
 pub mod interrupt;
 pub mod memory;
+pub mod module;
 pub mod proc;
 
 pub use alloc::format;
@@ -33,14 +35,20 @@ use boot::BootInfo;
 pub fn init(boot_info: &'static BootInfo) {
     serial::init(); // init serial output
     logger::init(boot_info); // init logger system
+    cmdline::init(boot_info); // parse kernel command line
     memory::address::init(boot_info);
+    backtrace::init(boot_info); // init kernel symbol table
     memory::gdt::init(); // init gdt
     memory::allocator::init(); // init kernel heap allocator
     interrupt::init(); // init interrupts
     clock::init(boot_info); // init clock (uefi service)
     memory::init(boot_info); // init memory manager
     memory::user::init(); // init user heap allocator
+    ata::init(); // probe ata drives and their partitions
+    fs::init(boot_info); // init initramfs and mount filesystems
     proc::init(boot_info); // init task manager
+    module::init(boot_info); // init kernel module loader
+    gdb::init(); // init gdb stub on COM2
 
     x86_64::instructions::interrupts::enable();
     info!("Interrupts Enabled.");
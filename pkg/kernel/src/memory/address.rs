@@ -7,6 +7,10 @@ pub fn init(boot_info: &'static boot::BootInfo) {
     PHYSICAL_OFFSET.call_once(|| boot_info.physical_memory_offset);
 
     info!("Physical Offset  : {:#x}", PHYSICAL_OFFSET.get().unwrap());
+    info!(
+        "Kernel Base      : {:#x} (slide {:#x})",
+        boot_info.kernel_base, boot_info.kernel_slide
+    );
 }
 
 #[inline(always)]
@@ -1,12 +1,297 @@
 // reference: https://github.com/xfoxfu/rust-xos/blob/main/kernel/src/allocator.rs
 
-use linked_list_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use linked_list_allocator::Heap;
+use spin::Mutex;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+    PageTableFlags, PhysFrame, Size2MiB, Size4KiB,
+};
 use x86_64::VirtAddr;
 
+use super::slab::{CacheStats, SlabCache};
+use super::{physical_to_virtual, FRAME_ALLOCATOR, PAGE_SIZE, PHYSICAL_OFFSET};
+
+/// Size of the static heap in .bss, used before frames are available
 pub const HEAP_SIZE: usize = 8 * 1024 * 1024; // 8 MiB
 
-#[global_allocator]
-pub static ALLOCATOR: LockedHeap = LockedHeap::empty();
+/// The reserved virtual range that the kernel heap grows into
+pub const KERNEL_HEAP_START: u64 = 0xffff_fe00_0000_0000;
+pub const KERNEL_HEAP_MAX_SIZE: usize = 0x1_0000_0000; // 4 GiB
+
+/// Grow at least this size at a time
+const HEAP_GROW_SIZE: usize = 0x40000; // 256 KiB
+
+/// Grow the heap ahead of time when the free space is below this,
+/// so that the frame allocator can still allocate while mapping.
+const HEAP_LOW_WATERMARK: usize = 0x40000; // 256 KiB
+
+#[cfg_attr(not(test), global_allocator)]
+pub static ALLOCATOR: KernelAllocator = KernelAllocator::new();
+
+const CACHE_COUNT: usize = 12;
+
+pub struct KernelAllocator {
+    heap: Mutex<KernelHeap>,
+    /// object caches first, then size classes in ascending order
+    caches: [SlabCache; CACHE_COUNT],
+    growing: AtomicBool,
+}
+
+struct KernelHeap {
+    /// the static heap
+    fixed: Heap,
+    /// the heap in the reserved range
+    grown: Heap,
+    /// bytes mapped in the reserved range
+    mapped: usize,
+}
+
+impl KernelHeap {
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.fixed
+            .allocate_first_fit(layout)
+            .or_else(|_| self.grown.allocate_first_fit(layout))
+            .map_or(null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let ptr = NonNull::new_unchecked(ptr);
+        if self.fixed.bottom() <= ptr.as_ptr() && ptr.as_ptr() < self.fixed.top() {
+            self.fixed.deallocate(ptr, layout);
+        } else {
+            self.grown.deallocate(ptr, layout);
+        }
+    }
+
+    fn free(&self) -> usize {
+        self.fixed.free() + self.grown.free()
+    }
+}
+
+impl KernelAllocator {
+    const fn new() -> Self {
+        use crate::proc::{PROCESS_INNER_LAYOUT, PROCESS_LAYOUT, SEMAPHORE_LAYOUT};
+
+        Self {
+            heap: Mutex::new(KernelHeap {
+                fixed: Heap::empty(),
+                grown: Heap::empty(),
+                mapped: 0,
+            }),
+            caches: [
+                SlabCache::object("process", PROCESS_LAYOUT),
+                SlabCache::object("process_inner", PROCESS_INNER_LAYOUT),
+                SlabCache::object("semaphore", SEMAPHORE_LAYOUT),
+                SlabCache::sized("kmalloc-8", 8),
+                SlabCache::sized("kmalloc-16", 16),
+                SlabCache::sized("kmalloc-32", 32),
+                SlabCache::sized("kmalloc-64", 64),
+                SlabCache::sized("kmalloc-128", 128),
+                SlabCache::sized("kmalloc-256", 256),
+                SlabCache::sized("kmalloc-512", 512),
+                SlabCache::sized("kmalloc-1k", 1024),
+                SlabCache::sized("kmalloc-2k", 2048),
+            ],
+            growing: AtomicBool::new(false),
+        }
+    }
+
+    pub fn used(&self) -> usize {
+        let heap = self.heap.lock();
+        heap.fixed.used() + heap.grown.used()
+    }
+
+    pub fn size(&self) -> usize {
+        let heap = self.heap.lock();
+        heap.fixed.size() + heap.grown.size()
+    }
+
+    pub fn cache_stats(&self) -> impl Iterator<Item = CacheStats> + '_ {
+        self.caches.iter().map(|cache| cache.stats())
+    }
+
+    fn cache_of(&self, layout: &Layout) -> Option<&SlabCache> {
+        self.caches
+            .iter()
+            .filter(|cache| cache.is_exact())
+            .chain(self.caches.iter().filter(|cache| !cache.is_exact()))
+            .find(|cache| cache.fits(layout))
+    }
+
+    fn alloc_heap(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.heap.lock().alloc(layout);
+        if !ptr.is_null() {
+            return ptr;
+        }
+
+        if self.grow(layout.size() + layout.align()) {
+            self.heap.lock().alloc(layout)
+        } else {
+            null_mut()
+        }
+    }
+
+    /// Map more frames into the reserved range
+    ///
+    /// fails if the frame allocator is not ready or busy, since it
+    /// may be the one who is allocating.
+    fn grow(&self, min_size: usize) -> bool {
+        if self.growing.swap(true, Ordering::Acquire) {
+            return false;
+        }
+
+        let ret = self.grow_inner(min_size);
+
+        self.growing.store(false, Ordering::Release);
+
+        ret
+    }
+
+    fn grow_inner(&self, min_size: usize) -> bool {
+        let mut frame_alloc = match FRAME_ALLOCATOR.get().and_then(|alloc| alloc.try_lock()) {
+            Some(alloc) => alloc,
+            None => return false,
+        };
+
+        let mapped = self.heap.lock().mapped;
+        let mut size = min_size
+            .max(HEAP_GROW_SIZE)
+            .next_multiple_of(PAGE_SIZE as usize);
+
+        // large requests are mapped with 2 MiB pages
+        if size >= Size2MiB::SIZE as usize {
+            size = size.next_multiple_of(Size2MiB::SIZE as usize);
+        }
+
+        let size = size.min(KERNEL_HEAP_MAX_SIZE - mapped);
+
+        let mut mapper = kernel_mapper();
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+        let mut grown = 0;
+        while grown < size {
+            let addr = VirtAddr::new(KERNEL_HEAP_START + (mapped + grown) as u64);
+
+            if addr.is_aligned(Size2MiB::SIZE) && size - grown >= Size2MiB::SIZE as usize {
+                let page = Page::<Size2MiB>::containing_address(addr);
+                let frame: Option<PhysFrame<Size2MiB>> = frame_alloc.allocate_frame();
+
+                // fall back to 4 KiB pages if memory is fragmented
+                if let Some(frame) = frame {
+                    match unsafe { mapper.map_to(page, frame, flags, &mut *frame_alloc) } {
+                        Ok(flush) => flush.flush(),
+                        Err(_) => {
+                            unsafe { frame_alloc.deallocate_frame(frame) };
+                            break;
+                        }
+                    }
+
+                    grown += Size2MiB::SIZE as usize;
+                    continue;
+                }
+            }
+
+            let page = Page::<Size4KiB>::containing_address(addr);
+            let frame = match frame_alloc.allocate_frames(0) {
+                Some(frame) => frame,
+                None => break,
+            };
+
+            match unsafe { mapper.map_to(page, frame, flags, &mut *frame_alloc) } {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    unsafe { frame_alloc.deallocate_frames(frame, 0) };
+                    break;
+                }
+            }
+
+            grown += PAGE_SIZE as usize;
+        }
+
+        drop(frame_alloc);
+
+        // keep what has been mapped even if it is not enough
+        self.add_to_heap(grown);
+
+        trace!("Kernel heap grown by {:#x} bytes", grown);
+
+        grown > 0 && grown >= min_size
+    }
+
+    fn add_to_heap(&self, size: usize) {
+        if size == 0 {
+            return;
+        }
+
+        let mut heap = self.heap.lock();
+
+        unsafe {
+            if heap.mapped == 0 {
+                heap.grown.init(KERNEL_HEAP_START as *mut u8, size);
+            } else {
+                heap.grown.extend(size);
+            }
+        }
+
+        heap.mapped += size;
+    }
+
+    fn grow_if_low(&self) {
+        if self.growing.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let low = match self.heap.try_lock() {
+            Some(heap) => heap.free() < HEAP_LOW_WATERMARK,
+            None => false,
+        };
+
+        if low {
+            self.grow(HEAP_GROW_SIZE);
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = match self.cache_of(&layout) {
+            Some(cache) => cache.alloc(|slab| self.alloc_heap(slab)),
+            None => self.alloc_heap(layout),
+        };
+
+        self.grow_if_low();
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.cache_of(&layout) {
+            Some(cache) => cache.dealloc(ptr),
+            None => self.heap.lock().dealloc(ptr, layout),
+        }
+    }
+}
+
+/// Get the mapper of the current page table without allocating
+///
+/// the kernel space is shared by all page tables, so it can be mapped
+/// through any of them.
+pub fn kernel_mapper() -> OffsetPageTable<'static> {
+    let (frame, _) = Cr3::read();
+    unsafe {
+        OffsetPageTable::new(
+            (physical_to_virtual(frame.start_address().as_u64()) as *mut PageTable)
+                .as_mut()
+                .unwrap(),
+            VirtAddr::new_truncate(*PHYSICAL_OFFSET.get().unwrap()),
+        )
+    }
+}
 
 pub fn init() {
     static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
@@ -15,7 +300,11 @@ pub fn init() {
     let heap_end = heap_start + HEAP_SIZE as u64;
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP.as_mut_ptr(), HEAP_SIZE);
+        ALLOCATOR
+            .heap
+            .lock()
+            .fixed
+            .init(HEAP.as_mut_ptr(), HEAP_SIZE);
     }
 
     debug!(
@@ -30,6 +319,23 @@ pub fn init() {
     info!("Kernel Heap Initialized.");
 }
 
+/// Map the first chunk of the reserved range
+///
+/// page tables of processes copy the P4 entries of the kernel,
+/// so the entry of the reserved range must exist before any process.
+pub fn init_growth() {
+    if !ALLOCATOR.grow(HEAP_GROW_SIZE) {
+        panic!("Failed to reserve kernel heap.");
+    }
+
+    debug!(
+        "Kernel Heap Grow : 0x{:016x}-0x{:016x}",
+        KERNEL_HEAP_START,
+        KERNEL_HEAP_START + KERNEL_HEAP_MAX_SIZE as u64
+    );
+}
+
+#[cfg(not(test))]
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
     panic!("Allocation error: {:?}", layout);
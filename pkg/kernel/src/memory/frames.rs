@@ -1,28 +1,179 @@
 // reference: https://github.com/phil-opp/blog_os/blob/post-09/src/memory.rs
 // reference: https://github.com/xfoxfu/rust-xos/blob/main/kernel/src/memory.rs
 
+use alloc::collections::BTreeSet;
 use alloc::vec::Vec;
 use boot::{MemoryMap, MemoryType};
-use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+};
 use x86_64::PhysAddr;
 
-once_mutex!(pub FRAME_ALLOCATOR: BootInfoFrameAllocator);
+once_mutex!(pub FRAME_ALLOCATOR: BuddyFrameAllocator);
 
 guard_access_fn! {
-    pub get_frame_alloc(FRAME_ALLOCATOR: BootInfoFrameAllocator)
+    pub get_frame_alloc(FRAME_ALLOCATOR: BuddyFrameAllocator)
 }
 
-type BootInfoFrameIter = impl Iterator<Item = PhysFrame>;
+/// The largest block is 2^MAX_ORDER frames (4 MiB)
+pub const MAX_ORDER: usize = 10;
 
-/// A FrameAllocator that returns usable frames from the bootloader's memory map.
-pub struct BootInfoFrameAllocator {
+/// Get the smallest order that holds `count` frames
+pub fn order_of(count: usize) -> usize {
+    count.max(1).next_power_of_two().trailing_zeros() as usize
+}
+
+/// Usage of a memory region, for the `ps` output
+#[derive(Debug, Clone, Copy)]
+pub struct RegionStats {
+    pub start: PhysAddr,
+    pub end: PhysAddr,
+    pub total: usize,
+    pub free: usize,
+    pub largest_order: Option<usize>,
+}
+
+/// A buddy system over one CONVENTIONAL region of the memory map.
+///
+/// Blocks are identified by the number of their first frame, a block of
+/// order `n` is always aligned to 2^n frames in physical memory, so order 9
+/// blocks can be used as 2 MiB pages.
+struct BuddyRegion {
+    /// first frame number of the region
+    start: u64,
+    /// frame number after the region
+    end: u64,
+    /// free blocks of each order
+    free_lists: [BTreeSet<u64>; MAX_ORDER + 1],
+    /// count of free frames
+    free: u64,
+    /// one bit per frame, set when the frame is allocated
+    #[cfg(debug_assertions)]
+    allocated: Vec<u64>,
+}
+
+impl BuddyRegion {
+    fn new(start: u64, pages: u64) -> Self {
+        let mut region = Self {
+            start,
+            end: start + pages,
+            free_lists: core::array::from_fn(|_| BTreeSet::new()),
+            free: pages,
+            #[cfg(debug_assertions)]
+            allocated: vec![0; pages.div_ceil(64) as usize],
+        };
+
+        // split the region into the largest aligned blocks
+        let mut block = region.start;
+        while block < region.end {
+            let mut order = (block.trailing_zeros() as usize).min(MAX_ORDER);
+            while block + (1 << order) > region.end {
+                order -= 1;
+            }
+            region.free_lists[order].insert(block);
+            block += 1 << order;
+        }
+
+        region
+    }
+
+    fn contains(&self, block: u64) -> bool {
+        self.start <= block && block < self.end
+    }
+
+    fn allocate(&mut self, order: usize) -> Option<u64> {
+        let mut current = (order..=MAX_ORDER).find(|&o| !self.free_lists[o].is_empty())?;
+        let block = self.free_lists[current].pop_first()?;
+
+        // put the upper halves back until the block fits
+        while current > order {
+            current -= 1;
+            self.free_lists[current].insert(block + (1 << current));
+        }
+
+        self.free -= 1 << order;
+
+        #[cfg(debug_assertions)]
+        self.mark(block, order, true);
+
+        Some(block)
+    }
+
+    fn deallocate(&mut self, mut block: u64, mut order: usize) {
+        assert!(
+            block % (1 << order) == 0 && block + (1 << order) <= self.end,
+            "Invalid block {:#x} of order {} to deallocate",
+            block << 12,
+            order
+        );
+
+        #[cfg(debug_assertions)]
+        self.mark(block, order, false);
+
+        self.free += 1 << order;
+
+        // merge with the buddy as long as it is free
+        while order < MAX_ORDER {
+            let buddy = block ^ (1 << order);
+            if !self.contains(buddy) || buddy + (1 << order) > self.end {
+                break;
+            }
+            if !self.free_lists[order].remove(&buddy) {
+                break;
+            }
+            block = block.min(buddy);
+            order += 1;
+        }
+
+        self.free_lists[order].insert(block);
+    }
+
+    /// Update the allocation bitmap, panics on double allocation or free
+    #[cfg(debug_assertions)]
+    fn mark(&mut self, block: u64, order: usize, allocated: bool) {
+        for frame in block..block + (1 << order) {
+            let idx = frame - self.start;
+            let (word, bit) = ((idx / 64) as usize, idx % 64);
+            let was_allocated = self.allocated[word] & (1 << bit) != 0;
+
+            if was_allocated == allocated {
+                panic!(
+                    "Frame {:#x} is {}",
+                    frame << 12,
+                    if allocated {
+                        "allocated twice"
+                    } else {
+                        "freed twice"
+                    }
+                );
+            }
+
+            self.allocated[word] ^= 1 << bit;
+        }
+    }
+
+    fn stats(&self) -> RegionStats {
+        RegionStats {
+            start: PhysAddr::new(self.start << 12),
+            end: PhysAddr::new(self.end << 12),
+            total: (self.end - self.start) as usize,
+            free: self.free as usize,
+            largest_order: (0..=MAX_ORDER)
+                .rev()
+                .find(|&o| !self.free_lists[o].is_empty()),
+        }
+    }
+}
+
+/// A buddy FrameAllocator over the usable regions of the bootloader's memory map.
+pub struct BuddyFrameAllocator {
     size: usize,
     used: usize,
-    frames: BootInfoFrameIter,
-    recycle: Vec<PhysFrame>,
+    recycled: usize,
+    regions: Vec<BuddyRegion>,
 }
 
-impl BootInfoFrameAllocator {
+impl BuddyFrameAllocator {
     /// Create a FrameAllocator from the passed memory map.
     ///
     /// # Safety
@@ -30,12 +181,26 @@ impl BootInfoFrameAllocator {
     /// This function is unsafe because the caller must guarantee that the passed
     /// memory map is valid. The main requirement is that all frames that are marked
     /// as `USABLE` in it are really unused.
+    ///
+    /// at most `size` frames are used, from the lowest usable regions.
     pub unsafe fn init(memory_map: &MemoryMap, size: usize) -> Self {
-        BootInfoFrameAllocator {
+        let mut left = size as u64;
+        let regions = memory_map
+            .iter()
+            // get usable regions from memory map
+            .filter(|r| r.ty == MemoryType::CONVENTIONAL && r.page_count > 0)
+            .filter_map(|r| {
+                let count = r.page_count.min(left);
+                left -= count;
+                (count > 0).then(|| BuddyRegion::new(r.phys_start >> 12, count))
+            })
+            .collect();
+
+        BuddyFrameAllocator {
             size,
-            frames: create_frame_iter(memory_map),
             used: 0,
-            recycle: Vec::new()
+            recycled: 0,
+            regions,
         }
     }
 
@@ -47,39 +212,173 @@ impl BootInfoFrameAllocator {
         self.size
     }
 
+    pub fn frames_free(&self) -> usize {
+        self.size - self.used
+    }
+
+    /// Count of frames that have been freed since boot
     pub fn frames_recycled(&self) -> usize {
-        self.recycle.len()
+        self.recycled
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = RegionStats> + '_ {
+        self.regions.iter().map(|r| r.stats())
     }
 
+    /// Allocate 2^order physically contiguous frames
+    pub fn allocate_frames(&mut self, order: usize) -> Option<PhysFrame> {
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        let block = self.regions.iter_mut().find_map(|r| r.allocate(order))?;
+
+        self.used += 1 << order;
+
+        Some(PhysFrame::containing_address(PhysAddr::new(block << 12)))
+    }
+
+    /// Free 2^order frames that were allocated by `allocate_frames`
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the frames are unused.
+    pub unsafe fn deallocate_frames(&mut self, frame: PhysFrame, order: usize) {
+        let block = frame.start_address().as_u64() >> 12;
+
+        match self.regions.iter_mut().find(|r| r.contains(block)) {
+            Some(region) => region.deallocate(block, order),
+            None => {
+                warn!(
+                    "Frame {:#x} is not managed by the frame allocator",
+                    frame.start_address()
+                );
+                return;
+            }
+        }
+
+        self.used -= 1 << order;
+        self.recycled += 1 << order;
+    }
 }
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        if let Some(frame) = self.recycle.pop() {
-            Some(frame)
-        } else {
-            self.used += 1;
-            self.frames.next()
-        }
+        self.allocate_frames(0)
     }
 }
 
-impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
     unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
-        // TODO: deallocate frame
-        self.used -= 1;
-        self.recycle.push(frame);
+        self.deallocate_frames(frame, 0)
+    }
+}
+
+const HUGE_PAGE_ORDER: usize = (Size2MiB::SIZE / Size4KiB::SIZE).trailing_zeros() as usize;
+
+unsafe impl FrameAllocator<Size2MiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let frame = self.allocate_frames(HUGE_PAGE_ORDER)?;
+        PhysFrame::from_start_address(frame.start_address()).ok()
     }
 }
 
-unsafe fn create_frame_iter(memory_map: &MemoryMap) -> BootInfoFrameIter {
-    memory_map
-        .clone()
-        .into_iter()
-        // get usable regions from memory map
-        .filter(|r| r.ty == MemoryType::CONVENTIONAL)
-        // align to page boundary
-        .flat_map(|r| (0..r.page_count).map(move |v| (v * 4096 + r.phys_start)))
-        // create `PhysFrame` types from the start addresses
-        .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+impl FrameDeallocator<Size2MiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let frame = PhysFrame::containing_address(frame.start_address());
+        self.deallocate_frames(frame, HUGE_PAGE_ORDER)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn region_is_split_into_aligned_blocks() {
+        // frames 3..1030: 3, 4..8, 8..16, ..., 512..1024, 1024..1030
+        let region = BuddyRegion::new(3, 1027);
+
+        assert_eq!(region.free, 1027);
+        assert!(region.free_lists[0].contains(&3));
+        assert!(region.free_lists[2].contains(&4));
+        assert!(region.free_lists[9].contains(&512));
+        assert!(region.free_lists[2].contains(&1024));
+        assert!(region.free_lists[1].contains(&1028));
+        assert_eq!(region.stats().largest_order, Some(9));
+    }
+
+    #[test]
+    fn allocate_splits_and_deallocate_merges() {
+        let mut region = BuddyRegion::new(0, 1 << MAX_ORDER);
+
+        let a = region.allocate(0).unwrap();
+        let b = region.allocate(0).unwrap();
+        assert_eq!((a, b), (0, 1));
+        assert_eq!(region.free, (1 << MAX_ORDER) - 2);
+        assert!(region.free_lists[MAX_ORDER].is_empty());
+        for order in 1..MAX_ORDER {
+            assert_eq!(region.free_lists[order].first(), Some(&(1 << order)));
+        }
+
+        region.deallocate(a, 0);
+        assert!(region.free_lists[0].contains(&a));
+
+        region.deallocate(b, 0);
+        assert_eq!(region.free, 1 << MAX_ORDER);
+        assert!(region.free_lists[MAX_ORDER].contains(&0));
+        assert!((0..MAX_ORDER).all(|o| region.free_lists[o].is_empty()));
+    }
+
+    #[test]
+    fn blocks_are_aligned_to_their_order() {
+        let mut region = BuddyRegion::new(1, 2047);
+
+        let small = region.allocate(0).unwrap();
+        let huge = region.allocate(9).unwrap();
+        assert_eq!(small, 1);
+        assert_eq!(huge % 512, 0);
+
+        // a huge block can be freed in 4K pieces
+        for frame in huge..huge + 512 {
+            region.deallocate(frame, 0);
+        }
+        region.deallocate(small, 0);
+        assert_eq!(region.free, 2047);
+        assert!(region.free_lists[9].contains(&huge));
+    }
+
+    #[test]
+    fn buddy_outside_the_region_is_not_merged() {
+        let mut region = BuddyRegion::new(0, 3);
+
+        let block = region.allocate(1).unwrap();
+        assert_eq!(block, 0);
+        assert_eq!(region.allocate(1), None);
+
+        region.deallocate(block, 1);
+        assert!(region.free_lists[1].contains(&0));
+        assert!(region.free_lists[0].contains(&2));
+        assert!(region.free_lists[2].is_empty());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "freed twice")]
+    fn double_free_is_detected() {
+        let mut region = BuddyRegion::new(0, 16);
+
+        let block = region.allocate(0).unwrap();
+        region.deallocate(block, 0);
+        region.deallocate(block, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid block")]
+    fn unaligned_block_is_rejected() {
+        let mut region = BuddyRegion::new(0, 16);
+
+        region.allocate(1).unwrap();
+        region.deallocate(1, 1);
+    }
 }
@@ -1,4 +1,6 @@
+use core::ptr::addr_of;
 use lazy_static::lazy_static;
+use x86_64::structures::paging::{Mapper, Page, Size4KiB};
 use x86_64::registers::segmentation::Segment;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
@@ -11,14 +13,55 @@ pub const CONTEXT_SWITCH_IST_INDEX: u16 = 0;
 
 pub const IST_SIZES: [usize; 4] = [0x1000, 0x1000, 0x4000, 0x1000];
 
+pub const IST_GUARD_SIZE: usize = 0x1000;
+
+/// A stack for TSS with a guard page below it
+///
+/// the guard page is unmapped in `init`, so that an overflow
+/// causes a page fault instead of corrupting the memory below.
+#[repr(C, align(4096))]
+struct GuardedStack<const N: usize> {
+    guard: [u8; IST_GUARD_SIZE],
+    stack: [u8; N],
+}
+
+impl<const N: usize> GuardedStack<N> {
+    const fn new() -> Self {
+        Self {
+            guard: [0; IST_GUARD_SIZE],
+            stack: [0; N],
+        }
+    }
+}
+
+static mut PRIVILEGE_STACK: GuardedStack<{ IST_SIZES[0] }> = GuardedStack::new();
+static mut DOUBLE_FAULT_STACK: GuardedStack<{ IST_SIZES[1] }> = GuardedStack::new();
+static mut SYSCALL_STACK: GuardedStack<{ IST_SIZES[2] }> = GuardedStack::new();
+static mut PAGE_FAULT_STACK: GuardedStack<{ IST_SIZES[3] }> = GuardedStack::new();
+
+/// Get the guard page address and the stack range of a guarded stack
+fn stack_range<const N: usize>(stack: *const GuardedStack<N>) -> (VirtAddr, VirtAddr, VirtAddr) {
+    let guard = VirtAddr::from_ptr(stack);
+    let stack_start = guard + IST_GUARD_SIZE as u64;
+    let stack_end = stack_start + N as u64;
+    (guard, stack_start, stack_end)
+}
+
+/// Guard pages of TSS stacks with their names
+fn guard_pages() -> [(VirtAddr, &'static str); 4] {
+    [
+        (stack_range(addr_of!(PRIVILEGE_STACK)).0, "PRIVILEGE STACK"),
+        (stack_range(addr_of!(DOUBLE_FAULT_STACK)).0, "DOUBLE FAULT IST"),
+        (stack_range(addr_of!(SYSCALL_STACK)).0, "SYSCALL IST"),
+        (stack_range(addr_of!(PAGE_FAULT_STACK)).0, "PAGE FAULT IST"),
+    ]
+}
+
 lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
         tss.privilege_stack_table[0] = {
-            const STACK_SIZE: usize = IST_SIZES[0];
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(unsafe { STACK.as_ptr() });
-            let stack_end = stack_start + STACK_SIZE as u64;
+            let (_, stack_start, stack_end) = stack_range(addr_of!(PRIVILEGE_STACK));
             info!(
                 "Privilege Stack  : 0x{:016x}-0x{:016x}",
                 stack_start.as_u64(),
@@ -27,10 +70,7 @@ lazy_static! {
             stack_end
         };
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = IST_SIZES[1];
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(unsafe { STACK.as_ptr() });
-            let stack_end = stack_start + STACK_SIZE as u64;
+            let (_, stack_start, stack_end) = stack_range(addr_of!(DOUBLE_FAULT_STACK));
             info!(
                 "Double Fault IST : 0x{:016x}-0x{:016x}",
                 stack_start.as_u64(),
@@ -39,10 +79,7 @@ lazy_static! {
             stack_end
         };
         tss.interrupt_stack_table[SYSCALL_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = IST_SIZES[2];
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(unsafe { STACK.as_ptr() });
-            let stack_end = stack_start + STACK_SIZE as u64;
+            let (_, stack_start, stack_end) = stack_range(addr_of!(SYSCALL_STACK));
             info!(
                 "Syscall IST      : 0x{:016x}-0x{:016x}",
                 stack_start.as_u64(),
@@ -51,10 +88,7 @@ lazy_static! {
             stack_end
         };
         tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = IST_SIZES[3];
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(unsafe { STACK.as_ptr() });
-            let stack_end = stack_start + STACK_SIZE as u64;
+            let (_, stack_start, stack_end) = stack_range(addr_of!(PAGE_FAULT_STACK));
             info!(
                 "Page Fault IST   : 0x{:016x}-0x{:016x}",
                 stack_start.as_u64(),
@@ -127,9 +161,34 @@ pub fn init() {
     let (size, unit) = crate::humanized_size(size as u64);
     info!("Kernel IST Size  : {:>7.*} {}", 3, size, unit);
 
+    unmap_guard_pages();
+
     info!("GDT Initialized.");
 }
 
+/// Unmap the guard pages below TSS stacks
+///
+/// the frames belong to the kernel image, so they are not deallocated
+fn unmap_guard_pages() {
+    let mut mapper = crate::proc::PageTableContext::new().mapper();
+
+    for (addr, name) in guard_pages() {
+        let page = Page::<Size4KiB>::containing_address(addr);
+        match mapper.unmap(page) {
+            Ok((_, flush)) => flush.flush(),
+            Err(e) => warn!("Failed to unmap guard page of {}: {:?}", name, e),
+        }
+    }
+}
+
+/// Get the name of the stack if `addr` is on one of the guard pages
+pub fn guard_page_of(addr: VirtAddr) -> Option<&'static str> {
+    guard_pages()
+        .into_iter()
+        .find(|(guard, _)| *guard <= addr && addr < *guard + IST_GUARD_SIZE as u64)
+        .map(|(_, name)| name)
+}
+
 pub fn get_user_selector() -> UserSelectors {
     GDT.2
 }
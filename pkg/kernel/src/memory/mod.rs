@@ -3,6 +3,9 @@ pub mod allocator;
 mod frames;
 
 pub mod gdt;
+pub mod slab;
+pub mod swap;
+pub mod uaccess;
 pub mod user;
 
 pub use address::*;
@@ -27,12 +30,25 @@ pub fn init(boot_info: &'static boot::BootInfo) {
     let (size, unit) = crate::humanized_size(usable_mem_size * PAGE_SIZE);
     info!("Free Usable Memory : {:>7.*} {}", 3, size, unit);
 
+    if let Some(limit) = crate::cmdline::get().mem {
+        usable_mem_size = usable_mem_size.min(limit / PAGE_SIZE);
+
+        let (size, unit) = crate::humanized_size(usable_mem_size * PAGE_SIZE);
+        info!("Memory Limited To  : {:>7.*} {}", 3, size, unit);
+    }
+
     unsafe {
-        init_FRAME_ALLOCATOR(BootInfoFrameAllocator::init(
+        init_FRAME_ALLOCATOR(BuddyFrameAllocator::init(
             memory_map,
             usable_mem_size as usize,
         ));
     }
 
     info!("Frame Allocator initialized.");
+
+    allocator::init_growth();
+
+    swap::init();
+
+    uaccess::init();
 }
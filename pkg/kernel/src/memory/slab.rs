@@ -0,0 +1,185 @@
+use core::alloc::Layout;
+use core::ptr::{null_mut, NonNull};
+use core::sync::atomic::AtomicUsize;
+
+use spin::Mutex;
+
+/// The smallest memory chunk that a cache takes from the heap
+const SLAB_SIZE: usize = 0x1000;
+
+/// Objects per slab for large objects
+const SLAB_MIN_OBJECTS: usize = 8;
+
+/// The memory layout of `alloc::sync::ArcInner`
+#[repr(C)]
+#[allow(dead_code)]
+struct ArcInner<T> {
+    strong: AtomicUsize,
+    weak: AtomicUsize,
+    data: T,
+}
+
+/// Get the layout of the allocation made by `Arc::new`
+pub const fn arc_inner_layout<T>() -> Layout {
+    Layout::new::<ArcInner<T>>()
+}
+
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+struct SlabList {
+    free: Option<NonNull<FreeObject>>,
+    slabs: usize,
+    total: usize,
+    used: usize,
+}
+
+unsafe impl Send for SlabList {}
+
+/// Statistics of a slab cache, for the `ps` output
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub size: usize,
+    pub slabs: usize,
+    pub slab_size: usize,
+    pub total: usize,
+    pub used: usize,
+}
+
+/// A cache of same-sized objects
+///
+/// objects are carved from slabs taken from the kernel heap, freed objects
+/// are kept in a free list and slabs are never given back.
+pub struct SlabCache {
+    name: &'static str,
+    /// size of each object slot, a multiple of `align`
+    size: usize,
+    align: usize,
+    slab_size: usize,
+    /// only serve this exact layout size if set, otherwise any smaller size
+    exact: bool,
+    list: Mutex<SlabList>,
+}
+
+impl SlabCache {
+    const fn with(name: &'static str, size: usize, align: usize, exact: bool) -> Self {
+        let align = if align < 8 { 8 } else { align };
+        let size = if size < 8 { 8 } else { size };
+        let size = size.div_ceil(align) * align;
+
+        let slab_size = if size * SLAB_MIN_OBJECTS > SLAB_SIZE {
+            (size * SLAB_MIN_OBJECTS).next_power_of_two()
+        } else {
+            SLAB_SIZE
+        };
+
+        Self {
+            name,
+            size,
+            align,
+            slab_size,
+            exact,
+            list: Mutex::new(SlabList {
+                free: None,
+                slabs: 0,
+                total: 0,
+                used: 0,
+            }),
+        }
+    }
+
+    /// Create a cache for objects of size `size`, `size` must be a power of two
+    pub const fn sized(name: &'static str, size: usize) -> Self {
+        Self::with(name, size, size, false)
+    }
+
+    /// Create a cache for a kernel object
+    pub const fn object(name: &'static str, layout: Layout) -> Self {
+        Self::with(name, layout.size(), layout.align(), true)
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    /// Check if the cache can serve the allocation
+    pub fn fits(&self, layout: &Layout) -> bool {
+        let size_fits = if self.exact {
+            layout.size().div_ceil(self.align) * self.align == self.size
+        } else {
+            layout.size() <= self.size
+        };
+
+        size_fits && layout.align() <= self.align
+    }
+
+    pub fn slab_layout(&self) -> Layout {
+        Layout::from_size_align(self.slab_size, self.slab_size.min(SLAB_SIZE)).unwrap()
+    }
+
+    /// Allocate an object, `new_slab` is called without holding
+    /// the cache lock if there is no free object
+    pub fn alloc(&self, new_slab: impl FnOnce(Layout) -> *mut u8) -> *mut u8 {
+        if let Some(ptr) = self.pop() {
+            return ptr;
+        }
+
+        let slab = new_slab(self.slab_layout());
+        if slab.is_null() {
+            return null_mut();
+        }
+
+        let mut list = self.list.lock();
+
+        for offset in (0..=self.slab_size - self.size).step_by(self.size) {
+            let object = unsafe { slab.add(offset) } as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: list.free }) };
+            list.free = NonNull::new(object);
+        }
+
+        list.slabs += 1;
+        list.total += self.slab_size / self.size;
+
+        drop(list);
+
+        self.pop().unwrap_or(null_mut())
+    }
+
+    fn pop(&self) -> Option<*mut u8> {
+        let mut list = self.list.lock();
+        let object = list.free?;
+
+        list.free = unsafe { object.as_ref().next };
+        list.used += 1;
+
+        Some(object.as_ptr() as *mut u8)
+    }
+
+    /// Put an object back to the free list
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be allocated by this cache.
+    pub unsafe fn dealloc(&self, ptr: *mut u8) {
+        let mut list = self.list.lock();
+        let object = ptr as *mut FreeObject;
+
+        object.write(FreeObject { next: list.free });
+        list.free = NonNull::new(object);
+        list.used -= 1;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let list = self.list.lock();
+        CacheStats {
+            name: self.name,
+            size: self.size,
+            slabs: list.slabs,
+            slab_size: self.slab_size,
+            total: list.total,
+            used: list.used,
+        }
+    }
+}
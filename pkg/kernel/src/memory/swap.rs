@@ -0,0 +1,414 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use lru::LruCache;
+use spin::Mutex;
+use x86_64::instructions::tlb;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    page::{PageRange, PageRangeInclusive},
+    page_table::PageTableEntry,
+    FrameAllocator, FrameDeallocator, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::drivers::block::*;
+use crate::drivers::ramdisk::Ramdisk;
+
+use super::{get_frame_alloc_for_sure, physical_to_virtual, BuddyFrameAllocator, PAGE_SIZE};
+
+/// Default size of the ramdisk used as the swap area,
+/// set by the `swap` kernel option
+pub const DEF_SWAP_SIZE: u64 = 8 * 1024 * 1024; // 8 MiB
+
+/// Start reclaiming when free frames are below this
+pub const SWAP_LOW_WATERMARK: usize = 256;
+
+/// Count of pages to reclaim at a time
+pub const RECLAIM_BATCH: usize = 32;
+
+/// Marks a non-present entry whose address holds a swap slot
+const SWAPPED: PageTableFlags = PageTableFlags::BIT_9;
+
+const BLOCKS_PER_PAGE: usize = PAGE_SIZE as usize / BLOCK_SIZE;
+
+once_mutex!(SWAP_AREA: SwapArea);
+
+lazy_static! {
+    /// Resident user pages, keyed by the P4 frame and the page
+    static ref RESIDENT: Mutex<LruCache<(PhysFrame, Page), ()>> =
+        Mutex::new(LruCache::unbounded());
+}
+
+/// Count of swapped out pages of each page table, by its P4 frame
+static SWAPPED_PAGES: Mutex<BTreeMap<PhysFrame, u64>> = Mutex::new(BTreeMap::new());
+
+static SWAPPED_IN: AtomicU64 = AtomicU64::new(0);
+static SWAPPED_OUT: AtomicU64 = AtomicU64::new(0);
+
+/// Statistics of the swap area, for the `ps` output
+#[derive(Debug, Clone, Copy)]
+pub struct SwapStats {
+    pub total: usize,
+    pub used: usize,
+    pub swapped_in: u64,
+    pub swapped_out: u64,
+}
+
+/// Page sized slots on a block device
+pub struct SwapArea {
+    device: Box<dyn BlockDevice>,
+    /// one bit per slot, set when the slot is in use
+    slots: Vec<u64>,
+    count: usize,
+    used: usize,
+}
+
+impl SwapArea {
+    pub fn new(device: Box<dyn BlockDevice>) -> Self {
+        let count = device.block_count() / BLOCKS_PER_PAGE;
+        Self {
+            device,
+            slots: vec![0; count.div_ceil(64)],
+            count,
+            used: 0,
+        }
+    }
+
+    fn alloc_slot(&mut self) -> Option<u64> {
+        let (word, bits) = self
+            .slots
+            .iter_mut()
+            .enumerate()
+            .find(|(_, bits)| **bits != u64::MAX)?;
+
+        let slot = word * 64 + bits.trailing_ones() as usize;
+        if slot >= self.count {
+            return None;
+        }
+
+        *bits |= 1 << (slot % 64);
+        self.used += 1;
+
+        Some(slot as u64)
+    }
+
+    fn free_slot(&mut self, slot: u64) {
+        let (word, bit) = ((slot / 64) as usize, slot % 64);
+        debug_assert!(self.slots[word] & (1 << bit) != 0, "Swap slot is not used.");
+
+        self.slots[word] &= !(1 << bit);
+        self.used -= 1;
+    }
+
+    fn write_page(&self, slot: u64, frame: PhysFrame) -> Result<(), BlockError> {
+        let page = physical_to_virtual(frame.start_address().as_u64()) as *const Block;
+        for i in 0..BLOCKS_PER_PAGE {
+            let block = unsafe { &*page.add(i) };
+            self.device
+                .write_block(slot as usize * BLOCKS_PER_PAGE + i, block)?;
+        }
+        Ok(())
+    }
+
+    fn read_page(&self, slot: u64, frame: PhysFrame) -> Result<(), BlockError> {
+        let page = physical_to_virtual(frame.start_address().as_u64()) as *mut Block;
+        for i in 0..BLOCKS_PER_PAGE {
+            let block = unsafe { &mut *page.add(i) };
+            self.device
+                .read_block(slot as usize * BLOCKS_PER_PAGE + i, block)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn init() {
+    let size = crate::cmdline::get().swap.unwrap_or(DEF_SWAP_SIZE);
+
+    // the ramdisk is on the kernel heap
+    if size < PAGE_SIZE {
+        info!("Swap Disabled.");
+        return;
+    }
+
+    init_SWAP_AREA(SwapArea::new(Box::new(Ramdisk::new(size as usize))));
+
+    let (size, unit) = crate::humanized_size(size);
+    info!("Swap Area Size   : {:>7.*} {}", 3, size, unit);
+}
+
+pub fn stats() -> Option<SwapStats> {
+    let swap = SWAP_AREA.get()?.lock();
+    Some(SwapStats {
+        total: swap.count,
+        used: swap.used,
+        swapped_in: SWAPPED_IN.load(Ordering::Relaxed),
+        swapped_out: SWAPPED_OUT.load(Ordering::Relaxed),
+    })
+}
+
+/// Get the P4 frame of a mapper
+fn root_of(mapper: &OffsetPageTable) -> PhysFrame {
+    let virt = VirtAddr::from_ptr(mapper.level_4_table()).as_u64();
+    PhysFrame::containing_address(PhysAddr::new(virt - mapper.phys_offset().as_u64()))
+}
+
+/// Get the P1 entry of a page, if the page tables are present
+fn entry_of(root: PhysFrame, page: Page) -> Option<&'static mut PageTableEntry> {
+    let mut table =
+        unsafe { &mut *(physical_to_virtual(root.start_address().as_u64()) as *mut PageTable) };
+
+    for index in [page.p4_index(), page.p3_index(), page.p2_index()] {
+        let flags = table[index].flags();
+        if !flags.contains(PageTableFlags::PRESENT) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+
+        let next = physical_to_virtual(table[index].addr().as_u64()) as *mut PageTable;
+        table = unsafe { &mut *next };
+    }
+
+    Some(&mut table[page.p1_index()])
+}
+
+fn is_swapped(entry: &PageTableEntry) -> bool {
+    let flags = entry.flags();
+    flags.contains(SWAPPED) && !flags.contains(PageTableFlags::PRESENT)
+}
+
+fn flush(root: PhysFrame, page: Page) {
+    // other page tables are flushed when loaded
+    if Cr3::read().0 == root {
+        tlb::flush(page.start_address());
+    }
+}
+
+/// Record a resident user page as a candidate for eviction
+pub fn track(mapper: &OffsetPageTable, page: Page) {
+    RESIDENT.lock().put((root_of(mapper), page), ());
+}
+
+pub fn track_range(mapper: &OffsetPageTable, range: PageRange) {
+    let root = root_of(mapper);
+    let mut resident = RESIDENT.lock();
+    for page in range {
+        resident.put((root, page), ());
+    }
+}
+
+/// The count of swapped out pages of a page table
+pub fn swapped_pages(root: PhysFrame) -> u64 {
+    SWAPPED_PAGES.lock().get(&root).copied().unwrap_or(0)
+}
+
+fn count_swapped(root: PhysFrame, count: u64, swapped_out: bool) {
+    let mut swapped = SWAPPED_PAGES.lock();
+    let pages = swapped.entry(root).or_default();
+
+    if swapped_out {
+        *pages += count;
+    } else {
+        *pages = pages.saturating_sub(count);
+    }
+}
+
+/// Forget all pages of a page table which is going to be freed
+pub fn forget(root: PhysFrame) {
+    SWAPPED_PAGES.lock().remove(&root);
+
+    let mut resident = RESIDENT.lock();
+    let keys: Vec<_> = resident
+        .iter()
+        .map(|(key, _)| *key)
+        .filter(|(frame, _)| *frame == root)
+        .collect();
+
+    for key in keys {
+        resident.pop(&key);
+    }
+}
+
+/// Read a swapped out page back, returns false if the page is not swapped
+pub fn swap_in(mapper: &mut OffsetPageTable, page: Page, alloc: &mut BuddyFrameAllocator) -> bool {
+    let root = root_of(mapper);
+
+    let entry = match entry_of(root, page) {
+        Some(entry) if is_swapped(entry) => entry,
+        _ => return false,
+    };
+
+    let mut swap = match SWAP_AREA.get() {
+        Some(swap) => swap.lock(),
+        None => return false,
+    };
+
+    let frame = match alloc.allocate_frame() {
+        Some(frame) => frame,
+        None => {
+            error!("Swap in failed: no free frame");
+            return false;
+        }
+    };
+
+    let slot = entry.addr().as_u64() >> 12;
+    if let Err(err) = swap.read_page(slot, frame) {
+        error!("Swap in failed: {:?}", err);
+        unsafe { alloc.deallocate_frame(frame) };
+        return false;
+    }
+
+    swap.free_slot(slot);
+    drop(swap);
+
+    let flags = (entry.flags() - SWAPPED) | PageTableFlags::PRESENT;
+    entry.set_addr(frame.start_address(), flags);
+    flush(root, page);
+
+    trace!("Swap in page {:#x}", page.start_address().as_u64());
+
+    SWAPPED_IN.fetch_add(1, Ordering::Relaxed);
+    count_swapped(root, 1, false);
+    RESIDENT.lock().put((root, page), ());
+
+    true
+}
+
+/// Read all swapped out pages in the range back
+pub fn swap_in_range(
+    mapper: &mut OffsetPageTable,
+    range: PageRange,
+    alloc: &mut BuddyFrameAllocator,
+) {
+    for page in range {
+        swap_in(mapper, page, alloc);
+    }
+}
+
+/// Free the swap slots of swapped out pages in the range,
+/// returns the count of pages released
+pub fn release_range(mapper: &mut OffsetPageTable, range: PageRangeInclusive) -> u64 {
+    let root = root_of(mapper);
+
+    let mut swap = match SWAP_AREA.get() {
+        Some(swap) => swap.lock(),
+        None => return 0,
+    };
+
+    let mut count = 0;
+    for page in range {
+        if let Some(entry) = entry_of(root, page) {
+            if is_swapped(entry) {
+                swap.free_slot(entry.addr().as_u64() >> 12);
+                entry.set_unused();
+                count += 1;
+            }
+        }
+    }
+
+    drop(swap);
+    count_swapped(root, count, false);
+
+    count
+}
+
+enum Evict {
+    /// the page has been written to the swap area
+    Done,
+    /// the page is accessed recently, give it a second chance
+    Referenced,
+    /// the page is no longer resident
+    Gone,
+    /// the swap area is full or broken
+    Failed,
+}
+
+fn evict(root: PhysFrame, page: Page) -> Evict {
+    let entry = match entry_of(root, page) {
+        Some(entry) => entry,
+        None => return Evict::Gone,
+    };
+
+    let flags = entry.flags();
+    if !flags.contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE) {
+        return Evict::Gone;
+    }
+
+    if flags.contains(PageTableFlags::ACCESSED) {
+        entry.set_flags(flags - PageTableFlags::ACCESSED);
+        flush(root, page);
+        return Evict::Referenced;
+    }
+
+    let mut swap = match SWAP_AREA.get() {
+        Some(swap) => swap.lock(),
+        None => return Evict::Failed,
+    };
+
+    let slot = match swap.alloc_slot() {
+        Some(slot) => slot,
+        None => return Evict::Failed,
+    };
+
+    let frame = entry.frame().unwrap();
+    if let Err(err) = swap.write_page(slot, frame) {
+        error!("Swap out failed: {:?}", err);
+        swap.free_slot(slot);
+        return Evict::Failed;
+    }
+
+    drop(swap);
+
+    let flags = flags - PageTableFlags::PRESENT - PageTableFlags::ACCESSED - PageTableFlags::DIRTY;
+    entry.set_addr(PhysAddr::new(slot << 12), flags | SWAPPED);
+    flush(root, page);
+
+    unsafe { get_frame_alloc_for_sure().deallocate_frame(frame) };
+
+    trace!("Swap out page {:#x}", page.start_address().as_u64());
+
+    SWAPPED_OUT.fetch_add(1, Ordering::Relaxed);
+    count_swapped(root, 1, true);
+
+    Evict::Done
+}
+
+/// Swap out up to `count` least recently used pages,
+/// returns the count of frames freed
+///
+/// must be called without holding the frame allocator
+pub fn reclaim(count: usize) -> usize {
+    // every page gets at most one second chance
+    let limit = RESIDENT.lock().len() * 2;
+
+    let mut reclaimed = 0;
+    for _ in 0..limit {
+        if reclaimed >= count {
+            break;
+        }
+
+        let key = match RESIDENT.lock().pop_lru() {
+            Some((key, _)) => key,
+            None => break,
+        };
+
+        match evict(key.0, key.1) {
+            Evict::Done => reclaimed += 1,
+            Evict::Referenced => {
+                RESIDENT.lock().put(key, ());
+            }
+            Evict::Gone => {}
+            Evict::Failed => {
+                RESIDENT.lock().put(key, ());
+                break;
+            }
+        }
+    }
+
+    if reclaimed > 0 {
+        debug!("Reclaimed {} pages to swap", reclaimed);
+    }
+
+    reclaimed
+}
@@ -0,0 +1,193 @@
+//! Kernel access to user memory
+//!
+//! With SMAP enabled the kernel faults on any access to user pages,
+//! except between `stac` and `clac`. User pointers passed in syscalls
+//! must go through the helpers here instead of being dereferenced.
+//!
+//! the window is also recorded without SMAP, so a fault on a bad user
+//! pointer kills the process instead of the kernel.
+
+use core::arch::asm;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::VirtAddr;
+
+/// The end of the lower half, user pointers must be below this
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// The longest path or name copied from the user, with its terminator
+pub const PATH_MAX: usize = 4096;
+
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The kernel is accessing user pages, set by `UserAccessGuard`
+static IN_UACCESS: AtomicBool = AtomicBool::new(false);
+
+/// Check CPUID.(EAX=07H,ECX=0):EBX for SMEP (bit 7) and SMAP (bit 20)
+fn supported() -> (bool, bool) {
+    use core::arch::x86_64::{__cpuid_count, __get_cpuid_max};
+
+    let (max_leaf, _) = __get_cpuid_max(0);
+    if max_leaf < 7 {
+        return (false, false);
+    }
+
+    let ebx = __cpuid_count(7, 0).ebx;
+    (ebx & (1 << 7) != 0, ebx & (1 << 20) != 0)
+}
+
+/// Enable SMEP and SMAP if the CPU supports them
+pub fn init() {
+    let (smep, smap) = supported();
+
+    unsafe {
+        Cr4::update(|flags| {
+            flags.set(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION, smep);
+            flags.set(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION, smap);
+        });
+    }
+
+    SMAP_ENABLED.store(smap, Ordering::Relaxed);
+
+    info!(
+        "SMEP {}, SMAP {}.",
+        if smep { "enabled" } else { "not supported" },
+        if smap { "enabled" } else { "not supported" }
+    );
+}
+
+/// Check if SMAP is enabled
+pub fn is_enabled() -> bool {
+    SMAP_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Check if a fault at `addr` happens while the kernel accesses user pages
+pub fn is_user_access(addr: VirtAddr) -> bool {
+    IN_UACCESS.load(Ordering::Relaxed) && addr.as_u64() < USER_SPACE_END
+}
+
+/// Allows the kernel to access user pages until dropped
+///
+/// guards must not be nested, the inner one closes the window on drop.
+pub struct UserAccessGuard(());
+
+impl UserAccessGuard {
+    pub fn new() -> Self {
+        // `stac` is undefined without SMAP
+        if is_enabled() {
+            unsafe { asm!("stac", options(nostack)) };
+        }
+        IN_UACCESS.store(true, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for UserAccessGuard {
+    fn drop(&mut self) {
+        deny_user_access();
+    }
+}
+
+/// Run `f` with access to user pages
+pub fn with_user_access<T>(f: impl FnOnce() -> T) -> T {
+    let _guard = UserAccessGuard::new();
+    f()
+}
+
+/// Clear RFLAGS.AC, the user may have set it before trapping into the kernel
+///
+/// the window is also closed when a fault in it kills the process,
+/// as its guard is never dropped.
+#[inline]
+pub fn deny_user_access() {
+    IN_UACCESS.store(false, Ordering::Relaxed);
+    if is_enabled() {
+        unsafe { asm!("clac", options(nostack)) };
+    }
+}
+
+/// Check if `[addr, addr + len)` is in the user space
+pub fn is_user_range(addr: u64, len: usize) -> bool {
+    addr.checked_add(len as u64)
+        .is_some_and(|end| end <= USER_SPACE_END)
+}
+
+/// Copy `dst.len()` bytes from the user address `src`
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> bool {
+    if !is_user_range(src, dst.len()) {
+        return false;
+    }
+
+    with_user_access(|| unsafe {
+        core::ptr::copy_nonoverlapping(src as *const u8, dst.as_mut_ptr(), dst.len());
+    });
+
+    true
+}
+
+/// Copy `src` to the user address `dst`
+pub fn copy_to_user(dst: u64, src: &[u8]) -> bool {
+    if !is_user_range(dst, src.len()) {
+        return false;
+    }
+
+    with_user_access(|| unsafe {
+        core::ptr::copy_nonoverlapping(src.as_ptr(), dst as *mut u8, src.len());
+    });
+
+    true
+}
+
+/// Write a value of `T` to the user address `dst`
+///
+/// # Safety
+///
+/// `T` must have no padding bytes.
+pub unsafe fn write_to_user<T: Copy>(dst: u64, value: &T) -> bool {
+    let bytes = core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>());
+    copy_to_user(dst, bytes)
+}
+
+/// Read a value of `T` from the user address `src`
+///
+/// # Safety
+///
+/// The bytes at `src` must be a valid `T`, which holds for any `T`
+/// that is valid for all bit patterns.
+pub unsafe fn read_from_user<T: Copy>(src: u64) -> Option<T> {
+    if !is_user_range(src, core::mem::size_of::<T>()) {
+        return None;
+    }
+
+    let mut value = MaybeUninit::<T>::uninit();
+    with_user_access(|| unsafe {
+        core::ptr::copy_nonoverlapping(
+            src as *const u8,
+            value.as_mut_ptr() as *mut u8,
+            core::mem::size_of::<T>(),
+        );
+    });
+
+    Some(value.assume_init())
+}
+
+/// Copy `len` bytes from the user address `src` into a new buffer
+///
+/// `len` is checked before allocating, and at most `PATH_MAX`.
+pub fn vec_from_user(src: u64, len: usize) -> Option<Vec<u8>> {
+    if len > PATH_MAX || !is_user_range(src, len) {
+        return None;
+    }
+
+    let mut buf = vec![0; len];
+    copy_from_user(&mut buf, src).then_some(buf)
+}
+
+/// Copy an UTF-8 string of `len` bytes from the user address `src`
+pub fn string_from_user(src: u64, len: usize) -> Option<String> {
+    String::from_utf8(vec_from_user(src, len)?).ok()
+}
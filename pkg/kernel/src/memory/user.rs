@@ -1,7 +1,9 @@
 // reference: https://github.com/xfoxfu/rust-xos/blob/main/kernel/src/allocator.rs
 
 use crate::proc::PageTableContext;
-use linked_list_allocator::LockedHeap;
+use alloc::collections::BTreeMap;
+use core::alloc::Layout;
+use spin::Mutex;
 use x86_64::structures::paging::{
     mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
 };
@@ -11,7 +13,105 @@ pub const USER_HEAP_START: usize = 0x4000_0000_0000;
 pub const USER_HEAP_SIZE: usize = 1024 * 1024; // 1 MiB
 const USER_HEAP_PAGE: usize = USER_HEAP_SIZE / crate::memory::PAGE_SIZE as usize;
 
-pub static USER_ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub static USER_ALLOCATOR: Mutex<UserHeap> = Mutex::new(UserHeap::empty());
+
+/// A first-fit allocator of the user heap
+///
+/// its lists are kept in the kernel heap, as user pages may be changed
+/// by any process or fault while the allocator is locked.
+pub struct UserHeap {
+    /// free blocks by their start, with their sizes
+    free: BTreeMap<usize, usize>,
+    /// allocated blocks by their start, with their sizes
+    allocated: BTreeMap<usize, usize>,
+    used: usize,
+}
+
+impl UserHeap {
+    pub const fn empty() -> Self {
+        Self {
+            free: BTreeMap::new(),
+            allocated: BTreeMap::new(),
+            used: 0,
+        }
+    }
+
+    /// Free the whole heap of `size` bytes at `start`
+    pub fn init(&mut self, start: usize, size: usize) {
+        self.free = BTreeMap::from([(start, size)]);
+        self.allocated.clear();
+        self.used = 0;
+    }
+
+    /// the count of bytes allocated
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Allocate from the first free block that fits the layout
+    pub fn allocate(&mut self, layout: Layout) -> Option<usize> {
+        let (start, len, addr) = self.free.iter().find_map(|(&start, &len)| {
+            let addr = start.checked_next_multiple_of(layout.align())?;
+            let end = addr.checked_add(layout.size())?;
+            (end <= start + len).then_some((start, len, addr))
+        })?;
+
+        // the space before and after the block stays free
+        self.free.remove(&start);
+        if addr > start {
+            self.free.insert(start, addr - start);
+        }
+
+        let end = addr + layout.size();
+        if end < start + len {
+            self.free.insert(end, start + len - end);
+        }
+
+        self.allocated.insert(addr, layout.size());
+        self.used += layout.size();
+
+        Some(addr)
+    }
+
+    /// Free a block, merged with the free blocks around it
+    ///
+    /// returns false if no block of the size was allocated at `addr`.
+    pub fn deallocate(&mut self, addr: usize, layout: Layout) -> bool {
+        if self.allocated.get(&addr) != Some(&layout.size()) {
+            return false;
+        }
+
+        self.allocated.remove(&addr);
+        self.used -= layout.size();
+
+        let mut start = addr;
+        let mut len = layout.size();
+
+        if let Some((&prev, &prev_len)) = self.free.range(..addr).next_back() {
+            if prev + prev_len == addr {
+                self.free.remove(&prev);
+                start = prev;
+                len += prev_len;
+            }
+        }
+
+        if let Some(next_len) = self.free.remove(&(addr + layout.size())) {
+            len += next_len;
+        }
+
+        self.free.insert(start, len);
+
+        true
+    }
+}
+
+/// Check if `[addr, addr + len)` is in the user heap
+pub fn is_heap_range(addr: u64, len: usize) -> bool {
+    let start = USER_HEAP_START as u64;
+    let end = start + USER_HEAP_SIZE as u64;
+
+    addr >= start && addr.checked_add(len as u64).is_some_and(|last| last <= end)
+}
 
 pub fn init() {
     init_user_heap().expect("User Heap Initialization Failed.");
@@ -43,16 +143,14 @@ pub fn init_user_heap() -> Result<(), MapToError<Size4KiB>> {
         let frame = frame_allocator
             .allocate_frame()
             .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags =
-            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::NO_EXECUTE;
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
     }
 
-    unsafe {
-        USER_ALLOCATOR
-            .lock()
-            .init(USER_HEAP_START as *mut u8, USER_HEAP_SIZE);
-    }
+    USER_ALLOCATOR.lock().init(USER_HEAP_START, USER_HEAP_SIZE);
 
     Ok(())
 }
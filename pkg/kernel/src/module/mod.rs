@@ -0,0 +1,339 @@
+//! Loadable kernel modules
+//!
+//! A module is a position independent shared object (`<name>.ko`) in
+//! `/lib/modules` of the initramfs or in the app list, loaded into the
+//! module area of the kernel space. Its undefined symbols are resolved
+//! against the symbols exported by the kernel (see [`EXPORTS`]), and it
+//! defines the entry points:
+//!
+//! - `module_init`: `extern "C" fn() -> isize`, called once the module is
+//!   loaded. the module is unloaded again if it returns non-zero.
+//! - `module_exit`: `extern "C" fn()`, optional, called before unloading.
+//!
+//! The module area is in the top level page table entry of the kernel image,
+//! which is shared by the page tables of all processes, so a module is
+//! mapped in every address space once it is loaded.
+
+mod symbols;
+
+use alloc::{collections::BTreeSet, string::String, vec::Vec};
+use core::ops::Range;
+use elf::{Dynamic, ElfError};
+use spin::{Mutex, Once};
+use x86_64::structures::paging::Page;
+use x86_64::VirtAddr;
+use xmas_elf::{program, ElfFile};
+
+use crate::drivers::device;
+use crate::memory::{
+    allocator::kernel_mapper, get_frame_alloc_for_sure, PAGE_SIZE, PHYSICAL_OFFSET,
+};
+
+pub use symbols::EXPORTS;
+
+/// Modules are loaded one after another in
+/// [MODULE_AREA_START, MODULE_AREA_START + MODULE_AREA_SIZE)
+pub const MODULE_AREA_START: u64 = 0xffff_ff10_0000_0000;
+pub const MODULE_AREA_SIZE: u64 = 0x4000_0000; // 1 GiB
+
+static APP_LIST: Once<boot::AppListRef> = Once::new();
+
+/// Loaded modules, sorted by their base address
+static MODULES: Mutex<Vec<Module>> = Mutex::new(Vec::new());
+
+#[derive(Debug)]
+pub enum ModuleError {
+    /// no module of the name in the initramfs or the app list, or not loaded
+    NotFound,
+    /// a module of the name is already loaded
+    AlreadyLoaded,
+    /// a device provided by the module is opened
+    Busy,
+    /// the module area is full
+    NoSpace,
+    /// the module does not define `module_init`
+    NoInit,
+    /// `module_init` returned the error code
+    InitFailed(isize),
+    Elf(ElfError),
+}
+
+impl From<ElfError> for ModuleError {
+    fn from(err: ElfError) -> Self {
+        Self::Elf(err)
+    }
+}
+
+struct Module {
+    name: String,
+    /// the address range in the module area
+    range: Range<u64>,
+    exit: Option<extern "C" fn()>,
+}
+
+pub fn init(boot_info: &'static boot::BootInfo) {
+    APP_LIST.call_once(|| boot_info.loaded_apps.as_ref());
+
+    info!(
+        "Module Area      : {:#x}-{:#x}, {} symbols exported",
+        MODULE_AREA_START,
+        MODULE_AREA_START + MODULE_AREA_SIZE,
+        EXPORTS.len()
+    );
+}
+
+/// Check if the app is a kernel module
+pub fn is_module(name: &str) -> bool {
+    name.ends_with(".ko")
+}
+
+/// Load the module `<name>.ko` in `/lib/modules` of the initramfs,
+/// in `\APP\` of the ESP or in the app list
+pub fn insert(name: &str) -> Result<(), ModuleError> {
+    let name = name.strip_suffix(".ko").unwrap_or(name);
+    let file = format!("{}.ko", name);
+
+    let paths = [
+        format!("/lib/modules/{}", file),
+        format!("{}/APP/{}", crate::fs::ESP_PATH, file),
+    ];
+
+    for path in paths {
+        if let Ok(data) = crate::fs::read(&path) {
+            let elf = ElfFile::new(&data).map_err(|_| ElfError::Malformed("header"))?;
+            return load(name, &elf);
+        }
+    }
+
+    let app = APP_LIST
+        .get()
+        .copied()
+        .flatten()
+        .and_then(|apps| apps.iter().find(|app| app.name.as_str() == file))
+        .ok_or(ModuleError::NotFound)?;
+
+    load(name, &app.elf)
+}
+
+/// Load a module from an ELF file and run its `module_init`
+///
+/// the file is only read while loading.
+pub fn load(name: &str, elf: &ElfFile) -> Result<(), ModuleError> {
+    let mut modules = MODULES.lock();
+
+    if modules.iter().any(|module| module.name == name) {
+        return Err(ModuleError::AlreadyLoaded);
+    }
+
+    if !elf::is_pie(elf) {
+        return Err(ElfError::InvalidType.into());
+    }
+
+    elf::validate(elf)?;
+
+    let segments: Vec<_> = elf
+        .program_iter()
+        .filter(|segment| segment.get_type() == Ok(program::Type::Load))
+        .collect();
+
+    // modules run on the stacks of processes, which have their own TLS
+    if let Some(tls) = elf
+        .program_iter()
+        .find(|segment| segment.get_type() == Ok(program::Type::Tls))
+    {
+        return Err(ElfError::InvalidSegment(tls.virtual_addr()).into());
+    }
+
+    let size = segments
+        .iter()
+        .map(|segment| segment.virtual_addr() + segment.mem_size())
+        .max()
+        .map(|end| x86_64::align_up(end, PAGE_SIZE))
+        .unwrap_or_default();
+
+    let dynamic = Dynamic::new(elf)?.ok_or(ElfError::Malformed("dynamic"))?;
+
+    // the entry points, as offsets in the module
+    let entry = |name: &str| {
+        dynamic
+            .lookup(name)
+            .filter(|symbol| symbol.value < size)
+            .map(|symbol| symbol.value)
+    };
+
+    let init = entry("module_init").ok_or(ModuleError::NoInit)?;
+    let exit = entry("module_exit");
+
+    let base = free_range(&modules, size).ok_or(ModuleError::NoSpace)?;
+    let range = base..base + size;
+
+    let relocations = elf::relocations_with(elf, base, symbols::lookup)?;
+
+    if let Err(err) = map_module(elf, &segments, base, &relocations) {
+        unmap_module(&range);
+        return Err(err.into());
+    }
+
+    // SAFETY: the entry points are in the loaded module
+    let init: extern "C" fn() -> isize = unsafe { core::mem::transmute(base + init) };
+    let exit: Option<extern "C" fn()> =
+        exit.map(|exit| unsafe { core::mem::transmute(base + exit) });
+
+    debug!(
+        "Module {}: loaded at {:#x}, calling module_init",
+        name, base
+    );
+
+    let ret = init();
+    if ret != 0 {
+        device::unregister_range(&range);
+        unmap_module(&range);
+        return Err(ModuleError::InitFailed(ret));
+    }
+
+    let idx = modules.partition_point(|module| module.range.start < base);
+    modules.insert(
+        idx,
+        Module {
+            name: String::from(name),
+            range,
+            exit,
+        },
+    );
+
+    info!("Module loaded: {} at {:#x} ({} bytes)", name, base, size);
+
+    Ok(())
+}
+
+/// Run `module_exit` of a module and unload it
+///
+/// fails if a device provided by the module is opened.
+pub fn remove(name: &str) -> Result<(), ModuleError> {
+    let name = name.strip_suffix(".ko").unwrap_or(name);
+    let mut modules = MODULES.lock();
+
+    let idx = modules
+        .iter()
+        .position(|module| module.name == name)
+        .ok_or(ModuleError::NotFound)?;
+
+    let range = modules[idx].range.clone();
+
+    if device::is_range_busy(&range) {
+        return Err(ModuleError::Busy);
+    }
+
+    if let Some(exit) = modules[idx].exit {
+        exit();
+    }
+
+    // the code of the devices is about to be unmapped
+    let devices = device::unregister_range(&range);
+    if !devices.is_empty() {
+        warn!("Module {}: devices left registered: {:?}", name, devices);
+    }
+
+    unmap_module(&range);
+    modules.remove(idx);
+
+    info!("Module unloaded: {}", name);
+
+    Ok(())
+}
+
+pub fn print_module_list() {
+    let modules = MODULES.lock();
+
+    let mut output = String::from("  Module           | Address            | Size\n");
+
+    for module in modules.iter() {
+        output += format!(
+            "  {:<16} | {:#018x} | {}\n",
+            module.name,
+            module.range.start,
+            module.range.end - module.range.start
+        )
+        .as_str();
+    }
+
+    output += format!("Loaded: {}\n", modules.len()).as_str();
+
+    print!("{}", output);
+}
+
+/// The first gap of `size` bytes in the module area
+fn free_range(modules: &[Module], size: u64) -> Option<u64> {
+    let mut start = MODULE_AREA_START;
+
+    for module in modules {
+        if module.range.start - start >= size {
+            return Some(start);
+        }
+        start = module.range.end;
+    }
+
+    (MODULE_AREA_START + MODULE_AREA_SIZE - start >= size).then_some(start)
+}
+
+/// Load the pages of the segments, relocated to `base`
+fn map_module(
+    elf: &ElfFile,
+    segments: &[program::ProgramHeader],
+    base: u64,
+    relocations: &[elf::Relocation],
+) -> Result<(), ElfError> {
+    let pages: BTreeSet<Page> = segments
+        .iter()
+        .filter(|segment| segment.mem_size() > 0)
+        .flat_map(|segment| {
+            let start = VirtAddr::new(base + segment.virtual_addr());
+            let end = start + (segment.mem_size() - 1);
+            Page::range_inclusive(
+                Page::containing_address(start),
+                Page::containing_address(end),
+            )
+        })
+        .collect();
+
+    let mut mapper = kernel_mapper();
+    let alloc = &mut *get_frame_alloc_for_sure();
+    let physical_offset = *PHYSICAL_OFFSET.get().unwrap();
+
+    for page in pages {
+        elf::load_segment_page(
+            elf.input,
+            physical_offset,
+            segments,
+            base,
+            relocations,
+            page,
+            &mut mapper,
+            alloc,
+            false,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Unmap a module and free its frames
+fn unmap_module(range: &Range<u64>) {
+    if range.is_empty() {
+        return;
+    }
+
+    let pages = Page::range_inclusive(
+        Page::containing_address(VirtAddr::new(range.start)),
+        Page::containing_address(VirtAddr::new(range.end - 1)),
+    );
+
+    if let Err(err) = elf::unmap_range_sparse(
+        pages,
+        &mut kernel_mapper(),
+        &mut *get_frame_alloc_for_sure(),
+        true,
+    ) {
+        warn!("Failed to unmap module at {:#x}: {:?}", range.start, err);
+    }
+}
@@ -0,0 +1,100 @@
+//! Symbols exported by the kernel to modules
+//!
+//! modules link against these names, all of them use the C ABI.
+//! strings are passed as a pointer and a length in bytes.
+
+use alloc::string::String;
+use core::alloc::Layout;
+use core::ptr::null_mut;
+
+use crate::drivers::device::{self, CharDevice, ReadFn, WriteFn};
+
+macro_rules! exports {
+    ($($func:ident),* $(,)?) => {
+        /// The names of the exported symbols
+        pub const EXPORTS: &[&str] = &[$(stringify!($func)),*];
+
+        /// The address of an exported symbol
+        pub fn lookup(name: &str) -> Option<u64> {
+            match name {
+                $(stringify!($func) => Some($func as *const () as u64),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+exports! {
+    ysos_log,
+    ysos_alloc,
+    ysos_dealloc,
+    ysos_register_chrdev,
+    ysos_unregister_chrdev,
+}
+
+unsafe fn str_from_raw<'a>(ptr: *const u8, len: usize) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+
+    core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).ok()
+}
+
+/// Log a message, `level` is 1 (error) to 5 (trace)
+unsafe extern "C" fn ysos_log(level: usize, msg: *const u8, len: usize) {
+    let msg = str_from_raw(msg, len).unwrap_or("<invalid utf-8>");
+
+    match level {
+        1 => error!("{}", msg),
+        2 => warn!("{}", msg),
+        3 => info!("{}", msg),
+        4 => debug!("{}", msg),
+        _ => trace!("{}", msg),
+    }
+}
+
+/// Allocate from the kernel heap, null on failure
+unsafe extern "C" fn ysos_alloc(size: usize, align: usize) -> *mut u8 {
+    match Layout::from_size_align(size, align) {
+        Ok(layout) if layout.size() > 0 => alloc::alloc::alloc(layout),
+        _ => null_mut(),
+    }
+}
+
+/// Free memory given by `ysos_alloc` with the same size and alignment
+unsafe extern "C" fn ysos_dealloc(ptr: *mut u8, size: usize, align: usize) {
+    if let Ok(layout) = Layout::from_size_align(size, align) {
+        if !ptr.is_null() && layout.size() > 0 {
+            alloc::alloc::dealloc(ptr, layout);
+        }
+    }
+}
+
+/// Register a character device as `/dev/<name>`, 0 on success
+///
+/// either of the entry points may be null if the operation is not supported.
+unsafe extern "C" fn ysos_register_chrdev(
+    name: *const u8,
+    len: usize,
+    read: Option<ReadFn>,
+    write: Option<WriteFn>,
+) -> isize {
+    let name = match str_from_raw(name, len) {
+        Some(name) if !name.contains('/') => String::from(name),
+        _ => return -1,
+    };
+
+    if device::register(CharDevice::new(name, read, write)) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Unregister a character device, 0 on success
+unsafe extern "C" fn ysos_unregister_chrdev(name: *const u8, len: usize) -> isize {
+    match str_from_raw(name, len) {
+        Some(name) if device::unregister(name) => 0,
+        _ => -1,
+    }
+}
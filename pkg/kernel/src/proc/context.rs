@@ -2,11 +2,21 @@ use volatile::{access::ReadOnly, VolatileRef};
 use x86_64::{
     registers::rflags::RFlags,
     structures::{gdt::SegmentSelector, idt::InterruptStackFrameValue},
-    VirtAddr,
+    PrivilegeLevel, VirtAddr,
 };
 
 use crate::{memory::gdt::get_user_selector, RegistersValue};
 
+/// Flags a debugger may change, the others are kept by the kernel
+pub const USER_FLAGS: RFlags = RFlags::CARRY_FLAG
+    .union(RFlags::PARITY_FLAG)
+    .union(RFlags::AUXILIARY_CARRY_FLAG)
+    .union(RFlags::ZERO_FLAG)
+    .union(RFlags::SIGN_FLAG)
+    .union(RFlags::TRAP_FLAG)
+    .union(RFlags::DIRECTION_FLAG)
+    .union(RFlags::OVERFLOW_FLAG);
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct ProcessContextValue {
@@ -31,6 +41,12 @@ impl ProcessContext {
         VolatileRef::from_ref(&self.value)
     }
 
+    /// Check if the context is interrupted in ring 3
+    #[inline]
+    pub fn is_user_mode(&self) -> bool {
+        self.value.stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3
+    }
+
     #[inline]
     pub fn set_rax(&mut self, value: usize) {
         self.value.regs.rax = value;
@@ -65,6 +81,14 @@ impl ProcessContext {
     }
 }
 
+impl ProcessContextValue {
+    /// Set the [`USER_FLAGS`] in `flags`, the other flags are kept
+    pub fn set_user_flags(&mut self, flags: u64) {
+        let flags = RFlags::from_bits_truncate(flags) & USER_FLAGS;
+        self.stack_frame.cpu_flags = (self.stack_frame.cpu_flags - USER_FLAGS) | flags;
+    }
+}
+
 impl Default for ProcessContextValue {
     fn default() -> Self {
         Self {
@@ -1,5 +1,5 @@
 use super::*;
-use crate::resource::ResourceSet;
+use crate::resource::{Resource, ResourceSet};
 use alloc::collections::BTreeMap;
 use spin::RwLock;
 use sync::*;
@@ -34,6 +34,29 @@ impl ProcessData {
         self.resources.read().write(fd, buf)
     }
 
+    /// Open a resource, fails if there are `limit` files opened
+    pub fn open(&self, res: Resource, limit: u64) -> Option<u8> {
+        let mut resources = self.resources.write();
+
+        if resources.handles.len() as u64 >= limit {
+            return None;
+        }
+
+        resources.open(res)
+    }
+
+    pub fn close(&self, fd: u8) -> bool {
+        self.resources.write().close(fd)
+    }
+
+    pub fn with_resource<T>(&self, fd: u8, f: impl FnOnce(&mut Resource) -> T) -> Option<T> {
+        self.resources.read().with_resource(fd, f)
+    }
+
+    pub fn open_count(&self) -> usize {
+        self.resources.read().handles.len()
+    }
+
     pub fn env(&self, key: &str) -> Option<String> {
         self.env.read().get(key).cloned()
     }
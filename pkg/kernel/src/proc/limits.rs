@@ -0,0 +1,77 @@
+use syscall_def::Rlimit;
+
+use super::vm::stack::STACK_MAX_LIMIT;
+
+/// No limit on the resource
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+pub const DEF_OPEN_FILES: u64 = 64;
+pub const DEF_PROCESSES: u64 = 64;
+
+/// The max count of open files, as file descriptors are bytes
+pub const MAX_OPEN_FILES: u64 = 256;
+
+/// Resource limits of a process
+///
+/// the stack limit is kept by `Stack` itself,
+/// limits are copied to the child on fork and spawn.
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceLimits {
+    /// the max size of the address space in bytes
+    pub address_space: u64,
+    /// the max size of resident memory in bytes
+    pub resident: u64,
+    /// the max count of open files
+    pub open_files: u64,
+    /// the max count of living children
+    pub processes: u64,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            address_space: RLIM_INFINITY,
+            resident: crate::cmdline::get().proc_mem.unwrap_or(RLIM_INFINITY),
+            open_files: DEF_OPEN_FILES,
+            processes: DEF_PROCESSES,
+        }
+    }
+}
+
+/// The values a process cannot raise its limits above
+///
+/// they are the limits of the parent when the process is created. there
+/// are no separate hard limits, so a process may raise a limit it has
+/// lowered, but never above the one it inherited.
+#[derive(Debug, Clone, Copy)]
+pub struct LimitCeilings {
+    pub limits: ResourceLimits,
+    /// the stack limit in bytes
+    pub stack: u64,
+}
+
+impl LimitCeilings {
+    /// The ceilings of a process without a parent
+    pub fn root() -> Self {
+        Self {
+            limits: ResourceLimits {
+                address_space: RLIM_INFINITY,
+                resident: RLIM_INFINITY,
+                open_files: MAX_OPEN_FILES,
+                processes: RLIM_INFINITY,
+            },
+            stack: STACK_MAX_LIMIT * crate::memory::PAGE_SIZE,
+        }
+    }
+
+    pub fn get(&self, resource: Rlimit) -> Option<u64> {
+        match resource {
+            Rlimit::Stack => Some(self.stack),
+            Rlimit::Rss => Some(self.limits.resident),
+            Rlimit::Nproc => Some(self.limits.processes),
+            Rlimit::Nofile => Some(self.limits.open_files),
+            Rlimit::As => Some(self.limits.address_space),
+            Rlimit::Unknown => None,
+        }
+    }
+}
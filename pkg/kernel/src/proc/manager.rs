@@ -3,8 +3,8 @@ use alloc::collections::BTreeSet;
 use super::*;
 use crate::{
     memory::{
-        allocator::{ALLOCATOR, HEAP_SIZE},
-        get_frame_alloc_for_sure,
+        allocator::ALLOCATOR,
+        get_frame_alloc_for_sure, swap,
         user::{USER_ALLOCATOR, USER_HEAP_SIZE},
         PAGE_SIZE,
     },
@@ -12,6 +12,7 @@ use crate::{
 };
 use alloc::{collections::BTreeMap, collections::VecDeque, format, sync::Weak};
 use spin::{Mutex, RwLock};
+use elf::ElfError;
 
 pub static PROCESS_MANAGER: spin::Once<ProcessManager> = spin::Once::new();
 
@@ -61,10 +62,14 @@ impl ProcessManager {
     }
 
     #[inline]
-    fn get_proc(&self, pid: &ProcessId) -> Option<Arc<Process>> {
+    pub(super) fn get_proc(&self, pid: &ProcessId) -> Option<Arc<Process>> {
         self.processes.read().get(pid).cloned()
     }
 
+    pub(super) fn all_processes(&self) -> Vec<Arc<Process>> {
+        self.processes.read().values().cloned().collect()
+    }
+
     pub fn current(&self) -> Arc<Process> {
         self.get_proc(&processor::current_pid())
             .expect("No current process")
@@ -75,6 +80,11 @@ impl ProcessManager {
             return Some(ret);
         };
 
+        // a tracer is told about the stops of its tracee
+        if let Some(status) = self.take_stop_status(pid) {
+            return Some(status);
+        }
+
         // push the current process to the wait queue
         let mut wait_queue = self.wait_queue.lock();
         let entry = wait_queue.entry(pid).or_default();
@@ -83,6 +93,23 @@ impl ProcessManager {
         None
     }
 
+    /// Remove `waiter` from the processes waiting for `pid`,
+    /// returns false if it is not waiting
+    pub(super) fn remove_waiter(&self, pid: ProcessId, waiter: ProcessId) -> bool {
+        let mut wait_queue = self.wait_queue.lock();
+
+        let Some(waiters) = wait_queue.get_mut(&pid) else {
+            return false;
+        };
+
+        let removed = waiters.remove(&waiter);
+        if waiters.is_empty() {
+            wait_queue.remove(&pid);
+        }
+
+        removed
+    }
+
     pub(super) fn get_ret(&self, pid: ProcessId) -> Option<isize> {
         self.get_proc(&pid).and_then(|p| p.read().exit_code())
     }
@@ -126,33 +153,48 @@ impl ProcessManager {
 
     #[inline]
     pub fn read(&self, fd: u8, buf: &mut [u8]) -> isize {
-        self.current().read().read(fd, buf)
+        let data = self.current().read().data();
+        data.read(fd, buf)
     }
 
     #[inline]
     pub fn write(&self, fd: u8, buf: &[u8]) -> isize {
-        self.current().read().write(fd, buf)
+        let data = self.current().read().data();
+        data.write(fd, buf)
     }
 
     pub fn spawn(
         &self,
-        elf: &ElfFile,
+        elf: &ElfFile<'static>,
+        file: Option<Arc<[u8]>>,
         name: String,
         parent: Option<Weak<Process>>,
         proc_data: Option<ProcessData>,
-    ) -> ProcessId {
+    ) -> Result<ProcessId, String> {
+        if let Some(parent) = parent.as_ref().and_then(|p| p.upgrade()) {
+            if !parent.read().can_fork() {
+                return Err(format!("Too many children of process #{}", parent.pid()));
+            }
+        }
+
         let kproc = self.get_proc(&KERNEL_PID).unwrap();
-        let page_table = kproc.read().clone_page_table();
+        let page_table = self
+            .retry_on_oom(|| kproc.read().clone_page_table())
+            .ok_or("Cannot alloc page table for new process.")?;
         let proc_vm = Some(ProcessVm::new(page_table));
         let proc = Process::new(name, parent, proc_vm, proc_data);
 
         let mut inner = proc.write();
         inner.pause();
-        inner.load_elf(elf);
-        inner.init_stack_frame(
-            VirtAddr::new_truncate(elf.header.pt2.entry_point()),
-            VirtAddr::new_truncate(super::stack::STACK_INIT_TOP),
-        );
+        // only retry when out of memory, a malformed ELF never loads
+        let (entry, stack_top) = self
+            .retry_on_oom(|| match inner.load_elf(elf, file.clone()) {
+                Err(ElfError::Map(_)) => None,
+                res => Some(res),
+            })
+            .ok_or("Cannot alloc stack for new process.")?
+            .map_err(|err| format!("Invalid ELF: {:?}", err))?;
+        inner.init_stack_frame(entry, stack_top);
         drop(inner);
 
         trace!("New {:#?}", &proc);
@@ -161,7 +203,7 @@ impl ProcessManager {
         self.add_proc(pid, proc);
         self.push_ready(pid);
 
-        pid
+        Ok(pid)
     }
 
     // DEPRECATED: do not spawn kernel thread
@@ -196,6 +238,11 @@ impl ProcessManager {
             
             proc.set_return_value(ret);
 
+            // a process attached by a tracer while blocked stops now
+            if self.take_stop_request(pid, &mut proc) {
+                return;
+            }
+
             proc.pause();
             self.push_ready(pid);
         }
@@ -216,17 +263,84 @@ impl ProcessManager {
         if !err_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
             let cur_proc = self.current();
             trace!(
-                "Page Fault! Checking if {:#x} is on current process's stack",
+                "Page Fault! Checking if {:#x} is mapped on demand by current process",
                 addr
             );
 
-            let mut inner = cur_proc.write();
-            inner.handle_page_fault(addr)
+            if get_frame_alloc_for_sure().frames_free() < swap::SWAP_LOW_WATERMARK {
+                swap::reclaim(swap::RECLAIM_BATCH);
+            }
+
+            self.retry_on_oom(|| {
+                cur_proc
+                    .write()
+                    .handle_page_fault(addr, err_code)
+                    .then_some(())
+            })
+            .is_some()
         } else {
             false
         }
     }
 
+    /// Run `f` again after the OOM killer has freed some frames,
+    /// if it failed because of running out of frames
+    fn retry_on_oom<T>(&self, mut f: impl FnMut() -> Option<T>) -> Option<T> {
+        if let Some(ret) = f() {
+            return Some(ret);
+        }
+
+        if get_frame_alloc_for_sure().frames_free() > 0 {
+            return None;
+        }
+
+        // try to swap out some pages before killing
+        if swap::reclaim(swap::RECLAIM_BATCH) > 0 {
+            return f();
+        }
+
+        match self.oom_kill() {
+            Some(victim) if victim != processor::current_pid() => f(),
+            _ => None,
+        }
+    }
+
+    /// Kill the process whose exit frees the most memory
+    ///
+    /// the current process is in a syscall or a page fault, so it is only
+    /// killed if there is no other process to kill.
+    pub fn oom_kill(&self) -> Option<ProcessId> {
+        let current = processor::current_pid();
+        let candidates: Vec<_> = self
+            .processes
+            .read()
+            .values()
+            .filter(|p| p.pid() != KERNEL_PID)
+            .filter(|p| p.read().status() != ProgramStatus::Dead)
+            .cloned()
+            .collect();
+
+        let victim = candidates
+            .iter()
+            .filter(|p| p.pid() != current)
+            .max_by_key(|p| p.read().vm().private_usage())
+            .or_else(|| candidates.first())
+            .cloned()?;
+
+        let (size, unit) = humanized_size(victim.read().vm().private_usage());
+        warn!(
+            "Out of memory: kill process {}#{} using {:.2} {}",
+            victim.read().name(),
+            victim.pid(),
+            size,
+            unit
+        );
+
+        self.kill(victim.pid(), 0xdead);
+
+        Some(victim.pid())
+    }
+
     pub fn kill(&self, pid: ProcessId, ret: isize) {
         let proc = self.get_proc(&pid);
 
@@ -240,6 +354,8 @@ impl ProcessManager {
         trace!("Kill {:#?}", &proc);
 
         proc.kill(ret);
+        crate::gdb::process_exited(pid, ret);
+        self.release_tracees(pid);
 
         if let Some(pids) = self.wait_queue.lock().remove(&pid) {
             for p in pids {
@@ -257,11 +373,20 @@ impl ProcessManager {
             .filter(|p| p.read().status() != ProgramStatus::Dead)
             .for_each(|p| output += format!("{}\n", p).as_str());
 
-        let heap_used = ALLOCATOR.lock().used();
-        let heap_size = HEAP_SIZE;
+        let heap_used = ALLOCATOR.used();
+        let heap_size = ALLOCATOR.size();
 
         output += &format_usage("Kernel", heap_used, heap_size);
 
+        for cache in ALLOCATOR.cache_stats().filter(|cache| cache.slabs > 0) {
+            let (size, unit) = humanized_size((cache.slabs * cache.slab_size) as u64);
+            output += format!(
+                "  {:<14} : {:>5} B x {:>6} / {:>6} ({:>6.*} {:>3})\n",
+                cache.name, cache.size, cache.used, cache.total, 2, size, unit
+            )
+            .as_str();
+        }
+
         let user_heap_used = USER_ALLOCATOR.lock().used();
         let user_heap_size = USER_HEAP_SIZE;
 
@@ -276,6 +401,42 @@ impl ProcessManager {
 
         output += &format_usage("Memory", used, total);
 
+        if let Some(stats) = swap::stats() {
+            output += &format_usage(
+                "Swap",
+                stats.used * PAGE_SIZE as usize,
+                stats.total * PAGE_SIZE as usize,
+            );
+            output += format!(
+                "  swapped in: {}, swapped out: {}\n",
+                stats.swapped_in, stats.swapped_out
+            )
+            .as_str();
+        }
+
+        for region in alloc.regions() {
+            let (size, unit) = humanized_size(region.total as u64 * PAGE_SIZE);
+            let largest = region
+                .largest_order
+                .map(|order| humanized_size((1u64 << order) * PAGE_SIZE))
+                .unwrap_or((0.0, "B"));
+
+            output += format!(
+                "  {:#012x}-{:#012x} : {:>6.*} {:>3}, {:>5.2}% free, largest {:>.0} {}\n",
+                region.start.as_u64(),
+                region.end.as_u64(),
+                2,
+                size,
+                unit,
+                region.free as f32 / region.total as f32 * 100.0,
+                largest.0,
+                largest.1
+            )
+            .as_str();
+        }
+
+        drop(alloc);
+
         output += format!("Queue  : {:?}\n", self.ready_queue.lock()).as_str();
 
         output += &processor::print_processors();
@@ -283,11 +444,24 @@ impl ProcessManager {
         print!("{}", output);
     }
 
-    pub fn fork(&self) {
+    /// Fork the current process, returns false if the child cannot be created
+    pub fn fork(&self) -> bool {
         // FIXME: get current process
         let proc = self.current();
+
+        if !proc.read().can_fork() {
+            warn!("Too many children of process #{}", proc.pid());
+            return false;
+        }
+
         // FIXME: fork to get child
-        let child = proc.fork();
+        let child = match self.retry_on_oom(|| proc.fork().ok()) {
+            Some(child) => child,
+            None => {
+                warn!("Cannot fork process #{}: out of memory", proc.pid());
+                return false;
+            }
+        };
         
         debug!("fork proc {:?}\n", child);
         
@@ -301,6 +475,7 @@ impl ProcessManager {
         // FOR DBG: maybe print the process ready queue?
         // print_process_list();
 
+        true
     }
 
 }
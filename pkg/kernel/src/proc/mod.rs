@@ -7,20 +7,31 @@ mod process;
 mod processor;
 mod vm;
 mod sync;
+mod limits;
+mod trace;
+mod stats;
 
 use alloc::sync::Arc;
+use core::alloc::Layout;
 use alloc::vec::Vec;
 use manager::*;
 use process::*;
+use limits::*;
+use trace::*;
+use stats::*;
 
-pub use context::ProcessContext;
+pub use context::{ProcessContext, ProcessContextValue};
 pub use data::ProcessData;
 pub use paging::PageTableContext;
 pub use pid::ProcessId;
 pub use vm::*;
 use xmas_elf::ElfFile;
 
+use crate::memory::slab::arc_inner_layout;
+
 use alloc::string::{String, ToString};
+use crate::cmdline::Scheduler;
+use syscall_def::{PtraceOp, PtraceRegs, PtraceStop, Rlimit};
 use x86_64::structures::idt::PageFaultErrorCode;
 use x86_64::VirtAddr;
 
@@ -28,11 +39,18 @@ use self::sync::SemaphoreResult;
 
 pub const KERNEL_PID: ProcessId = ProcessId(1);
 
+/// Layouts of process objects, which have their own slab caches
+pub const PROCESS_LAYOUT: Layout = arc_inner_layout::<Process>();
+pub const PROCESS_INNER_LAYOUT: Layout = arc_inner_layout::<spin::RwLock<ProcessInner>>();
+pub const SEMAPHORE_LAYOUT: Layout = Layout::new::<spin::Mutex<sync::Semaphore>>();
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum ProgramStatus {
     Running,
     Ready,
     Blocked,
+    /// a traced process stopped for its tracer
+    Stopped,
     Dead,
 }
 
@@ -48,6 +66,7 @@ pub fn init(boot_info: &'static boot::BootInfo) {
     kproc.write().resume();
     let app_list = boot_info.loaded_apps.as_ref();
     manager::init(kproc, app_list);
+    dylib::init(app_list);
 
     info!("Process Manager Initialized.");
 }
@@ -55,6 +74,14 @@ pub fn init(boot_info: &'static boot::BootInfo) {
 pub fn switch(context: &mut ProcessContext) {
     x86_64::instructions::interrupts::without_interrupts(|| {
         let manager = get_process_manager();
+
+        // without preemption, only the kernel gives up the cpu on ticks
+        if crate::cmdline::get().sched == Scheduler::Fifo
+            && processor::current_pid() != KERNEL_PID
+        {
+            return;
+        }
+
         let pid = manager.save_current(context);
         manager.push_ready(pid);
         manager.switch_next(context);
@@ -67,7 +94,9 @@ pub fn fork(context: &mut ProcessContext) {
         // FIXME: save_current as parent
         let parent = manager.save_current(context);
         // FIXME: fork to get child
-        manager.fork();
+        if !manager.fork() {
+            manager.current().write().set_return_value(-1);
+        }
         
         // FIXME: push to child & parent to ready queue
         manager.push_ready(parent);
@@ -121,10 +150,160 @@ pub fn write(fd: u8, buf: &[u8]) -> isize {
     x86_64::instructions::interrupts::without_interrupts(|| get_process_manager().write(fd, buf))
 }
 
+/// Open a resource in the current process
+///
+/// fails if the limit of open files is reached
+pub fn open(res: crate::resource::Resource) -> Option<u8> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let current = get_process_manager().current();
+        let inner = current.read();
+        inner.open(res, inner.rlimit(Rlimit::Nofile)?)
+    })
+}
+
+pub fn close(fd: u8) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().current().read().close(fd)
+    })
+}
+
+/// Run `f` on a resource opened by the current process
+pub fn with_resource<T>(
+    fd: u8,
+    f: impl FnOnce(&mut crate::resource::Resource) -> T,
+) -> Option<T> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().current().read().with_resource(fd, f)
+    })
+}
+
 pub fn current_pid() -> ProcessId {
     x86_64::instructions::interrupts::without_interrupts(processor::current_pid)
 }
 
+/// The load bias of the current app
+pub fn load_bias() -> u64 {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().current().read().vm().load_bias()
+    })
+}
+
+/// Read the memory of the current process through its page table
+pub fn read_memory(addr: u64, buf: &mut [u8]) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager()
+            .current()
+            .read()
+            .vm()
+            .read_memory(addr, buf)
+    })
+}
+
+/// Write the memory of the current process through its page table
+pub fn write_memory(addr: u64, data: &[u8]) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager()
+            .current()
+            .read()
+            .vm()
+            .write_memory(addr, data)
+    })
+}
+
+/// Attach, detach or resume a tracee of the current process
+pub fn ptrace_control(op: PtraceOp, pid: ProcessId) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let manager = get_process_manager();
+
+        match op {
+            PtraceOp::Attach => manager.ptrace_attach(pid),
+            PtraceOp::Detach => manager.ptrace_detach(pid),
+            PtraceOp::Cont => manager.ptrace_resume(pid, false, false),
+            PtraceOp::SingleStep => manager.ptrace_resume(pid, true, false),
+            PtraceOp::Syscall => manager.ptrace_resume(pid, false, true),
+            _ => false,
+        }
+    })
+}
+
+pub fn ptrace_peek(pid: ProcessId, addr: u64) -> Option<u64> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().ptrace_peek(pid, addr)
+    })
+}
+
+pub fn ptrace_poke(pid: ProcessId, addr: u64, word: u64) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().ptrace_poke(pid, addr, word)
+    })
+}
+
+pub fn ptrace_get_regs(pid: ProcessId) -> Option<PtraceRegs> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().ptrace_get_regs(pid)
+    })
+}
+
+pub fn ptrace_set_regs(pid: ProcessId, regs: &PtraceRegs) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().ptrace_set_regs(pid, regs)
+    })
+}
+
+/// Stop the current process on a trap if it is traced,
+/// returns false if it is not
+pub fn trace_trap(reason: PtraceStop, context: &mut ProcessContext) -> bool {
+    if !context.is_user_mode() {
+        return false;
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().stop_current(reason, context)
+    })
+}
+
+/// Stop the current process at a syscall entry if its tracer asked to,
+/// returns true if it is stopped
+pub fn trace_syscall_entry(context: &mut ProcessContext) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().trace_syscall_entry(context)
+    })
+}
+
+/// Stop `pid` at the exit of a syscall if its tracer asked to
+pub fn trace_syscall_exit(pid: ProcessId, context: &mut ProcessContext) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().trace_syscall_exit(pid, context)
+    })
+}
+
+/// Enable or disable the syscall tracer of a process
+pub fn strace(pid: ProcessId, enable: bool) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().set_strace(pid, enable)
+    })
+}
+
+/// Check if the syscalls of the current process are logged
+pub fn strace_enabled() -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().current().read().strace()
+    })
+}
+
+/// Count a syscall of `pid` which took `cycles` TSC cycles
+pub fn record_syscall(pid: ProcessId, syscall: usize, cycles: u64) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().record_syscall(pid, syscall, cycles)
+    })
+}
+
+pub fn print_syscall_stats(pid: ProcessId) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().print_syscall_stats(pid)
+    })
+}
+
 pub fn kill(pid: ProcessId, context: &mut ProcessContext) {
     x86_64::instructions::interrupts::without_interrupts(|| {
         let manager = get_process_manager();
@@ -137,32 +316,83 @@ pub fn kill(pid: ProcessId, context: &mut ProcessContext) {
     })
 }
 
+/// Spawn a program by its path, or by its name in `/bin` of the initramfs,
+/// in `\APP\` of the ESP and then in the app list of the bootloader
 pub fn spawn(name: &str) -> Result<ProcessId, String> {
+    let (elf, file) = find_app(name)?;
+
+    // the process is named after the file
+    let name = name.rsplit('/').next().unwrap_or(name);
+
+    if dylib::is_library(name) {
+        return Err(format!("Cannot run a shared library: {}", name));
+    }
+
+    if crate::module::is_module(name) {
+        return Err(format!("Cannot run a kernel module: {}", name));
+    }
+
+    elf_spawn(name.to_string(), &elf, file)
+}
+
+/// The ELF file of an app, with the owner of its content
+/// if it was read from a filesystem
+fn find_app(name: &str) -> Result<(ElfFile<'static>, Option<Arc<[u8]>>), String> {
+    let paths = if name.starts_with('/') {
+        vec![String::from(name)]
+    } else {
+        vec![
+            format!("/bin/{}", name),
+            format!("{}/APP/{}", crate::fs::ESP_PATH, name),
+        ]
+    };
+
+    for path in paths {
+        let (data, file) = match crate::fs::load(&path) {
+            Ok(crate::fs::FileData::Static(data)) => (data, None),
+            Ok(crate::fs::FileData::Shared(file)) => {
+                // the segments of the process keep the file alive
+                (unsafe { &*Arc::as_ptr(&file) }, Some(file))
+            }
+            Err(crate::fs::FsError::NotFound) => continue,
+            Err(err) => return Err(format!("Cannot load {}: {:?}", path, err)),
+        };
+
+        return ElfFile::new(data)
+            .map(|elf| (elf, file))
+            .map_err(|err| format!("Invalid ELF {}: {}", path, err));
+    }
+
     let app = x86_64::instructions::interrupts::without_interrupts(|| {
         let app_list = get_process_manager().app_list()?;
 
         app_list.iter().find(|&app| app.name.eq(name))
     });
 
-    if app.is_none() {
-        return Err(format!("App not found: {}", name));
-    };
-
-    elf_spawn(name.to_string(), &app.unwrap().elf)
+    match app {
+        Some(app) => ElfFile::new(app.elf.input)
+            .map(|elf| (elf, None))
+            .map_err(|err| format!("Invalid ELF {}: {}", name, err)),
+        None => Err(format!("App not found: {}", name)),
+    }
 }
 
-pub fn elf_spawn(name: String, elf: &ElfFile) -> Result<ProcessId, String> {
+pub fn elf_spawn(
+    name: String,
+    elf: &ElfFile<'static>,
+    file: Option<Arc<[u8]>>,
+) -> Result<ProcessId, String> {
     let pid = x86_64::instructions::interrupts::without_interrupts(|| {
         let manager = get_process_manager();
         let process_name = name.to_lowercase();
 
         let parent = Arc::downgrade(&manager.current());
 
-        let pid = manager.spawn(elf, name, Some(parent), None);
+        let pid = manager.spawn(elf, file, name, Some(parent), None)?;
 
         debug!("Spawned process: {}#{}", process_name, pid);
-        pid
-    });
+        Ok::<_, String>(pid)
+    })?;
 
     Ok(pid)
 }
@@ -177,22 +407,122 @@ pub fn handle_page_fault(addr: VirtAddr, err_code: PageFaultErrorCode) -> bool {
     })
 }
 
+/// Kill the current process on a page fault that cannot be handled
+pub fn kill_on_page_fault(
+    addr: VirtAddr,
+    err_code: PageFaultErrorCode,
+    context: &mut ProcessContext,
+) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let manager = get_process_manager();
+        let pid = processor::current_pid();
+
+        // the process may have been killed by the OOM killer
+        if manager.current().read().status() == ProgramStatus::Dead {
+            manager.switch_next(context);
+            return;
+        }
+
+        if manager.current().read().vm().is_stack_overflow(addr) {
+            warn!(
+                "Stack overflow in pid {}: {:#x} is beyond the stack limit",
+                pid, addr
+            );
+        } else {
+            warn!(
+                "Segmentation fault in pid {}: trying to access {:#x} ({:?})",
+                pid, addr, err_code
+            );
+        }
+
+        // a fault in copy_from_user/copy_to_user has the kernel context
+        if err_code.contains(PageFaultErrorCode::USER_MODE) {
+            manager.current().read().vm().print_backtrace(
+                context.stack_frame.instruction_pointer.as_u64(),
+                context.regs.rbp as u64,
+            );
+        }
+
+        manager.kill_self(0xdead);
+        manager.switch_next(context);
+    })
+}
+
+pub fn is_stack_overflow(addr: VirtAddr) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager()
+            .current()
+            .read()
+            .vm()
+            .is_stack_overflow(addr)
+    })
+}
+
+pub fn get_rlimit(resource: Rlimit) -> Option<u64> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().current().read().rlimit(resource)
+    })
+}
+
+pub fn set_rlimit(resource: Rlimit, limit: u64) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager()
+            .current()
+            .write()
+            .set_rlimit(resource, limit)
+    })
+}
+
+pub fn get_fs_base() -> u64 {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager().current().read().fs_base().as_u64()
+    })
+}
+
+/// Set the thread pointer of the current process
+///
+/// fails if the address is not in the user space
+pub fn set_fs_base(addr: u64) -> bool {
+    if !crate::memory::uaccess::is_user_range(addr, 0) {
+        return false;
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let fs_base = VirtAddr::new(addr);
+        get_process_manager().current().write().set_fs_base(fs_base);
+        // the context is not restored when returning from the syscall
+        x86_64::registers::model_specific::FsBase::write(fs_base);
+    });
+
+    true
+}
+
 pub fn list_app() {
     x86_64::instructions::interrupts::without_interrupts(|| {
-        let app_list = get_process_manager().app_list();
-        if app_list.is_none() {
+        let esp_apps = format!("{}/APP", crate::fs::ESP_PATH);
+
+        let mut apps: Vec<String> = ["/bin", esp_apps.as_str()]
+            .into_iter()
+            .filter_map(|dir| crate::fs::lookup(dir).and_then(|dir| dir.read_dir()).ok())
+            .flatten()
+            .filter(|(_, ty)| *ty == syscall_def::FileType::File)
+            .map(|(name, _)| name)
+            .collect();
+
+        apps.extend(
+            get_process_manager()
+                .app_list()
+                .into_iter()
+                .flatten()
+                .map(|app| app.name.to_string()),
+        );
+
+        if apps.is_empty() {
             println!(">>> No app found in list!");
             return;
         }
 
-        let apps = app_list
-            .unwrap()
-            .iter()
-            .map(|app| app.name.as_str())
-            .collect::<Vec<&str>>()
-            .join(", ");
-
-        println!(">>> App list: {}", apps);
+        println!(">>> App list: {}", apps.join(", "));
     });
 }
 
@@ -31,12 +31,10 @@ impl PageTableContext {
         }
     }
 
-    pub fn clone_level_4(&self) -> Self {
+    pub fn clone_level_4(&self) -> Option<Self> {
         // 1. alloc new page table
         let mut frame_alloc = crate::memory::get_frame_alloc_for_sure();
-        let page_table_addr = frame_alloc
-            .allocate_frame()
-            .expect("Cannot alloc page table for new process.");
+        let page_table_addr = frame_alloc.allocate_frame()?;
 
         // 2. copy current page table to new page table
         unsafe {
@@ -48,9 +46,9 @@ impl PageTableContext {
         }
 
         // 3. create page table
-        Self {
+        Some(Self {
             reg: Arc::new(Cr3RegValue::new(page_table_addr, Cr3Flags::empty())),
-        }
+        })
     }
 
     pub fn using_count(&self) -> usize {
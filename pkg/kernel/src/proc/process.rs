@@ -2,6 +2,9 @@ use super::*;
 use alloc::sync::Weak;
 use spin::*;
 use crate::humanized_size;
+use x86_64::structures::paging::{mapper::MapToError, PageSize, Size2MiB, Size4KiB};
+use elf::ElfError;
+use x86_64::registers::model_specific::FsBase;
 
 #[derive(Clone)]
 pub struct Process {
@@ -16,10 +19,19 @@ pub struct ProcessInner {
     ticks_passed: usize,
     status: ProgramStatus,
     context: ProcessContext,
+    /// the thread pointer, saved alongside the context
+    fs_base: VirtAddr,
     exit_code: Option<isize>,
     proc_data: Option<ProcessData>,
     proc_vm: Option<ProcessVm>,
-
+    limits: ResourceLimits,
+    /// the limits cannot be raised above these
+    ceilings: LimitCeilings,
+    /// set while the process is traced by `Ptrace`
+    trace: Option<Trace>,
+    /// log the syscalls of the process
+    strace: bool,
+    syscall_stats: SyscallStats,
 }
 
 impl Process {
@@ -48,18 +60,35 @@ impl Process {
 
         // create context
         let pid = ProcessId::new();
-        let proc_vm = proc_vm.unwrap_or_else(|| ProcessVm::new(PageTableContext::new()));
+        let mut proc_vm = proc_vm.unwrap_or_else(|| ProcessVm::new(PageTableContext::new()));
+
+        // inherit limits from the parent, which cannot be raised above them
+        let (limits, ceilings) = match parent.as_ref().and_then(|p| p.upgrade()) {
+            Some(p) => {
+                let ceilings = p.read().limit_ceilings();
+                // kept as the default if the initial stack is already larger
+                proc_vm.set_stack_limit(ceilings.stack);
+                (ceilings.limits, ceilings)
+            }
+            None => (ResourceLimits::default(), LimitCeilings::root()),
+        };
 
         let inner = ProcessInner {
             name,
             parent,
             status: ProgramStatus::Ready,
             context: ProcessContext::default(),
+            fs_base: VirtAddr::zero(),
             ticks_passed: 0,
             exit_code: None,
             children: Vec::new(),
             proc_vm: Some(proc_vm),
             proc_data: Some(proc_data.unwrap_or_default()),
+            limits,
+            ceilings,
+            trace: None,
+            strace: crate::cmdline::get().strace(pid),
+            syscall_stats: SyscallStats::default(),
         };
 
         trace!("New process {}#{} created.", &inner.name, pid);
@@ -85,13 +114,14 @@ impl Process {
         inner.kill(ret);
     }
 
-    pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+    pub fn fork(self: &Arc<Self>) -> Result<Arc<Self>, MapToError<Size4KiB>> {
         // FIXME: lock inner as write
         let mut inner = self.write();
 
         // FIXME: inner fork with parent weak ref
-        let child_inner = inner.fork(Arc::downgrade(self));
+        let mut child_inner = inner.fork(Arc::downgrade(self))?;
         let child_pid = ProcessId::new();
+        child_inner.set_strace(crate::cmdline::get().strace(child_pid));
         // FOR DBG: maybe print the child process info
         //          e.g. parent, name, pid, etc.
 
@@ -111,7 +141,7 @@ impl Process {
         drop(inner);
 
         // FIXME: mark the child as ready & return it
-        child
+        Ok(child)
     }
 
 }
@@ -141,6 +171,51 @@ impl ProcessInner {
         self.status = ProgramStatus::Blocked;
     }
 
+    /// Stop a traced process, it is not scheduled until resumed
+    pub fn stop(&mut self, reason: PtraceStop) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.stop(reason);
+            self.status = ProgramStatus::Stopped;
+        }
+    }
+
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    pub fn trace_mut(&mut self) -> Option<&mut Trace> {
+        self.trace.as_mut()
+    }
+
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace;
+    }
+
+    pub fn strace(&self) -> bool {
+        self.strace
+    }
+
+    pub fn set_strace(&mut self, enable: bool) {
+        self.strace = enable;
+    }
+
+    pub fn syscall_stats(&self) -> &SyscallStats {
+        &self.syscall_stats
+    }
+
+    pub fn syscall_stats_mut(&mut self) -> &mut SyscallStats {
+        &mut self.syscall_stats
+    }
+
+    /// The saved context, valid while the process is not running
+    pub fn context(&self) -> &ProcessContext {
+        &self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut ProcessContext {
+        &mut self.context
+    }
+
     pub fn is_ready(&self) -> bool {
         self.status == ProgramStatus::Ready
     }
@@ -149,6 +224,14 @@ impl ProcessInner {
         self.exit_code
     }
 
+    /// Clone the process data, which is shared by reference
+    ///
+    /// use it to release the process lock before accessing user memory,
+    /// as pages may be mapped on demand by the page fault handler.
+    pub fn data(&self) -> ProcessData {
+        (**self).clone()
+    }
+
     pub fn vm(&self) -> &ProcessVm {
         self.proc_vm.as_ref().unwrap()
     }
@@ -157,26 +240,129 @@ impl ProcessInner {
         self.proc_vm.as_mut().unwrap()
     }
 
-    pub fn handle_page_fault(&mut self, addr: VirtAddr) -> bool {
-        self.vm_mut().handle_page_fault(addr)
+    pub fn handle_page_fault(&mut self, addr: VirtAddr, err_code: PageFaultErrorCode) -> bool {
+        let vm = self.vm();
+
+        if vm.memory_usage() + crate::memory::PAGE_SIZE > self.limits.resident {
+            warn!("{}: resident memory limit exceeded at {:#x}", self.name, addr);
+            return false;
+        }
+
+        if vm.address_space_size() + vm.stack_grow_size(addr) > self.limits.address_space {
+            warn!("{}: address space limit exceeded at {:#x}", self.name, addr);
+            return false;
+        }
+
+        // a 2 MiB heap page must also fit in the limit
+        let allow_huge = vm.memory_usage() + Size2MiB::SIZE <= self.limits.resident;
+
+        self.vm_mut().handle_page_fault(addr, err_code, allow_huge)
+    }
+
+    pub fn rlimit(&self, resource: Rlimit) -> Option<u64> {
+        match resource {
+            Rlimit::Stack => Some(self.vm().stack_limit()),
+            Rlimit::Rss => Some(self.limits.resident),
+            Rlimit::Nproc => Some(self.limits.processes),
+            Rlimit::Nofile => Some(self.limits.open_files),
+            Rlimit::As => Some(self.limits.address_space),
+            Rlimit::Unknown => None,
+        }
+    }
+
+    /// The limits of the process, as ceilings of its children
+    fn limit_ceilings(&self) -> LimitCeilings {
+        LimitCeilings {
+            limits: self.limits,
+            stack: self.vm().stack_limit(),
+        }
+    }
+
+    /// Set a resource limit
+    ///
+    /// fails if the resource is already in use beyond the new limit,
+    /// or if the limit is above the one inherited from the parent.
+    /// the count of open files is capped at `MAX_OPEN_FILES`.
+    pub fn set_rlimit(&mut self, resource: Rlimit, limit: u64) -> bool {
+        let limit = match resource {
+            Rlimit::Nofile => limit.min(MAX_OPEN_FILES),
+            _ => limit,
+        };
+
+        if self.ceilings.get(resource).is_none_or(|ceiling| limit > ceiling) {
+            return false;
+        }
+
+        let (current, slot) = match resource {
+            Rlimit::Stack => return self.vm_mut().set_stack_limit(limit),
+            Rlimit::Rss => (self.vm().memory_usage(), &mut self.limits.resident),
+            Rlimit::Nproc => (self.living_children() as u64, &mut self.limits.processes),
+            Rlimit::Nofile => (
+                self.proc_data.as_ref().map_or(0, |data| data.open_count()) as u64,
+                &mut self.limits.open_files,
+            ),
+            Rlimit::As => (
+                self.vm().address_space_size(),
+                &mut self.limits.address_space,
+            ),
+            Rlimit::Unknown => return false,
+        };
+
+        if limit < current {
+            return false;
+        }
+
+        *slot = limit;
+        true
+    }
+
+    pub fn living_children(&self) -> usize {
+        self.children
+            .iter()
+            .filter(|child| child.read().status() != ProgramStatus::Dead)
+            .count()
+    }
+
+    /// Check if the process can have one more child
+    pub fn can_fork(&self) -> bool {
+        (self.living_children() as u64) < self.limits.processes
     }
 
     pub fn set_return_value(&mut self, ret: isize) {
         self.context.set_rax(ret as usize);
     }
 
-    pub fn clone_page_table(&self) -> PageTableContext {
+    pub fn clone_page_table(&self) -> Option<PageTableContext> {
         self.vm().page_table.clone_level_4()
     }
 
-    pub fn load_elf(&mut self, elf: &ElfFile) {
-        self.vm_mut().load_elf(elf)
+    /// Load an ELF file, returns the entry point and the initial stack pointer
+    ///
+    /// the thread pointer is set to the TLS block of the file
+    pub fn load_elf(
+        &mut self,
+        elf: &ElfFile<'static>,
+        file: Option<Arc<[u8]>>,
+    ) -> Result<(VirtAddr, VirtAddr), ElfError> {
+        let (entry, stack_top, fs_base) = self.vm_mut().load_elf(elf, file)?;
+        self.fs_base = fs_base;
+        Ok((entry, stack_top))
+    }
+
+    pub fn fs_base(&self) -> VirtAddr {
+        self.fs_base
+    }
+
+    /// Set the thread pointer, which is loaded on the next restore
+    pub fn set_fs_base(&mut self, fs_base: VirtAddr) {
+        self.fs_base = fs_base;
     }
 
     /// Save the process's context
     /// mark the process as ready
     pub(super) fn save(&mut self, context: &ProcessContext) {
         self.context.save(context);
+        self.fs_base = FsBase::read();
         self.status = ProgramStatus::Ready;
     }
 
@@ -184,6 +370,7 @@ impl ProcessInner {
     /// mark the process as running
     pub(super) fn restore(&mut self, context: &mut ProcessContext) {
         self.context.restore(context);
+        FsBase::write(self.fs_base);
         self.vm().page_table.load();
         self.status = ProgramStatus::Running;
     }
@@ -203,13 +390,13 @@ impl ProcessInner {
         self.status = ProgramStatus::Dead;
     }
 
-    pub fn fork(&mut self, parent: Weak<Process>) -> ProcessInner {
+    pub fn fork(&mut self, parent: Weak<Process>) -> Result<ProcessInner, MapToError<Size4KiB>> {
         // 这里不能改self，因为self是从上面的inner继承来的，实际还是在一个parent里面
         // 应该返回一个构造而不是Self
 
         // FIXME: fork the process virtual memory struct
         // FIXME: calculate the real stack offset
-        let new_vm = self.vm().fork(self.children.len() as u64);
+        let new_vm = self.vm().fork(self.children.len() as u64)?;
         let offset = new_vm.stack.stack_offset(&self.vm().stack);
 
         // FIXME: update `rsp` in interrupt stack frame
@@ -219,23 +406,39 @@ impl ProcessInner {
         new_context.set_stack_offset(offset);
         new_context.set_rax(0);
 
+        let fs_base = tls::fork_thread_pointer(self.fs_base, self.vm().stack.range(), offset);
+
         // FIXME: construct the child process inner
         // NOTE: return inner because there's no pid record in inner
-        Self {
+        Ok(Self {
             name: self.name.clone(),
             parent: Some(parent),
             children: Vec::new(),
             ticks_passed: 0,
             status: ProgramStatus::Ready,
             context: new_context,
+            fs_base,
             exit_code: None,
             proc_data: self.proc_data.clone(),
             proc_vm: Some(new_vm),
-        }
-
+            limits: self.limits,
+            ceilings: self.limit_ceilings(),
+            // the child is not traced
+            trace: None,
+            strace: false,
+            syscall_stats: SyscallStats::default(),
+        })
     }
 
     pub fn brk(&self, addr: Option<usize>) -> usize {
+        if let Some(new_end) = addr {
+            let grow = (new_end as u64).saturating_sub(self.vm().heap.end());
+            if self.vm().address_space_size() + grow > self.limits.address_space {
+                warn!("{}: address space limit exceeded by brk", self.name);
+                return !0;
+            }
+        }
+
         match self.vm().brk(addr.map(|a| VirtAddr::new(a as u64))) {
             Some(addr) => addr.as_u64() as usize,
             None => !0,
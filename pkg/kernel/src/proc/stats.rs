@@ -0,0 +1,97 @@
+//! Per-process syscall statistics
+//!
+//! durations are measured in TSC cycles, from the dispatch of a syscall
+//! until it returns or switches to another process.
+
+use alloc::{collections::BTreeMap, format};
+use syscall_def::Syscall;
+
+use super::*;
+
+#[derive(Clone, Copy, Default)]
+struct SyscallCounter {
+    count: u64,
+    cycles: u64,
+    max_cycles: u64,
+}
+
+#[derive(Clone, Default)]
+pub struct SyscallStats {
+    /// counters by syscall number
+    counters: BTreeMap<usize, SyscallCounter>,
+}
+
+impl SyscallStats {
+    pub fn record(&mut self, syscall: usize, cycles: u64) {
+        let counter = self.counters.entry(syscall).or_default();
+        counter.count += 1;
+        counter.cycles += cycles;
+        counter.max_cycles = counter.max_cycles.max(cycles);
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counters.values().map(|counter| counter.count).sum()
+    }
+}
+
+impl core::fmt::Display for SyscallStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        writeln!(
+            f,
+            "  Syscall      |  Count  | Avg Cycles | Max Cycles | Total Cycles"
+        )?;
+
+        for (&syscall, counter) in self.counters.iter() {
+            writeln!(
+                f,
+                "  {:<12} | {:>7} | {:>10} | {:>10} | {:>12}",
+                format!("{:?}", Syscall::from(syscall)),
+                counter.count,
+                counter.cycles / counter.count,
+                counter.max_cycles,
+                counter.cycles
+            )?;
+        }
+
+        writeln!(f, "Total: {}", self.total())
+    }
+}
+
+impl ProcessManager {
+    /// Enable or disable the syscall tracer of a process
+    pub fn set_strace(&self, pid: ProcessId, enable: bool) -> bool {
+        let Some(proc) = self.get_proc(&pid) else {
+            return false;
+        };
+
+        let mut inner = proc.write();
+
+        if inner.status() == ProgramStatus::Dead {
+            return false;
+        }
+
+        inner.set_strace(enable);
+        true
+    }
+
+    pub fn record_syscall(&self, pid: ProcessId, syscall: usize, cycles: u64) {
+        if let Some(proc) = self.get_proc(&pid) {
+            proc.write().syscall_stats_mut().record(syscall, cycles);
+        }
+    }
+
+    pub fn print_syscall_stats(&self, pid: ProcessId) -> bool {
+        let Some(proc) = self.get_proc(&pid) else {
+            return false;
+        };
+
+        let inner = proc.read();
+        print!(
+            "Syscalls of {}#{}:\n{}",
+            inner.name(),
+            pid,
+            inner.syscall_stats()
+        );
+        true
+    }
+}
@@ -1,4 +1,5 @@
 use super::ProcessId;
+use alloc::boxed::Box;
 use alloc::collections::*;
 use spin::Mutex;
 
@@ -71,7 +72,8 @@ impl Semaphore {
 
 #[derive(Debug, Default)]
 pub struct SemaphoreSet {
-    sems: BTreeMap<SemaphoreId, Mutex<Semaphore>>,
+    // boxed to be allocated from the semaphore cache
+    sems: BTreeMap<SemaphoreId, Box<Mutex<Semaphore>>>,
 }
 
 impl SemaphoreSet {
@@ -80,7 +82,7 @@ impl SemaphoreSet {
 
         // FIXME: insert a new semaphore into the sems
         //          use `insert(/* ... */).is_none()`
-        self.sems.insert(SemaphoreId::new(key), Mutex::new(Semaphore::new(value))).is_none() 
+        self.sems.insert(SemaphoreId::new(key), Box::new(Mutex::new(Semaphore::new(value)))).is_none() 
     }
 
     pub fn remove(&mut self, key: u32) -> bool {
@@ -0,0 +1,381 @@
+//! Process tracing with `Ptrace`
+//!
+//! A tracer attaches to a process, which stops at once, or when it is
+//! woken up if it is blocked. A tracee also stops on breakpoints, single steps and, if
+//! asked to, on syscall entry and exit. Stops are reported to the tracer
+//! through `WaitPid`, as statuses given by [`PtraceStop::status`], and the
+//! tracer reads and writes the tracee while it is stopped.
+//!
+//! A syscall entry stop rewinds the `int 0x80`, so the syscall runs when
+//! the tracee resumes, with the registers as left by the tracer. syscalls
+//! which switch to another process (exit, blocking or yielding ones) have
+//! no exit stop.
+
+use syscall_def::PtraceRegs;
+use x86_64::registers::rflags::RFlags;
+
+use super::*;
+
+/// Size of the `int 0x80` instruction
+const SYSCALL_INSN_SIZE: u64 = 2;
+
+pub struct Trace {
+    tracer: ProcessId,
+    /// why the tracee is stopped, `None` if it is not
+    stop: Option<PtraceStop>,
+    /// the stop is reported to the tracer
+    reported: bool,
+    /// stop the tracee when it is woken up
+    stop_requested: bool,
+    /// stop at syscall entry and exit
+    syscall: bool,
+    /// the tracee stopped at the entry of the syscall in progress
+    in_syscall: bool,
+}
+
+impl Trace {
+    pub fn new(tracer: ProcessId) -> Self {
+        Self {
+            tracer,
+            stop: None,
+            reported: false,
+            stop_requested: true,
+            syscall: false,
+            in_syscall: false,
+        }
+    }
+
+    pub fn tracer(&self) -> ProcessId {
+        self.tracer
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.is_some()
+    }
+
+    pub(super) fn stop(&mut self, reason: PtraceStop) {
+        self.stop = Some(reason);
+        self.reported = false;
+        self.stop_requested = false;
+    }
+
+    /// The status of a stop not yet reported to the tracer
+    fn take_unreported(&mut self) -> Option<isize> {
+        if self.reported {
+            return None;
+        }
+
+        self.reported = true;
+        self.stop.map(PtraceStop::status)
+    }
+}
+
+impl ProcessManager {
+    /// Trace a process by the current process
+    pub fn ptrace_attach(&self, pid: ProcessId) -> bool {
+        let tracer = processor::current_pid();
+
+        if pid == tracer || pid == KERNEL_PID {
+            return false;
+        }
+
+        // a tracer cannot be stopped by its tracee
+        if self.current().read().trace().map(Trace::tracer) == Some(pid) {
+            return false;
+        }
+
+        let Some(proc) = self.get_proc(&pid) else {
+            return false;
+        };
+
+        let mut inner = proc.write();
+
+        if inner.status() == ProgramStatus::Dead || inner.trace().is_some() {
+            return false;
+        }
+
+        inner.set_trace(Some(Trace::new(tracer)));
+
+        // the tracee is not running, as the tracer is
+        if inner.is_ready() {
+            inner.stop(PtraceStop::Attach);
+        }
+
+        debug!("Process #{} is traced by #{}", pid, tracer);
+
+        true
+    }
+
+    /// Stop tracing a process and resume it if it is stopped
+    pub fn ptrace_detach(&self, pid: ProcessId) -> bool {
+        let Some(proc) = self.get_proc(&pid) else {
+            return false;
+        };
+
+        let mut inner = proc.write();
+
+        if inner.trace().map(Trace::tracer) != Some(processor::current_pid()) {
+            return false;
+        }
+
+        self.detach(pid, &mut inner);
+        true
+    }
+
+    /// Resume a stopped tracee, single stepping or stopping at syscalls
+    pub fn ptrace_resume(&self, pid: ProcessId, step: bool, syscall: bool) -> bool {
+        let resumed = self
+            .with_stopped(pid, |inner| {
+                let mut value = inner.context().as_ref().as_ptr().read();
+                value.stack_frame.cpu_flags.set(RFlags::TRAP_FLAG, step);
+                inner.context_mut().as_mut().as_mut_ptr().write(value);
+
+                if let Some(trace) = inner.trace_mut() {
+                    trace.stop = None;
+                    trace.syscall = syscall;
+                }
+
+                inner.pause();
+            })
+            .is_some();
+
+        if resumed {
+            self.push_ready(pid);
+        }
+
+        resumed
+    }
+
+    /// Read a word of a stopped tracee
+    pub fn ptrace_peek(&self, pid: ProcessId, addr: u64) -> Option<u64> {
+        self.with_stopped(pid, |inner| {
+            let mut word = [0u8; 8];
+            inner
+                .vm()
+                .read_memory(addr, &mut word)
+                .then(|| u64::from_ne_bytes(word))
+        })
+        .flatten()
+    }
+
+    /// Write a word of a stopped tracee, read-only pages are written too
+    pub fn ptrace_poke(&self, pid: ProcessId, addr: u64, word: u64) -> bool {
+        self.with_stopped(pid, |inner| {
+            inner.vm().write_memory(addr, &word.to_ne_bytes())
+        })
+        .unwrap_or(false)
+    }
+
+    pub fn ptrace_get_regs(&self, pid: ProcessId) -> Option<PtraceRegs> {
+        self.with_stopped(pid, |inner| {
+            let value = inner.context().as_ref().as_ptr().read();
+            let regs = &value.regs;
+            let frame = &value.stack_frame;
+
+            PtraceRegs {
+                rax: regs.rax as u64,
+                rbx: regs.rbx as u64,
+                rcx: regs.rcx as u64,
+                rdx: regs.rdx as u64,
+                rsi: regs.rsi as u64,
+                rdi: regs.rdi as u64,
+                rbp: regs.rbp as u64,
+                rsp: frame.stack_pointer.as_u64(),
+                r8: regs.r8 as u64,
+                r9: regs.r9 as u64,
+                r10: regs.r10 as u64,
+                r11: regs.r11 as u64,
+                r12: regs.r12 as u64,
+                r13: regs.r13 as u64,
+                r14: regs.r14 as u64,
+                r15: regs.r15 as u64,
+                rip: frame.instruction_pointer.as_u64(),
+                rflags: frame.cpu_flags.bits(),
+            }
+        })
+    }
+
+    /// Set the registers of a stopped tracee
+    ///
+    /// fails if `rip` or `rsp` is not canonical.
+    pub fn ptrace_set_regs(&self, pid: ProcessId, new: &PtraceRegs) -> bool {
+        let (Ok(rip), Ok(rsp)) = (VirtAddr::try_new(new.rip), VirtAddr::try_new(new.rsp)) else {
+            return false;
+        };
+
+        self.with_stopped(pid, |inner| {
+            let mut value = inner.context().as_ref().as_ptr().read();
+            let regs = &mut value.regs;
+
+            regs.rax = new.rax as usize;
+            regs.rbx = new.rbx as usize;
+            regs.rcx = new.rcx as usize;
+            regs.rdx = new.rdx as usize;
+            regs.rsi = new.rsi as usize;
+            regs.rdi = new.rdi as usize;
+            regs.rbp = new.rbp as usize;
+            regs.r8 = new.r8 as usize;
+            regs.r9 = new.r9 as usize;
+            regs.r10 = new.r10 as usize;
+            regs.r11 = new.r11 as usize;
+            regs.r12 = new.r12 as usize;
+            regs.r13 = new.r13 as usize;
+            regs.r14 = new.r14 as usize;
+            regs.r15 = new.r15 as usize;
+
+            value.stack_frame.stack_pointer = rsp;
+            value.stack_frame.instruction_pointer = rip;
+            value.set_user_flags(new.rflags);
+
+            inner.context_mut().as_mut().as_mut_ptr().write(value);
+        })
+        .is_some()
+    }
+
+    /// The status of an unreported stop of `pid`, if the current process traces it
+    pub(super) fn take_stop_status(&self, pid: ProcessId) -> Option<isize> {
+        let proc = self.get_proc(&pid)?;
+        let mut inner = proc.write();
+        let trace = inner.trace_mut()?;
+
+        if trace.tracer != processor::current_pid() {
+            return None;
+        }
+
+        trace.take_unreported()
+    }
+
+    /// Stop a process being woken up if it is attached while blocked,
+    /// returns false if it can run
+    pub(super) fn take_stop_request(&self, pid: ProcessId, inner: &mut ProcessInner) -> bool {
+        match inner.trace() {
+            Some(trace) if trace.stop_requested => {
+                inner.stop(PtraceStop::Attach);
+                self.notify_stop(pid, inner);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stop the current process if it is traced
+    ///
+    /// returns false if it is not traced, and the process keeps running.
+    pub fn stop_current(&self, reason: PtraceStop, context: &mut ProcessContext) -> bool {
+        let proc = self.current();
+
+        if proc.read().trace().is_none() {
+            return false;
+        }
+
+        let pid = self.save_current(context);
+
+        {
+            let mut inner = proc.write();
+            inner.stop(reason);
+            self.notify_stop(pid, &mut inner);
+        }
+
+        self.switch_next(context);
+        true
+    }
+
+    /// Stop at the entry of a syscall if the tracer asked to
+    ///
+    /// the `int 0x80` is rewound, so the syscall runs when the process resumes.
+    pub fn trace_syscall_entry(&self, context: &mut ProcessContext) -> bool {
+        let proc = self.current();
+
+        {
+            let mut inner = proc.write();
+            match inner.trace_mut() {
+                Some(trace) if trace.syscall && !trace.in_syscall => trace.in_syscall = true,
+                _ => return false,
+            }
+        }
+
+        let mut value = context.as_ref().as_ptr().read();
+        value.stack_frame.instruction_pointer -= SYSCALL_INSN_SIZE;
+        context.as_mut().as_mut_ptr().write(value);
+
+        self.stop_current(PtraceStop::SyscallEntry, context)
+    }
+
+    /// Stop at the exit of a syscall of `pid` if the tracer asked to,
+    /// skipped if the syscall switched to another process
+    pub fn trace_syscall_exit(&self, pid: ProcessId, context: &mut ProcessContext) {
+        let Some(proc) = self.get_proc(&pid) else {
+            return;
+        };
+
+        let stop = match proc.write().trace_mut() {
+            Some(trace) => {
+                let stop = trace.syscall && trace.in_syscall;
+                trace.in_syscall = false;
+                stop
+            }
+            None => false,
+        };
+
+        if stop && pid == processor::current_pid() {
+            self.stop_current(PtraceStop::SyscallExit, context);
+        }
+    }
+
+    /// Detach the tracees of a process that is killed
+    pub(super) fn release_tracees(&self, tracer: ProcessId) {
+        let tracees: Vec<_> = self
+            .all_processes()
+            .into_iter()
+            .filter(|proc| proc.read().trace().map(Trace::tracer) == Some(tracer))
+            .collect();
+
+        for proc in tracees {
+            self.detach(proc.pid(), &mut proc.write());
+        }
+    }
+
+    fn detach(&self, pid: ProcessId, inner: &mut ProcessInner) {
+        let stopped = inner.trace().is_some_and(Trace::is_stopped);
+        inner.set_trace(None);
+
+        let mut value = inner.context().as_ref().as_ptr().read();
+        value.stack_frame.cpu_flags.remove(RFlags::TRAP_FLAG);
+        inner.context_mut().as_mut().as_mut_ptr().write(value);
+
+        if stopped && inner.status() == ProgramStatus::Stopped {
+            inner.pause();
+            self.push_ready(pid);
+        }
+
+        debug!("Process #{} is detached", pid);
+    }
+
+    /// Wake up the tracer if it is waiting for the tracee
+    fn notify_stop(&self, pid: ProcessId, inner: &mut ProcessInner) {
+        let Some(trace) = inner.trace_mut() else {
+            return;
+        };
+
+        let tracer = trace.tracer;
+
+        if self.remove_waiter(pid, tracer) {
+            if let Some(status) = trace.take_unreported() {
+                self.wake_up(tracer, status);
+            }
+        }
+    }
+
+    /// Run `f` on a tracee stopped for the current process
+    fn with_stopped<T>(&self, pid: ProcessId, f: impl FnOnce(&mut ProcessInner) -> T) -> Option<T> {
+        let proc = self.get_proc(&pid)?;
+        let mut inner = proc.write();
+
+        match inner.trace() {
+            Some(trace) if trace.tracer == processor::current_pid() && trace.is_stopped() => {
+                Some(f(&mut inner))
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Shared libraries of user processes
+//!
+//! Libraries are the ELF shared objects (`lib*.so`) in `/lib` of the
+//! initramfs or in the app list. Each one is parsed once at boot, and its
+//! read-only pages without relocations are loaded once and mapped into
+//! every process which needs it. Writable pages (.data, .got) and pages
+//! with relocations are private to processes.
+//!
+//! Initializers of libraries (DT_INIT, DT_INIT_ARRAY) are not run.
+
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use elf::{Dynamic, ElfError};
+use spin::{Mutex, Once};
+use x86_64::structures::paging::{PageSize, PhysFrame, Size2MiB};
+use xmas_elf::{program, ElfFile};
+
+use crate::fs::initramfs::InitRamFs;
+
+static LIBRARIES: Once<Vec<Arc<SharedObject>>> = Once::new();
+
+/// Libraries are loaded at random 2 MiB aligned bases from
+/// [LIB_BASE, LIB_BASE + LIB_RANDOM_RANGE), one after another
+pub const LIB_BASE: u64 = 0x0800_0000_0000;
+pub const LIB_RANDOM_RANGE: u64 = 0x0400_0000_0000; // 4 TiB
+
+pub struct SharedObject {
+    /// the file name in `/lib` or in the app list
+    name: &'static str,
+    elf: &'static ElfFile<'static>,
+    dynamic: Dynamic<'static>,
+    /// frames of the shared pages, by their linked address
+    frames: Mutex<BTreeMap<u64, PhysFrame>>,
+}
+
+impl SharedObject {
+    fn new(name: &'static str, elf: &'static ElfFile<'static>) -> Result<Self, ElfError> {
+        if !elf::is_pie(elf) {
+            return Err(ElfError::InvalidType);
+        }
+
+        elf::validate(elf)?;
+
+        let dynamic = Dynamic::new(elf)?.ok_or(ElfError::Malformed("dynamic"))?;
+
+        if elf
+            .program_iter()
+            .any(|segment| segment.get_type() == Ok(program::Type::Tls))
+        {
+            warn!("Library {}: TLS is not supported", name);
+        }
+
+        Ok(Self {
+            name,
+            elf,
+            dynamic,
+            frames: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn elf(&self) -> &'static ElfFile<'static> {
+        self.elf
+    }
+
+    pub fn dynamic(&self) -> &Dynamic<'static> {
+        &self.dynamic
+    }
+
+    /// Check if the library is called `name` in DT_NEEDED
+    ///
+    /// the name may carry a `-<hash>` suffix (`libyslib-0123abcd.so`)
+    /// given by rustc to dylib crates.
+    fn is_named(&self, name: &str) -> bool {
+        fn stem(name: &str) -> &str {
+            let name = name.strip_suffix(".so").unwrap_or(name);
+            name.split('-').next().unwrap_or(name)
+        }
+
+        self.dynamic.soname() == Some(name) || stem(self.name) == stem(name)
+    }
+
+    /// The size of the address space taken by the library, 2 MiB aligned
+    pub fn span(&self) -> u64 {
+        let end = self
+            .elf
+            .program_iter()
+            .filter(|segment| segment.get_type() == Ok(program::Type::Load))
+            .map(|segment| segment.virtual_addr() + segment.mem_size())
+            .max()
+            .unwrap_or_default();
+
+        x86_64::align_up(end, Size2MiB::SIZE)
+    }
+
+    /// The shared frame of the page at the linked address `addr`,
+    /// `load` fills a new frame when the page is not loaded yet
+    pub fn frame(&self, addr: u64, load: impl FnOnce() -> Option<PhysFrame>) -> Option<PhysFrame> {
+        let mut frames = self.frames.lock();
+
+        if let Some(frame) = frames.get(&addr) {
+            return Some(*frame);
+        }
+
+        let frame = load()?;
+        frames.insert(addr, frame);
+        Some(frame)
+    }
+
+    /// Check if the frame is the shared page at the linked address `addr`
+    pub fn is_shared(&self, addr: u64, frame: PhysFrame) -> bool {
+        self.frames.lock().get(&addr) == Some(&frame)
+    }
+
+    /// The count of shared pages loaded
+    pub fn shared_pages(&self) -> usize {
+        self.frames.lock().len()
+    }
+}
+
+impl core::fmt::Debug for SharedObject {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SharedObject")
+            .field("name", &self.name)
+            .field("soname", &self.dynamic.soname())
+            .field("shared_pages", &self.shared_pages())
+            .finish()
+    }
+}
+
+/// Collect the shared objects in `/lib` of the initramfs and in the app list
+pub fn init(app_list: boot::AppListRef) {
+    LIBRARIES.call_once(|| {
+        let mut libraries = Vec::new();
+
+        // the files of the initramfs are parsed here, and kept as long as the image
+        let files = crate::fs::initramfs::get()
+            .into_iter()
+            .flat_map(InitRamFs::files)
+            .filter_map(|(path, data)| {
+                let name = path.strip_prefix("/lib/")?;
+                (is_library(name) && !name.contains('/')).then_some((name, data))
+            })
+            .filter_map(|(name, data)| match ElfFile::new(data) {
+                Ok(elf) => Some((name, &*Box::leak(Box::new(elf)))),
+                Err(err) => {
+                    warn!("Invalid library {}: {}", name, err);
+                    None
+                }
+            });
+
+        let apps = app_list
+            .into_iter()
+            .flatten()
+            .map(|app| (app.name.as_str(), &app.elf));
+
+        for (name, elf) in files.chain(apps) {
+            if !is_library(name) {
+                continue;
+            }
+
+            match SharedObject::new(name, elf) {
+                Ok(library) => libraries.push(Arc::new(library)),
+                Err(err) => warn!("Invalid library {}: {:?}", name, err),
+            }
+        }
+
+        info!("Found {} shared libraries.", libraries.len());
+
+        libraries
+    });
+}
+
+/// Check if the app is a shared library
+pub fn is_library(name: &str) -> bool {
+    name.ends_with(".so")
+}
+
+/// Find a library by the name in DT_NEEDED
+pub fn find(name: &str) -> Option<Arc<SharedObject>> {
+    LIBRARIES
+        .get()?
+        .iter()
+        .find(|library| library.is_named(name))
+        .cloned()
+}
+
+/// The libraries needed by an ELF file and by those libraries,
+/// in breadth-first order without duplicates
+pub fn needed(dynamic: &Dynamic) -> Result<Vec<Arc<SharedObject>>, ElfError> {
+    let mut libraries: Vec<Arc<SharedObject>> = Vec::new();
+
+    let mut queue: Vec<&str> = dynamic.needed().collect();
+    let mut idx = 0;
+
+    while idx < queue.len() {
+        let name = queue[idx];
+        idx += 1;
+
+        let library = find(name).ok_or_else(|| {
+            warn!("Library not found: {}", name);
+            ElfError::MissingLibrary
+        })?;
+
+        if libraries.iter().any(|l| Arc::ptr_eq(l, &library)) {
+            continue;
+        }
+
+        queue.extend(library.dynamic().needed());
+        libraries.push(library);
+    }
+
+    Ok(libraries)
+}
@@ -1,12 +1,18 @@
+use core::ptr::write_bytes;
 use core::sync::atomic::{AtomicU64, Ordering};
 
 use alloc::sync::Arc;
-use x86::current;
 use x86_64::{
-    structures::paging::{mapper::UnmapError, Page},
+    structures::paging::{
+        mapper::{MappedFrame, TranslateResult, UnmapError},
+        FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, PhysFrame,
+        Size2MiB, Size4KiB, Translate,
+    },
     VirtAddr,
 };
 
+use crate::memory::{physical_to_virtual, PAGE_SIZE};
+
 use super::{FrameAllocatorRef, MapperRef};
 
 // user process runtime heap
@@ -17,6 +23,13 @@ pub const HEAP_PAGES: u64 = 0x100000;
 pub const HEAP_SIZE: u64 = HEAP_PAGES * crate::memory::PAGE_SIZE;
 pub const HEAP_END: u64 = HEAP_START + HEAP_SIZE - 8;
 
+/// The heap base is randomized in [HEAP_START, HEAP_START + HEAP_RANDOM_RANGE),
+/// aligned to 2 MiB so that huge pages can be used
+pub const HEAP_RANDOM_RANGE: u64 = 0x0800_0000_0000; // 8 TiB
+
+/// Count of 4 KiB pages in a 2 MiB page
+const HUGE_PAGES: u64 = Size2MiB::SIZE / PAGE_SIZE;
+
 /// User process runtime heap
 ///
 /// always page aligned, the range is [base, end)
@@ -30,20 +43,39 @@ pub struct Heap {
     ///
     /// use atomic to allow multiple threads to access the heap
     end: Arc<AtomicU64>,
+
+    /// the count of pages which have been mapped
+    ///
+    /// pages are mapped on demand, so this may be less than `end - base`.
+    /// pages swapped out are counted, and left out by `ProcessVm`
+    usage: Arc<AtomicU64>,
 }
 
 impl Heap {
     pub fn empty() -> Self {
+        Self::new(VirtAddr::new(HEAP_START))
+    }
+
+    pub fn new(base: VirtAddr) -> Self {
         Self {
-            base: VirtAddr::new(HEAP_START),
-            end: Arc::new(AtomicU64::new(HEAP_START)),
+            base,
+            end: Arc::new(AtomicU64::new(base.as_u64())),
+            usage: Arc::new(AtomicU64::new(0)),
         }
     }
 
+    /// Create a heap at a random base
+    pub fn random() -> Self {
+        let slots = HEAP_RANDOM_RANGE / Size2MiB::SIZE;
+        let offset = boot::random::random_below(slots) * Size2MiB::SIZE;
+        Self::new(VirtAddr::new(HEAP_START + offset))
+    }
+
     pub fn fork(&self) -> Self {
         Self {
             base: self.base,
             end: self.end.clone(),
+            usage: self.usage.clone(),
         }
     }
 
@@ -53,54 +85,217 @@ impl Heap {
         mapper: MapperRef,
         alloc: FrameAllocatorRef,
     ) -> Option<VirtAddr> {
-
-        // FIXME: if new_end is None, return the current end address
+        // if new_end is None, return the current end address
         if new_end.is_none() {
             return Some(VirtAddr::new(self.end.load(Ordering::Relaxed)));
         }
-        // FIXME: check if the new_end is valid (in range [base, base + HEAP_SIZE])
+
+        // check if the new_end is valid (in range [base, base + HEAP_SIZE])
         let new_end = new_end.unwrap();
         if new_end > self.base + HEAP_SIZE || new_end < self.base {
             error!("Brk: new_end is invalid: {:#x}", new_end);
             return None;
         }
-        
-        // FIXME: calculate the difference between the current end and the new end
-        
-        let diff = new_end.as_u64() - self.end.load(Ordering::Acquire);
-        let current_end = self.end.load(Ordering::Acquire);
-
-        let mut current_end_page = Page::containing_address(VirtAddr::new(current_end));
-        let mut new_end_page = Page::containing_address(new_end);
-
-        if current_end != self.base.as_u64() { current_end_page += 1; }
-        if new_end != self.base { new_end_page += 1; }
 
+        let current_end = VirtAddr::new(self.end.load(Ordering::Acquire));
 
-        // NOTE: print the heap difference for debugging
-        debug!("Brk: diff: {:#x}", diff);
         debug!("Brk: current_end: {:#x}", current_end);
         debug!("Brk: new_end: {:#x}", new_end);
-        debug!("Brk: current_end_page: {:#x}", current_end_page.start_address().as_u64());
-        debug!("Brk: new_end_page: {:#x}", new_end_page.start_address().as_u64());
-        // FIXME: do the actual mapping or unmapping
-
-        if diff > 0 {
-            // expand heap
-            let range = Page::range_inclusive(current_end_page, new_end_page - 1);
-            elf::map_range(range, mapper, alloc, true).ok()?;
-        }
-        else if diff < 0 {
-            // shrink heap
-            let range = Page::range_inclusive(new_end_page, current_end_page - 1);
-            elf::unmap_range(range, mapper, alloc, true).ok()?;
-        }
 
-        // FIXME: update the end address
+        // growing the heap only records the new end,
+        // pages are mapped on the first access (see `handle_page_fault`)
+        if new_end < current_end {
+            // shrink heap, unmap the pages that are no longer covered
+            let start_page = Page::containing_address(new_end.align_up(PAGE_SIZE));
+            let end_page = Page::containing_address(current_end - 1u64);
 
+            if start_page <= end_page {
+                // the pages of a huge page before the new end are kept
+                if !self.split_huge_page(start_page, mapper, alloc) {
+                    return None;
+                }
+
+                let range = Page::range_inclusive(start_page, end_page);
+                let swapped = crate::memory::swap::release_range(mapper, range);
+                let count = elf::unmap_range_sparse(range, mapper, alloc, true).ok()?;
+                self.usage.fetch_sub(count + swapped, Ordering::Relaxed);
+            }
+        }
+
+        // update the end address
         self.end.store(new_end.as_u64(), Ordering::Release);
         Some(new_end)
+    }
 
+    pub fn handle_page_fault(
+        &self,
+        addr: VirtAddr,
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+        allow_huge: bool,
+    ) -> bool {
+        let end = self.end.load(Ordering::Acquire);
+        if addr < self.base || addr.as_u64() >= end {
+            return false;
+        }
+
+        if allow_huge && self.map_huge_page(addr, end, mapper, alloc) {
+            return true;
+        }
+
+        let page = Page::<Size4KiB>::containing_address(addr);
+        trace!(
+            "Fill heap page {:#x} on demand",
+            page.start_address().as_u64()
+        );
+
+        let frame = match alloc.allocate_frame() {
+            Some(frame) => frame,
+            None => {
+                error!("Grow heap failed: no free frame");
+                return false;
+            }
+        };
+
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::NO_EXECUTE;
+
+        unsafe {
+            // zero-fill the page, frames may be recycled from other processes
+            write_bytes(
+                physical_to_virtual(frame.start_address().as_u64()) as *mut u8,
+                0,
+                PAGE_SIZE as usize,
+            );
+
+            match mapper.map_to(page, frame, flags, alloc) {
+                Ok(flush) => flush.flush(),
+                Err(m) => {
+                    error!("Grow heap failed: {:?}", m);
+                    alloc.deallocate_frame(frame);
+                    return false;
+                }
+            }
+        }
+
+        self.usage.fetch_add(1, Ordering::Relaxed);
+
+        true
+    }
+
+    /// Map the 2 MiB page containing `addr` at once
+    ///
+    /// only if the whole page lies in the heap and none of its 4 KiB pages
+    /// is mapped, otherwise the fault is handled with a 4 KiB page.
+    fn map_huge_page(
+        &self,
+        addr: VirtAddr,
+        end: u64,
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+    ) -> bool {
+        let page = Page::<Size2MiB>::containing_address(addr);
+        let start = page.start_address();
+        if start < self.base || start.as_u64() + Size2MiB::SIZE > end {
+            return false;
+        }
+
+        // huge pages cannot be swapped out, leave the frames for others
+        if alloc.frames_free() < crate::memory::swap::SWAP_LOW_WATERMARK + HUGE_PAGES as usize {
+            return false;
+        }
+
+        let frame: PhysFrame<Size2MiB> = match alloc.allocate_frame() {
+            Some(frame) => frame,
+            None => return false,
+        };
+
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::NO_EXECUTE;
+
+        unsafe {
+            write_bytes(
+                physical_to_virtual(frame.start_address().as_u64()) as *mut u8,
+                0,
+                Size2MiB::SIZE as usize,
+            );
+
+            // fails with `PageAlreadyMapped` if the P2 entry holds a P1 table
+            match mapper.map_to(page, frame, flags, alloc) {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    alloc.deallocate_frame(frame);
+                    return false;
+                }
+            }
+        }
+
+        trace!("Fill heap huge page {:#x} on demand", start.as_u64());
+
+        self.usage.fetch_add(HUGE_PAGES, Ordering::Relaxed);
+
+        true
+    }
+
+    /// Map the 2 MiB page containing `page` with 4 KiB pages to the same
+    /// frames, so the pages from `page` can be unmapped one by one
+    ///
+    /// returns false if the page table of the 4 KiB pages cannot be allocated.
+    fn split_huge_page(&self, page: Page, mapper: MapperRef, alloc: FrameAllocatorRef) -> bool {
+        let huge = Page::<Size2MiB>::containing_address(page.start_address());
+
+        // a huge page starting at `page` is unmapped as a whole
+        if huge.start_address() == page.start_address() {
+            return true;
+        }
+
+        let (frame, flags) = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size2MiB(frame),
+                flags,
+                ..
+            } => (frame, flags),
+            _ => return true,
+        };
+
+        match Mapper::<Size2MiB>::unmap(mapper, huge) {
+            Ok((_, flush)) => flush.flush(),
+            Err(_) => return false,
+        }
+
+        // the frames of the huge page are freed one by one when unmapped
+        for idx in 0..HUGE_PAGES {
+            let small =
+                Page::<Size4KiB>::containing_address(huge.start_address() + idx * PAGE_SIZE);
+            let small_frame =
+                PhysFrame::containing_address(frame.start_address() + idx * PAGE_SIZE);
+            let small_flags = flags - PageTableFlags::HUGE_PAGE;
+
+            match unsafe {
+                Mapper::<Size4KiB>::map_to(mapper, small, small_frame, small_flags, alloc)
+            } {
+                Ok(flush) => flush.flush(),
+                Err(err) => {
+                    // only the first page allocates the page table,
+                    // nothing is mapped yet, so the huge page is put back
+                    error!("Split heap huge page failed: {:?}", err);
+                    if let Ok(flush) =
+                        unsafe { Mapper::<Size2MiB>::map_to(mapper, huge, frame, flags, alloc) }
+                    {
+                        flush.flush();
+                    }
+                    return false;
+                }
+            }
+        }
+
+        trace!("Split heap huge page {:#x}", huge.start_address().as_u64());
+
+        true
     }
 
     pub(super) fn clean_up(
@@ -108,26 +303,40 @@ impl Heap {
         mapper: MapperRef,
         dealloc: FrameAllocatorRef,
     ) -> Result<(), UnmapError> {
-        if self.memory_usage() == 0 {
+        // load the current end address and **reset it to base**
+        let end = self.end.swap(self.base.as_u64(), Ordering::Relaxed);
+
+        if self.usage.load(Ordering::Relaxed) == 0 || end == self.base.as_u64() {
             return Ok(());
         }
 
-        // FIXME: load the current end address and **reset it to base** (use `swap`)
-        let end = self.end.swap(self.base.as_u64(), Ordering::Relaxed);
-
         let start_page = Page::containing_address(self.base);
-        let end_page = Page::containing_address(VirtAddr::new(end));
+        let end_page = Page::containing_address(VirtAddr::new(end - 1));
         let range = Page::range_inclusive(start_page, end_page);
 
-        // FIXME: unmap the heap pages
-        elf::unmap_range(range, mapper, dealloc, true)?;
+        // unmap the heap pages which have been touched
+        crate::memory::swap::release_range(mapper, range);
+        elf::unmap_range_sparse(range, mapper, dealloc, true)?;
+
+        self.usage.store(0, Ordering::Relaxed);
 
         Ok(())
     }
 
-    pub fn memory_usage(&self) -> u64 {
+    /// the current end address of the heap
+    pub fn end(&self) -> u64 {
+        self.end.load(Ordering::Relaxed)
+    }
+
+    /// the size of the heap, including pages not mapped yet
+    pub fn size(&self) -> u64 {
         self.end.load(Ordering::Relaxed) - self.base.as_u64()
     }
+
+    /// the memory which is really mapped for the heap
+    pub fn memory_usage(&self) -> u64 {
+        self.usage.load(Ordering::Relaxed) * PAGE_SIZE
+    }
 }
 
 impl core::fmt::Debug for Heap {
@@ -138,6 +347,7 @@ impl core::fmt::Debug for Heap {
                 "end",
                 &format_args!("{:#x}", self.end.load(Ordering::Relaxed)),
             )
+            .field("usage", &self.usage.load(Ordering::Relaxed))
             .finish()
     }
 }
@@ -1,20 +1,25 @@
-use alloc::{format, vec::Vec};
+use alloc::{format, sync::Arc, vec::Vec};
 use boot::KernelPages;
 use x86_64::{
     structures::paging::{
-        mapper::{CleanUp, UnmapError},
+        mapper::{CleanUp, MapToError, MappedFrame, TranslateResult, UnmapError},
         page::*,
         *,
     },
     VirtAddr,
 };
+use x86_64::structures::idt::PageFaultErrorCode;
+use elf::ElfError;
 use xmas_elf::ElfFile;
 use crate::{humanized_size, memory::*};
 
+pub mod dylib;
 pub mod heap;
+pub mod segment;
 pub mod stack;
+pub mod tls;
 
-use self::{heap::Heap, stack::Stack};
+use self::{heap::Heap, segment::Segments, stack::Stack, tls::TlsTemplate};
 
 use super::PageTableContext;
 
@@ -23,8 +28,13 @@ use super::PageTableContext;
 //
 // use boot::KernelPages;
 
+/// Position independent apps are loaded at a random 2 MiB aligned
+/// base in [PIE_BASE, PIE_BASE + PIE_RANDOM_RANGE)
+pub const PIE_BASE: u64 = 0x1000_0000_0000;
+pub const PIE_RANDOM_RANGE: u64 = 0x0800_0000_0000; // 8 TiB
+
 type MapperRef<'a> = &'a mut OffsetPageTable<'static>;
-type FrameAllocatorRef<'a> = &'a mut BootInfoFrameAllocator;
+type FrameAllocatorRef<'a> = &'a mut BuddyFrameAllocator;
 
 pub struct ProcessVm {
     // page table is shared by parent and child
@@ -36,6 +46,10 @@ pub struct ProcessVm {
     // heap is allocated by brk syscall
     pub(super) heap: Heap,
 
+    // elf segments of the app and then its libraries, mapped on demand
+    // shared by parent and child as the page table is
+    pub(super) segments: Arc<Vec<Segments>>,
+
     // code is hold by the first process
     // these fields will be empty for other processes
     pub(super) code: Vec<PageRangeInclusive>,
@@ -48,6 +62,7 @@ impl ProcessVm {
             page_table,
             stack: Stack::empty(),
             heap: Heap::empty(),
+            segments: Arc::new(Vec::new()),
             code: Vec::new(),
             code_usage: 0,
         }
@@ -85,51 +100,383 @@ impl ProcessVm {
         )
     }
 
-    pub fn load_elf(&mut self, elf: &ElfFile) {
+    /// Load an ELF file with randomized stack, heap and code (if PIE)
+    ///
+    /// `file` owns the ELF file if it was read from a filesystem.
+    /// returns the entry point, the initial stack pointer and
+    /// the thread pointer (zero if the file has no TLS)
+    pub fn load_elf(
+        &mut self,
+        elf: &ElfFile<'static>,
+        file: Option<Arc<[u8]>>,
+    ) -> Result<(VirtAddr, VirtAddr, VirtAddr), ElfError> {
+        elf::validate(elf)?;
+
         let mapper = &mut self.page_table.mapper();
 
         let alloc = &mut *get_frame_alloc_for_sure();
 
-        self.load_elf_code(elf, mapper, alloc);
-        self.stack.init(mapper, alloc);
-    }
+        let bias = if elf::is_pie(elf) {
+            let slots = PIE_RANDOM_RANGE / Size2MiB::SIZE;
+            PIE_BASE + boot::random::random_below(slots) * Size2MiB::SIZE
+        } else {
+            0
+        };
+
+        // segments are filled on demand in `handle_page_fault`
+        let segments = Self::load_segments(elf, bias, file)?;
+        let tls = TlsTemplate::new(elf, bias, segments[0].relocations())?;
 
-    fn load_elf_code(&mut self, elf: &ElfFile, mapper: MapperRef, alloc: FrameAllocatorRef) {
-        // FIXME: make the `load_elf` function return the code pages
-        self.code =
-            elf::load_elf(elf, *PHYSICAL_OFFSET.get().unwrap(), mapper, alloc, true).unwrap();
+        self.segments = Arc::new(segments);
+        self.heap = Heap::random();
+        let stack_top = self.stack.init(mapper, alloc, tls.reserved_size())?;
 
-        // FIXME: calculate code usage
-        self.code_usage = self.code.iter().map(|range| range.count()).sum::<usize>() as u64 * crate::memory::PAGE_SIZE;
+        // the TLS block is placed at the top of the stack
+        let (fs_base, stack_top) = tls.init(stack_top, mapper);
+
+        swap::track_range(mapper, self.stack.range());
+
+        let entry = VirtAddr::new(elf.header.pt2.entry_point() + bias);
+
+        Ok((entry, stack_top, fs_base))
+    }
+
+    /// Segments of an ELF file loaded at `bias`, followed by the segments
+    /// of the libraries it needs, which are loaded at random bases
+    fn load_segments(
+        elf: &ElfFile<'static>,
+        bias: u64,
+        file: Option<Arc<[u8]>>,
+    ) -> Result<Vec<Segments>, ElfError> {
+        let dynamic = elf::Dynamic::new(elf)?;
+
+        let libraries = match dynamic.as_ref() {
+            Some(dynamic) => dylib::needed(dynamic)?,
+            None => Vec::new(),
+        };
+
+        let slots = dylib::LIB_RANDOM_RANGE / Size2MiB::SIZE;
+        let mut base = dylib::LIB_BASE + boot::random::random_below(slots) * Size2MiB::SIZE;
+
+        let libraries: Vec<_> = libraries
+            .into_iter()
+            .map(|library| {
+                let bias = base;
+                base += library.span();
+                (library, bias)
+            })
+            .collect();
+
+        // symbols are looked up in the app, then in the libraries in load order
+        let resolve = |name: &str| {
+            let app = dynamic.as_ref().map(|dynamic| (dynamic, bias));
+            let libraries = libraries.iter().map(|(l, bias)| (l.dynamic(), *bias));
+
+            app.into_iter()
+                .chain(libraries)
+                .find_map(|(dynamic, bias)| Some(dynamic.lookup(name)?.value + bias))
+        };
+
+        let mut segments = vec![Segments::new(elf, bias, resolve, None, file)?];
+
+        for (library, lib_bias) in libraries.iter() {
+            trace!("Load library {} at {:#x}", library.name(), lib_bias);
+            segments.push(Segments::new(
+                library.elf(),
+                *lib_bias,
+                resolve,
+                Some(library.clone()),
+                None,
+            )?);
+        }
 
+        Ok(segments)
     }
 
-    pub fn fork(&self, stack_offset_count: u64) -> Self {
+    pub fn fork(&self, stack_offset_count: u64) -> Result<Self, MapToError<Size4KiB>> {
         let owned_page_table = self.page_table.fork();
         let mapper = &mut owned_page_table.mapper();
 
         let alloc = &mut *get_frame_alloc_for_sure();
 
-        Self {
+        let stack = self.stack.fork(mapper, alloc, stack_offset_count)?;
+
+        swap::track_range(mapper, stack.range());
+
+        Ok(Self {
             page_table: owned_page_table,
-            stack: self.stack.fork(mapper, alloc, stack_offset_count),
+            stack,
             heap: self.heap.fork(),
+            segments: self.segments.clone(),
 
             // do not share code info
             code: Vec::new(),
             code_usage: 0,
+        })
+    }
+
+    /// Handle a page fault in the user space
+    ///
+    /// `allow_huge` allows the heap to map a whole 2 MiB page at once.
+    pub fn handle_page_fault(
+        &mut self,
+        addr: VirtAddr,
+        err_code: PageFaultErrorCode,
+        allow_huge: bool,
+    ) -> bool {
+        let mapper = &mut self.page_table.mapper();
+        let alloc = &mut *get_frame_alloc_for_sure();
+
+        let is_write = err_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+        let page = Page::containing_address(addr);
+
+        if swap::swap_in(mapper, page, alloc) {
+            return true;
+        }
+
+        let stack_grow = self.stack.grow_size(addr) / PAGE_SIZE;
+        if self.stack.handle_page_fault(addr, mapper, alloc) {
+            swap::track_range(mapper, Page::range(page, page + stack_grow));
+            return true;
         }
+
+        if self.heap.handle_page_fault(addr, mapper, alloc, allow_huge) {
+            swap::track(mapper, page);
+            return true;
+        }
+
+        self.segments
+            .iter()
+            .any(|segments| segments.handle_page_fault(addr, is_write, mapper, alloc))
+    }
+
+    /// Check if a page fault at `addr` is caused by stack overflow
+    pub fn is_stack_overflow(&self, addr: VirtAddr) -> bool {
+        self.stack.is_overflow(addr)
+    }
+
+    /// Print the backtrace of the process from `rip` and `rbp`,
+    /// symbolised with the app and its libraries
+    ///
+    /// the stack is read through the page table, pages not mapped
+    /// stop the walk.
+    pub fn print_backtrace(&self, rip: u64, rbp: u64) {
+        let mapper = self.page_table.mapper();
+
+        let read = |addr: u64| {
+            if !uaccess::is_user_range(addr, 8) {
+                return None;
+            }
+
+            let phys = mapper.translate_addr(VirtAddr::new(addr))?;
+            Some(unsafe { (physical_to_virtual(phys.as_u64()) as *const u64).read() })
+        };
+
+        let mut frames = vec![rip];
+        frames.extend(crate::backtrace::walk(rbp, read));
+
+        crate::backtrace::print(&frames, |addr| {
+            self.segments
+                .iter()
+                .find_map(|segments| segments.symbol(addr))
+                .map(|(name, offset)| (name.into(), offset))
+        });
     }
 
-    pub fn handle_page_fault(&mut self, addr: VirtAddr) -> bool {
+    /// The load bias of the app, zero if it is not position independent
+    pub fn load_bias(&self) -> u64 {
+        self.segments.first().map(|s| s.bias()).unwrap_or_default()
+    }
+
+    /// Read the memory of the process through its page table
+    ///
+    /// fails if a page in the range is not mapped.
+    pub fn read_memory(&self, addr: u64, buf: &mut [u8]) -> bool {
+        self.access_memory(addr, buf.len(), |ptr, offset, len| unsafe {
+            core::ptr::copy_nonoverlapping(ptr, buf[offset..].as_mut_ptr(), len)
+        })
+    }
+
+    /// Write the memory of the process through its page table
+    ///
+    /// the page flags are not checked, so read-only code can be patched.
+    /// read-only and shared pages are copied first, so the write is only
+    /// seen through this page table. fails if a page in the range is not
+    /// mapped.
+    pub fn write_memory(&self, addr: u64, data: &[u8]) -> bool {
+        if data.is_empty() || !uaccess::is_user_range(addr, data.len()) {
+            return data.is_empty();
+        }
+
+        let start = Page::containing_address(VirtAddr::new(addr));
+        let end = Page::containing_address(VirtAddr::new(addr + data.len() as u64 - 1));
+
+        if !Page::range_inclusive(start, end).all(|page| self.make_private(page)) {
+            return false;
+        }
+
+        self.access_memory(addr, data.len(), |ptr, offset, len| unsafe {
+            core::ptr::copy_nonoverlapping(data[offset..].as_ptr(), ptr, len)
+        })
+    }
+
+    /// Copy a read-only or shared page to a frame of its own
+    ///
+    /// returns false if the page is not mapped or there is no free frame.
+    fn make_private(&self, page: Page) -> bool {
         let mapper = &mut self.page_table.mapper();
+
+        let (frame, flags) = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(frame),
+                flags,
+                ..
+            } => (frame, flags),
+            // huge pages are only used by the heap, which is private
+            TranslateResult::Mapped { .. } => return true,
+            _ => return false,
+        };
+
+        let shared = self
+            .segments
+            .iter()
+            .any(|segments| segments.is_shared(page, frame));
+
+        if flags.contains(PageTableFlags::WRITABLE) && !shared {
+            return true;
+        }
+
         let alloc = &mut *get_frame_alloc_for_sure();
 
-        self.stack.handle_page_fault(addr, mapper, alloc)
+        let new_frame = match alloc.allocate_frame() {
+            Some(new_frame) => new_frame,
+            None => return false,
+        };
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                physical_to_virtual(frame.start_address().as_u64()) as *const u8,
+                physical_to_virtual(new_frame.start_address().as_u64()) as *mut u8,
+                PAGE_SIZE as usize,
+            );
+        }
+
+        // the tables of the page exist, so it can always be mapped again
+        let remapped = match mapper.unmap(page) {
+            Ok((_, flush)) => {
+                flush.flush();
+                unsafe { mapper.map_to(page, new_frame, flags, alloc) }
+                    .map(|flush| flush.flush())
+                    .is_ok()
+            }
+            Err(_) => false,
+        };
+
+        if !remapped {
+            error!("Copy page {:#x} failed", page.start_address().as_u64());
+            unsafe { alloc.deallocate_frame(new_frame) };
+            return false;
+        }
+
+        // the shared frame is kept by the library
+        if !shared {
+            unsafe { alloc.deallocate_frame(frame) };
+        }
+
+        trace!(
+            "Copy page {:#x} for writing: {:#x} -> {:#x}",
+            page.start_address().as_u64(),
+            frame.start_address().as_u64(),
+            new_frame.start_address().as_u64()
+        );
+
+        true
     }
 
+    /// Call `f` with the kernel address, the offset in the range and the
+    /// length of each piece of [addr, addr + len) in a page
+    fn access_memory(
+        &self,
+        addr: u64,
+        len: usize,
+        mut f: impl FnMut(*mut u8, usize, usize),
+    ) -> bool {
+        if !uaccess::is_user_range(addr, len) {
+            return false;
+        }
+
+        let mapper = self.page_table.mapper();
+
+        // translate all pages first, nothing is accessed on failure
+        let mut pieces = Vec::new();
+        let mut offset = 0;
+
+        while offset < len {
+            let start = addr + offset as u64;
+            let size = (PAGE_SIZE - start % PAGE_SIZE).min((len - offset) as u64) as usize;
+
+            match mapper.translate_addr(VirtAddr::new(start)) {
+                Some(phys) => pieces.push((physical_to_virtual(phys.as_u64()), offset, size)),
+                None => return false,
+            }
+
+            offset += size;
+        }
+
+        for (ptr, offset, size) in pieces {
+            f(ptr as *mut u8, offset, size);
+        }
+
+        true
+    }
+
+    /// The size of the address space in bytes
+    ///
+    /// pages reserved by `brk` and ELF segments are counted even if not mapped
+    pub fn address_space_size(&self) -> u64 {
+        self.stack.memory_usage()
+            + self.heap.size()
+            + self.segments.iter().map(|s| s.size()).sum::<u64>()
+            + self.code_usage
+    }
+
+    /// The size in bytes the stack grows by on a page fault at `addr`
+    pub fn stack_grow_size(&self, addr: VirtAddr) -> u64 {
+        self.stack.grow_size(addr)
+    }
+
+    /// The stack limit in bytes
+    pub fn stack_limit(&self) -> u64 {
+        self.stack.limit() * crate::memory::PAGE_SIZE
+    }
+
+    /// Set the stack limit in bytes, rounded up to pages
+    pub fn set_stack_limit(&mut self, limit: u64) -> bool {
+        let pages = limit.div_ceil(crate::memory::PAGE_SIZE);
+        self.stack.set_limit(pages)
+    }
+
+    /// The size of resident memory in bytes, pages swapped out are not counted
     pub(super) fn memory_usage(&self) -> u64 {
-        self.stack.memory_usage() + self.heap.memory_usage() + self.code_usage
+        let swapped = swap::swapped_pages(self.page_table.reg.addr) * PAGE_SIZE;
+
+        (self.stack.memory_usage()
+            + self.heap.memory_usage()
+            + self.segments.iter().map(|s| s.memory_usage()).sum::<u64>()
+            + self.code_usage)
+            .saturating_sub(swapped)
+    }
+
+    /// The size of resident memory freed if the process exits
+    ///
+    /// memory shared with forked processes is only counted for the last
+    /// of them, as `clean_up` only frees it then.
+    pub(super) fn private_usage(&self) -> u64 {
+        if self.page_table.using_count() > 1 {
+            return self.stack.memory_usage();
+        }
+
+        self.memory_usage()
     }
 
     pub(super) fn clean_up(&mut self) -> Result<(), UnmapError> {
@@ -146,22 +493,29 @@ impl ProcessVm {
             // FIXME: implement the `clean_up` function for `Heap`
             self.heap.clean_up(mapper, dealloc)?;
 
+            // free segments that have been filled
+            for segments in self.segments.iter() {
+                segments.clean_up(mapper, dealloc)?;
+            }
+
             // free code
             for page_range in self.code.iter() {
                 elf::unmap_range(*page_range, mapper, dealloc, true)?;
             }
 
             unsafe {
-                // free P1-P3
-                mapper.clean_up(dealloc);
+                // free P1-P3 of the user space, tables of the kernel space
+                // are shared and may hold huge pages
+                mapper.clean_up_addr_range(user_space(), dealloc);
 
                 // free P4
                 dealloc.deallocate_frame(self.page_table.reg.addr);
+                swap::forget(self.page_table.reg.addr);
             }
         }
 
         // NOTE: maybe print how many frames are recycled
-        //       **you may need to add some functions to `BootInfoFrameAllocator`**
+        //       **you may need to add some functions to `BuddyFrameAllocator`**
         
         let end_count = dealloc.frames_recycled();
 
@@ -177,6 +531,14 @@ impl ProcessVm {
     }
 }
 
+/// Pages of the lower half of the address space
+fn user_space() -> PageRangeInclusive {
+    Page::range_inclusive(
+        Page::containing_address(VirtAddr::zero()),
+        Page::containing_address(VirtAddr::new(0x0000_7fff_ffff_ffff)),
+    )
+}
+
 impl core::fmt::Debug for ProcessVm {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         let (size, unit) = humanized_size(self.memory_usage());
@@ -184,6 +546,7 @@ impl core::fmt::Debug for ProcessVm {
         f.debug_struct("ProcessVm")
             .field("stack", &self.stack)
             .field("heap", &self.heap)
+            .field("segments", &self.segments)
             .field("memory_usage", &format!("{} {}", size, unit))
             .field("page_table", &self.page_table)
             .finish()
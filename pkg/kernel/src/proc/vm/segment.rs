@@ -0,0 +1,341 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::{sync::Arc, vec::Vec};
+use elf::{ElfError, Relocation};
+use x86_64::{
+    structures::paging::{
+        mapper::*, page::*, FrameAllocator, FrameDeallocator, Page, PageTableFlags, PhysFrame,
+    },
+    VirtAddr,
+};
+use xmas_elf::{program, ElfFile};
+
+use crate::memory::{physical_to_virtual, uaccess::is_user_range, PHYSICAL_OFFSET};
+
+use super::{dylib::SharedObject, FrameAllocatorRef, MapperRef};
+
+/// Loadable ELF segments of a user process or one of its libraries
+///
+/// pages are not mapped when the process is spawned, they are filled from
+/// the ELF file on first touch, and the .bss part is zero-filled.
+pub struct Segments {
+    /// the ELF file, which lives as long as the app list, the library
+    /// or `_file`
+    data: &'static [u8],
+
+    /// the owner of `data` if the file was read from a filesystem,
+    /// it is freed when the last process mapping it exits
+    _file: Option<Arc<[u8]>>,
+
+    /// loadable segments of the ELF file
+    headers: Vec<program::ProgramHeader<'static>>,
+
+    /// the offset of the segments from their linked addresses,
+    /// only position independent apps have a non-zero bias
+    bias: u64,
+
+    /// relocations applied when a page is filled, sorted by offset
+    relocations: Vec<Relocation>,
+
+    /// the pages covered by each segment
+    ranges: Vec<PageRangeInclusive>,
+
+    /// the library the segments belong to, whose read-only pages are shared
+    library: Option<Arc<SharedObject>>,
+
+    /// the count of pages that have been filled
+    ///
+    /// segments are shared by parent and child
+    usage: AtomicU64,
+}
+
+impl Segments {
+    /// Segments of an ELF file (or a `library`) loaded at `bias`
+    ///
+    /// symbols not defined by the file are resolved by `resolve`.
+    /// the file must have been validated by `elf::validate`, and be kept
+    /// alive by `file` if it is not static
+    pub fn new(
+        elf: &ElfFile<'static>,
+        bias: u64,
+        resolve: impl Fn(&str) -> Option<u64>,
+        library: Option<Arc<SharedObject>>,
+        file: Option<Arc<[u8]>>,
+    ) -> Result<Self, ElfError> {
+        let headers: Vec<_> = elf
+            .program_iter()
+            .filter(|segment| segment.get_type() == Ok(program::Type::Load))
+            .filter(|segment| segment.mem_size() > 0)
+            .collect();
+
+        let ranges = headers
+            .iter()
+            .map(|segment| {
+                let start = segment.virtual_addr() + bias;
+                if !is_user_range(start, segment.mem_size() as usize) {
+                    return Err(ElfError::InvalidSegment(segment.virtual_addr()));
+                }
+
+                let start = VirtAddr::new(start);
+                let end = start + segment.mem_size() - 1u64;
+                Ok(Page::range_inclusive(
+                    Page::containing_address(start),
+                    Page::containing_address(end),
+                ))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            data: elf.input,
+            _file: file,
+            headers,
+            bias,
+            relocations: elf::relocations_with(elf, bias, resolve)?,
+            ranges,
+            library,
+            usage: AtomicU64::new(0),
+        })
+    }
+
+    /// the address the ELF file is loaded at, relative to its linked address
+    pub fn bias(&self) -> u64 {
+        self.bias
+    }
+
+    /// relocations of the segments, sorted by offset
+    pub fn relocations(&self) -> &[Relocation] {
+        &self.relocations
+    }
+
+    /// The function containing a user address in the segments,
+    /// from the symbol table of the ELF file
+    pub fn symbol(&self, addr: u64) -> Option<(&'static str, u64)> {
+        self.segment_of(Page::containing_address(VirtAddr::try_new(addr).ok()?))?;
+
+        let elf = ElfFile::new(self.data).ok()?;
+        elf::find_symbol(&elf, addr - self.bias)
+    }
+
+    fn segment_of(&self, page: Page) -> Option<&program::ProgramHeader<'static>> {
+        self.ranges
+            .iter()
+            .position(|range| range.start <= page && page <= range.end)
+            .map(|idx| &self.headers[idx])
+    }
+
+    /// Check if a page is mapped to a frame shared by every process
+    /// using the library
+    pub fn is_shared(&self, page: Page, frame: PhysFrame) -> bool {
+        match self.library.as_ref() {
+            Some(library) if self.segment_of(page).is_some() => {
+                library.is_shared(page.start_address().as_u64() - self.bias, frame)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn handle_page_fault(
+        &self,
+        addr: VirtAddr,
+        is_write: bool,
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+    ) -> bool {
+        let page = Page::containing_address(addr);
+
+        if self.segment_of(page).is_none() {
+            return false;
+        }
+
+        // a page may be covered by several segments, as .rodata and .data
+        let flags = elf::segment_page_flags(&self.headers, self.bias, page, true);
+
+        if is_write && !flags.contains(PageTableFlags::WRITABLE) {
+            warn!("Write to read-only segment at {:#x}", addr);
+            return false;
+        }
+
+        trace!(
+            "Fill segment page {:#x} on demand",
+            page.start_address().as_u64()
+        );
+
+        if let Some(library) = self.library.as_ref() {
+            if !flags.contains(PageTableFlags::WRITABLE) && !self.has_relocation(page) {
+                return self.map_shared(library, page, flags, mapper, alloc);
+            }
+        }
+
+        if let Err(m) = elf::load_segment_page(
+            self.data,
+            *PHYSICAL_OFFSET.get().unwrap(),
+            &self.headers,
+            self.bias,
+            &self.relocations,
+            page,
+            mapper,
+            alloc,
+            true,
+        ) {
+            error!("Load segment page failed: {:?}", m);
+            return false;
+        }
+
+        // shared pages are not tracked, as others may map them
+        crate::memory::swap::track(mapper, page);
+
+        self.usage.fetch_add(1, Ordering::Relaxed);
+
+        true
+    }
+
+    /// Check if any relocation is applied to the page
+    fn has_relocation(&self, page: Page) -> bool {
+        let start = page.start_address().as_u64() - self.bias;
+        let end = start + page.size();
+
+        // a relocation may start in the page before
+        let first = self
+            .relocations
+            .partition_point(|rela| rela.offset + 8 <= start);
+
+        self.relocations
+            .get(first)
+            .is_some_and(|rela| rela.offset < end)
+    }
+
+    /// Map a read-only page of a library to its shared frame
+    fn map_shared(
+        &self,
+        library: &SharedObject,
+        page: Page,
+        flags: PageTableFlags,
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+    ) -> bool {
+        let linked = page.start_address().as_u64() - self.bias;
+        let linked_page = Page::containing_address(VirtAddr::new(linked));
+
+        // pages without relocations are the same at any base
+        let frame = library.frame(linked, || {
+            let frame = alloc.allocate_frame()?;
+            let content = unsafe {
+                core::slice::from_raw_parts_mut(
+                    physical_to_virtual(frame.start_address().as_u64()) as *mut u8,
+                    page.size() as usize,
+                )
+            };
+            elf::fill_segment_page(self.data, content, &self.headers, 0, &[], linked_page, true);
+            Some(frame)
+        });
+
+        let frame = match frame {
+            Some(frame) => frame,
+            None => {
+                error!("Load shared page failed: out of memory");
+                return false;
+            }
+        };
+
+        trace!(
+            "Map shared page {:#x} of {} -> {:#x}",
+            page.start_address().as_u64(),
+            library.name(),
+            frame.start_address().as_u64()
+        );
+
+        match unsafe { mapper.map_to(page, frame, flags, alloc) } {
+            Ok(flush) => flush.flush(),
+            Err(m) => {
+                error!("Map shared page failed: {:?}", m);
+                return false;
+            }
+        }
+
+        self.usage.fetch_add(1, Ordering::Relaxed);
+
+        true
+    }
+
+    /// the size of all segments, including pages not filled yet
+    pub fn size(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|range| range.count() as u64)
+            .sum::<u64>()
+            * crate::memory::PAGE_SIZE
+    }
+
+    pub fn memory_usage(&self) -> u64 {
+        self.usage.load(Ordering::Relaxed) * crate::memory::PAGE_SIZE
+    }
+
+    pub(super) fn clean_up(
+        &self,
+        mapper: MapperRef,
+        dealloc: FrameAllocatorRef,
+    ) -> Result<(), UnmapError> {
+        if self.usage.load(Ordering::Relaxed) == 0 {
+            return Ok(());
+        }
+
+        for range in self.ranges.iter() {
+            // some pages may be swapped out
+            crate::memory::swap::release_range(mapper, *range);
+
+            // pages shared by two segments are unmapped with the first one
+            match self.library.as_ref() {
+                Some(library) => self.unmap_library_range(library, *range, mapper, dealloc)?,
+                None => {
+                    elf::unmap_range_sparse(*range, mapper, dealloc, true)?;
+                }
+            }
+        }
+
+        self.usage.store(0, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Unmap the filled pages of a library, shared frames are kept
+    fn unmap_library_range(
+        &self,
+        library: &SharedObject,
+        range: PageRangeInclusive,
+        mapper: MapperRef,
+        dealloc: FrameAllocatorRef,
+    ) -> Result<(), UnmapError> {
+        for page in range {
+            let frame = match mapper.unmap(page) {
+                Ok((frame, flush)) => {
+                    flush.flush();
+                    frame
+                }
+                Err(UnmapError::PageNotMapped) => continue,
+                Err(err) => return Err(err),
+            };
+
+            let linked = page.start_address().as_u64() - self.bias;
+            if !library.is_shared(linked, frame) {
+                unsafe { dealloc.deallocate_frame(frame) };
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl core::fmt::Debug for Segments {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Segments")
+            .field("count", &self.headers.len())
+            .field("relocations", &self.relocations.len())
+            .field("library", &self.library.as_ref().map(|l| l.name()))
+            .field(
+                "pages",
+                &self.ranges.iter().map(|range| range.count()).sum::<usize>(),
+            )
+            .field("usage", &self.usage.load(Ordering::Relaxed))
+            .finish()
+    }
+}
@@ -1,7 +1,11 @@
 use core::ptr::copy_nonoverlapping;
 
 use x86_64::{
-    structures::paging::{mapper::{MapToError, UnmapError}, page::*, Page},
+    structures::paging::{
+        mapper::{MapToError, UnmapError},
+        page::*,
+        FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags,
+    },
     VirtAddr,
 };
 
@@ -32,6 +36,20 @@ pub const STACK_INIT_TOP: u64 = STACK_MAX - 8;
 
 const STACK_INIT_TOP_PAGE: Page<Size4KiB> = Page::containing_address(VirtAddr::new(STACK_INIT_TOP));
 
+// the initial stack is placed in a random one of the top slots,
+// the slots below are left for the stacks of children
+pub const STACK_RANDOM_SLOTS: u64 = 0x800;
+// the initial stack pointer is moved down by up to this many bytes
+pub const STACK_RANDOM_OFFSET: u64 = 0x800;
+
+// the lowest pages of every stack slot are never mapped,
+// so that a stack can never grow into the slot below it
+pub const STACK_GUARD_PAGES: u64 = 1;
+// the default limit of a stack in pages, 8 MiB
+pub const STACK_DEF_LIMIT: u64 = 0x800;
+// the hard limit of a stack in pages
+pub const STACK_MAX_LIMIT: u64 = STACK_MAX_PAGES - STACK_GUARD_PAGES;
+
 // [bot..0xffffff0100000000..top..0xffffff01ffffffff]
 // kernel stack
 pub const KSTACK_MAX: u64 = 0xffff_ff02_0000_0000;
@@ -43,12 +61,13 @@ pub const KSTACK_INIT_BOT: u64 = KSTACK_MAX - KSTACK_DEF_SIZE;
 pub const KSTACK_INIT_TOP: u64 = KSTACK_MAX - 8;
 
 const KSTACK_INIT_PAGE: Page<Size4KiB> = Page::containing_address(VirtAddr::new(KSTACK_INIT_BOT));
-const KSTACK_INIT_TOP_PAGE: Page<Size4KiB> =
-    Page::containing_address(VirtAddr::new(KSTACK_INIT_TOP));
+const KSTACK_END_PAGE: Page<Size4KiB> = Page::containing_address(VirtAddr::new(KSTACK_MAX));
 
 pub struct Stack {
     range: PageRange<Size4KiB>,
     usage: u64,
+    /// the max count of pages the stack can grow to
+    limit: u64,
 }
 
 impl Stack {
@@ -56,6 +75,7 @@ impl Stack {
         Self {
             range: Page::range(top - size + 1, top + 1),
             usage: size,
+            limit: STACK_DEF_LIMIT,
         }
     }
 
@@ -63,21 +83,67 @@ impl Stack {
         Self {
             range: Page::range(STACK_INIT_TOP_PAGE, STACK_INIT_TOP_PAGE),
             usage: 0,
+            limit: STACK_DEF_LIMIT,
         }
     }
 
     pub const fn kstack() -> Self {
         Self {
-            range: Page::range(KSTACK_INIT_PAGE, KSTACK_INIT_TOP_PAGE),
+            range: Page::range(KSTACK_INIT_PAGE, KSTACK_END_PAGE),
             usage: KSTACK_DEF_PAGE,
+            limit: STACK_DEF_LIMIT,
         }
     }
 
-    pub fn init(&mut self, mapper: MapperRef, alloc: FrameAllocatorRef) {
+    pub fn range(&self) -> PageRange {
+        self.range
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Set the max count of pages the stack can grow to
+    ///
+    /// fails if the stack is already larger than the new limit
+    pub fn set_limit(&mut self, limit: u64) -> bool {
+        if limit < self.usage.max(1) || limit > STACK_MAX_LIMIT {
+            return false;
+        }
+
+        self.limit = limit;
+        true
+    }
+
+    /// Map the initial stack in a random slot
+    ///
+    /// `reserved` bytes at the top are mapped as well (for the TLS block).
+    /// returns the initial stack pointer, which is also randomized
+    /// within the top page
+    pub fn init(
+        &mut self,
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+        reserved: u64,
+    ) -> Result<VirtAddr, MapToError<Size4KiB>> {
         debug_assert!(self.usage == 0, "Stack is not empty.");
 
-        self.range = elf::map_pages(STACK_INIT_BOT, STACK_DEF_PAGE, mapper, alloc, true).unwrap();
-        self.usage = STACK_DEF_PAGE;
+        let slot_offset = boot::random::random_below(STACK_RANDOM_SLOTS) * STACK_MAX_SIZE;
+        let pages = STACK_DEF_PAGE + reserved.div_ceil(crate::memory::PAGE_SIZE);
+
+        self.range = elf::map_pages(
+            STACK_MAX - pages * crate::memory::PAGE_SIZE - slot_offset,
+            pages,
+            mapper,
+            alloc,
+            true,
+        )?;
+        self.usage = pages;
+
+        // keep the alignment of STACK_INIT_TOP
+        let offset = boot::random::random_below(STACK_RANDOM_OFFSET / 16) * 16;
+
+        Ok(VirtAddr::new(STACK_INIT_TOP - slot_offset - offset))
     }
 
     pub fn stack_offset(&self, old_stack: &Stack) -> u64 {
@@ -94,7 +160,7 @@ impl Stack {
         mapper: MapperRef,
         alloc: FrameAllocatorRef,
     ) -> bool {
-        if !self.is_on_stack(addr) {
+        if !self.is_on_stack(addr) || self.is_overflow(addr) {
             return false;
         }
 
@@ -114,6 +180,32 @@ impl Stack {
         addr & STACK_START_MASK == cur_stack_bot & STACK_START_MASK
     }
 
+    /// Check if the address is on the stack slot but beyond the limit
+    ///
+    /// the pages below the limit are left unmapped as guard pages
+    pub fn is_overflow(&self, addr: VirtAddr) -> bool {
+        if !self.is_on_stack(addr) {
+            return false;
+        }
+
+        let lowest_page = self.range.end - self.limit;
+        Page::containing_address(addr) < lowest_page
+    }
+
+    /// The size in bytes the stack grows by on a page fault at `addr`
+    pub fn grow_size(&self, addr: VirtAddr) -> u64 {
+        if !self.is_on_stack(addr) || self.is_overflow(addr) {
+            return 0;
+        }
+
+        let page = Page::containing_address(addr);
+        if page >= self.range.start {
+            return 0;
+        }
+
+        (self.range.start - page) * crate::memory::PAGE_SIZE
+    }
+
     fn grow_stack(
         &mut self,
         addr: VirtAddr,
@@ -157,15 +249,24 @@ impl Stack {
         mapper: MapperRef,
         alloc: FrameAllocatorRef,
         stack_offset_count: u64,
-    ) -> Self {
+    ) -> Result<Self, MapToError<Size4KiB>> {
         // FIXME: alloc & map new stack for child (see instructions)
         // 这里的offset是child个数 即多少个max_stack
         let mut new_stack_top = self.range.start.start_address().as_u64() - stack_offset_count * STACK_MAX_SIZE;
-        while elf::map_pages(new_stack_top, self.usage, mapper, alloc, true).is_err() {
-            trace!("Failed to map new stack on {:#x}, retrying...", new_stack_top);
-            new_stack_top -= STACK_MAX_SIZE;
+        loop {
+            match Self::map_new(new_stack_top, self.usage, mapper, alloc) {
+                Ok(_) => break,
+                Err(MapToError::PageAlreadyMapped(_)) => {
+                    trace!("Failed to map new stack on {:#x}, retrying...", new_stack_top);
+                    new_stack_top -= STACK_MAX_SIZE;
+                }
+                Err(err) => return Err(err),
+            }
         }
 
+        // the parent's stack is copied through its mapping
+        crate::memory::swap::swap_in_range(mapper, self.range, alloc);
+
         // FIXME: copy the *entire stack* from parent to child
         self.clone_range(
             self.range.start.start_address().as_u64(),
@@ -175,10 +276,55 @@ impl Stack {
 
         let start = Page::containing_address(VirtAddr::new(new_stack_top));
         // FIXME: return the new stack
-        Self {
+        Ok(Self {
             range: Page::range(start, start + self.usage),
-            usage: self.usage
+            usage: self.usage,
+            limit: self.limit,
+        })
+    }
+
+    /// Map `count` pages from `addr` for a new stack
+    ///
+    /// the page table is shared with the stacks of other processes, so the
+    /// pages mapped before a failure are unmapped and freed, and pages
+    /// already mapped are left as they are.
+    fn map_new(
+        addr: u64,
+        count: u64,
+        mapper: MapperRef,
+        alloc: FrameAllocatorRef,
+    ) -> Result<PageRange, MapToError<Size4KiB>> {
+        let start = Page::containing_address(VirtAddr::new(addr));
+        let range = Page::range(start, start + count);
+
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_EXECUTE
+            | PageTableFlags::USER_ACCESSIBLE;
+
+        for page in range {
+            let result = match mapper.translate_page(page) {
+                Ok(frame) => Err(MapToError::PageAlreadyMapped(frame)),
+                Err(_) => match alloc.allocate_frame() {
+                    Some(frame) => unsafe { mapper.map_to(page, frame, flags, alloc) }
+                        .map(|flush| flush.flush())
+                        .inspect_err(|_| unsafe { alloc.deallocate_frame(frame) }),
+                    None => Err(MapToError::FrameAllocationFailed),
+                },
+            };
+
+            if let Err(err) = result {
+                if page > start {
+                    let mapped = Page::range_inclusive(start, page - 1);
+                    if let Err(m) = elf::unmap_range_sparse(mapped, mapper, alloc, true) {
+                        error!("Unmap partial stack failed: {:?}", m);
+                    }
+                }
+                return Err(err);
+            }
         }
+
+        Ok(range)
     }
 
     /// Clone a range of memory
@@ -188,13 +334,13 @@ impl Stack {
     /// - `size`: the count of pages to be cloned
     fn clone_range(&self, cur_addr: u64, dest_addr: u64, size: u64) {
         trace!("Clone range: {:#x} -> {:#x}", cur_addr, dest_addr);
-        unsafe {
+        crate::memory::uaccess::with_user_access(|| unsafe {
             copy_nonoverlapping::<u64>(
                 cur_addr as *mut u64,
                 dest_addr as *mut u64,
                 (size * Size4KiB::SIZE / 8) as usize,
             );
-        }
+        });
     }
     
     pub fn memory_usage(&self) -> u64 {
@@ -215,9 +361,11 @@ impl Stack {
 
         // FIXME: unmap stack pages with `elf::unmap_pages`
 
-        let start = self.range.start.start_address().as_u64();
+        let range = Page::range_inclusive(self.range.start, self.range.end - 1);
 
-        elf::unmap_pages(start, self.usage, mapper, dealloc, true)?;
+        // some pages may be swapped out
+        crate::memory::swap::release_range(mapper, range);
+        elf::unmap_range_sparse(range, mapper, dealloc, true)?;
 
         self.usage = 0;
 
@@ -237,6 +385,7 @@ impl core::fmt::Debug for Stack {
                 "bot",
                 &format_args!("{:#x}", self.range.start.start_address().as_u64()),
             )
+            .field("limit", &self.limit)
             .finish()
     }
 }
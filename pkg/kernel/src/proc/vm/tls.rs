@@ -0,0 +1,167 @@
+//! Thread-local storage of user processes
+//!
+//! x86_64 uses TLS variant II: the TLS block sits right below the thread
+//! pointer (FS base), and the first word at the thread pointer points to
+//! itself. The block of a process is placed at the top of its stack, so
+//! that a forked child gets its own copy with the stack.
+
+use alloc::vec::Vec;
+use elf::{ElfError, Relocation};
+use x86_64::{
+    structures::paging::{page::PageRange, Page, Translate},
+    VirtAddr,
+};
+use xmas_elf::{program, ElfFile};
+
+use crate::memory::{physical_to_virtual, uaccess::with_user_access, PAGE_SIZE};
+
+use super::MapperRef;
+
+/// the size of the thread control block, which only holds the self pointer,
+/// 16 bytes to keep the stack below it aligned
+const TCB_SIZE: u64 = 16;
+
+/// the max size of .tdata and .tbss
+pub const TLS_MAX_SIZE: u64 = 0x10_0000; // 1 MiB
+
+/// The initial image of the TLS block, from the PT_TLS segment
+pub struct TlsTemplate {
+    /// the initialized part (.tdata), relocations applied
+    image: Vec<u8>,
+    /// the size of .tdata and .tbss
+    mem_size: u64,
+    /// the alignment of the thread pointer
+    align: u64,
+}
+
+impl TlsTemplate {
+    pub fn empty() -> Self {
+        Self {
+            image: Vec::new(),
+            mem_size: 0,
+            align: TCB_SIZE,
+        }
+    }
+
+    /// The TLS template of an ELF file loaded at `bias`
+    ///
+    /// the file must have been validated by `elf::validate`
+    pub fn new(elf: &ElfFile, bias: u64, relocations: &[Relocation]) -> Result<Self, ElfError> {
+        let segment = match elf
+            .program_iter()
+            .find(|segment| segment.get_type() == Ok(program::Type::Tls))
+        {
+            Some(segment) => segment,
+            None => return Ok(Self::empty()),
+        };
+
+        // an empty segment is not checked by `elf::validate`
+        if segment.mem_size() == 0 {
+            return Ok(Self::empty());
+        }
+
+        // the block is built in the kernel heap and placed on the stack
+        if segment.mem_size() > TLS_MAX_SIZE || segment.align() > PAGE_SIZE {
+            return Err(ElfError::InvalidSegment(segment.virtual_addr()));
+        }
+
+        let start = segment.offset() as usize;
+        let mut image = elf.input[start..start + segment.file_size() as usize].to_vec();
+
+        // pointers in .tdata are relocated like in the loaded segments
+        elf::relocate_page(&mut image, segment.virtual_addr() + bias, bias, relocations);
+
+        Ok(Self {
+            image,
+            mem_size: segment.mem_size(),
+            align: segment.align().max(TCB_SIZE),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mem_size == 0
+    }
+
+    /// The bytes to reserve at the top of the stack for the TLS block
+    pub fn reserved_size(&self) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+
+        // the thread pointer may be moved down by `align - 1` to align it
+        self.block_size() + self.align + TCB_SIZE
+    }
+
+    fn block_size(&self) -> u64 {
+        x86_64::align_up(self.mem_size, self.align)
+    }
+
+    /// Build the TLS block below `top` in the address space of `mapper`
+    ///
+    /// returns the thread pointer and the stack pointer below the block,
+    /// the reserved pages must have been mapped.
+    pub fn init(&self, top: VirtAddr, mapper: MapperRef) -> (VirtAddr, VirtAddr) {
+        if self.is_empty() {
+            return (VirtAddr::zero(), top);
+        }
+
+        let tp = x86_64::align_down(top.as_u64() - TCB_SIZE, self.align);
+        let start = tp - self.block_size();
+
+        let mut block = vec![0u8; (tp + TCB_SIZE - start) as usize];
+        block[..self.image.len()].copy_from_slice(&self.image);
+
+        let tcb = (tp - start) as usize;
+        block[tcb..tcb + 8].copy_from_slice(&tp.to_ne_bytes());
+
+        write_to(mapper, start, &block);
+
+        trace!("TLS block: [{:#x}, {:#x}), tp = {:#x}", start, tp, tp);
+
+        // keep the alignment of STACK_INIT_TOP
+        (VirtAddr::new(tp), VirtAddr::new(start - 8))
+    }
+}
+
+/// The thread pointer of a forked child
+///
+/// a TLS block on the parent's stack has been copied to the child's stack
+/// at `offset`, so its self pointer is fixed. a block elsewhere is shared.
+pub fn fork_thread_pointer(fs_base: VirtAddr, parent_stack: PageRange, offset: u64) -> VirtAddr {
+    let page = Page::containing_address(fs_base);
+    if fs_base.is_null() || page < parent_stack.start || page >= parent_stack.end {
+        return fs_base;
+    }
+
+    let tp = fs_base.as_u64().wrapping_add(offset);
+
+    // the page table is shared by parent and child
+    with_user_access(|| unsafe { (tp as *mut u64).write(tp) });
+
+    VirtAddr::new(tp)
+}
+
+/// Write `data` to `addr` in another address space, through the physical memory window
+fn write_to(mapper: MapperRef, addr: u64, data: &[u8]) {
+    let mut written = 0;
+
+    while written < data.len() {
+        let virt = VirtAddr::new(addr + written as u64);
+        let phys = mapper
+            .translate_addr(virt)
+            .expect("TLS block is not mapped");
+
+        let count =
+            (PAGE_SIZE as usize - u16::from(virt.page_offset()) as usize).min(data.len() - written);
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data[written..].as_ptr(),
+                physical_to_virtual(phys.as_u64()) as *mut u8,
+                count,
+            );
+        }
+
+        written += count;
+    }
+}
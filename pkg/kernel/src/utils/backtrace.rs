@@ -0,0 +1,162 @@
+//! Symbolised stack backtraces
+//!
+//! Frames are walked through the frame pointer chain: the kernel, modules
+//! and apps are built with frame pointers, so `[rbp]` is the frame pointer
+//! of the caller and `[rbp + 8]` the return address.
+//!
+//! Kernel addresses are symbolised with the symbol table packed by the
+//! bootloader, and user addresses with the symbol tables of the app and
+//! its libraries.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use elf::SymbolTable;
+use spin::Once;
+use x86_64::structures::paging::Translate;
+use x86_64::VirtAddr;
+
+use crate::memory::{allocator::kernel_mapper, PHYSICAL_OFFSET};
+
+/// The max count of frames to walk
+const MAX_FRAMES: usize = 32;
+
+/// The lowest address of the kernel space
+const KERNEL_SPACE_START: u64 = 0xffff_8000_0000_0000;
+
+/// The symbol table of the kernel and the kernel slide
+static KERNEL_SYMBOLS: Once<(SymbolTable<'static>, u64)> = Once::new();
+
+/// Set when a backtrace is being printed, a fault in the walk does
+/// not print another one
+static WALKING: AtomicBool = AtomicBool::new(false);
+
+pub fn init(boot_info: &'static boot::BootInfo) {
+    let symbols = match SymbolTable::new(boot_info.kernel_symbols) {
+        Some(symbols) => symbols,
+        None => {
+            warn!("Kernel symbol table is missing, backtraces are not symbolised.");
+            return;
+        }
+    };
+
+    info!("Kernel Symbols   : {}", symbols.len());
+
+    KERNEL_SYMBOLS.call_once(|| (symbols, boot_info.kernel_slide));
+}
+
+/// Walk the frame pointer chain from `rbp`, returns the return addresses
+///
+/// `read` reads a word of the stack, `None` if it is not mapped.
+pub fn walk(mut rbp: u64, read: impl Fn(u64) -> Option<u64>) -> Vec<u64> {
+    let mut frames = Vec::new();
+
+    while frames.len() < MAX_FRAMES && rbp != 0 && rbp % 8 == 0 {
+        let (next, ret) = match (read(rbp), read(rbp + 8)) {
+            (Some(next), Some(ret)) => (next, ret),
+            _ => break,
+        };
+
+        if ret == 0 {
+            break;
+        }
+
+        frames.push(ret);
+
+        // the stack grows down, callers are above
+        if next <= rbp {
+            break;
+        }
+
+        rbp = next;
+    }
+
+    frames
+}
+
+/// Print the frames, `symbolize` gives the name of the function
+/// containing an address and the offset in it
+pub fn print(frames: &[u64], symbolize: impl Fn(u64) -> Option<(String, u64)>) {
+    println_warn!("Backtrace:");
+
+    for (idx, &addr) in frames.iter().enumerate() {
+        // return addresses may be past the end of the calling function
+        let lookup = if idx == 0 { addr } else { addr.wrapping_sub(1) };
+
+        match symbolize(lookup) {
+            Some((name, offset)) => println_warn!(
+                "  #{:<2} {:#018x} {:#}+{:#x}",
+                idx,
+                addr,
+                rustc_demangle::demangle(&name),
+                offset + addr - lookup
+            ),
+            None => println_warn!("  #{:<2} {:#018x} <unknown>", idx, addr),
+        }
+    }
+}
+
+/// The function containing a kernel address
+pub fn kernel_symbol(addr: u64) -> Option<(String, u64)> {
+    let (symbols, slide) = KERNEL_SYMBOLS.get()?;
+    symbols.lookup(addr.checked_sub(*slide)?)
+}
+
+/// Describe a kernel address as `addr <function+offset>`
+pub fn describe(addr: u64) -> String {
+    match kernel_symbol(addr) {
+        Some((name, offset)) => format!(
+            "{:#x} <{:#}+{:#x}>",
+            addr,
+            rustc_demangle::demangle(&name),
+            offset
+        ),
+        None => format!("{:#x}", addr),
+    }
+}
+
+/// Print the backtrace of the kernel from `rip` and `rbp`
+pub fn print_kernel(rip: u64, rbp: u64) {
+    if WALKING.swap(true, Ordering::Acquire) {
+        return;
+    }
+
+    let mut frames = vec![rip];
+    frames.extend(walk(rbp, read_kernel));
+    print(&frames, kernel_symbol);
+
+    WALKING.store(false, Ordering::Release);
+}
+
+/// Print the backtrace of the caller
+#[inline(always)]
+pub fn print_current() {
+    let (rip, rbp): (u64, u64);
+
+    unsafe {
+        core::arch::asm!(
+            "lea {}, [rip]",
+            "mov {}, rbp",
+            out(reg) rip,
+            out(reg) rbp,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    print_kernel(rip, rbp);
+}
+
+/// Read a word of the kernel space, if it is mapped
+fn read_kernel(addr: u64) -> Option<u64> {
+    // the page table cannot be walked before the physical memory is known
+    PHYSICAL_OFFSET.get()?;
+
+    if addr < KERNEL_SPACE_START {
+        return None;
+    }
+
+    kernel_mapper().translate_addr(VirtAddr::try_new(addr).ok()?)?;
+
+    // an aligned word does not cross pages
+    Some(unsafe { (addr as *const u64).read() })
+}
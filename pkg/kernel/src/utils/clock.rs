@@ -1,6 +1,15 @@
 use super::uefi;
 use boot::BootInfo;
 use chrono::naive::*;
+use core::time::Duration;
+use spin::Once;
+use x86::{cpuid::CpuId, time::rdtsc};
+
+/// The TSC at boot and its frequency in Hz
+static TSC: Once<(u64, u64)> = Once::new();
+
+/// Assumed TSC frequency if cpuid does not report it
+const DEFAULT_TSC_FREQUENCY: u64 = 3_000_000_000;
 
 pub fn init(boot_info: &'static BootInfo) {
     if uefi::get_uefi_runtime().is_none() {
@@ -20,3 +29,25 @@ pub fn now() -> NaiveDateTime {
         )
         .unwrap_or_default()
 }
+
+/// Time since the first call, which is made when the logger starts
+pub fn uptime() -> Duration {
+    let (boot, frequency) = *TSC.call_once(|| {
+        let cpuid = CpuId::new();
+        let frequency = cpuid
+            .get_tsc_info()
+            .and_then(|info| info.tsc_frequency())
+            .or_else(|| {
+                cpuid
+                    .get_processor_frequency_info()
+                    .map(|info| info.processor_base_frequency() as u64 * 1_000_000)
+            })
+            .filter(|&frequency| frequency != 0)
+            .unwrap_or(DEFAULT_TSC_FREQUENCY);
+
+        (unsafe { rdtsc() }, frequency)
+    });
+
+    let cycles = unsafe { rdtsc() } - boot;
+    Duration::from_nanos((cycles as u128 * 1_000_000_000 / frequency as u128) as u64)
+}
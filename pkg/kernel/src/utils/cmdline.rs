@@ -0,0 +1,171 @@
+//! Kernel command line
+//!
+//! the `cmdline` of `boot.conf`, as space separated `key=value` options:
+//!
+//! - `init=<app>`: the program spawned by the kernel, `sh` by default
+//! - `log=<filters>`: log filters on top of `log_level`, see [`super::logger`]
+//! - `sched=rr|fifo`: preempt processes on each tick, or only switch when
+//!   the current process blocks or exits
+//! - `timer_hz=<n>`: the frequency of the timer interrupt
+//! - `mem=<size>`: the max physical memory to use, e.g. `256M`
+//! - `proc_mem=<size>`: the default resident memory limit of processes
+//! - `swap=<size>`: the size of the swap ramdisk, 8M by default, 0 disables it
+//! - `strace=<pid>,...`: log the syscalls of the processes, at the debug level
+
+use spin::Once;
+
+use crate::proc::ProcessId;
+
+static CMDLINE: Once<Cmdline> = Once::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheduler {
+    /// switch to the next ready process on each timer tick
+    RoundRobin,
+    /// run a process until it blocks or exits
+    Fifo,
+}
+
+#[derive(Debug)]
+pub struct Cmdline {
+    raw: &'static str,
+    pub init: &'static str,
+    pub log: Option<&'static str>,
+    pub sched: Scheduler,
+    pub timer_hz: Option<u32>,
+    /// in bytes
+    pub mem: Option<u64>,
+    /// in bytes
+    pub proc_mem: Option<u64>,
+    /// in bytes
+    pub swap: Option<u64>,
+    /// comma separated pids
+    strace: &'static str,
+}
+
+impl Default for Cmdline {
+    fn default() -> Self {
+        Self {
+            raw: "",
+            init: "sh",
+            log: None,
+            sched: Scheduler::RoundRobin,
+            timer_hz: None,
+            mem: None,
+            proc_mem: None,
+            swap: None,
+            strace: "",
+        }
+    }
+}
+
+impl Cmdline {
+    /// Parse options, invalid and unknown ones are ignored with a warning
+    pub fn parse(raw: &'static str) -> Self {
+        let mut cmdline = Self {
+            raw,
+            ..Self::default()
+        };
+
+        for option in raw.split_whitespace() {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+
+            let valid = match key {
+                "init" => {
+                    cmdline.init = value;
+                    !value.is_empty()
+                }
+                "log" => {
+                    cmdline.log = Some(value);
+                    true
+                }
+                "sched" => match value {
+                    "rr" => {
+                        cmdline.sched = Scheduler::RoundRobin;
+                        true
+                    }
+                    "fifo" => {
+                        cmdline.sched = Scheduler::Fifo;
+                        true
+                    }
+                    _ => false,
+                },
+                "timer_hz" => {
+                    cmdline.timer_hz = value.parse().ok().filter(|&hz| hz > 0);
+                    cmdline.timer_hz.is_some()
+                }
+                "mem" => {
+                    cmdline.mem = parse_size(value);
+                    cmdline.mem.is_some()
+                }
+                "proc_mem" => {
+                    cmdline.proc_mem = parse_size(value);
+                    cmdline.proc_mem.is_some()
+                }
+                "swap" => {
+                    cmdline.swap = parse_size(value);
+                    cmdline.swap.is_some()
+                }
+                "strace" => {
+                    cmdline.strace = value;
+                    value.split(',').all(|pid| pid.parse::<u16>().is_ok())
+                }
+                _ => {
+                    warn!("Unknown kernel option: {}", option);
+                    continue;
+                }
+            };
+
+            if !valid {
+                warn!("Invalid kernel option: {}", option);
+            }
+        }
+
+        if cmdline.init.is_empty() {
+            cmdline.init = "sh";
+        }
+
+        cmdline
+    }
+
+    /// The command line as given by the bootloader
+    pub fn raw(&self) -> &'static str {
+        self.raw
+    }
+
+    /// Check if the syscalls of `pid` are logged from its start
+    pub fn strace(&self, pid: ProcessId) -> bool {
+        self.strace
+            .split(',')
+            .any(|item| item.parse::<u16>() == Ok(pid.0))
+    }
+}
+
+/// Parse a size in bytes, with an optional `K`, `M` or `G` suffix
+fn parse_size(value: &str) -> Option<u64> {
+    let (digits, shift) = match value.as_bytes().last()? {
+        b'K' | b'k' => (&value[..value.len() - 1], 10),
+        b'M' | b'm' => (&value[..value.len() - 1], 20),
+        b'G' | b'g' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+pub fn init(boot_info: &'static boot::BootInfo) {
+    let cmdline = CMDLINE.call_once(|| Cmdline::parse(boot_info.cmdline));
+
+    if let Some(filters) = cmdline.log {
+        if !super::logger::set_filters(filters) {
+            warn!("Invalid log filters: {}", filters);
+        }
+    }
+
+    info!("Kernel command line: {}", cmdline.raw);
+}
+
+/// The parsed command line, the defaults before `init`
+pub fn get() -> &'static Cmdline {
+    CMDLINE.call_once(Cmdline::default)
+}
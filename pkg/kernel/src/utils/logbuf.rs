@@ -0,0 +1,119 @@
+//! Kernel log ring buffer
+//!
+//! records are kept as text, `<level>[seconds.micros] message\n`, and
+//! the oldest bytes are overwritten when the buffer is full. see
+//! `syscall_def::SyslogAction` for how user processes read it.
+
+use core::fmt::{Arguments, Write};
+use log::Level;
+use spin::Mutex;
+
+use super::clock;
+
+pub const LOG_BUFFER_SIZE: usize = 0x10000;
+
+static LOG_BUFFER: Mutex<LogBuffer> = Mutex::new(LogBuffer::new());
+
+struct LogBuffer {
+    data: [u8; LOG_BUFFER_SIZE],
+    /// index of the oldest byte
+    head: usize,
+    len: usize,
+    /// the oldest record is cut by an overwrite
+    wrapped: bool,
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        Self {
+            data: [0; LOG_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+            wrapped: false,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len == LOG_BUFFER_SIZE {
+            self.head = (self.head + 1) % LOG_BUFFER_SIZE;
+            self.len -= 1;
+            self.wrapped = true;
+        }
+
+        self.data[(self.head + self.len) % LOG_BUFFER_SIZE] = byte;
+        self.len += 1;
+    }
+
+    fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.len).map(|i| self.data[(self.head + i) % LOG_BUFFER_SIZE])
+    }
+
+    /// Copy the latest whole records which fit in `buf`
+    fn copy_latest(&self, buf: &mut [u8]) -> usize {
+        let skip = self.len.saturating_sub(buf.len());
+        let mut bytes = self.bytes().skip(skip.saturating_sub(1));
+
+        // drop the record the cut falls in, from the byte before the cut,
+        // so a record starting right at the cut is kept
+        if skip > 0 || self.wrapped {
+            for byte in bytes.by_ref() {
+                if byte == b'\n' {
+                    break;
+                }
+            }
+        }
+
+        let mut count = 0;
+        for (dst, byte) in buf.iter_mut().zip(bytes) {
+            *dst = byte;
+            count += 1;
+        }
+
+        count
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+        self.wrapped = false;
+    }
+}
+
+impl Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Append a record to the log
+pub fn record(level: Level, args: &Arguments) {
+    let time = clock::uptime();
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut buffer = LOG_BUFFER.lock();
+        writeln!(
+            buffer,
+            "<{}>[{:>5}.{:06}] {}",
+            level as usize,
+            time.as_secs(),
+            time.subsec_micros(),
+            args
+        )
+        .ok();
+    })
+}
+
+/// Copy the latest whole records which fit in `buf`,
+/// returns the count of bytes copied
+///
+/// nothing is allocated while the buffer is locked, as the allocator logs.
+pub fn read(buf: &mut [u8]) -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| LOG_BUFFER.lock().copy_latest(buf))
+}
+
+pub fn clear() {
+    x86_64::instructions::interrupts::without_interrupts(|| LOG_BUFFER.lock().clear())
+}
@@ -1,30 +1,190 @@
+//! Kernel logger with per-module filters
+//!
+//! filters are given as `level,module=level,...`, e.g. `info,proc=trace`.
+//! a module is a path in the kernel (`proc::manager`) or a full path with
+//! the crate name, which matches itself and its submodules. the longest
+//! matching module wins, and a bare level is the default for the rest.
+
+use core::fmt::{Display, Formatter};
+use core::str::FromStr;
+
 use log::{LevelFilter, Metadata, Record};
+use spin::RwLock;
+
+const MAX_MODULE_FILTERS: usize = 16;
+const MAX_MODULE_LEN: usize = 48;
+
+static FILTERS: RwLock<Filters> = RwLock::new(Filters::new());
 
 pub fn init(boot_info: &'static boot::BootInfo) {
     static LOGGER: Logger = Logger;
     log::set_logger(&LOGGER).unwrap();
-    log::set_max_level(match boot_info.log_level {
-        "error" => LevelFilter::Error,
-        "warn" => LevelFilter::Warn,
-        "info" => LevelFilter::Info,
-        "debug" => LevelFilter::Debug,
-        "trace" => LevelFilter::Trace,
-        _ => LevelFilter::Info,
-    });
 
-    info!("Current log level: {}", log::max_level());
+    if !set_filters(boot_info.log_level) {
+        set_filters("info");
+        warn!("Invalid log level: {}", boot_info.log_level);
+    }
+
+    info!("Current log level: {}", *FILTERS.read());
 
     info!("Logger Initialized.");
 }
 
+/// Apply the filters of `spec` on top of the current ones
+///
+/// nothing is changed if any of them is invalid. fails when there are too
+/// many modules, with the modules before applied.
+pub fn set_filters(spec: &str) -> bool {
+    let directives = || {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|directive| !directive.is_empty())
+    };
+
+    if !directives().all(|directive| parse_directive(directive).is_some()) {
+        return false;
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut filters = FILTERS.write();
+
+        for directive in directives() {
+            match parse_directive(directive) {
+                Some((Some(module), level)) => {
+                    if !filters.set_module(module, level) {
+                        return false;
+                    }
+                }
+                Some((None, level)) => filters.default = level,
+                None => unreachable!(),
+            }
+        }
+
+        log::set_max_level(filters.max_level());
+        true
+    })
+}
+
+pub fn print_filters() {
+    println!("Log level: {}", *FILTERS.read());
+}
+
+/// Parse `level` or `module=level`
+fn parse_directive(directive: &str) -> Option<(Option<&str>, LevelFilter)> {
+    match directive.split_once('=') {
+        Some((module, level)) => {
+            let module = module.trim();
+            if module.is_empty() || module.len() > MAX_MODULE_LEN {
+                return None;
+            }
+            Some((Some(module), LevelFilter::from_str(level.trim()).ok()?))
+        }
+        None => Some((None, LevelFilter::from_str(directive).ok()?)),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ModuleFilter {
+    path: [u8; MAX_MODULE_LEN],
+    len: usize,
+    level: LevelFilter,
+}
+
+impl ModuleFilter {
+    fn path(&self) -> &str {
+        core::str::from_utf8(&self.path[..self.len]).unwrap_or("")
+    }
+
+    /// Check if `target` is the module or one of its submodules
+    fn matches(&self, target: &str) -> bool {
+        let path = self.path();
+        target.starts_with(path)
+            && (target.len() == path.len() || target[path.len()..].starts_with("::"))
+    }
+}
+
+struct Filters {
+    default: LevelFilter,
+    modules: [Option<ModuleFilter>; MAX_MODULE_FILTERS],
+}
+
+impl Filters {
+    const fn new() -> Self {
+        Self {
+            default: LevelFilter::Info,
+            modules: [None; MAX_MODULE_FILTERS],
+        }
+    }
+
+    /// Set the level of a module, fails if there are too many modules
+    fn set_module(&mut self, module: &str, level: LevelFilter) -> bool {
+        let existing = self
+            .modules
+            .iter()
+            .position(|filter| filter.is_some_and(|filter| filter.path() == module));
+
+        let Some(slot) = existing.or_else(|| self.modules.iter().position(Option::is_none)) else {
+            return false;
+        };
+
+        let mut path = [0; MAX_MODULE_LEN];
+        path[..module.len()].copy_from_slice(module.as_bytes());
+
+        self.modules[slot] = Some(ModuleFilter {
+            path,
+            len: module.len(),
+            level,
+        });
+        true
+    }
+
+    /// The level for a target, which is the module path of a record
+    fn level(&self, target: &str) -> LevelFilter {
+        // the kernel modules are also matched without the crate name
+        let path = target.split_once("::").map_or("", |(_, path)| path);
+
+        self.modules
+            .iter()
+            .flatten()
+            .filter(|filter| filter.matches(path) || filter.matches(target))
+            .max_by_key(|filter| filter.len)
+            .map_or(self.default, |filter| filter.level)
+    }
+
+    /// The most verbose level of all filters, to let records through `log`
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .flatten()
+            .map(|filter| filter.level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+impl Display for Filters {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.default)?;
+        for filter in self.modules.iter().flatten() {
+            write!(f, ",{}={}", filter.path(), filter.level)?;
+        }
+        Ok(())
+    }
+}
+
 struct Logger;
 
 impl log::Log for Logger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= FILTERS.read().level(metadata.target())
     }
 
     fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        super::logbuf::record(record.level(), record.args());
+
         match record.level() {
             log::Level::Error => println_warn!(
                 "[E] {}@{}: {}",
@@ -136,5 +136,6 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
         "No more message...".to_string()
     };
     error!("\n\n\rERROR: panicked at {}\n\n\r{}", location, msg);
+    crate::backtrace::print_current();
     loop {}
 }
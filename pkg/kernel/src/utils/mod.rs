@@ -5,8 +5,11 @@ mod macros;
 #[macro_use]
 mod regs;
 
+pub mod backtrace;
 pub mod clock;
+pub mod cmdline;
 pub mod func;
+pub mod logbuf;
 pub mod logger;
 pub mod resource;
 
@@ -87,4 +87,58 @@ macro_rules! as_handler {
             }
         }
     };
+    // for exceptions which push an error code, e.g. page fault
+    //
+    // the error code is passed as the first argument (rdi),
+    // and removed from the stack so that `ProcessContext` follows it.
+    ($fn: ident, $err: ty) => {
+        paste::item! {
+            #[naked]
+            pub extern "x86-interrupt" fn [<$fn _handler>](_sf: InterruptStackFrame, _err: $err) {
+                unsafe {
+                    core::arch::asm!("
+                    push rbp
+                    push rax
+                    push rbx
+                    push rcx
+                    push rdx
+                    push rsi
+                    push rdi
+                    push r8
+                    push r9
+                    push r10
+                    push r11
+                    push r12
+                    push r13
+                    push r14
+                    push r15
+                    mov rdi, [rsp + 15 * 8]
+                    mov rcx, 15
+                    2:
+                    mov rax, [rsp + rcx * 8 - 8]
+                    mov [rsp + rcx * 8], rax
+                    loop 2b
+                    add rsp, 8
+                    call {}
+                    pop r15
+                    pop r14
+                    pop r13
+                    pop r12
+                    pop r11
+                    pop r10
+                    pop r9
+                    pop r8
+                    pop rdi
+                    pop rsi
+                    pop rdx
+                    pop rcx
+                    pop rbx
+                    pop rax
+                    pop rbp
+                    iretq",
+                    sym $fn, options(noreturn));
+                }
+            }
+        }
+    };
 }
@@ -1,5 +1,6 @@
-use crate::drivers::input::*;
-use alloc::{collections::BTreeMap, string::String};
+use crate::drivers::{device::CharDevice, input::*};
+use crate::fs::File;
+use alloc::{collections::BTreeMap, string::String, sync::Arc};
 use spin::Mutex;
 
 #[derive(Debug, Clone)]
@@ -29,10 +30,13 @@ impl Default for ResourceSet {
 }
 
 impl ResourceSet {
-    pub fn open(&mut self, res: Resource) -> u8 {
-        let fd = self.handles.len() as u8;
+    /// Open a resource at the lowest free descriptor,
+    /// fails if all descriptors are in use
+    pub fn open(&mut self, res: Resource) -> Option<u8> {
+        // handles may have been closed
+        let fd = (0..=u8::MAX).find(|fd| !self.handles.contains_key(fd))?;
         self.handles.insert(fd, Mutex::new(res));
-        fd
+        Some(fd)
     }
 
     pub fn close(&mut self, fd: u8) -> bool {
@@ -54,10 +58,19 @@ impl ResourceSet {
             -1
         }
     }
+
+    /// Run `f` on an opened resource
+    pub fn with_resource<T>(&self, fd: u8, f: impl FnOnce(&mut Resource) -> T) -> Option<T> {
+        self.handles.get(&fd).map(|h| f(&mut h.lock()))
+    }
 }
 
 pub enum Resource {
     Console(StdIO),
+    Device(Arc<CharDevice>),
+    /// read-only text, with the offset of the next read
+    Text(&'static str, usize),
+    File(File),
     Null,
 }
 
@@ -76,6 +89,15 @@ impl Resource {
                 }
                 _ => None,
             },
+            Resource::Device(device) => device.read(buf),
+            Resource::Text(text, offset) => {
+                let rest = &text.as_bytes()[*offset..];
+                let count = rest.len().min(buf.len());
+                buf[..count].copy_from_slice(&rest[..count]);
+                *offset += count;
+                Some(count)
+            }
+            Resource::File(file) => file.read(buf).ok(),
             Resource::Null => Some(0),
         }
     }
@@ -93,6 +115,9 @@ impl Resource {
                     Some(buf.len())
                 }
             },
+            Resource::Device(device) => device.write(buf),
+            Resource::Text(..) => None,
+            Resource::File(file) => file.write(buf).ok(),
             Resource::Null => Some(buf.len()),
         }
     }
@@ -102,6 +127,9 @@ impl core::fmt::Debug for Resource {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Resource::Console(stdio) => write!(f, "Console({:?})", stdio),
+            Resource::Device(device) => write!(f, "Device({})", device.name()),
+            Resource::Text(_, offset) => write!(f, "Text({})", offset),
+            Resource::File(file) => write!(f, "File({})", file.path()),
             Resource::Null => write!(f, "Null"),
         }
     }
@@ -1,6 +1,11 @@
 use chrono::{naive::*, DateTime, Utc};
 use syscall_def::Syscall;
 
+pub use syscall_def::{
+    ArchPrctlCode, DirEntry, FileStat, FileType, OpenFlags, PtraceOp, PtraceRegs, PtraceStop,
+    Rlimit, SeekWhence, SyslogAction,
+};
+
 #[inline(always)]
 pub fn sys_write(fd: u8, buf: &[u8]) -> Option<usize> {
     let ret = syscall!(
@@ -31,9 +36,60 @@ pub fn sys_read(fd: u8, buf: &mut [u8]) -> Option<usize> {
     }
 }
 
+/// Open a file or a device (`/dev/<name>`) by its absolute path
+#[inline(always)]
+pub fn sys_open(path: &str) -> Option<u8> {
+    sys_open_with(path, OpenFlags::empty())
+}
+
+/// Open a file, creating or truncating it as `flags` asks
+#[inline(always)]
+pub fn sys_open_with(path: &str, flags: OpenFlags) -> Option<u8> {
+    let ret = syscall!(
+        Syscall::Open,
+        path.as_ptr() as u64,
+        path.len() as u64,
+        flags.bits()
+    ) as isize;
+    if ret.is_negative() {
+        None
+    } else {
+        Some(ret as u8)
+    }
+}
+
+/// Move the offset of a file, returns the new offset
+#[inline(always)]
+pub fn sys_lseek(fd: u8, offset: isize, whence: SeekWhence) -> Option<usize> {
+    let ret = syscall!(Syscall::Lseek, fd as u64, offset, whence as usize) as isize;
+    if ret.is_negative() {
+        None
+    } else {
+        Some(ret as usize)
+    }
+}
+
+#[inline(always)]
+pub fn sys_fstat(fd: u8) -> Option<FileStat> {
+    let mut stat = FileStat::default();
+    (syscall!(Syscall::Fstat, fd as u64, &mut stat as *mut FileStat) == 0).then_some(stat)
+}
+
+/// The next entry of an opened directory, `None` at the end or on errors
+#[inline(always)]
+pub fn sys_read_dir(fd: u8) -> Option<DirEntry> {
+    let mut entry = DirEntry::default();
+    (syscall!(Syscall::ReadDir, fd as u64, &mut entry as *mut DirEntry) == 1).then_some(entry)
+}
+
+#[inline(always)]
+pub fn sys_close(fd: u8) -> bool {
+    syscall!(Syscall::Close, fd as u64) == 0
+}
+
 #[inline(always)]
 pub fn sys_allocate(layout: &core::alloc::Layout) -> *mut u8 {
-    syscall!(Syscall::Allocate, layout as *const _) as *mut u8
+    syscall!(Syscall::Allocate, layout.size(), layout.align()) as *mut u8
 }
 
 #[inline(always)]
@@ -43,7 +99,7 @@ pub fn sys_list_app() {
 
 #[inline(always)]
 pub fn sys_deallocate(ptr: *mut u8, layout: &core::alloc::Layout) -> usize {
-    syscall!(Syscall::Deallocate, ptr, layout as *const _)
+    syscall!(Syscall::Deallocate, ptr, layout.size(), layout.align())
 }
 
 #[inline(always)]
@@ -116,4 +172,151 @@ pub fn sys_brk(addr: Option<usize>) -> Option<usize> {
         BRK_FAILED => None,
         ret => Some(ret),
     }
-}
\ No newline at end of file
+}
+
+#[inline(always)]
+pub fn sys_get_rlimit(resource: Rlimit) -> Option<usize> {
+    const RLIMIT_FAILED: usize = !0;
+    match syscall!(Syscall::GetRlimit, resource as usize) {
+        RLIMIT_FAILED => None,
+        ret => Some(ret),
+    }
+}
+
+#[inline(always)]
+pub fn sys_set_rlimit(resource: Rlimit, limit: usize) -> bool {
+    syscall!(Syscall::SetRlimit, resource as usize, limit) == 0
+}
+
+#[inline(always)]
+pub fn sys_arch_prctl(code: ArchPrctlCode, addr: usize) -> bool {
+    syscall!(Syscall::ArchPrctl, code as usize, addr) == 0
+}
+
+/// Set the FS base (the thread pointer)
+#[inline(always)]
+pub fn sys_set_fs_base(addr: usize) -> bool {
+    sys_arch_prctl(ArchPrctlCode::SetFs, addr)
+}
+
+/// Get the FS base (the thread pointer)
+#[inline(always)]
+pub fn sys_get_fs_base() -> Option<usize> {
+    let mut addr = 0usize;
+    sys_arch_prctl(ArchPrctlCode::GetFs, &mut addr as *mut usize as usize).then_some(addr)
+}
+
+/// Load the kernel module `<name>.ko` from the app list
+#[inline(always)]
+pub fn sys_init_module(name: &str) -> bool {
+    syscall!(Syscall::InitModule, name.as_ptr() as u64, name.len() as u64) == 0
+}
+
+/// Unload a kernel module, fails if it is in use
+#[inline(always)]
+pub fn sys_delete_module(name: &str) -> bool {
+    syscall!(Syscall::DeleteModule, name.as_ptr() as u64, name.len() as u64) == 0
+}
+
+#[inline(always)]
+pub fn sys_list_module() {
+    syscall!(Syscall::ListModule);
+}
+
+/// Read or clear the kernel log, see [`SyslogAction`]
+///
+/// returns the count of bytes read, or the size of the log buffer.
+#[inline(always)]
+pub fn sys_syslog(action: SyslogAction, buf: &mut [u8]) -> Option<usize> {
+    let ret = syscall!(
+        Syscall::Syslog,
+        action as u64,
+        buf.as_mut_ptr() as u64,
+        buf.len() as u64
+    );
+    if ret == !0 {
+        None
+    } else {
+        Some(ret)
+    }
+}
+
+/// Change the kernel log filters, e.g. `info,proc::manager=trace`,
+/// or print them if `filters` is empty
+#[inline(always)]
+pub fn sys_log_level(filters: &str) -> bool {
+    syscall!(
+        Syscall::LogLevel,
+        filters.as_ptr() as u64,
+        filters.len() as u64
+    ) == 0
+}
+
+/// Log the syscalls of a process in the kernel log
+#[inline(always)]
+pub fn sys_strace(pid: u16, enable: bool) -> bool {
+    syscall!(Syscall::Strace, pid as u64, enable as u64) == 0
+}
+
+/// Print the syscall counters of a process
+#[inline(always)]
+pub fn sys_syscall_stat(pid: u16) -> bool {
+    syscall!(Syscall::SyscallStat, pid as u64) == 0
+}
+
+#[inline(always)]
+fn sys_ptrace(op: PtraceOp, pid: u16, addr: usize, data: usize) -> bool {
+    syscall!(Syscall::Ptrace, op as usize, pid, addr, data) == 0
+}
+
+/// Trace a process, `sys_wait_pid` reports its stops
+#[inline(always)]
+pub fn sys_ptrace_attach(pid: u16) -> bool {
+    sys_ptrace(PtraceOp::Attach, pid, 0, 0)
+}
+
+/// Stop tracing a process and resume it
+#[inline(always)]
+pub fn sys_ptrace_detach(pid: u16) -> bool {
+    sys_ptrace(PtraceOp::Detach, pid, 0, 0)
+}
+
+/// Resume a stopped tracee
+#[inline(always)]
+pub fn sys_ptrace_cont(pid: u16) -> bool {
+    sys_ptrace(PtraceOp::Cont, pid, 0, 0)
+}
+
+/// Resume a stopped tracee for one instruction
+#[inline(always)]
+pub fn sys_ptrace_step(pid: u16) -> bool {
+    sys_ptrace(PtraceOp::SingleStep, pid, 0, 0)
+}
+
+/// Resume a stopped tracee until the next syscall entry or exit
+#[inline(always)]
+pub fn sys_ptrace_syscall(pid: u16) -> bool {
+    sys_ptrace(PtraceOp::Syscall, pid, 0, 0)
+}
+
+#[inline(always)]
+pub fn sys_ptrace_peek(pid: u16, addr: usize) -> Option<u64> {
+    let mut word = 0u64;
+    sys_ptrace(PtraceOp::PeekData, pid, addr, &mut word as *mut u64 as usize).then_some(word)
+}
+
+#[inline(always)]
+pub fn sys_ptrace_poke(pid: u16, addr: usize, word: u64) -> bool {
+    sys_ptrace(PtraceOp::PokeData, pid, addr, word as usize)
+}
+
+#[inline(always)]
+pub fn sys_ptrace_get_regs(pid: u16) -> Option<PtraceRegs> {
+    let mut regs = PtraceRegs::default();
+    sys_ptrace(PtraceOp::GetRegs, pid, 0, &mut regs as *mut PtraceRegs as usize).then_some(regs)
+}
+
+#[inline(always)]
+pub fn sys_ptrace_set_regs(pid: u16, regs: &PtraceRegs) -> bool {
+    sys_ptrace(PtraceOp::SetRegs, pid, 0, regs as *const PtraceRegs as usize)
+}
@@ -0,0 +1,59 @@
+//! A sample kernel module, which provides `/dev/zero`
+//!
+//! reads fill the buffer with zeros, and writes are discarded.
+
+#![no_std]
+
+type ReadFn = extern "C" fn(buf: *mut u8, len: usize) -> isize;
+type WriteFn = extern "C" fn(buf: *const u8, len: usize) -> isize;
+
+// exported by the kernel, see kernel/src/module/symbols.rs
+extern "C" {
+    fn ysos_log(level: usize, msg: *const u8, len: usize);
+    fn ysos_register_chrdev(
+        name: *const u8,
+        len: usize,
+        read: Option<ReadFn>,
+        write: Option<WriteFn>,
+    ) -> isize;
+    fn ysos_unregister_chrdev(name: *const u8, len: usize) -> isize;
+}
+
+const NAME: &str = "zero";
+
+fn log(msg: &str) {
+    unsafe { ysos_log(3, msg.as_ptr(), msg.len()) };
+}
+
+extern "C" fn read(buf: *mut u8, len: usize) -> isize {
+    unsafe { core::ptr::write_bytes(buf, 0, len) };
+    len as isize
+}
+
+extern "C" fn write(_buf: *const u8, len: usize) -> isize {
+    len as isize
+}
+
+#[no_mangle]
+pub extern "C" fn module_init() -> isize {
+    let ret = unsafe { ysos_register_chrdev(NAME.as_ptr(), NAME.len(), Some(read), Some(write)) };
+
+    if ret == 0 {
+        log("zero: /dev/zero is ready");
+    }
+
+    ret
+}
+
+#[no_mangle]
+pub extern "C" fn module_exit() {
+    unsafe { ysos_unregister_chrdev(NAME.as_ptr(), NAME.len()) };
+    log("zero: goodbye");
+}
+
+#[allow(clippy::empty_loop)]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    log("zero: panicked");
+    loop {}
+}
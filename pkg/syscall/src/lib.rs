@@ -9,11 +9,17 @@ pub mod macros;
 pub enum Syscall {
     Read = 0,
     Write = 1,
+    Open = 2,
+    Close = 3,
+    Fstat = 5,
+    Lseek = 8,
 
     Brk = 12,
 
     GetPid = 39,
 
+    ReadDir = 89,
+
     Fork = 58,
     Spawn = 59,
     Exit = 60,
@@ -21,8 +27,22 @@ pub enum Syscall {
     Kill = 62,
     Sem = 63,
 
+    Ptrace = 101,
+    Syslog = 103,
+
+    GetRlimit = 97,
+    ArchPrctl = 158,
+    SetRlimit = 160,
+
+    InitModule = 175,
+    DeleteModule = 176,
+
     Time = 201,
 
+    LogLevel = 65525,
+    SyscallStat = 65526,
+    Strace = 65527,
+    ListModule = 65528,
     ListApp = 65529,
     Stat = 65530,
     Allocate = 65533,
@@ -31,3 +51,273 @@ pub enum Syscall {
     #[num_enum(default)]
     None = 65535,
 }
+
+/// Resources that can be limited by `SetRlimit`
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+pub enum Rlimit {
+    /// the max size of the stack in bytes
+    Stack = 3,
+
+    /// the max size of resident memory in bytes
+    Rss = 5,
+
+    /// the max count of living children
+    Nproc = 6,
+
+    /// the max count of open files
+    Nofile = 7,
+
+    /// the max size of the address space in bytes
+    As = 9,
+
+    #[num_enum(default)]
+    Unknown = 65535,
+}
+
+/// Operations of `ArchPrctl`
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+pub enum ArchPrctlCode {
+    /// set the FS base (the thread pointer)
+    SetFs = 0x1002,
+
+    /// get the FS base
+    GetFs = 0x1003,
+
+    #[num_enum(default)]
+    Unknown = 65535,
+}
+
+/// Operations of `Ptrace`
+///
+/// all operations but `Attach` need the tracee to be stopped, and the
+/// caller to be its tracer.
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+pub enum PtraceOp {
+    /// read the word at `addr` of the tracee into `*data`
+    PeekData = 2,
+
+    /// write the word `data` at `addr` of the tracee
+    PokeData = 5,
+
+    /// resume the tracee
+    Cont = 7,
+
+    /// resume the tracee for one instruction
+    SingleStep = 9,
+
+    /// read the registers of the tracee into `*data` as [`PtraceRegs`]
+    GetRegs = 12,
+
+    /// write the registers of the tracee from `*data` as [`PtraceRegs`]
+    SetRegs = 13,
+
+    /// trace a process, which stops the next time it is scheduled
+    Attach = 16,
+
+    /// stop tracing a process and resume it
+    Detach = 17,
+
+    /// resume the tracee until the next syscall entry or exit
+    Syscall = 24,
+
+    #[num_enum(default)]
+    Unknown = 65535,
+}
+
+/// Registers of a tracee, read and written by `Ptrace`
+///
+/// only the arithmetic, trap and direction flags of `rflags` can be changed.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PtraceRegs {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+}
+
+/// Why a tracee stopped, reported to the tracer by `WaitPid`
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FromPrimitive)]
+pub enum PtraceStop {
+    /// stopped after `Attach`
+    Attach = 1,
+
+    /// hit an `int3`, the instruction pointer is after it
+    Breakpoint = 2,
+
+    /// finished a single step
+    Step = 3,
+
+    /// about to run a syscall, the number is in `rax`
+    SyscallEntry = 4,
+
+    /// returned from a syscall, the return value is in `rax`
+    SyscallExit = 5,
+
+    #[num_enum(default)]
+    Unknown = 255,
+}
+
+/// `WaitPid` statuses of stops are in [STOP_STATUS_BASE, STOP_STATUS_BASE + 256),
+/// exit codes are not expected to be in the range
+pub const STOP_STATUS_BASE: isize = isize::MIN;
+
+impl PtraceStop {
+    /// The `WaitPid` status of the stop
+    pub fn status(self) -> isize {
+        STOP_STATUS_BASE + self as isize
+    }
+
+    /// The stop of a `WaitPid` status, `None` if the process exited
+    pub fn from_status(status: isize) -> Option<Self> {
+        let reason = status.checked_sub(STOP_STATUS_BASE)?;
+        (reason < 256).then(|| Self::from(reason as usize))
+    }
+}
+
+/// Actions of `Syslog` on the kernel log
+///
+/// the log is a text of records as `<level>[seconds.micros] message`,
+/// where the level is 1 (error) to 5 (trace). a message may span lines.
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+pub enum SyslogAction {
+    /// read the latest records which fit in the buffer
+    ReadAll = 3,
+
+    /// read like `ReadAll`, then clear the log
+    ReadClear = 4,
+
+    /// clear the log
+    Clear = 5,
+
+    /// get the size of the log buffer
+    SizeBuffer = 10,
+
+    #[num_enum(default)]
+    Unknown = 65535,
+}
+
+bitflags::bitflags! {
+    /// Flags of `Open` for files
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct OpenFlags: usize {
+        /// create the file if it does not exist
+        const CREATE = 1 << 0;
+        /// open or create a directory
+        const DIRECTORY = 1 << 1;
+        /// empty the file when it is opened
+        const TRUNCATE = 1 << 2;
+        /// write at the end of the file
+        const APPEND = 1 << 3;
+    }
+}
+
+/// Where `Lseek` counts the offset from
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, FromPrimitive)]
+pub enum SeekWhence {
+    /// the start of the file
+    Set = 0,
+
+    /// the current offset
+    Current = 1,
+
+    /// the end of the file
+    End = 2,
+
+    #[num_enum(default)]
+    Unknown = 65535,
+}
+
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FromPrimitive)]
+pub enum FileType {
+    File = 1,
+    Directory = 2,
+
+    #[num_enum(default)]
+    Unknown = 65535,
+}
+
+/// A file, given by `Fstat`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FileStat {
+    /// the inode number, unique in the filesystem
+    pub ino: u64,
+    /// the file type as [`FileType`]
+    pub ty: usize,
+    /// the size in bytes, the count of entries for a directory
+    pub size: u64,
+}
+
+impl FileStat {
+    pub fn file_type(&self) -> FileType {
+        FileType::from(self.ty)
+    }
+}
+
+/// The max length of a name in a directory
+pub const NAME_MAX: usize = 64;
+
+/// An entry of a directory, given by `ReadDir`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DirEntry {
+    /// the file type as [`FileType`]
+    pub ty: usize,
+    pub name_len: usize,
+    pub name: [u8; NAME_MAX],
+}
+
+impl Default for DirEntry {
+    fn default() -> Self {
+        Self {
+            ty: FileType::Unknown as usize,
+            name_len: 0,
+            name: [0; NAME_MAX],
+        }
+    }
+}
+
+impl DirEntry {
+    /// An entry of `name`, cut at [`NAME_MAX`] bytes
+    pub fn new(name: &str, ty: FileType) -> Self {
+        let mut entry = Self {
+            ty: ty as usize,
+            ..Self::default()
+        };
+
+        let len = name.len().min(NAME_MAX);
+        entry.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        entry.name_len = len;
+        entry
+    }
+
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len.min(NAME_MAX)]).unwrap_or("")
+    }
+
+    pub fn file_type(&self) -> FileType {
+        FileType::from(self.ty)
+    }
+}
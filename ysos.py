@@ -1,8 +1,10 @@
 #!/usr/bin/env python3
 
+import glob
 import os
 import shutil
 import subprocess
+import tarfile
 import argparse
 
 
@@ -25,6 +27,8 @@ parser.add_argument('--bios', type=str,
 parser.add_argument('--boot', type=str, default='esp', help='Set boot path')
 parser.add_argument('--debug-listen', type=str, default='0.0.0.0:1234',
                     help='Set listen address for gdbserver')
+parser.add_argument('--gdb-stub', type=str, metavar='ADDR',
+                    help='Connect COM2 to a TCP listen address for the in-kernel gdb stub')
 
 parser.add_argument('task', type=str, choices=[
                     'build', 'clean', 'launch', 'run'
@@ -58,6 +62,18 @@ def get_apps():
     return apps
 
 
+def get_modules():
+    module_path = os.path.join(os.getcwd(), 'pkg', 'module')
+
+    if not os.path.exists(module_path):
+        return []
+
+    modules = [name for name in os.listdir(module_path) if os.path.isdir(
+        os.path.join(module_path, name)) and name not in ['config', '.cargo']]
+
+    return modules
+
+
 def execute_command(cmd: list, workdir: str | None = None, shell: bool = False) -> int:
     debug('Executing', " ".join(cmd) + (f' in {workdir}' if workdir else ''))
 
@@ -87,6 +103,11 @@ def qemu(output: str = '-nographic', memory: str = '96M', debug: bool = False, i
     qemu_args = [qemu_exe, '-bios', args.bios, '-net', 'none', *output.split(),
                  '-m', memory, '-drive', 'format=raw,file=fat:esp', '-snapshot']
 
+    if args.gdb_stub:
+        # COM1 stays on the console, COM2 is used by the gdb stub
+        qemu_args += ['-serial', 'mon:stdio',
+                      '-serial', f'tcp:{args.gdb_stub},server,nowait']
+
     if debug:
         qemu_args += ['-gdb', f'tcp:{args.debug_listen}', '-S']
     elif intdbg:
@@ -115,6 +136,20 @@ def copy_to_esp(src: str, dst: str):
         raise Exception(f'{src} is not a file')
 
 
+def make_initramfs(files: list, dst: str):
+    dst = os.path.join(os.getcwd(), args.boot, dst)
+
+    if args.dry_run:
+        debug('Would pack', f'{len(files)} files -> {dst}')
+        return
+
+    # the kernel reads ustar archives
+    with tarfile.open(dst, 'w', format=tarfile.USTAR_FORMAT) as tar:
+        for src, name in files:
+            debug('Packing', f'{src} -> {name}')
+            tar.add(src, arcname=name)
+
+
 def build():
     cargo_exe = shutil.which('cargo')
 
@@ -145,6 +180,9 @@ def build():
         os.getcwd(), 'target', 'x86_64-unknown-none', profile_dir, 'ysos_kernel')
     copy_to_esp(compile_output, 'KERNEL.ELF')
 
+    # files of the initramfs, as (source, path in the archive)
+    initramfs = []
+
     # build apps
     apps = get_apps()
     for app in apps:
@@ -162,6 +200,35 @@ def build():
         compile_output = os.path.join(
             os.getcwd(), 'target', 'x86_64-unknown-ysos', profile_dir, app_name)
         copy_to_esp(compile_output, os.path.join('APP', app))
+        initramfs.append((compile_output, f'bin/{app}'))
+
+    # apps link to yslib as a shared library, copy the latest build
+    deps_path = os.path.join(
+        os.getcwd(), 'target', 'x86_64-unknown-ysos', profile_dir, 'deps')
+    libraries = glob.glob(os.path.join(deps_path, 'libyslib-*.so'))
+    if libraries:
+        library = max(libraries, key=os.path.getmtime)
+        copy_to_esp(library, os.path.join('APP', 'libyslib.so'))
+        initramfs.append((library, 'lib/libyslib.so'))
+
+    # kernel modules are loaded from the app list as <name>.ko
+    for module in get_modules():
+        module_path = os.path.join(os.getcwd(), 'pkg', 'module', module)
+
+        with open(os.path.join(module_path, 'Cargo.toml'), 'r') as f:
+            for line in f.readlines():
+                if 'name' in line:
+                    module_name = line.split('"')[1]
+                    break
+
+        info('Building', f'module {module}...')
+        execute_command([cargo_exe, 'build', profile], module_path)
+        compile_output = os.path.join(
+            os.getcwd(), 'target', 'x86_64-unknown-ysos-module', profile_dir, f'{module_name}.ko')
+        copy_to_esp(compile_output, os.path.join('APP', f'{module}.ko'))
+        initramfs.append((compile_output, f'lib/modules/{module}.ko'))
+
+    make_initramfs(initramfs, 'INITRAMFS.TAR')
 
 
 def clean():